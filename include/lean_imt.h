@@ -0,0 +1,172 @@
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#ifndef LEAN_IMT_H
+#define LEAN_IMT_H
+
+
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Size in bytes of a node.
+ */
+#define LEAN_IMT_NODE_SIZE 32
+
+/**
+ * Result of a call.
+ */
+typedef enum LeanImtStatus {
+  LEAN_IMT_STATUS_OK = 0,
+  LEAN_IMT_STATUS_NULL_POINTER = 1,
+  LEAN_IMT_STATUS_ERROR = 2,
+  /**
+   * The insert would exceed the maximum size of the tree.
+   */
+  LEAN_IMT_STATUS_TREE_FULL = 3,
+} LeanImtStatus;
+
+/**
+ * Opaque tree handle.
+ */
+typedef struct LeanImt LeanImt;
+
+/**
+ * Hash callback: hashes the `count` nodes at `nodes` into `out`.
+ */
+typedef void (*LeanImtHashFn)(const uint8_t *nodes, size_t count, uint8_t *out);
+
+/**
+ * Inclusion proof, whose `siblings` hold `siblings_len` contiguous nodes
+ * owned by the library and released with `lean_imt_proof_free`.
+ */
+typedef struct LeanImtProof {
+  uint8_t root[LEAN_IMT_NODE_SIZE];
+  uint8_t leaf[LEAN_IMT_NODE_SIZE];
+  uint64_t index;
+  uint8_t *siblings;
+  size_t siblings_len;
+} LeanImtProof;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an empty tree hashing with `hash`. Release it with `lean_imt_free`.
+ *
+ * Returns null, with a message for `lean_imt_last_error`, if the tree
+ * cannot be created.
+ */
+struct LeanImt *lean_imt_new(LeanImtHashFn hash);
+
+/**
+ * Releases a tree.
+ *
+ * # Safety
+ *
+ * `tree` must be null or a tree returned by `lean_imt_new` and not yet freed.
+ */
+void lean_imt_free(struct LeanImt *tree);
+
+/**
+ * Limits the tree to the `2^max_depth` leaves of a tree of depth
+ * `max_depth`; further inserts fail with `TreeFull`.
+ *
+ * # Safety
+ *
+ * `tree` must be a live tree.
+ */
+enum LeanImtStatus lean_imt_set_max_depth(struct LeanImt *tree, size_t max_depth);
+
+/**
+ * Inserts the leaf at `leaf`.
+ *
+ * # Safety
+ *
+ * `tree` must be a live tree and `leaf` must point to a node.
+ */
+enum LeanImtStatus lean_imt_insert(struct LeanImt *tree, const uint8_t *leaf);
+
+/**
+ * Inserts the `count` contiguous leaves at `leaves`.
+ *
+ * # Safety
+ *
+ * `tree` must be a live tree and `leaves` must point to `count` nodes.
+ */
+enum LeanImtStatus lean_imt_insert_many(struct LeanImt *tree, const uint8_t *leaves, size_t count);
+
+/**
+ * Writes the root of the tree to `out`, failing if the tree is empty.
+ *
+ * # Safety
+ *
+ * `tree` must be a live tree and `out` must point to a writable node.
+ */
+enum LeanImtStatus lean_imt_root(const struct LeanImt *tree, uint8_t *out);
+
+/**
+ * Returns the number of leaves of the tree, or 0 if `tree` is null.
+ *
+ * # Safety
+ *
+ * `tree` must be null or a live tree.
+ */
+uint64_t lean_imt_size(const struct LeanImt *tree);
+
+/**
+ * Writes the proof of the leaf at `index` to `out`.
+ *
+ * # Safety
+ *
+ * `tree` must be a live tree and `out` must point to a writable proof,
+ * whose previous siblings are not released.
+ */
+enum LeanImtStatus lean_imt_generate_proof(const struct LeanImt *tree,
+                                           uint64_t index,
+                                           struct LeanImtProof *out);
+
+/**
+ * Releases the siblings of a proof filled by `lean_imt_generate_proof`.
+ *
+ * # Safety
+ *
+ * `proof` must be null or a proof filled by `lean_imt_generate_proof`
+ * whose siblings were not released yet.
+ */
+void lean_imt_proof_free(struct LeanImtProof *proof);
+
+/**
+ * Checks that a proof hashes up to its root with `hash`.
+ *
+ * # Safety
+ *
+ * `proof` must be null or point to a proof whose siblings are valid.
+ */
+bool lean_imt_proof_verify(const struct LeanImtProof *proof, LeanImtHashFn hash);
+
+/**
+ * Checks that a proof is valid against the current root of the tree.
+ *
+ * # Safety
+ *
+ * `tree` must be null or a live tree, and `proof` null or a proof whose
+ * siblings are valid.
+ */
+bool lean_imt_verify_proof(const struct LeanImt *tree, const struct LeanImtProof *proof);
+
+/**
+ * Returns the message of the last error on this thread, or null. The
+ * string stays valid until the next failing call on the thread.
+ */
+const char *lean_imt_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LEAN_IMT_H */
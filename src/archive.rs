@@ -0,0 +1,161 @@
+//! Zero-copy access to snapshots archived with rkyv.
+//!
+//! Restoring a tree from a snapshot decodes every leaf and rebuilds the leaf
+//! index, which takes minutes for tens of millions of leaves. A snapshot
+//! archived by `to_rkyv` is laid out as it is read: once the file holding it
+//! is memory-mapped, `access_rkyv` validates it in place and returns an
+//! [`ArchivedLeanIMTSnapshot`], whose root, leaves and payloads are read
+//! without deserializing them, paged in by the OS as they are touched.
+
+use core::hash::Hash;
+
+use rkyv::api::high::{HighSerializer, HighValidator};
+use rkyv::bytecheck::CheckBytes;
+use rkyv::rancor::Error;
+use rkyv::ser::allocator::ArenaHandle;
+use rkyv::util::AlignedVec;
+use rkyv::{Archive, Serialize};
+
+use crate::{ArchivedLeanIMTSnapshot, LeanIMT, LeanIMTSnapshot, LeanIMTStore};
+
+impl<N> LeanIMTSnapshot<N>
+where
+    N: Archive + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, Error>>,
+{
+    /// Encodes the snapshot in the rkyv archive format.
+    ///
+    /// The archive follows the fields of the snapshot, stored little-endian
+    /// with 64-bit integers, so it is read alike on every platform but may
+    /// change between versions of this crate.
+    pub fn to_rkyv(&self) -> Result<AlignedVec, &'static str> {
+        rkyv::to_bytes::<Error>(self).map_err(|_| "Failed to archive snapshot")
+    }
+}
+
+impl<N: Archive> LeanIMTSnapshot<N>
+where
+    N::Archived: for<'a> CheckBytes<HighValidator<'a, Error>>,
+{
+    /// Checks that `bytes` hold a snapshot archived by `to_rkyv` and returns
+    /// it in place.
+    ///
+    /// `bytes` must be aligned to 16 bytes, as memory maps and the buffers
+    /// returned by `to_rkyv` are. The layout of the archive is checked, but,
+    /// as with `from_snapshot`, its side nodes are trusted.
+    pub fn access_rkyv(bytes: &[u8]) -> Result<&ArchivedLeanIMTSnapshot<N>, &'static str> {
+        rkyv::access::<ArchivedLeanIMTSnapshot<N>, Error>(bytes)
+            .map_err(|_| "Invalid rkyv snapshot")
+    }
+}
+
+impl<N: Archive> ArchivedLeanIMTSnapshot<N> {
+    pub fn get_size(&self) -> usize {
+        self.size.to_native() as usize
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth.to_native() as usize
+    }
+
+    /// Returns the root of the archived tree, or `None` if it is empty.
+    pub fn root(&self) -> Option<&N::Archived> {
+        if self.get_size() == 0 {
+            return None;
+        }
+        let arity = self.arity.to_native() as usize;
+        let root_key = self.get_depth().checked_mul(arity.checked_sub(1)?)?;
+        let position = self
+            .side_nodes
+            .binary_search_by_key(&root_key, |entry| entry.0.to_native() as usize)
+            .ok()?;
+        Some(&self.side_nodes[position].1)
+    }
+
+    /// Returns the leaf at `index`, the zero value if it was removed, or
+    /// `None` if the index is out of range.
+    pub fn leaf(&self, index: usize) -> Option<&N::Archived> {
+        if index >= self.get_size() {
+            return None;
+        }
+        match self
+            .leaves
+            .binary_search_by_key(&index, |entry| entry.1.to_native() as usize)
+        {
+            Ok(position) => Some(&self.leaves[position].0),
+            Err(_) => Some(&self.zero),
+        }
+    }
+
+    /// Returns the payload attached to the leaf at `index`, if any.
+    pub fn payload(&self, index: usize) -> Option<&[u8]> {
+        let position = self
+            .payloads
+            .binary_search_by_key(&index, |entry| entry.0.to_native() as usize)
+            .ok()?;
+        Some(self.payloads[position].1.as_slice())
+    }
+}
+
+impl<N, S> LeanIMT<N, S>
+where
+    N: Clone
+        + Eq
+        + Hash
+        + Archive
+        + for<'a> Serialize<HighSerializer<AlignedVec, ArenaHandle<'a>, Error>>,
+    S: LeanIMTStore<N>,
+{
+    /// Encodes the state of the tree in the rkyv archive format.
+    pub fn to_rkyv(&self) -> Result<AlignedVec, &'static str> {
+        self.snapshot()?.to_rkyv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DuplicatePolicy, IMTHashFunction};
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_rkyv_snapshot() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+
+        let bytes = imt.to_rkyv().unwrap();
+        let archived = LeanIMTSnapshot::<String>::access_rkyv(&bytes).unwrap();
+        assert_eq!(archived.get_size(), 5);
+        assert_eq!(archived.get_depth(), 3);
+        assert_eq!(archived.duplicate_policy, DuplicatePolicy::Reject);
+        assert_eq!(
+            archived.root().map(|root| root.as_str()),
+            imt.root().as_deref()
+        );
+        assert_eq!(archived.leaf(0).map(|leaf| leaf.as_str()), Some("leaf1"));
+        assert_eq!(archived.leaf(1).map(|leaf| leaf.as_str()), Some("0"));
+        assert_eq!(archived.leaf(5), None);
+        assert_eq!(archived.payload(0), None);
+
+        let snapshot: LeanIMTSnapshot<String> = rkyv::deserialize::<_, Error>(archived).unwrap();
+        assert_eq!(snapshot, imt.snapshot().unwrap());
+
+        let empty = LeanIMT::new(hash).to_rkyv().unwrap();
+        let archived = LeanIMTSnapshot::<String>::access_rkyv(&empty).unwrap();
+        assert_eq!(archived.root(), None);
+
+        assert_eq!(
+            LeanIMTSnapshot::<String>::access_rkyv(&bytes[1..]).err(),
+            Some("Invalid rkyv snapshot")
+        );
+    }
+}
@@ -0,0 +1,712 @@
+//! Async variant of the tree, for stores that are reached over the network
+//! or through async I/O.
+//!
+//! [`AsyncLeanIMT`] runs the same algorithms as [`LeanIMT`] without blocking
+//! the executor: before each operation it loads the side nodes and leaves the
+//! operation can touch from the [`AsyncLeanIMTStore`], runs the operation in
+//! memory, then writes the changes back and commits them. A failed operation
+//! writes nothing. Proofs load every leaf, as with the other stores.
+//!
+//! Async trees do not keep a changelog.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::{ready, Future};
+use core::hash::Hash;
+use core::ops::Range;
+
+use crate::changelog::Changelog;
+use crate::store::{StoreIter, StoreMetadata};
+use crate::{
+    depth_of, DuplicatePolicy, HashMap, IMTHashFunction, IMTNode, LeanIMT, LeanIMTStore,
+    MemoryStore, MerkleProof, NaryMerkleProof, RemovalPolicy, TreeHash,
+};
+
+/// Async get/put/delete access to the side nodes and leaves of a tree.
+///
+/// The methods mirror those of [`LeanIMTStore`]; every tree operation ends
+/// with `commit`, or with `rollback` if writing its changes failed.
+pub trait AsyncLeanIMTStore<N> {
+    fn side_node(&self, key: usize)
+        -> impl Future<Output = Result<Option<N>, &'static str>> + Send;
+
+    fn put_side_node(
+        &mut self,
+        key: usize,
+        node: N,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send;
+
+    fn delete_side_node(
+        &mut self,
+        key: usize,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send;
+
+    fn leaf_indices(
+        &self,
+        leaf: &N,
+    ) -> impl Future<Output = Result<Option<Vec<usize>>, &'static str>> + Send;
+
+    fn put_leaf_indices(
+        &mut self,
+        leaf: N,
+        indices: Vec<usize>,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send;
+
+    fn delete_leaf(&mut self, leaf: &N) -> impl Future<Output = Result<(), &'static str>> + Send;
+
+    /// Saves the leaf at `index`, as `LeanIMTStore::put_leaf_at`.
+    fn put_leaf_at(
+        &mut self,
+        _index: usize,
+        _leaf: Option<N>,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(Ok(()))
+    }
+
+    /// Returns every `(leaf, indices)` entry, in no particular order.
+    fn leaves(&self) -> impl Future<Output = Result<Vec<(N, Vec<usize>)>, &'static str>> + Send;
+
+    fn metadata(
+        &self,
+    ) -> impl Future<Output = Result<Option<StoreMetadata<N>>, &'static str>> + Send;
+
+    fn put_metadata(
+        &mut self,
+        metadata: StoreMetadata<N>,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send;
+
+    fn commit(&mut self) -> impl Future<Output = Result<(), &'static str>> + Send;
+
+    fn rollback(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+impl<N: Clone + Eq + Hash + Send> AsyncLeanIMTStore<N> for MemoryStore<N> {
+    fn side_node(
+        &self,
+        key: usize,
+    ) -> impl Future<Output = Result<Option<N>, &'static str>> + Send {
+        ready(LeanIMTStore::side_node(self, key))
+    }
+
+    fn put_side_node(
+        &mut self,
+        key: usize,
+        node: N,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(LeanIMTStore::put_side_node(self, key, node))
+    }
+
+    fn delete_side_node(
+        &mut self,
+        key: usize,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(LeanIMTStore::delete_side_node(self, key))
+    }
+
+    fn leaf_indices(
+        &self,
+        leaf: &N,
+    ) -> impl Future<Output = Result<Option<Vec<usize>>, &'static str>> + Send {
+        ready(LeanIMTStore::leaf_indices(self, leaf))
+    }
+
+    fn put_leaf_indices(
+        &mut self,
+        leaf: N,
+        indices: Vec<usize>,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(LeanIMTStore::put_leaf_indices(self, leaf, indices))
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(LeanIMTStore::delete_leaf(self, leaf))
+    }
+
+    fn put_leaf_at(
+        &mut self,
+        index: usize,
+        leaf: Option<N>,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(LeanIMTStore::put_leaf_at(self, index, leaf))
+    }
+
+    fn leaves(&self) -> impl Future<Output = Result<Vec<(N, Vec<usize>)>, &'static str>> + Send {
+        ready(LeanIMTStore::leaves(self).collect())
+    }
+
+    fn metadata(
+        &self,
+    ) -> impl Future<Output = Result<Option<StoreMetadata<N>>, &'static str>> + Send {
+        ready(Ok(None))
+    }
+
+    fn put_metadata(
+        &mut self,
+        _metadata: StoreMetadata<N>,
+    ) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(Ok(()))
+    }
+
+    fn commit(&mut self) -> impl Future<Output = Result<(), &'static str>> + Send {
+        ready(Ok(()))
+    }
+
+    fn rollback(&mut self) -> impl Future<Output = ()> + Send {
+        ready(())
+    }
+}
+
+/// Lean incremental Merkle tree over an async store.
+#[derive(Debug)]
+pub struct AsyncLeanIMT<N = IMTNode, S = MemoryStore<N>> {
+    size: usize,
+    depth: usize,
+    arity: usize,
+    store: S,
+    duplicate_policy: DuplicatePolicy,
+    max_size: Option<usize>,
+    hash: IMTHashFunction<N>,
+    zero: N,
+    version: u64,
+}
+
+impl AsyncLeanIMT {
+    /// Creates an in-memory tree of string nodes, with "0" as zero value.
+    pub fn new(hash: IMTHashFunction) -> Self {
+        Self::from_metadata(hash, MemoryStore::default(), initial_metadata("0".into()))
+    }
+}
+
+impl<N: Clone + Eq + Hash + Send, S: AsyncLeanIMTStore<N>> AsyncLeanIMT<N, S> {
+    /// Creates an empty tree keeping its nodes in `store`.
+    ///
+    /// Fails with "Store is not empty" if `store` already holds a tree.
+    pub async fn with_store(
+        hash: IMTHashFunction<N>,
+        zero: N,
+        mut store: S,
+    ) -> Result<Self, &'static str> {
+        if store.metadata().await?.is_some() || store.side_node(0).await?.is_some() {
+            return Err("Store is not empty");
+        }
+
+        let metadata = initial_metadata(zero);
+        store.put_metadata(metadata.clone()).await?;
+        store.commit().await?;
+        Ok(Self::from_metadata(hash, store, metadata))
+    }
+
+    /// Reopens the tree saved in `store`.
+    pub async fn open(hash: IMTHashFunction<N>, store: S) -> Result<Self, &'static str> {
+        let metadata = store.metadata().await?.ok_or("Missing tree metadata")?;
+        if metadata.arity < 2 {
+            return Err("Invalid arity");
+        }
+
+        let tree = Self::from_metadata(hash, store, metadata);
+        if tree.size > 0 && tree.store.side_node(tree.root_key()).await?.is_none() {
+            return Err("Missing root");
+        }
+        Ok(tree)
+    }
+
+    pub async fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        let keys = 0..self.root_key_at(self.size + 1) + 1;
+        let leaves = [leaf.clone()];
+        self.run(keys, &leaves, |tree| tree.insert(leaf)).await
+    }
+
+    pub async fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        let keys = 0..self.root_key_at(self.size + leaves.len()) + 1;
+        let loaded = leaves.clone();
+        self.run(keys, &loaded, |tree| tree.insert_many(leaves))
+            .await
+    }
+
+    pub async fn update(
+        &mut self,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        let index = self.index_of(old_leaf).await?;
+        self.update_at(index, old_leaf, new_leaf, sibling_nodes)
+            .await
+    }
+
+    pub async fn update_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        let keys = 0..self.root_key() + 1;
+        let leaves = [old_leaf.clone(), new_leaf.clone()];
+        self.run(keys, &leaves, |tree| {
+            tree.update_at(index, old_leaf, new_leaf, sibling_nodes)
+        })
+        .await
+    }
+
+    pub async fn remove(&mut self, old_leaf: &N, sibling_nodes: &[N]) -> Result<N, &'static str> {
+        self.update(old_leaf, self.zero.clone(), sibling_nodes)
+            .await
+    }
+
+    pub async fn remove_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.update_at(index, old_leaf, self.zero.clone(), sibling_nodes)
+            .await
+    }
+
+    /// Sets how leaves already in the tree are handled from now on.
+    pub async fn set_duplicate_policy(
+        &mut self,
+        policy: DuplicatePolicy,
+    ) -> Result<(), &'static str> {
+        if policy == DuplicatePolicy::Reject
+            && self
+                .store
+                .leaves()
+                .await?
+                .iter()
+                .any(|(_, indices)| indices.len() > 1)
+        {
+            return Err("Tree contains duplicate leaves");
+        }
+        self.run(0..0, &[], |tree| tree.set_duplicate_policy(policy))
+            .await
+    }
+
+    /// Limits the tree to `max_size` leaves, or lifts the limit with `None`.
+    pub async fn set_max_size(&mut self, max_size: Option<usize>) -> Result<(), &'static str> {
+        self.run(0..0, &[], |tree| tree.set_max_size(max_size))
+            .await
+    }
+
+    /// Checks if a leaf exists in the tree.
+    ///
+    /// A leaf that cannot be read from the store is reported as missing.
+    pub async fn has(&self, leaf: &N) -> bool {
+        self.store
+            .leaf_indices(leaf)
+            .await
+            .is_ok_and(|indices| indices.is_some())
+    }
+
+    /// Returns the lowest index of a leaf.
+    pub async fn index_of(&self, leaf: &N) -> Result<usize, &'static str> {
+        self.store
+            .leaf_indices(leaf)
+            .await?
+            .and_then(|indices| indices.first().copied())
+            .ok_or("Leaf does not exist")
+    }
+
+    /// Returns the root of the tree.
+    ///
+    /// A root that cannot be read from the store is reported as missing.
+    pub async fn root(&self) -> Option<N> {
+        self.store.side_node(self.root_key()).await.ok().flatten()
+    }
+
+    /// Generates the inclusion proof of the leaf at `index`.
+    pub async fn generate_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.load_leaves().await?.generate_proof(index)
+    }
+
+    /// Generates the inclusion proof of the leaf at `index`, for trees of any
+    /// arity.
+    pub async fn generate_nary_proof(
+        &self,
+        index: usize,
+    ) -> Result<NaryMerkleProof<N>, &'static str> {
+        self.load_leaves().await?.generate_nary_proof(index)
+    }
+
+    /// Checks a proof against the current root of the tree.
+    pub async fn verify_proof(&self, proof: &MerkleProof<N>) -> bool {
+        self.arity == 2
+            && self.root().await.is_some_and(|root| root == proof.root)
+            && proof.verify(self.hash)
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn get_arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Returns the number of mutations applied to the tree.
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get_duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    pub fn get_max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    pub fn get_zero(&self) -> &N {
+        &self.zero
+    }
+
+    pub fn get_store(&self) -> &S {
+        &self.store
+    }
+
+    fn root_key(&self) -> usize {
+        self.depth * (self.arity - 1)
+    }
+
+    /// Returns the key of the root of the tree once it holds `size` leaves.
+    fn root_key_at(&self, size: usize) -> usize {
+        depth_of(size, self.arity) * (self.arity - 1)
+    }
+
+    /// Loads the side nodes at `keys` and the given leaves, runs `operation`
+    /// on them in memory, then writes its changes to the store.
+    async fn run<T>(
+        &mut self,
+        keys: Range<usize>,
+        leaves: &[N],
+        operation: impl FnOnce(&mut LeanIMT<N, LoadedNodes<N>>) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        let mut nodes = LoadedNodes::default();
+        for key in keys {
+            nodes
+                .side_nodes
+                .insert(key, self.store.side_node(key).await?);
+        }
+        for leaf in leaves {
+            let indices = self.store.leaf_indices(leaf).await?;
+            nodes.leaves.insert(leaf.clone(), indices);
+        }
+
+        let mut tree = self.in_memory(nodes);
+        let value = operation(&mut tree)?;
+
+        if let Err(error) = self.write_changes(tree.store).await {
+            self.store.rollback().await;
+            return Err(error);
+        }
+        self.size = tree.size;
+        self.depth = tree.depth;
+        self.arity = tree.arity;
+        self.duplicate_policy = tree.duplicate_policy;
+        self.max_size = tree.max_size;
+        self.version = tree.version;
+        Ok(value)
+    }
+
+    async fn write_changes(&mut self, nodes: LoadedNodes<N>) -> Result<(), &'static str> {
+        for (key, node) in nodes.side_node_writes {
+            match node {
+                Some(node) => self.store.put_side_node(key, node).await?,
+                None => self.store.delete_side_node(key).await?,
+            }
+        }
+        for (leaf, indices) in nodes.leaf_writes {
+            match indices {
+                Some(indices) => self.store.put_leaf_indices(leaf, indices).await?,
+                None => self.store.delete_leaf(&leaf).await?,
+            }
+        }
+        for (index, leaf) in nodes.leaf_at_writes {
+            self.store.put_leaf_at(index, leaf).await?;
+        }
+        if let Some(metadata) = nodes.metadata {
+            self.store.put_metadata(metadata).await?;
+        }
+        self.store.commit().await
+    }
+
+    /// Returns an in-memory copy of the tree holding the root and every leaf.
+    async fn load_leaves(&self) -> Result<LeanIMT<N, LoadedNodes<N>>, &'static str> {
+        let mut nodes = LoadedNodes::default();
+        nodes.side_nodes.insert(
+            self.root_key(),
+            self.store.side_node(self.root_key()).await?,
+        );
+        for (leaf, indices) in self.store.leaves().await? {
+            nodes.leaves.insert(leaf, Some(indices));
+        }
+        Ok(self.in_memory(nodes))
+    }
+
+    fn in_memory(&self, nodes: LoadedNodes<N>) -> LeanIMT<N, LoadedNodes<N>> {
+        LeanIMT {
+            size: self.size,
+            depth: self.depth,
+            arity: self.arity,
+            store: nodes,
+            duplicate_policy: self.duplicate_policy,
+            removal_policy: RemovalPolicy::Forget,
+            gaps: None,
+            max_size: self.max_size,
+            hash: TreeHash::Fn(self.hash),
+            leaf_hash: None,
+            node_eq: None,
+            zero: self.zero.clone(),
+            version: self.version,
+            changelog: Changelog::new(),
+            stats: Default::default(),
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash> AsyncLeanIMT<N> {
+    /// Creates an in-memory tree using `zero` as the value of removed leaves.
+    pub fn with_zero(hash: IMTHashFunction<N>, zero: N) -> Self {
+        Self::from_metadata(hash, MemoryStore::default(), initial_metadata(zero))
+    }
+}
+
+impl<N, S> AsyncLeanIMT<N, S> {
+    fn from_metadata(hash: IMTHashFunction<N>, store: S, metadata: StoreMetadata<N>) -> Self {
+        AsyncLeanIMT {
+            size: metadata.size,
+            depth: depth_of(metadata.size, metadata.arity),
+            arity: metadata.arity,
+            store,
+            duplicate_policy: metadata.duplicate_policy,
+            max_size: metadata.max_size,
+            hash,
+            zero: metadata.zero,
+            version: metadata.version,
+        }
+    }
+}
+
+fn initial_metadata<N>(zero: N) -> StoreMetadata<N> {
+    StoreMetadata {
+        size: 0,
+        version: 0,
+        zero,
+        duplicate_policy: DuplicatePolicy::Reject,
+        arity: 2,
+        max_size: None,
+    }
+}
+
+/// Side nodes and leaves loaded from an async store, with the writes of the
+/// operation running over them.
+///
+/// Reading a node that was not loaded fails, so a missing prefetch cannot
+/// silently produce a wrong root.
+struct LoadedNodes<N> {
+    side_nodes: HashMap<usize, Option<N>>,
+    leaves: HashMap<N, Option<Vec<usize>>>,
+    side_node_writes: HashMap<usize, Option<N>>,
+    leaf_writes: HashMap<N, Option<Vec<usize>>>,
+    leaf_at_writes: Vec<(usize, Option<N>)>,
+    metadata: Option<StoreMetadata<N>>,
+}
+
+impl<N> Default for LoadedNodes<N> {
+    fn default() -> Self {
+        LoadedNodes {
+            side_nodes: HashMap::default(),
+            leaves: HashMap::default(),
+            side_node_writes: HashMap::default(),
+            leaf_writes: HashMap::default(),
+            leaf_at_writes: Vec::new(),
+            metadata: None,
+        }
+    }
+}
+
+const NOT_LOADED: &str = "Node not loaded";
+
+impl<N: Clone + Eq + Hash> LeanIMTStore<N> for LoadedNodes<N> {
+    fn side_node(&self, key: usize) -> Result<Option<N>, &'static str> {
+        self.side_node_writes
+            .get(&key)
+            .or_else(|| self.side_nodes.get(&key))
+            .cloned()
+            .ok_or(NOT_LOADED)
+    }
+
+    fn put_side_node(&mut self, key: usize, node: N) -> Result<(), &'static str> {
+        self.side_node_writes.insert(key, Some(node));
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, key: usize) -> Result<(), &'static str> {
+        self.side_node_writes.insert(key, None);
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        let loaded = self
+            .side_nodes
+            .iter()
+            .filter(|(&key, _)| !self.side_node_writes.contains_key(&key));
+        Box::new(
+            self.side_node_writes
+                .iter()
+                .chain(loaded)
+                .filter_map(|(&key, node)| Some(Ok((key, node.clone()?)))),
+        )
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        self.leaf_writes
+            .get(leaf)
+            .or_else(|| self.leaves.get(leaf))
+            .cloned()
+            .ok_or(NOT_LOADED)
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.leaf_writes.insert(leaf, Some(indices));
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.leaf_writes.insert(leaf.clone(), None);
+        Ok(())
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        self.leaf_at_writes.push((index, leaf));
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        let loaded = self
+            .leaves
+            .iter()
+            .filter(|(leaf, _)| !self.leaf_writes.contains_key(*leaf));
+        Box::new(
+            self.leaf_writes
+                .iter()
+                .chain(loaded)
+                .filter_map(|(leaf, indices)| Some(Ok((leaf.clone(), indices.clone()?)))),
+        )
+    }
+
+    fn put_metadata(&mut self, metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        self.metadata = Some(metadata);
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        self.side_node_writes.clear();
+        self.leaf_writes.clear();
+        self.metadata = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    /// Polls a future whose store never has to wait.
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(value) = future.as_mut().poll(&mut context) {
+                return value;
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_tree_matches_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        let mut async_imt = AsyncLeanIMT::new(hash);
+
+        block_on(async {
+            for i in 1..=5 {
+                imt.insert(format!("leaf{}", i)).unwrap();
+                async_imt.insert(format!("leaf{}", i)).await.unwrap();
+            }
+            let leaves: Vec<_> = (6..=9).map(|i| format!("leaf{}", i)).collect();
+            imt.insert_many(leaves.clone()).unwrap();
+            async_imt.insert_many(leaves).await.unwrap();
+            assert_eq!(async_imt.root().await, imt.root());
+
+            let proof = async_imt.generate_proof(2).await.unwrap();
+            assert_eq!(proof, imt.generate_proof(2).unwrap());
+            assert!(async_imt.verify_proof(&proof).await);
+
+            let leaf = "leaf3".to_string();
+            imt.update(&leaf, "leaf10".to_string(), &proof.siblings)
+                .unwrap();
+            async_imt
+                .update(&leaf, "leaf10".to_string(), &proof.siblings)
+                .await
+                .unwrap();
+            assert_eq!(async_imt.root().await, imt.root());
+            assert!(!async_imt.has(&leaf).await);
+            assert_eq!(async_imt.index_of(&"leaf10".to_string()).await, Ok(2));
+
+            let proof = imt.generate_proof(8).unwrap();
+            imt.remove(&"leaf9".to_string(), &proof.siblings).unwrap();
+            async_imt
+                .remove(&"leaf9".to_string(), &proof.siblings)
+                .await
+                .unwrap();
+            assert_eq!(async_imt.root().await, imt.root());
+            assert_eq!(async_imt.get_version(), imt.get_version());
+            assert_eq!(
+                async_imt.get_store().side_nodes().count(),
+                imt.get_side_nodes().len()
+            );
+        });
+    }
+
+    #[test]
+    fn test_failed_async_operation_writes_nothing() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut async_imt = AsyncLeanIMT::new(hash);
+
+        block_on(async {
+            async_imt
+                .insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+                .await
+                .unwrap();
+            let root = async_imt.root().await;
+
+            let sibling_nodes = vec!["wrong".to_string()];
+            let result = async_imt
+                .update(&"leaf2".to_string(), "leaf3".to_string(), &sibling_nodes)
+                .await;
+            assert_eq!(result.unwrap_err(), "Wrong sibling nodes");
+            assert_eq!(
+                async_imt.insert("leaf1".to_string()).await.unwrap_err(),
+                "Leaf already exists"
+            );
+
+            assert_eq!(async_imt.root().await, root);
+            assert_eq!(async_imt.get_version(), 1);
+            assert!(!async_imt.has(&"leaf3".to_string()).await);
+        });
+    }
+}
@@ -0,0 +1,76 @@
+//! Modular reduction of `num_bigint::BigUint` nodes.
+//!
+//! Pipelines passing field elements around as decimal strings can keep them
+//! as `BigUint` nodes, written and parsed by `codec::BigUintCodec`. Nothing
+//! keeps such nodes below the field order, though, so a value coming from
+//! elsewhere may not be the representative that circuits hash. A [`Modulus`]
+//! names a field order: [`reduce`] is a leaf hash reducing the leaves
+//! entering a tree, as in `imt.set_leaf_hash(Some(reduce::<Bn254>))`, and
+//! `codec::ReducedCodec` only decodes reduced nodes. Hash functions over
+//! `BigUint` nodes reduce their own outputs.
+
+use num_bigint::BigUint;
+
+/// Order of a prime field, for the reduction of `BigUint` nodes.
+pub trait Modulus {
+    fn modulus() -> BigUint;
+}
+
+/// Order of the BN254 scalar field, the field of circom and Semaphore
+/// circuits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bn254;
+
+impl Modulus for Bn254 {
+    fn modulus() -> BigUint {
+        BigUint::from_bytes_be(&[
+            0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81,
+            0x58, 0x5d, 0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91, 0x43, 0xe1, 0xf5, 0x93,
+            0xf0, 0x00, 0x00, 0x01,
+        ])
+    }
+}
+
+/// Reduces a node modulo `M`, e.g. as the leaf hash of a tree.
+pub fn reduce<M: Modulus>(node: BigUint) -> BigUint {
+    node % M::modulus()
+}
+
+/// Returns true if a node is lower than the order `M`.
+pub fn is_reduced<M: Modulus>(node: &BigUint) -> bool {
+    *node < M::modulus()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::string::ToString;
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<BigUint>) -> BigUint {
+        reduce::<Bn254>(
+            nodes
+                .into_iter()
+                .fold(BigUint::from(7u64), |node, child| node * 3u64 + child),
+        )
+    }
+
+    #[test]
+    fn test_reduced_leaves() {
+        let order = Bn254::modulus();
+        assert_eq!(
+            order.to_string(),
+            "21888242871839275222246405745257275088548364400416034343698204186575808495617"
+        );
+
+        let hash: IMTHashFunction<BigUint> = simple_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, BigUint::ZERO);
+        imt.set_leaf_hash(Some(reduce::<Bn254>));
+        imt.insert(&order + 5u64).unwrap();
+        assert!(imt.has(&BigUint::from(5u64)));
+        assert!(imt.insert(BigUint::from(5u64)).is_err());
+        assert!(is_reduced::<Bn254>(&imt.root().unwrap()));
+        assert!(!is_reduced::<Bn254>(&order));
+    }
+}
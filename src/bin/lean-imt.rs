@@ -0,0 +1,286 @@
+//! Command line tool to build trees from files of leaves, print their root,
+//! and generate and verify proofs.
+//!
+//! Nodes are text: a `0x`-prefixed hex node is hashed as the bytes it
+//! encodes, any other node as its UTF-8 bytes. A parent is the `0x`-prefixed
+//! hex digest of the bytes of its children, concatenated in order.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use lean_imt::import::{open_leaves, ImportOptions, LeafFormat};
+use lean_imt::{IMTHashFunction, LeanIMT, MerkleProof};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+#[derive(Parser)]
+#[command(
+    name = "lean-imt",
+    version,
+    about = "Build LeanIMTs and generate proofs"
+)]
+struct Cli {
+    /// Hash function of the tree
+    #[arg(long, value_enum, default_value_t = HashKind::Sha256, global = true)]
+    hash: HashKind,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the root of the tree built from the leaves
+    Root {
+        #[command(flatten)]
+        input: Input,
+    },
+    /// Print the proofs of the given leaves as JSON
+    Prove {
+        #[command(flatten)]
+        input: Input,
+        /// Leaves to prove
+        #[arg(required = true)]
+        leaves: Vec<String>,
+    },
+    /// Verify JSON proofs, a single proof or an array, from a file or stdin
+    Verify {
+        /// File holding the proofs, `-` for stdin
+        #[arg(default_value = "-")]
+        proofs: PathBuf,
+        /// Root the proofs must be against
+        #[arg(long)]
+        root: Option<String>,
+    },
+}
+
+#[derive(clap::Args)]
+struct Input {
+    /// File of leaves, `-` for stdin, decompressed if it ends in `.gz`
+    /// (with the `gzip` feature)
+    file: PathBuf,
+    /// Format of the file, guessed from its extension by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// CSV column holding the leaves
+    #[arg(long, default_value_t = 0)]
+    column: usize,
+    /// Skip the first row of a CSV file
+    #[arg(long)]
+    header: bool,
+    /// JSONL object field holding the leaves
+    #[arg(long, default_value = "leaf")]
+    field: String,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum HashKind {
+    Sha256,
+    Keccak256,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+enum Format {
+    /// One leaf per line
+    Lines,
+    Csv,
+    /// One JSON string, number or object per line
+    Jsonl,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs a command, returning false if a proof is invalid.
+fn run(cli: Cli) -> Result<bool, String> {
+    let hash = match cli.hash {
+        HashKind::Sha256 => sha256_hash as IMTHashFunction,
+        HashKind::Keccak256 => keccak256_hash,
+    };
+
+    match cli.command {
+        Command::Root { input } => {
+            let tree = build_tree(&input, hash)?;
+            println!("{}", tree.root().ok_or("No leaves")?);
+        }
+        Command::Prove { input, leaves } => {
+            let tree = build_tree(&input, hash)?;
+            let proofs = leaves
+                .iter()
+                .map(|leaf| {
+                    let index = tree
+                        .index_of(leaf)
+                        .map_err(|_| format!("Leaf does not exist: {}", leaf))?;
+                    tree.generate_proof(index).map_err(String::from)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let json = serde_json::to_string_pretty(&proofs).map_err(|e| e.to_string())?;
+            println!("{}", json);
+        }
+        Command::Verify { proofs, root } => {
+            let json: Value = serde_json::from_reader(open(&proofs)?).map_err(|e| e.to_string())?;
+            let proofs: Vec<MerkleProof> = match json {
+                Value::Array(_) => serde_json::from_value(json),
+                _ => serde_json::from_value(json).map(|proof| vec![proof]),
+            }
+            .map_err(|e| e.to_string())?;
+
+            let mut all_valid = true;
+            for proof in &proofs {
+                let valid = proof.verify(hash) && root.as_ref().is_none_or(|r| *r == proof.root);
+                println!(
+                    "{}: {}",
+                    proof.leaf,
+                    if valid { "valid" } else { "invalid" }
+                );
+                all_valid &= valid;
+            }
+            return Ok(all_valid);
+        }
+    }
+    Ok(true)
+}
+
+fn build_tree(input: &Input, hash: IMTHashFunction) -> Result<LeanIMT, String> {
+    let reader =
+        open_leaves(&input.file).map_err(|e| format!("{}: {}", input.file.display(), e))?;
+    let mut tree = LeanIMT::new(hash);
+    tree.import_leaves(reader, &import_options(input), Ok, |_| {})?;
+    Ok(tree)
+}
+
+fn import_options(input: &Input) -> ImportOptions {
+    let format = match input.format {
+        Some(Format::Lines) => LeafFormat::Lines,
+        Some(Format::Csv) => LeafFormat::Csv,
+        Some(Format::Jsonl) => LeafFormat::Jsonl,
+        None => LeafFormat::from_path(&input.file),
+    };
+    ImportOptions {
+        column: input.column,
+        header: input.header,
+        field: input.field.clone(),
+        ..ImportOptions::new(format)
+    }
+}
+
+fn open(path: &Path) -> Result<Box<dyn Read>, String> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin()));
+    }
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(Box::new(file))
+}
+
+/// Returns the bytes hashed for a node.
+fn node_bytes(node: &str) -> Vec<u8> {
+    node.strip_prefix("0x")
+        .and_then(|hex| hex::decode(hex).ok())
+        .unwrap_or_else(|| node.as_bytes().to_vec())
+}
+
+fn digest_nodes<D: Digest>(nodes: Vec<String>) -> String {
+    let mut hasher = D::new();
+    for node in &nodes {
+        hasher.update(node_bytes(node));
+    }
+    format!("0x{}", hex::encode(hasher.finalize()))
+}
+
+fn sha256_hash(nodes: Vec<String>) -> String {
+    digest_nodes::<Sha256>(nodes)
+}
+
+fn keccak256_hash(nodes: Vec<String>) -> String {
+    digest_nodes::<Keccak256>(nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lean_imt::import::LeafReader;
+
+    fn input(format: Format) -> Input {
+        Input {
+            file: PathBuf::from("-"),
+            format: Some(format),
+            column: 1,
+            header: true,
+            field: "address".to_string(),
+        }
+    }
+
+    fn parse_leaves(text: &str, format: Format) -> Result<Vec<String>, &'static str> {
+        LeafReader::new(text.as_bytes(), &import_options(&input(format))).collect()
+    }
+
+    #[test]
+    fn test_parse_leaves() {
+        let lines = "alice\n\n  bob \n";
+        assert_eq!(
+            parse_leaves(lines, Format::Lines).unwrap(),
+            vec!["alice", "bob"]
+        );
+
+        let csv = "amount,address\n10,alice\n20,\"b,ob\"\n";
+        assert_eq!(
+            parse_leaves(csv, Format::Csv).unwrap(),
+            vec!["alice", "b,ob"]
+        );
+
+        let jsonl = "{\"address\": \"alice\"}\n\"bob\"\n42\n";
+        assert_eq!(
+            parse_leaves(jsonl, Format::Jsonl).unwrap(),
+            vec!["alice", "bob", "42"]
+        );
+        assert!(parse_leaves("{}", Format::Jsonl).is_err());
+
+        let mut input = input(Format::Csv);
+        input.format = None;
+        input.file = PathBuf::from("leaves.ndjson");
+        assert_eq!(import_options(&input).format, LeafFormat::Jsonl);
+    }
+
+    #[test]
+    fn test_hash_functions() {
+        // Hex nodes are hashed as bytes, so a parent matches the digest of
+        // the concatenated child digests
+        let left = sha256_hash(vec!["a".to_string()]);
+        let right = sha256_hash(vec!["b".to_string()]);
+        let mut hasher = Sha256::new();
+        hasher.update(Sha256::digest(b"a"));
+        hasher.update(Sha256::digest(b"b"));
+        assert_eq!(
+            sha256_hash(vec![left, right]),
+            format!("0x{}", hex::encode(hasher.finalize()))
+        );
+
+        assert_eq!(
+            keccak256_hash(vec![]),
+            "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+
+        let mut tree = LeanIMT::new(sha256_hash);
+        tree.insert_many(vec![
+            "alice".to_string(),
+            "bob".to_string(),
+            "carol".to_string(),
+        ])
+        .unwrap();
+        let proof = tree.generate_proof(2).unwrap();
+        assert!(proof.verify(sha256_hash));
+        assert!(!proof.verify(keccak256_hash));
+    }
+}
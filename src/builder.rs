@@ -0,0 +1,232 @@
+//! Builder configuring a tree in one place.
+
+use alloc::string::{String, ToString};
+use core::hash::Hash;
+
+use crate::{
+    max_size_of, DuplicatePolicy, IMTHashFunction, IMTLeafHashFunction, IMTNode, IMTNodeEqFunction,
+    IMTPairHashFunction, LeanIMT, LeanIMTStore, MemoryStore, RemovalPolicy, SharedHashFunction,
+    TreeHash,
+};
+
+/// Limit on the number of leaves of the built tree.
+#[derive(Debug, Clone, Copy)]
+enum SizeLimit {
+    Size(usize),
+    /// Depth of a tree with the configured arity.
+    Depth(usize),
+}
+
+/// Builder of a [`LeanIMT`], validating the whole configuration in `build`.
+///
+/// ```ignore
+/// let imt = LeanIMTBuilder::with_zero(poseidon_hash, U256::ZERO)
+///     .arity(2)
+///     .max_depth(20)
+///     .root_history(1024)
+///     .store(sled_store)
+///     .build()?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct LeanIMTBuilder<N = IMTNode, S = MemoryStore<N>> {
+    hash: TreeHash<N>,
+    zero: N,
+    store: S,
+    arity: usize,
+    size_limit: Option<SizeLimit>,
+    duplicate_policy: DuplicatePolicy,
+    removal_policy: RemovalPolicy,
+    compaction: bool,
+    leaf_hash: Option<IMTLeafHashFunction<N>>,
+    node_eq: Option<IMTNodeEqFunction<N>>,
+    root_history: usize,
+    capacity: usize,
+}
+
+impl LeanIMTBuilder<String> {
+    /// Starts a tree of string nodes using `"0"` as the zero value.
+    pub fn new(hash: IMTHashFunction) -> Self {
+        Self::with_zero(hash, "0".to_string())
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMTBuilder<N> {
+    /// Starts a tree using `zero` as the value of empty (removed) leaves,
+    /// kept in a `MemoryStore` unless `store` is called.
+    pub fn with_zero(hash: IMTHashFunction<N>, zero: N) -> Self {
+        Self::with_hash(TreeHash::Fn(hash), zero)
+    }
+
+    /// Starts a tree like `with_zero`, hashing with a function that can
+    /// capture state.
+    pub fn with_shared_hash(hash: SharedHashFunction<N>, zero: N) -> Self {
+        Self::with_hash(TreeHash::Shared(hash), zero)
+    }
+
+    /// Starts a tree like `with_zero`, hashing pairs of nodes by reference.
+    pub fn with_pair_hash(hash: IMTPairHashFunction<N>, zero: N) -> Self {
+        Self::with_hash(TreeHash::Pair(hash), zero)
+    }
+
+    fn with_hash(hash: TreeHash<N>, zero: N) -> Self {
+        LeanIMTBuilder {
+            hash,
+            zero,
+            store: MemoryStore::default(),
+            arity: 2,
+            size_limit: None,
+            duplicate_policy: DuplicatePolicy::Reject,
+            removal_policy: RemovalPolicy::Forget,
+            compaction: false,
+            leaf_hash: None,
+            node_eq: None,
+            root_history: 0,
+            capacity: 0,
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMTBuilder<N, S> {
+    /// Keeps the nodes of the tree in `store`, which must be empty.
+    pub fn store<T: LeanIMTStore<N>>(self, store: T) -> LeanIMTBuilder<N, T> {
+        LeanIMTBuilder {
+            hash: self.hash,
+            zero: self.zero,
+            store,
+            arity: self.arity,
+            size_limit: self.size_limit,
+            duplicate_policy: self.duplicate_policy,
+            removal_policy: self.removal_policy,
+            compaction: self.compaction,
+            leaf_hash: self.leaf_hash,
+            node_eq: self.node_eq,
+            root_history: self.root_history,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Hashes up to `arity` children per node, 2 by default.
+    pub fn arity(mut self, arity: usize) -> Self {
+        self.arity = arity;
+        self
+    }
+
+    /// Limits the tree to `max_size` leaves, replacing any `max_depth`.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.size_limit = Some(SizeLimit::Size(max_size));
+        self
+    }
+
+    /// Limits the tree to the leaves of a tree of depth `max_depth` with the
+    /// configured arity, replacing any `max_size`.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.size_limit = Some(SizeLimit::Depth(max_depth));
+        self
+    }
+
+    pub fn duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+
+    pub fn removal_policy(mut self, policy: RemovalPolicy) -> Self {
+        self.removal_policy = policy;
+        self
+    }
+
+    /// Tracks the slots of removed leaves, for `insert_into_gap` to fill.
+    pub fn compaction(mut self, enabled: bool) -> Self {
+        self.compaction = enabled;
+        self
+    }
+
+    /// Hashes the leaves entering the tree with `leaf_hash`.
+    pub fn leaf_hash(mut self, leaf_hash: IMTLeafHashFunction<N>) -> Self {
+        self.leaf_hash = Some(leaf_hash);
+        self
+    }
+
+    /// Compares roots and proof nodes with `node_eq`, e.g. in constant time.
+    pub fn node_eq(mut self, node_eq: IMTNodeEqFunction<N>) -> Self {
+        self.node_eq = Some(node_eq);
+        self
+    }
+
+    /// Retains up to `capacity` change records, one per changed leaf, so
+    /// past roots and proofs can be served as far back as they reach.
+    pub fn root_history(mut self, capacity: usize) -> Self {
+        self.root_history = capacity;
+        self
+    }
+
+    /// Makes room for `capacity` leaves in the store.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Builds the tree, failing if the arity is invalid or the store is not
+    /// empty.
+    pub fn build(self) -> Result<LeanIMT<N, S>, &'static str> {
+        if self.arity < 2 {
+            return Err("Invalid arity");
+        }
+
+        let mut imt = LeanIMT::create(self.hash, self.zero, self.store)?;
+        imt.arity = self.arity;
+        imt.max_size = match self.size_limit {
+            Some(SizeLimit::Size(max_size)) => Some(max_size),
+            Some(SizeLimit::Depth(max_depth)) => max_size_of(max_depth, self.arity),
+            None => None,
+        };
+        imt.duplicate_policy = self.duplicate_policy;
+        imt.removal_policy = self.removal_policy;
+        imt.set_compaction(self.compaction)?;
+        imt.leaf_hash = self.leaf_hash;
+        imt.node_eq = self.node_eq;
+        imt.set_changelog_capacity(self.root_history);
+        imt.reserve(self.capacity);
+        imt.persist()?;
+        Ok(imt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interned_store::InternedStore;
+    use alloc::format;
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_builder() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMTBuilder::new(hash)
+            .max_depth(2)
+            .arity(3)
+            .duplicate_policy(DuplicatePolicy::Allow)
+            .root_history(16)
+            .store(InternedStore::default())
+            .build()
+            .unwrap();
+        assert_eq!(imt.get_arity(), 3);
+        assert_eq!(imt.get_max_size(), Some(9));
+        assert_eq!(imt.get_duplicate_policy(), DuplicatePolicy::Allow);
+
+        let leaves: Vec<_> = (1..=9).map(|i| format!("leaf{}", i % 5)).collect();
+        imt.insert_many(leaves).unwrap();
+        assert_eq!(imt.insert("leaf".to_string()).unwrap_err(), "Tree is full");
+        assert_eq!(imt.root_at(0), Ok(None));
+
+        assert_eq!(
+            LeanIMTBuilder::new(hash).arity(1).build().unwrap_err(),
+            "Invalid arity"
+        );
+        let imt = LeanIMTBuilder::new(hash).max_size(5).build().unwrap();
+        assert_eq!(imt.get_max_size(), Some(5));
+    }
+}
@@ -0,0 +1,472 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[cfg(feature = "std")]
+use crate::root_stream::{RootSender, RootStream, RootUpdate};
+use crate::IMTNode;
+
+/// A structured record of a single leaf change.
+///
+/// Every leaf touched by a mutation produces one record. All records emitted
+/// by the same mutation (e.g. the leaves of one `insert_many` batch) share the
+/// same `version` and carry the root reached at the end of that mutation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord<N = IMTNode> {
+    pub version: u64,
+    pub index: usize,
+    /// `None` when the leaf was inserted, appended or into the slot of a
+    /// removed leaf, the previous value otherwise.
+    pub old_leaf: Option<N>,
+    /// Whether the leaf was appended, growing the tree.
+    pub appended: bool,
+    pub new_leaf: N,
+    pub new_root: N,
+}
+
+/// Kind of mutation that changed the root of a tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Leaves were appended by `insert`, `insert_many` or `import_subtree`.
+    Insert,
+    Update,
+    Remove,
+    /// Leaves were dropped from the end by `truncate` or `pop`, which
+    /// produces no change record.
+    Truncate,
+    /// The leaves were replaced by `rebuild_from_leaves`, which produces no
+    /// change record.
+    Rebuild,
+}
+
+/// Root transition passed to the hooks registered with `on_root_change`.
+#[derive(Debug)]
+pub struct RootChange<'a, N = IMTNode> {
+    pub version: u64,
+    pub operation: Operation,
+    pub old_root: Option<&'a N>,
+    pub new_root: Option<&'a N>,
+    /// Size of the tree after the mutation.
+    pub size: usize,
+    /// Records of the leaves changed by the mutation.
+    pub changes: &'a [ChangeRecord<N>],
+}
+
+/// Identifies a hook registered with `on_root_change`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+type RootHook<N> = Box<dyn FnMut(&RootChange<'_, N>) + Send + Sync>;
+
+/// Bounded buffer of recent change records plus push subscribers.
+///
+/// Recording is disabled until a capacity is set, so trees that don't need a
+/// changelog pay nothing for it.
+pub struct Changelog<N> {
+    records: VecDeque<ChangeRecord<N>>,
+    capacity: usize,
+    /// Every change with a version `<=` this one may have been dropped.
+    pruned_through: u64,
+    #[cfg(feature = "std")]
+    subscribers: Vec<Sender<ChangeRecord<N>>>,
+    #[cfg(feature = "std")]
+    root_streams: Vec<RootSender<N>>,
+    hooks: Vec<(HookId, RootHook<N>)>,
+    next_hook: u64,
+}
+
+impl<N: fmt::Debug> fmt::Debug for Changelog<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Changelog");
+        debug
+            .field("records", &self.records)
+            .field("capacity", &self.capacity)
+            .field("pruned_through", &self.pruned_through);
+        #[cfg(feature = "std")]
+        debug
+            .field("subscribers", &self.subscribers)
+            .field("root_streams", &self.root_streams.len());
+        debug.field("hooks", &self.hooks.len()).finish()
+    }
+}
+
+impl<N: Clone> Changelog<N> {
+    pub fn new() -> Self {
+        Changelog {
+            records: VecDeque::new(),
+            capacity: 0,
+            pruned_through: 0,
+            #[cfg(feature = "std")]
+            subscribers: Vec::new(),
+            #[cfg(feature = "std")]
+            root_streams: Vec::new(),
+            hooks: Vec::new(),
+            next_hook: 0,
+        }
+    }
+
+    /// Returns a changelog retaining the same records, without the
+    /// subscribers and hooks, which stay with this one.
+    pub fn clone_records(&self) -> Self {
+        Changelog {
+            records: self.records.clone(),
+            capacity: self.capacity,
+            pruned_through: self.pruned_through,
+            ..Self::new()
+        }
+    }
+
+    /// Returns true if records need to be built for this changelog.
+    pub fn is_active(&self) -> bool {
+        if self.has_hooks() {
+            return true;
+        }
+        #[cfg(feature = "std")]
+        if !self.subscribers.is_empty() {
+            return true;
+        }
+        self.capacity > 0
+    }
+
+    /// Sets how many records are retained, dropping the oldest ones if needed.
+    ///
+    /// `current_version` is the tree version at the time of the call; nothing
+    /// older than it can be served if the changelog was not retaining records.
+    pub fn set_capacity(&mut self, capacity: usize, current_version: u64) {
+        if self.capacity == 0 {
+            self.pruned_through = current_version;
+        }
+        self.capacity = capacity;
+        self.prune();
+    }
+
+    pub fn push(&mut self, record: ChangeRecord<N>) {
+        #[cfg(feature = "std")]
+        self.subscribers
+            .retain(|subscriber| subscriber.send(record.clone()).is_ok());
+
+        if self.capacity == 0 {
+            self.pruned_through = record.version;
+            return;
+        }
+        self.records.push_back(record);
+        self.prune();
+    }
+
+    /// Removes and returns the retained records.
+    pub fn take_records(&mut self) -> Vec<ChangeRecord<N>> {
+        self.records.drain(..).collect()
+    }
+
+    /// Returns the retained records with a version greater than `version`.
+    pub fn since(&self, version: u64) -> Result<impl Iterator<Item = &ChangeRecord<N>>, &'static str> {
+        if version < self.pruned_through {
+            return Err("Changelog has been pruned");
+        }
+        Ok(self
+            .records
+            .iter()
+            .filter(move |record| record.version > version))
+    }
+
+    /// Returns the root reached by the mutation that produced `version`, if
+    /// one of its records is retained.
+    pub fn root_at(&self, version: u64) -> Option<&N> {
+        self.records
+            .iter()
+            .find(|record| record.version == version)
+            .map(|record| &record.new_root)
+    }
+
+    /// Drops the records with a version up to `version`.
+    pub fn prune_through(&mut self, version: u64) {
+        while self
+            .records
+            .front()
+            .is_some_and(|record| record.version <= version)
+        {
+            self.records.pop_front();
+        }
+        self.pruned_through = self.pruned_through.max(version);
+    }
+
+    /// Drops the records with a version greater than `version`, once the
+    /// tree has been reset to it.
+    #[cfg(feature = "concurrent")]
+    pub fn discard_after(&mut self, version: u64) {
+        while self
+            .records
+            .back()
+            .is_some_and(|record| record.version > version)
+        {
+            self.records.pop_back();
+        }
+        self.pruned_through = self.pruned_through.min(version);
+    }
+
+    /// Registers a new push subscriber.
+    ///
+    /// Subscribers whose receiver has been dropped are removed on the next change.
+    #[cfg(feature = "std")]
+    pub fn subscribe(&mut self) -> Receiver<ChangeRecord<N>> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Returns a stream receiving the root reached by every mutation from
+    /// now on.
+    #[cfg(feature = "std")]
+    pub fn subscribe_roots(&mut self) -> RootStream<N> {
+        let (sender, stream) = RootStream::new();
+        self.root_streams.push(sender);
+        stream
+    }
+
+    /// Returns true if root change hooks or root streams are registered.
+    pub fn has_hooks(&self) -> bool {
+        #[cfg(feature = "std")]
+        if !self.root_streams.is_empty() {
+            return true;
+        }
+        !self.hooks.is_empty()
+    }
+
+    /// Registers a hook called after every mutation that commits.
+    pub fn add_hook(
+        &mut self,
+        hook: impl FnMut(&RootChange<'_, N>) + Send + Sync + 'static,
+    ) -> HookId {
+        let id = HookId(self.next_hook);
+        self.next_hook += 1;
+        self.hooks.push((id, Box::new(hook)));
+        id
+    }
+
+    /// Unregisters a hook, returning false if it was not registered.
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|(hook_id, _)| *hook_id != id);
+        self.hooks.len() != len
+    }
+
+    /// Calls the hooks with the records of a committed mutation.
+    pub fn notify(
+        &mut self,
+        old_root: Option<&N>,
+        new_root: Option<&N>,
+        size: usize,
+        changes: &[ChangeRecord<N>],
+        zero: &N,
+    ) where
+        N: PartialEq,
+    {
+        let Some(record) = changes.first() else {
+            return;
+        };
+        let operation = if record.old_leaf.as_ref().is_none_or(|leaf| leaf == zero) {
+            Operation::Insert
+        } else if record.new_leaf == *zero {
+            Operation::Remove
+        } else {
+            Operation::Update
+        };
+        self.notify_operation(RootChange {
+            version: record.version,
+            operation,
+            old_root,
+            new_root,
+            size,
+            changes,
+        });
+    }
+
+    /// Calls the hooks with a root change, for mutations whose operation
+    /// cannot be told from their records.
+    pub fn notify_operation(&mut self, change: RootChange<'_, N>) {
+        #[cfg(feature = "std")]
+        self.root_streams.retain(|stream| {
+            stream.send(RootUpdate {
+                version: change.version,
+                size: change.size,
+                root: change.new_root.cloned(),
+            })
+        });
+
+        for (_, hook) in &mut self.hooks {
+            hook(&change);
+        }
+    }
+
+    fn prune(&mut self) {
+        while self.records.len() > self.capacity {
+            if let Some(record) = self.records.pop_front() {
+                self.pruned_through = record.version;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_changelog_capacity(16);
+
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        let sibling_nodes = vec!["leaf1".to_string()];
+        imt.update(&"leaf2".to_string(), "leaf3".to_string(), &sibling_nodes)
+            .unwrap();
+        let sibling_nodes = vec!["leaf3".to_string()];
+        imt.remove(&"leaf1".to_string(), &sibling_nodes).unwrap();
+        assert_eq!(imt.get_version(), 3);
+
+        let batch: Vec<_> = imt.changes_since(0).unwrap().take(2).cloned().collect();
+        assert_eq!(batch[0].version, 1);
+        assert_eq!(batch[0].index, 0);
+        assert_eq!(batch[1].version, 1);
+        assert_eq!(batch[1].index, 1);
+        assert_eq!(batch[1].new_root, "leaf1,leaf2".to_string());
+
+        let changes: Vec<_> = imt.changes_since(1).unwrap().cloned().collect();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].version, 2);
+        assert_eq!(changes[0].index, 1);
+        assert_eq!(changes[0].old_leaf, Some("leaf2".to_string()));
+        assert_eq!(changes[0].new_leaf, "leaf3".to_string());
+        assert_eq!(changes[0].new_root, "leaf1,leaf3".to_string());
+        assert_eq!(changes[1].version, 3);
+        assert_eq!(changes[1].index, 0);
+        assert_eq!(changes[1].new_leaf, "0".to_string());
+        assert_eq!(changes[1].new_root, "0,leaf3".to_string());
+
+        assert_eq!(imt.changes_since(3).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_changes_since_pruned_version() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        // Nothing is retained before the changelog is enabled
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.set_changelog_capacity(2);
+        assert!(imt.changes_since(0).is_err());
+
+        imt.insert("leaf2".to_string()).unwrap();
+        imt.insert("leaf3".to_string()).unwrap();
+        imt.insert("leaf4".to_string()).unwrap();
+
+        let result = imt.changes_since(1);
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), "Changelog has been pruned");
+        assert_eq!(imt.changes_since(2).unwrap().count(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_subscribe_changes() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        let receiver = imt.subscribe_changes();
+
+        imt.insert("leaf1".to_string()).unwrap();
+        let sibling_nodes = vec![];
+        imt.remove(&"leaf1".to_string(), &sibling_nodes).unwrap();
+
+        let insertion = receiver.try_recv().unwrap();
+        assert_eq!(insertion.new_leaf, "leaf1".to_string());
+        let removal = receiver.try_recv().unwrap();
+        assert_eq!(removal.old_leaf, Some("leaf1".to_string()));
+        assert_eq!(removal.new_leaf, "0".to_string());
+        assert_eq!(removal.new_root, "0".to_string());
+        assert!(receiver.try_recv().is_err());
+
+        // Subscribers do not enable the pull-based changelog
+        assert!(imt.changes_since(0).is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_root_change_hooks() {
+        use crate::Operation;
+        use std::sync::{Arc, Mutex};
+
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let id = imt.on_root_change(move |change| {
+            log.lock().unwrap().push((
+                change.operation,
+                change.old_root.cloned(),
+                change.new_root.cloned(),
+                change.changes.len(),
+            ));
+        });
+
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.insert_many(vec!["leaf2".to_string(), "leaf3".to_string()])
+            .unwrap();
+        let siblings = imt.generate_proof(2).unwrap().siblings;
+        imt.update(&"leaf3".to_string(), "new3".to_string(), &siblings)
+            .unwrap();
+        let siblings = imt.generate_proof(0).unwrap().siblings;
+        imt.remove(&"leaf1".to_string(), &siblings).unwrap();
+        // Failed mutations do not call the hooks
+        assert!(imt.insert("leaf2".to_string()).is_err());
+        imt.pop().unwrap();
+
+        let root = |s: &str| Some(s.to_string());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (Operation::Insert, None, root("leaf1"), 1),
+                (
+                    Operation::Insert,
+                    root("leaf1"),
+                    root("leaf1,leaf2,leaf3"),
+                    2
+                ),
+                (
+                    Operation::Update,
+                    root("leaf1,leaf2,leaf3"),
+                    root("leaf1,leaf2,new3"),
+                    1
+                ),
+                (
+                    Operation::Remove,
+                    root("leaf1,leaf2,new3"),
+                    root("0,leaf2,new3"),
+                    1
+                ),
+                (
+                    Operation::Truncate,
+                    root("0,leaf2,new3"),
+                    root("0,leaf2"),
+                    0
+                ),
+            ]
+        );
+
+        assert!(imt.remove_root_change_hook(id));
+        assert!(!imt.remove_root_change_hook(id));
+        imt.insert("leaf4".to_string()).unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,181 @@
+//! Witness inputs of circom Merkle inclusion templates.
+//!
+//! Circom templates are compiled for a maximum depth and take a proof as
+//! arrays of field elements of that length: the siblings padded with zeros,
+//! and the bits of the path as elements equal to 0 or 1. [`CircomProof`]
+//! lays a proof out this way, so the padding and the bit decomposition are
+//! not rewritten, off by one level, for every circuit.
+//!
+//! Lean proofs skip the levels where the path node has no sibling, and the
+//! bits of their `index` are the directions at the levels that are hashed.
+//! zk-kit's `BinaryMerkleRoot`, used by Semaphore v4, takes exactly that: the
+//! leaf, the number of hashed levels as `depth`, the path as `index`, and the
+//! padded siblings. Templates hashing every level, such as the circomlib-style
+//! `MerkleTreeInclusionProof` taking `pathIndices` and `siblings`, need the
+//! proofs of a `fixed_depth::FixedDepthLeanIMT`, which have a sibling on
+//! every level.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use ark_ff::PrimeField;
+
+use crate::{LeafIndex, LeanIMT, LeanIMTStore, MerkleProof};
+
+/// Proof laid out as the inputs of a circom template compiled for
+/// `siblings.len()` levels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircomProof<F> {
+    pub leaf: F,
+    pub root: F,
+    /// Number of levels hashed by the proof.
+    pub depth: usize,
+    /// Path of the proof, whose bit `i` is the direction at level `i`.
+    pub index: F,
+    /// Bits of the path, one element per level.
+    pub path_indices: Vec<F>,
+    /// Siblings of the proof, padded with zeros to the maximum depth.
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> CircomProof<F> {
+    /// Lays out `proof` for a circuit compiled for `max_depth` levels.
+    ///
+    /// Fails with "Tree is deeper than target depth" if the proof has more
+    /// siblings.
+    pub fn new(proof: &MerkleProof<F>, max_depth: usize) -> Result<Self, &'static str> {
+        let depth = proof.siblings.len();
+        if depth > max_depth {
+            return Err("Tree is deeper than target depth");
+        }
+
+        let path = proof.path();
+        let mut siblings = proof.siblings.clone();
+        siblings.resize(max_depth, F::ZERO);
+        Ok(CircomProof {
+            leaf: proof.leaf,
+            root: proof.root,
+            depth,
+            index: F::from(path.get()),
+            path_indices: (0..max_depth)
+                .map(|level| F::from(u64::from(path.bit(level))))
+                .collect(),
+            siblings,
+        })
+    }
+
+    /// Returns the proof without padding.
+    ///
+    /// Fails with "Invalid path" if the path does not fit a `u64`.
+    pub fn to_merkle_proof(&self) -> Result<MerkleProof<F>, &'static str> {
+        let path = self.index.into_bigint();
+        let limbs = path.as_ref();
+        if limbs.iter().skip(1).any(|&limb| limb != 0) {
+            return Err("Invalid path");
+        }
+        Ok(MerkleProof {
+            root: self.root,
+            leaf: self.leaf,
+            index: LeafIndex(limbs[0]).to_usize()?,
+            siblings: self.siblings[..self.depth.min(self.siblings.len())].to_vec(),
+        })
+    }
+
+    /// Writes the `leaf`, `pathIndices` and `siblings` inputs of
+    /// circomlib-style templates as an `input.json` object, with elements as
+    /// decimal strings.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"leaf\":\"{}\",\"pathIndices\":{},\"siblings\":{}}}",
+            self.leaf,
+            json_array(&self.path_indices),
+            json_array(&self.siblings)
+        )
+    }
+
+    /// Writes the `leaf`, `depth`, `index` and `siblings` inputs of zk-kit's
+    /// `BinaryMerkleRoot` template as an `input.json` object.
+    pub fn to_binary_merkle_root_json(&self) -> String {
+        format!(
+            "{{\"leaf\":\"{}\",\"depth\":\"{}\",\"index\":\"{}\",\"siblings\":{}}}",
+            self.leaf,
+            self.depth,
+            self.index,
+            json_array(&self.siblings)
+        )
+    }
+}
+
+/// Writes field elements as a JSON array of decimal strings.
+fn json_array<F: PrimeField>(elements: &[F]) -> String {
+    let elements: Vec<String> = elements
+        .iter()
+        .map(|element| format!("\"{}\"", element))
+        .collect();
+    format!("[{}]", elements.join(","))
+}
+
+impl<F: PrimeField + Hash, S: LeanIMTStore<F>> LeanIMT<F, S> {
+    /// Generates the proof of the leaf at `index`, laid out for a circom
+    /// circuit compiled for `max_depth` levels.
+    pub fn generate_circom_proof(
+        &self,
+        index: usize,
+        max_depth: usize,
+    ) -> Result<CircomProof<F>, &'static str> {
+        CircomProof::new(&self.generate_proof(index)?, max_depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::vec;
+    use ark_bn254::Fr;
+
+    fn simple_hash_function(nodes: Vec<Fr>) -> Fr {
+        nodes
+            .into_iter()
+            .fold(Fr::from(7u64), |node, child| node * Fr::from(3u64) + child)
+    }
+
+    #[test]
+    fn test_circom_proof() {
+        let hash: IMTHashFunction<Fr> = simple_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, Fr::from(0u64));
+        imt.insert_many((1..=3).map(Fr::from).collect::<Vec<Fr>>())
+            .unwrap();
+
+        // The last leaf has no sibling on the first level
+        let proof = imt.generate_circom_proof(2, 4).unwrap();
+        let left = hash(vec![Fr::from(1u64), Fr::from(2u64)]);
+        assert_eq!(proof.depth, 1);
+        assert_eq!(proof.index, Fr::from(1u64));
+        assert_eq!(proof.path_indices, [1u64, 0, 0, 0].map(Fr::from).to_vec());
+        assert_eq!(
+            proof.siblings,
+            vec![left, Fr::from(0u64), Fr::from(0u64), Fr::from(0u64)]
+        );
+        assert_eq!(
+            proof.to_binary_merkle_root_json(),
+            format!(
+                "{{\"leaf\":\"3\",\"depth\":\"1\",\"index\":\"1\",\"siblings\":[\"{}\",\"0\",\"0\",\"0\"]}}",
+                left
+            )
+        );
+        assert!(proof
+            .to_json()
+            .contains("\"pathIndices\":[\"1\",\"0\",\"0\",\"0\"]"));
+        let unpadded = proof.to_merkle_proof().unwrap();
+        assert_eq!(unpadded, imt.generate_proof(2).unwrap());
+        assert!(imt.verify_proof(&unpadded));
+
+        assert_eq!(
+            imt.generate_circom_proof(0, 1).unwrap_err(),
+            "Tree is deeper than target depth"
+        );
+    }
+}
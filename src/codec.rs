@@ -0,0 +1,907 @@
+//! Conversion of nodes between their in-memory type and external encodings.
+//!
+//! External layers (serialization, bindings) exchange nodes through a
+//! [`NodeCodec`] instead of relying on the in-memory node type, so changing
+//! the node representation only requires swapping the codec.
+
+use alloc::string::String;
+#[cfg(any(feature = "ark-ff", feature = "num-bigint"))]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+#[cfg(feature = "num-bigint")]
+use core::marker::PhantomData;
+
+#[cfg(feature = "num-bigint")]
+use num_bigint::BigUint;
+
+#[cfg(feature = "postcard")]
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{LeanIMTSnapshot, MerkleProof};
+
+/// Encodes nodes of type `N` as bytes and as text.
+pub trait NodeCodec<N> {
+    fn encode(node: &N) -> Vec<u8>;
+
+    fn decode(bytes: &[u8]) -> Result<N, &'static str>;
+
+    /// Encodes a node as text, as `0x`-prefixed hex of its bytes by default.
+    fn to_text(node: &N) -> String {
+        encode_hex(&Self::encode(node))
+    }
+
+    /// Decodes a node from the text produced by `to_text`.
+    fn from_text(text: &str) -> Result<N, &'static str> {
+        Self::decode(&decode_hex(text)?)
+    }
+}
+
+/// Codec for string nodes: UTF-8 bytes, and the string itself as text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StringCodec;
+
+impl NodeCodec<String> for StringCodec {
+    fn encode(node: &String) -> Vec<u8> {
+        node.as_bytes().to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<String, &'static str> {
+        String::from_utf8(bytes.to_vec()).map_err(|_| "Invalid UTF-8 node")
+    }
+
+    fn to_text(node: &String) -> String {
+        node.clone()
+    }
+
+    fn from_text(text: &str) -> Result<String, &'static str> {
+        Ok(text.into())
+    }
+}
+
+/// Codec for raw byte nodes, written as hex text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BytesCodec;
+
+impl NodeCodec<Vec<u8>> for BytesCodec {
+    fn encode(node: &Vec<u8>) -> Vec<u8> {
+        node.clone()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl<const L: usize> NodeCodec<[u8; L]> for BytesCodec {
+    fn encode(node: &[u8; L]) -> Vec<u8> {
+        node.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<[u8; L], &'static str> {
+        bytes.try_into().map_err(|_| "Invalid node length")
+    }
+}
+
+/// Fixed-size byte nodes, such as the outputs of a `Digest`, use the same
+/// encoding as byte vectors.
+#[cfg(feature = "digest")]
+impl<L: digest::array::ArraySize> NodeCodec<digest::array::Array<u8, L>> for BytesCodec {
+    fn encode(node: &digest::array::Array<u8, L>) -> Vec<u8> {
+        node.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<digest::array::Array<u8, L>, &'static str> {
+        digest::array::Array::try_from(bytes).map_err(|_| "Invalid node length")
+    }
+}
+
+/// Codec for arkworks prime field elements.
+///
+/// Nodes are encoded in the canonical little-endian form of `ark-serialize`,
+/// the layout expected by arkworks witness generation, and written as decimal
+/// text, as in the input files of circom witness generators. Decoding rejects
+/// values that are not reduced modulo the field order.
+#[cfg(feature = "ark-ff")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FieldCodec;
+
+#[cfg(feature = "ark-ff")]
+impl<F: ark_ff::PrimeField> NodeCodec<F> for FieldCodec {
+    fn encode(node: &F) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(node.compressed_size());
+        node.serialize_compressed(&mut bytes)
+            .expect("Writing to a vector cannot fail");
+        bytes
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<F, &'static str> {
+        let node = F::deserialize_compressed(&mut bytes).map_err(|_| "Invalid field element")?;
+        if !bytes.is_empty() {
+            return Err("Invalid field element");
+        }
+        Ok(node)
+    }
+
+    fn to_text(node: &F) -> String {
+        node.to_string()
+    }
+
+    fn from_text(text: &str) -> Result<F, &'static str> {
+        // Parsing reduces the value, so only the canonical text round-trips
+        text.parse::<F>()
+            .ok()
+            .filter(|node| node.to_string() == text)
+            .ok_or("Invalid field element")
+    }
+}
+
+/// Codec for `alloy_primitives::U256` nodes: 32 big-endian bytes, written as
+/// `0x`-prefixed hex without leading zeros.
+#[cfg(feature = "alloy-primitives")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U256Codec;
+
+#[cfg(feature = "alloy-primitives")]
+impl NodeCodec<alloy_primitives::U256> for U256Codec {
+    fn encode(node: &alloy_primitives::U256) -> Vec<u8> {
+        node.to_be_bytes_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<alloy_primitives::U256, &'static str> {
+        if bytes.len() != 32 {
+            return Err("Invalid node length");
+        }
+        Ok(alloy_primitives::U256::from_be_slice(bytes))
+    }
+
+    fn to_text(node: &alloy_primitives::U256) -> String {
+        alloc::format!("{:#x}", node)
+    }
+
+    fn from_text(text: &str) -> Result<alloy_primitives::U256, &'static str> {
+        let digits = text.strip_prefix("0x").ok_or("Missing 0x prefix")?;
+        alloy_primitives::U256::from_str_radix(digits, 16).map_err(|_| "Invalid hex node")
+    }
+}
+
+/// Codec for `alloy_primitives::B256` nodes: their 32 bytes, written as
+/// `0x`-prefixed hex.
+#[cfg(feature = "alloy-primitives")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct B256Codec;
+
+#[cfg(feature = "alloy-primitives")]
+impl NodeCodec<alloy_primitives::B256> for B256Codec {
+    fn encode(node: &alloy_primitives::B256) -> Vec<u8> {
+        node.to_vec()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<alloy_primitives::B256, &'static str> {
+        alloy_primitives::B256::try_from(bytes).map_err(|_| "Invalid node length")
+    }
+
+    fn from_text(text: &str) -> Result<alloy_primitives::B256, &'static str> {
+        if !text.starts_with("0x") {
+            return Err("Missing 0x prefix");
+        }
+        Self::decode(&decode_hex(text)?)
+    }
+}
+
+/// Codec for `num_bigint::BigUint` nodes: big-endian bytes without leading
+/// zeros, written as decimal text, as the field elements of snarkjs inputs.
+/// Only these canonical encodings decode.
+#[cfg(feature = "num-bigint")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BigUintCodec;
+
+#[cfg(feature = "num-bigint")]
+impl NodeCodec<BigUint> for BigUintCodec {
+    fn encode(node: &BigUint) -> Vec<u8> {
+        node.to_bytes_be()
+    }
+
+    fn decode(bytes: &[u8]) -> Result<BigUint, &'static str> {
+        let node = BigUint::from_bytes_be(bytes);
+        if node.to_bytes_be() != bytes {
+            return Err("Invalid big integer");
+        }
+        Ok(node)
+    }
+
+    fn to_text(node: &BigUint) -> String {
+        node.to_string()
+    }
+
+    fn from_text(text: &str) -> Result<BigUint, &'static str> {
+        BigUint::parse_bytes(text.as_bytes(), 10)
+            .filter(|node| node.to_string() == text)
+            .ok_or("Invalid big integer")
+    }
+}
+
+/// Codec for `num_bigint::BigUint` nodes encoded as by [`BigUintCodec`], but
+/// written as `0x`-prefixed hex without leading zeros.
+#[cfg(feature = "num-bigint")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BigUintHexCodec;
+
+#[cfg(feature = "num-bigint")]
+impl NodeCodec<BigUint> for BigUintHexCodec {
+    fn encode(node: &BigUint) -> Vec<u8> {
+        BigUintCodec::encode(node)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<BigUint, &'static str> {
+        BigUintCodec::decode(bytes)
+    }
+
+    fn to_text(node: &BigUint) -> String {
+        alloc::format!("{:#x}", node)
+    }
+
+    fn from_text(text: &str) -> Result<BigUint, &'static str> {
+        let digits = text.strip_prefix("0x").ok_or("Missing 0x prefix")?;
+        BigUint::parse_bytes(digits.as_bytes(), 16)
+            .filter(|node| node.to_str_radix(16) == digits)
+            .ok_or("Invalid hex node")
+    }
+}
+
+/// Codec for `num_bigint::BigUint` nodes reduced modulo `M`, encoded and
+/// written as by [`BigUintCodec`]. Decoding rejects values that are not
+/// reduced.
+#[cfg(feature = "num-bigint")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReducedCodec<M>(PhantomData<M>);
+
+#[cfg(feature = "num-bigint")]
+impl<M: crate::bigint::Modulus> NodeCodec<BigUint> for ReducedCodec<M> {
+    fn encode(node: &BigUint) -> Vec<u8> {
+        BigUintCodec::encode(node)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<BigUint, &'static str> {
+        Self::reduced(BigUintCodec::decode(bytes)?)
+    }
+
+    fn to_text(node: &BigUint) -> String {
+        BigUintCodec::to_text(node)
+    }
+
+    fn from_text(text: &str) -> Result<BigUint, &'static str> {
+        Self::reduced(BigUintCodec::from_text(text)?)
+    }
+}
+
+#[cfg(feature = "num-bigint")]
+impl<M: crate::bigint::Modulus> ReducedCodec<M> {
+    fn reduced(node: BigUint) -> Result<BigUint, &'static str> {
+        if !crate::bigint::is_reduced::<M>(&node) {
+            return Err("Invalid field element");
+        }
+        Ok(node)
+    }
+}
+
+/// Encodes bytes as lowercase `0x`-prefixed hex.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let mut text = String::with_capacity(2 + bytes.len() * 2);
+    text.push_str("0x");
+    for byte in bytes {
+        text.push(DIGITS[(byte >> 4) as usize] as char);
+        text.push(DIGITS[(byte & 0xf) as usize] as char);
+    }
+    text
+}
+
+/// Decodes hex text, with or without a `0x` prefix.
+pub fn decode_hex(text: &str) -> Result<Vec<u8>, &'static str> {
+    let digits = text.strip_prefix("0x").unwrap_or(text).as_bytes();
+    if !digits.len().is_multiple_of(2) {
+        return Err("Invalid hex length");
+    }
+
+    fn value(digit: u8) -> Result<u8, &'static str> {
+        match digit {
+            b'0'..=b'9' => Ok(digit - b'0'),
+            b'a'..=b'f' => Ok(digit - b'a' + 10),
+            b'A'..=b'F' => Ok(digit - b'A' + 10),
+            _ => Err("Invalid hex digit"),
+        }
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| Ok((value(pair[0])? << 4) | value(pair[1])?))
+        .collect()
+}
+
+const BASE64_DIGITS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as standard base64, padded with `=`.
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_DIGITS[(group >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Decodes standard base64 written by `encode_base64`.
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, &'static str> {
+    let digits = text.as_bytes();
+    if !digits.len().is_multiple_of(4) {
+        return Err("Invalid base64 length");
+    }
+    let padding = digits
+        .iter()
+        .rev()
+        .take_while(|&&digit| digit == b'=')
+        .count();
+    if padding > 2 {
+        return Err("Invalid base64 digit");
+    }
+
+    let mut bytes = Vec::with_capacity(digits.len() / 4 * 3);
+    let last = digits.len() / 4;
+    for (index, chunk) in digits.chunks(4).enumerate() {
+        let mut group = 0u32;
+        let length = if index + 1 == last { 4 - padding } else { 4 };
+        for (i, &digit) in chunk[..length].iter().enumerate() {
+            let value = BASE64_DIGITS
+                .iter()
+                .position(|&other| other == digit)
+                .ok_or("Invalid base64 digit")?;
+            group |= (value as u32) << (18 - 6 * i);
+        }
+        bytes.extend_from_slice(&group.to_be_bytes()[1..length]);
+    }
+    Ok(bytes)
+}
+
+/// Appends `value` as an unsigned LEB128 varint.
+pub(crate) fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Appends a maximum tree size as a varint of the size plus one, zero for
+/// unlimited trees.
+///
+/// No tree holds more than `usize::MAX` leaves, so that limit is written as
+/// unlimited: it has no successor on 64-bit targets.
+pub(crate) fn write_max_size(bytes: &mut Vec<u8>, max_size: Option<usize>) {
+    let max_size = max_size.filter(|&max_size| max_size != usize::MAX);
+    write_varint(bytes, max_size.map_or(0, |max_size| max_size as u64 + 1));
+}
+
+/// Appends a varint length followed by `data`.
+pub(crate) fn write_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_varint(bytes, data.len() as u64);
+    bytes.extend_from_slice(data);
+}
+
+/// Cursor over bytes written with `write_varint` and `write_bytes`.
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub(crate) fn read_byte(&mut self) -> Result<u8, &'static str> {
+        let (&byte, rest) = self.bytes.split_first().ok_or("Unexpected end of input")?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    pub(crate) fn read_varint(&mut self) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err("Invalid varint");
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid varint")
+    }
+
+    pub(crate) fn read_usize(&mut self) -> Result<usize, &'static str> {
+        usize::try_from(self.read_varint()?).map_err(|_| "Value out of range")
+    }
+
+    pub(crate) fn read_bytes(&mut self) -> Result<&'a [u8], &'static str> {
+        let length = self.read_usize()?;
+        if length > self.bytes.len() {
+            return Err("Unexpected end of input");
+        }
+        let (data, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(data)
+    }
+}
+
+impl<N> MerkleProof<N> {
+    /// Converts the nodes of the proof to their text encoding.
+    pub fn encode<C: NodeCodec<N>>(&self) -> MerkleProof<String> {
+        MerkleProof {
+            root: C::to_text(&self.root),
+            leaf: C::to_text(&self.leaf),
+            index: self.index,
+            siblings: self.siblings.iter().map(C::to_text).collect(),
+        }
+    }
+
+    /// Decodes a proof whose nodes are text encoded.
+    pub fn decode<C: NodeCodec<N>>(proof: &MerkleProof<String>) -> Result<Self, &'static str> {
+        Ok(MerkleProof {
+            root: C::from_text(&proof.root)?,
+            leaf: C::from_text(&proof.leaf)?,
+            index: proof.index,
+            siblings: proof
+                .siblings
+                .iter()
+                .map(|sibling| C::from_text(sibling))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Encodes the proof as bytes: the root, the leaf, the index and the
+    /// siblings prefixed by their count. Integers are LEB128 varints and
+    /// nodes are length-prefixed bytes from `C`.
+    pub fn to_bytes<C: NodeCodec<N>>(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_bytes(&mut bytes, &C::encode(&self.root));
+        write_bytes(&mut bytes, &C::encode(&self.leaf));
+        write_varint(&mut bytes, self.index as u64);
+        write_varint(&mut bytes, self.siblings.len() as u64);
+        for sibling in &self.siblings {
+            write_bytes(&mut bytes, &C::encode(sibling));
+        }
+        bytes
+    }
+
+    /// Decodes a proof written by `to_bytes`.
+    pub fn from_bytes<C: NodeCodec<N>>(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut reader = ByteReader::new(bytes);
+        let root = C::decode(reader.read_bytes()?)?;
+        let leaf = C::decode(reader.read_bytes()?)?;
+        let index = reader.read_usize()?;
+        let count = reader.read_usize()?;
+        let mut siblings = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            siblings.push(C::decode(reader.read_bytes()?)?);
+        }
+        if !reader.is_empty() {
+            return Err("Trailing bytes");
+        }
+
+        Ok(MerkleProof {
+            root,
+            leaf,
+            index,
+            siblings,
+        })
+    }
+
+    /// Encodes the proof as `0x`-prefixed hex of its `to_bytes` encoding.
+    pub fn to_hex<C: NodeCodec<N>>(&self) -> String {
+        encode_hex(&self.to_bytes::<C>())
+    }
+
+    /// Decodes a proof written by `to_hex`.
+    pub fn from_hex<C: NodeCodec<N>>(text: &str) -> Result<Self, &'static str> {
+        Self::from_bytes::<C>(&decode_hex(text)?)
+    }
+
+    /// Encodes the proof as JSON in the shape of the zk-kit proofs, with
+    /// the `root`, `leaf`, `index` and `siblings` fields and nodes as text
+    /// from `C`.
+    #[cfg(feature = "json")]
+    pub fn to_json<C: NodeCodec<N>>(&self) -> String {
+        serde_json::to_string(&self.encode::<C>()).expect("Proof is serializable")
+    }
+
+    /// Decodes a proof written by `to_json`, or by zk-kit with nodes as text.
+    #[cfg(feature = "json")]
+    pub fn from_json<C: NodeCodec<N>>(json: &str) -> Result<Self, &'static str> {
+        let proof = serde_json::from_str(json).map_err(|_| "Invalid proof JSON")?;
+        Self::decode::<C>(&proof)
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<N: Serialize + DeserializeOwned> MerkleProof<N> {
+    /// Encodes the proof with [postcard](https://postcard.jamesmunns.com), a
+    /// compact format that is not self-describing: fields are written in
+    /// declaration order, and nodes by their own `Serialize` implementation.
+    ///
+    /// The layout follows the fields of the type, so it may change between
+    /// versions of this crate; `to_bytes` is the format for long-term storage.
+    pub fn to_postcard(&self) -> Vec<u8> {
+        postcard::to_allocvec(self).expect("Proof is serializable")
+    }
+
+    /// Decodes a proof written by `to_postcard`.
+    pub fn from_postcard(bytes: &[u8]) -> Result<Self, &'static str> {
+        from_postcard(bytes, "Invalid postcard proof")
+    }
+}
+
+#[cfg(feature = "postcard")]
+impl<N: Serialize + DeserializeOwned> LeanIMTSnapshot<N> {
+    /// Encodes the snapshot with postcard, like `MerkleProof::to_postcard`.
+    pub fn to_postcard(&self) -> Vec<u8> {
+        postcard::to_allocvec(self).expect("Snapshot is serializable")
+    }
+
+    /// Decodes a snapshot written by `to_postcard`.
+    pub fn from_postcard(bytes: &[u8]) -> Result<Self, &'static str> {
+        from_postcard(bytes, "Invalid postcard snapshot")
+    }
+}
+
+#[cfg(feature = "postcard")]
+fn from_postcard<T: DeserializeOwned>(
+    bytes: &[u8],
+    error: &'static str,
+) -> Result<T, &'static str> {
+    match postcard::take_from_bytes(bytes) {
+        Ok((value, [])) => Ok(value),
+        Ok(_) => Err("Trailing bytes"),
+        Err(_) => Err(error),
+    }
+}
+
+impl<N> LeanIMTSnapshot<N> {
+    /// Converts the nodes of the snapshot to their text encoding.
+    pub fn encode<C: NodeCodec<N>>(&self) -> LeanIMTSnapshot<String> {
+        LeanIMTSnapshot {
+            zero: C::to_text(&self.zero),
+            duplicate_policy: self.duplicate_policy,
+            arity: self.arity,
+            max_size: self.max_size,
+            size: self.size,
+            depth: self.depth,
+            side_nodes: self
+                .side_nodes
+                .iter()
+                .map(|(level, node)| (*level, C::to_text(node)))
+                .collect(),
+            leaves: self
+                .leaves
+                .iter()
+                .map(|(leaf, index)| (C::to_text(leaf), *index))
+                .collect(),
+            payloads: self.payloads.clone(),
+            removal_policy: self.removal_policy,
+            tombstones: self
+                .tombstones
+                .iter()
+                .map(|(leaf, indices)| (C::to_text(leaf), indices.clone()))
+                .collect(),
+        }
+    }
+
+    /// Decodes a snapshot whose nodes are text encoded.
+    pub fn decode<C: NodeCodec<N>>(
+        snapshot: &LeanIMTSnapshot<String>,
+    ) -> Result<Self, &'static str> {
+        Ok(LeanIMTSnapshot {
+            zero: C::from_text(&snapshot.zero)?,
+            duplicate_policy: snapshot.duplicate_policy,
+            arity: snapshot.arity,
+            max_size: snapshot.max_size,
+            size: snapshot.size,
+            depth: snapshot.depth,
+            side_nodes: snapshot
+                .side_nodes
+                .iter()
+                .map(|(level, node)| Ok((*level, C::from_text(node)?)))
+                .collect::<Result<_, &'static str>>()?,
+            leaves: snapshot
+                .leaves
+                .iter()
+                .map(|(leaf, index)| Ok((C::from_text(leaf)?, *index)))
+                .collect::<Result<_, &'static str>>()?,
+            payloads: snapshot.payloads.clone(),
+            removal_policy: snapshot.removal_policy,
+            tombstones: snapshot
+                .tombstones
+                .iter()
+                .map(|(leaf, indices)| Ok((C::from_text(leaf)?, indices.clone())))
+                .collect::<Result<_, &'static str>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn bytes_hash_function(nodes: Vec<Vec<u8>>) -> Vec<u8> {
+        nodes.concat()
+    }
+
+    #[test]
+    fn test_hex_roundtrip() {
+        assert_eq!(encode_hex(&[0x00, 0xab, 0x1f]), "0x00ab1f");
+        assert_eq!(decode_hex("0x00ab1f").unwrap(), vec![0x00, 0xab, 0x1f]);
+        assert_eq!(decode_hex("00AB1F").unwrap(), vec![0x00, 0xab, 0x1f]);
+        assert_eq!(decode_hex("0x0").unwrap_err(), "Invalid hex length");
+        assert_eq!(decode_hex("0xzz").unwrap_err(), "Invalid hex digit");
+    }
+
+    #[test]
+    fn test_base64_roundtrip() {
+        for (bytes, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (&[0xfb, 0xff], "+/8="),
+        ] {
+            assert_eq!(encode_base64(bytes), text);
+            assert_eq!(decode_base64(text).unwrap(), bytes);
+        }
+        assert_eq!(decode_base64("Zg=").unwrap_err(), "Invalid base64 length");
+        assert_eq!(decode_base64("Z===").unwrap_err(), "Invalid base64 digit");
+        assert_eq!(decode_base64("Zg=a").unwrap_err(), "Invalid base64 digit");
+    }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        let mut bytes = Vec::new();
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            write_varint(&mut bytes, value);
+        }
+        write_bytes(&mut bytes, b"leaf1");
+        assert_eq!(&bytes[..4], &[0, 1, 127, 0x80]);
+
+        let mut reader = ByteReader::new(&bytes);
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            assert_eq!(reader.read_varint().unwrap(), value);
+        }
+        assert_eq!(reader.read_bytes().unwrap(), b"leaf1");
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_byte().unwrap_err(), "Unexpected end of input");
+
+        let mut reader = ByteReader::new(&[0xff; 11]);
+        assert_eq!(reader.read_varint().unwrap_err(), "Invalid varint");
+        let mut reader = ByteReader::new(&[5, b'a']);
+        assert_eq!(reader.read_bytes().unwrap_err(), "Unexpected end of input");
+    }
+
+    #[test]
+    fn test_codec_with_byte_nodes() {
+        let hash: IMTHashFunction<Vec<u8>> = bytes_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, vec![0]);
+
+        imt.insert_many(vec![vec![1], vec![2], vec![3]]).unwrap();
+        assert_eq!(imt.root().unwrap(), vec![1, 2, 3]);
+
+        let proof = imt.generate_proof(1).unwrap().encode::<BytesCodec>();
+        assert_eq!(proof.leaf, "0x02");
+        assert_eq!(proof.siblings, vec!["0x01".to_string(), "0x03".to_string()]);
+
+        let proof = MerkleProof::decode::<BytesCodec>(&proof).unwrap();
+        assert!(imt.verify_proof(&proof));
+
+        let snapshot = imt.snapshot().unwrap().encode::<BytesCodec>();
+        assert_eq!(snapshot.zero, "0x00");
+        let snapshot = LeanIMTSnapshot::decode::<BytesCodec>(&snapshot).unwrap();
+        let restored = LeanIMT::from_snapshot(snapshot, hash).unwrap();
+        assert_eq!(restored.root(), imt.root());
+    }
+
+    #[test]
+    fn test_proof_encodings() {
+        let hash: IMTHashFunction<Vec<u8>> = bytes_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, vec![0]);
+        imt.insert_many(vec![vec![1], vec![2], vec![3]]).unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+
+        let hex = proof.to_hex::<BytesCodec>();
+        assert_eq!(hex, "0x030102030102010201010103");
+        assert_eq!(MerkleProof::from_hex::<BytesCodec>(&hex), Ok(proof.clone()));
+        assert_eq!(
+            MerkleProof::<Vec<u8>>::from_hex::<BytesCodec>(&format!("{}00", hex)).unwrap_err(),
+            "Trailing bytes"
+        );
+
+        #[cfg(feature = "json")]
+        {
+            let json = proof.to_json::<BytesCodec>();
+            assert_eq!(
+                json,
+                r#"{"root":"0x010203","leaf":"0x02","index":1,"siblings":["0x01","0x03"]}"#
+            );
+            assert_eq!(MerkleProof::from_json::<BytesCodec>(&json), Ok(proof));
+            assert_eq!(
+                MerkleProof::<Vec<u8>>::from_json::<BytesCodec>(r#"{"root":"0x01"}"#).unwrap_err(),
+                "Invalid proof JSON"
+            );
+        }
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard_encodings() {
+        let hash: IMTHashFunction<Vec<u8>> = bytes_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, vec![0]);
+        imt.insert_many(vec![vec![1], vec![2], vec![3]]).unwrap();
+
+        // Byte nodes are written as length-prefixed bytes, like `to_bytes`
+        let proof = imt.generate_proof(1).unwrap();
+        let bytes = proof.to_postcard();
+        assert_eq!(bytes, proof.to_bytes::<BytesCodec>());
+        assert_eq!(MerkleProof::from_postcard(&bytes), Ok(proof));
+        assert_eq!(
+            MerkleProof::<Vec<u8>>::from_postcard(&bytes[..4]).unwrap_err(),
+            "Invalid postcard proof"
+        );
+
+        let snapshot = imt.snapshot().unwrap();
+        let mut bytes = snapshot.to_postcard();
+        assert_eq!(LeanIMTSnapshot::from_postcard(&bytes), Ok(snapshot));
+        bytes.push(0);
+        assert_eq!(
+            LeanIMTSnapshot::<Vec<u8>>::from_postcard(&bytes).unwrap_err(),
+            "Trailing bytes"
+        );
+    }
+
+    #[test]
+    fn test_string_codec() {
+        let node = "leaf1".to_string();
+        assert_eq!(StringCodec::to_text(&node), "leaf1");
+        assert_eq!(StringCodec::encode(&node), b"leaf1".to_vec());
+        assert_eq!(StringCodec::decode(b"leaf1").unwrap(), node);
+        assert!(StringCodec::decode(&[0xff]).is_err());
+    }
+
+    #[cfg(feature = "ark-ff")]
+    #[test]
+    fn test_field_codec() {
+        use ark_bn254::Fr;
+        use ark_ff::{AdditiveGroup, BigInteger, PrimeField};
+
+        fn field_hash_function(nodes: Vec<Fr>) -> Fr {
+            nodes
+                .into_iter()
+                .fold(Fr::from(7u64), |acc, node| acc * Fr::from(31u64) + node)
+        }
+
+        let hash: IMTHashFunction<Fr> = field_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, Fr::ZERO);
+        imt.insert_many((1..=5u64).map(Fr::from).collect()).unwrap();
+
+        let node = Fr::from(258u64);
+        assert_eq!(FieldCodec::encode(&node)[..3], [2, 1, 0]);
+        assert_eq!(FieldCodec::encode(&node).len(), 32);
+        assert_eq!(FieldCodec::to_text(&node), "258");
+        assert_eq!(FieldCodec::from_text("258"), Ok(node));
+        assert!(<FieldCodec as NodeCodec<Fr>>::from_text("0258").is_err());
+
+        // Values not reduced modulo the field order are rejected
+        let modulus = Fr::MODULUS.to_string();
+        assert!(<FieldCodec as NodeCodec<Fr>>::from_text(&modulus).is_err());
+        assert!(<FieldCodec as NodeCodec<Fr>>::decode(&Fr::MODULUS.to_bytes_le()).is_err());
+        assert!(<FieldCodec as NodeCodec<Fr>>::decode(&[1, 2, 3]).is_err());
+
+        let proof = imt.generate_proof(3).unwrap().encode::<FieldCodec>();
+        assert_eq!(proof.leaf, "4");
+        let proof = MerkleProof::decode::<FieldCodec>(&proof).unwrap();
+        assert!(imt.verify_proof(&proof));
+
+        let bytes = imt.to_bytes::<FieldCodec>().unwrap();
+        let restored = LeanIMT::from_bytes::<FieldCodec>(&bytes, hash).unwrap();
+        assert_eq!(restored.root(), imt.root());
+
+        let mut bls = LeanIMT::with_zero(
+            |nodes: Vec<ark_bls12_381::Fr>| nodes.into_iter().sum(),
+            ark_bls12_381::Fr::ZERO,
+        );
+        bls.insert(ark_bls12_381::Fr::from(3u64)).unwrap();
+        assert_eq!(FieldCodec::to_text(&bls.root().unwrap()), "3".to_string());
+    }
+
+    #[cfg(feature = "num-bigint")]
+    #[test]
+    fn test_biguint_codecs() {
+        use crate::bigint::{Bn254, Modulus};
+
+        let node = BigUint::from(0x1234u64);
+        assert_eq!(BigUintCodec::to_text(&node), "4660");
+        assert_eq!(BigUintCodec::from_text("4660"), Ok(node.clone()));
+        assert!(BigUintCodec::from_text("04660").is_err());
+        assert!(BigUintCodec::from_text("4_660").is_err());
+        assert_eq!(BigUintCodec::encode(&node), [0x12, 0x34]);
+        assert!(BigUintCodec::decode(&[0, 0x12, 0x34]).is_err());
+        assert_eq!(BigUintCodec::decode(&[0]), Ok(BigUint::ZERO));
+
+        assert_eq!(BigUintHexCodec::to_text(&node), "0x1234");
+        assert_eq!(BigUintHexCodec::from_text("0x1234"), Ok(node.clone()));
+        assert!(BigUintHexCodec::from_text("0x01234").is_err());
+        assert!(BigUintHexCodec::from_text("1234").is_err());
+
+        let order = Bn254::modulus();
+        assert_eq!(
+            ReducedCodec::<Bn254>::from_text(&(&order - 1u64).to_string()),
+            Ok(&order - 1u64)
+        );
+        assert_eq!(
+            ReducedCodec::<Bn254>::decode(&order.to_bytes_be()),
+            Err("Invalid field element")
+        );
+    }
+
+    #[cfg(feature = "alloy-primitives")]
+    #[test]
+    fn test_alloy_codecs() {
+        use alloy_primitives::{B256, U256};
+
+        fn u256_hash_function(nodes: Vec<U256>) -> U256 {
+            nodes
+                .into_iter()
+                .fold(U256::from(7), |acc, node| acc * U256::from(31) + node)
+        }
+
+        let node = U256::from(0x1234);
+        assert_eq!(U256Codec::to_text(&node), "0x1234");
+        assert_eq!(U256Codec::from_text("0x1234"), Ok(node));
+        assert_eq!(U256Codec::from_text("0x00001234"), Ok(node));
+        assert!(U256Codec::from_text("1234").is_err());
+        assert!(U256Codec::from_text(&format!("0x1{}", "0".repeat(64))).is_err());
+        assert_eq!(U256Codec::encode(&node)[30..], [0x12, 0x34]);
+        assert_eq!(U256Codec::decode(&U256Codec::encode(&node)), Ok(node));
+
+        let hash: IMTHashFunction<U256> = u256_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, U256::ZERO);
+        imt.insert_many((1..=3u64).map(U256::from).collect())
+            .unwrap();
+        let proof = imt.generate_proof(2).unwrap().encode::<U256Codec>();
+        assert_eq!(proof.leaf, "0x3");
+        let proof = MerkleProof::decode::<U256Codec>(&proof).unwrap();
+        assert!(imt.verify_proof(&proof));
+
+        let node = B256::with_last_byte(1);
+        let text = B256Codec::to_text(&node);
+        assert_eq!(text, format!("0x{}01", "0".repeat(62)));
+        assert_eq!(B256Codec::from_text(&text), Ok(node));
+        assert!(B256Codec::from_text("0x01").is_err());
+        assert!(B256Codec::from_text(&text[2..]).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Compressed snapshots.
+//!
+//! Checkpoints of large trees are mostly leaves, which compress well.
+//! `write_snapshot` streams the binary snapshot of a tree through a gzip or
+//! zstd encoder into any writer, e.g. an upload to object storage, and
+//! `read_snapshot` streams it back through the matching decoder, recognized
+//! from the first bytes of the data. Gzip needs the `gzip` feature and zstd
+//! the `zstd` feature.
+
+use std::io::{BufRead, BufReader, Read, Write};
+
+use core::hash::Hash;
+
+use crate::{IMTHashFunction, LeanIMT, LeanIMTSnapshot, LeanIMTStore, NodeCodec};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression of a written snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// The binary snapshot as written by `to_bytes`.
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Recognizes the compression of a snapshot from its first bytes.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl<N> LeanIMTSnapshot<N> {
+    /// Writes the binary snapshot, with nodes encoded by `C`, to `writer`
+    /// with `compression`.
+    pub fn write_to<C: NodeCodec<N>>(
+        &self,
+        writer: impl Write,
+        compression: Compression,
+    ) -> Result<(), &'static str> {
+        let bytes = self.to_bytes::<C>()?;
+        match compression {
+            Compression::None => {
+                let mut writer = writer;
+                writer
+                    .write_all(&bytes)
+                    .and_then(|_| writer.flush())
+                    .map_err(|_| "Failed to write snapshot")
+            }
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+                encoder
+                    .write_all(&bytes)
+                    .and_then(|_| encoder.finish())
+                    .and_then(|mut writer| writer.flush())
+                    .map_err(|_| "Failed to write snapshot")
+            }
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => Err("Gzip snapshots need the gzip feature"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .map_err(|_| "Failed to write snapshot")?;
+                encoder
+                    .write_all(&bytes)
+                    .and_then(|_| encoder.finish())
+                    .and_then(|mut writer| writer.flush())
+                    .map_err(|_| "Failed to write snapshot")
+            }
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => Err("Zstd snapshots need the zstd feature"),
+        }
+    }
+
+    /// Reads a snapshot written by `write_to`, with any compression, from
+    /// `reader`.
+    pub fn read_from<C: NodeCodec<N>>(reader: impl Read) -> Result<Self, &'static str> {
+        let mut reader = BufReader::new(reader);
+        let header = reader.fill_buf().map_err(|_| "Failed to read snapshot")?;
+        let mut bytes = Vec::new();
+        match Compression::detect(header) {
+            Compression::None => reader.read_to_end(&mut bytes),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                flate2::bufread::MultiGzDecoder::new(reader).read_to_end(&mut bytes)
+            }
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => return Err("Gzip snapshots need the gzip feature"),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::Decoder::with_buffer(reader)
+                .and_then(|mut decoder| decoder.read_to_end(&mut bytes)),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err("Zstd snapshots need the zstd feature"),
+        }
+        .map_err(|_| "Failed to read snapshot")?;
+        Self::from_bytes::<C>(&bytes)
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMT<N> {
+    /// Restores a tree from a snapshot written by `write_snapshot`, with any
+    /// compression, read from `reader`.
+    pub fn read_snapshot<C: NodeCodec<N>>(
+        reader: impl Read,
+        hash: IMTHashFunction<N>,
+    ) -> Result<Self, &'static str> {
+        Self::from_snapshot(LeanIMTSnapshot::read_from::<C>(reader)?, hash)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Writes the binary snapshot of the tree, with nodes encoded by `C`, to
+    /// `writer` with `compression`.
+    pub fn write_snapshot<C: NodeCodec<N>>(
+        &self,
+        writer: impl Write,
+        compression: Compression,
+    ) -> Result<(), &'static str> {
+        self.snapshot()?.write_to::<C>(writer, compression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_compressed_snapshots() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((0..1000).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let raw = imt.to_bytes::<StringCodec>().unwrap();
+
+        let mut compressions = vec![Compression::None];
+        if cfg!(feature = "gzip") {
+            compressions.push(Compression::Gzip);
+        }
+        if cfg!(feature = "zstd") {
+            compressions.push(Compression::Zstd);
+        }
+        for compression in compressions {
+            let mut bytes = Vec::new();
+            imt.write_snapshot::<StringCodec>(&mut bytes, compression)
+                .unwrap();
+            assert_eq!(Compression::detect(&bytes), compression);
+            if compression != Compression::None {
+                assert!(bytes.len() < raw.len() / 2);
+            }
+
+            let restored = LeanIMT::read_snapshot::<StringCodec>(&bytes[..], hash).unwrap();
+            assert_eq!(restored.root(), imt.root());
+        }
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn test_compression_needs_its_feature() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let imt = LeanIMT::new(hash);
+        assert_eq!(
+            imt.write_snapshot::<StringCodec>(Vec::new(), Compression::Zstd),
+            Err("Zstd snapshots need the zstd feature")
+        );
+        assert_eq!(
+            LeanIMT::read_snapshot::<StringCodec>(&ZSTD_MAGIC[..], hash).unwrap_err(),
+            "Zstd snapshots need the zstd feature"
+        );
+    }
+}
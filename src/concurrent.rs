@@ -0,0 +1,219 @@
+//! Tree with a single writer and lock-free readers.
+//!
+//! [`ConcurrentLeanIMT`] keeps two copies of the tree: the writer works on its
+//! own copy behind a mutex, and publishes an immutable copy after each write
+//! through an `ArcSwap`. Readers load the latest published copy without
+//! taking any lock, so they never wait for the writer, and a loaded copy stays
+//! consistent however long it is kept.
+//!
+//! Publishing forks the writer copy, so the tree defaults to a
+//! [`PersistentStore`], whose structural sharing makes each publication
+//! cheap. Other cloneable stores work too, but a `MemoryStore` copies every
+//! entry on each publication; writes that come in bursts should then be
+//! grouped with `write` to publish once per burst.
+//!
+//! A write that fails is rolled back: the writer copy is reset to the
+//! published version, so the next write starts from what readers see.
+
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use arc_swap::ArcSwap;
+
+use crate::persistent_store::PersistentStore;
+use crate::{IMTHashFunction, IMTNode, LeanIMT, LeanIMTStore, MerkleProof};
+
+/// Tree whose reads never block, shared between threads through an `Arc`.
+#[derive(Debug)]
+pub struct ConcurrentLeanIMT<N = IMTNode, S = PersistentStore<N>> {
+    writer: Mutex<LeanIMT<N, S>>,
+    current: ArcSwap<LeanIMT<N, S>>,
+}
+
+impl<N: Clone + Eq + Hash> ConcurrentLeanIMT<N> {
+    /// Creates an empty tree kept in a `PersistentStore`, using `zero` as the
+    /// value of removed leaves.
+    pub fn with_zero(hash: IMTHashFunction<N>, zero: N) -> Result<Self, &'static str> {
+        let tree = LeanIMT::with_store(hash, zero, PersistentStore::default())?;
+        Ok(Self::new(tree))
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N> + Clone> ConcurrentLeanIMT<N, S> {
+    pub fn new(tree: LeanIMT<N, S>) -> Self {
+        ConcurrentLeanIMT {
+            current: ArcSwap::from_pointee(tree.fork()),
+            writer: Mutex::new(tree),
+        }
+    }
+
+    /// Returns the latest published version of the tree.
+    pub fn load(&self) -> Arc<LeanIMT<N, S>> {
+        self.current.load_full()
+    }
+
+    pub fn root(&self) -> Option<N> {
+        self.current.load().root()
+    }
+
+    pub fn has(&self, leaf: &N) -> bool {
+        self.current.load().has(leaf)
+    }
+
+    pub fn size(&self) -> usize {
+        self.current.load().get_size()
+    }
+
+    /// Generates a proof against the latest published root.
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.current.load().generate_proof(index)
+    }
+
+    /// Runs `operation` on the writer copy of the tree, then publishes the
+    /// result if it succeeded.
+    ///
+    /// Writers are serialized; readers keep seeing the previous version until
+    /// the operation returns. If it fails, every change it made is rolled
+    /// back, though hooks and subscribers have already been notified of the
+    /// operations applied before the failure.
+    pub fn write<T>(
+        &self,
+        operation: impl FnOnce(&mut LeanIMT<N, S>) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        let mut tree = self.writer.lock().unwrap_or_else(|poisoned| {
+            // A writer panicked: drop what it left half done
+            let mut tree = poisoned.into_inner();
+            tree.reset_to(&self.current.load());
+            tree
+        });
+        let result = operation(&mut tree);
+        match result {
+            // Payload changes leave the version as is, so successful writes
+            // are always published
+            Ok(_) => self.current.store(Arc::new(tree.fork())),
+            Err(_) => tree.reset_to(&self.current.load()),
+        }
+        result
+    }
+
+    pub fn insert(&self, leaf: N) -> Result<N, &'static str> {
+        self.write(|tree| tree.insert(leaf))
+    }
+
+    pub fn insert_many(&self, leaves: Vec<N>) -> Result<N, &'static str> {
+        self.write(|tree| tree.insert_many(leaves))
+    }
+
+    pub fn update(
+        &self,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.write(|tree| tree.update(old_leaf, new_leaf, sibling_nodes))
+    }
+
+    pub fn remove(&self, old_leaf: &N, sibling_nodes: &[N]) -> Result<N, &'static str> {
+        self.write(|tree| tree.remove(old_leaf, sibling_nodes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use std::thread;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_readers_see_published_versions() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let tree = Arc::new(ConcurrentLeanIMT::new(LeanIMT::new(hash)));
+        assert_eq!(tree.root(), None);
+
+        let pinned = tree.load();
+        let writer = {
+            let tree = Arc::clone(&tree);
+            thread::spawn(move || {
+                for i in 0..100 {
+                    tree.insert(format!("leaf{}", i)).unwrap();
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let tree = Arc::clone(&tree);
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        // Each loaded version is internally consistent
+                        let version = tree.load();
+                        if version.get_size() > 0 {
+                            let proof = version.generate_proof(version.get_size() - 1).unwrap();
+                            assert!(version.verify_proof(&proof));
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(pinned.get_size(), 0);
+        assert_eq!(tree.size(), 100);
+        assert!(tree.has(&"leaf99".to_string()));
+    }
+
+    #[test]
+    fn test_grouped_writes() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let tree = ConcurrentLeanIMT::with_zero(hash, "0".to_string()).unwrap();
+        tree.write(|tree| {
+            tree.set_changelog_capacity(16);
+            Ok(())
+        })
+        .unwrap();
+
+        let root = tree
+            .write(|tree| {
+                tree.insert("leaf1".to_string())?;
+                tree.insert("leaf2".to_string())
+            })
+            .unwrap();
+        assert_eq!(tree.root(), Some(root));
+        assert_eq!(tree.load().get_version(), 2);
+
+        // A failed write leaves the published version unchanged
+        assert!(tree.insert("leaf1".to_string()).is_err());
+        assert_eq!(tree.load().get_version(), 2);
+
+        // and rolls back the operations it applied before failing
+        let result = tree.write(|tree| {
+            tree.insert("leaf3".to_string())?;
+            tree.insert("leaf1".to_string())
+        });
+        assert!(result.is_err());
+        assert_eq!(tree.size(), 2);
+        assert_eq!(tree.write(|tree| Ok(tree.get_version())), Ok(2));
+        assert_eq!(tree.write(|tree| Ok(tree.changes_since(0)?.count())), Ok(2));
+        assert_eq!(
+            tree.insert("leaf3".to_string()),
+            Ok("leaf1,leaf2,leaf3".to_string())
+        );
+
+        let proof = tree.generate_proof(0).unwrap();
+        tree.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+        assert!(!tree.has(&"leaf1".to_string()));
+        assert_eq!(tree.load().get_size(), 3);
+
+        tree.write(|tree| tree.set_value(1, Some(b"two".to_vec())))
+            .unwrap();
+        assert_eq!(tree.load().value_at(1), Ok(Some(b"two".to_vec())));
+    }
+}
@@ -0,0 +1,228 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{IMTNode, LeanIMT, LeanIMTStore};
+
+/// Proof that the tree of `new_size` leaves extends the tree of `old_size`
+/// leaves by appending leaves only.
+///
+/// A LeanIMT has the shape of the Certificate Transparency Merkle tree, so
+/// `nodes` is the consistency proof of RFC 9162, computed with the tree hash
+/// function. An auditor checks `old_root` against the root it previously
+/// trusted, then calls `verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConsistencyProof<N = IMTNode> {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub old_root: N,
+    pub new_root: N,
+    pub nodes: Vec<N>,
+}
+
+impl<N: Clone + PartialEq> ConsistencyProof<N> {
+    /// Checks that the nodes link `old_root` to `new_root`.
+    pub fn verify(&self, hash: impl Fn(Vec<N>) -> N) -> bool {
+        if self.old_size == 0 || self.old_size > self.new_size {
+            return false;
+        }
+        if self.old_size == self.new_size {
+            return self.nodes.is_empty() && self.old_root == self.new_root;
+        }
+
+        let mut path = self.nodes.iter();
+        let first = if self.old_size.is_power_of_two() {
+            &self.old_root
+        } else {
+            match path.next() {
+                Some(node) => node,
+                None => return false,
+            }
+        };
+
+        let mut old_index = self.old_size - 1;
+        let mut new_index = self.new_size - 1;
+        while old_index & 1 == 1 {
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+
+        let mut old_root = first.clone();
+        let mut new_root = first.clone();
+        for node in path {
+            if new_index == 0 {
+                return false;
+            }
+            if old_index & 1 == 1 || old_index == new_index {
+                old_root = hash(vec![node.clone(), old_root]);
+                new_root = hash(vec![node.clone(), new_root]);
+                while old_index & 1 == 0 && old_index != 0 {
+                    old_index >>= 1;
+                    new_index >>= 1;
+                }
+            } else {
+                new_root = hash(vec![new_root, node.clone()]);
+            }
+            old_index >>= 1;
+            new_index >>= 1;
+        }
+
+        new_index == 0 && old_root == self.old_root && new_root == self.new_root
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Generates the proof that the first `new_size` leaves extend the first
+    /// `old_size` leaves.
+    ///
+    /// The roots are computed from the current leaves: if a leaf below
+    /// `old_size` was updated or removed since, the proof does not match the
+    /// old root an auditor holds.
+    pub fn consistency_proof(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<ConsistencyProof<N>, &'static str> {
+        self.ensure_binary()?;
+        if old_size == 0 || old_size > new_size || new_size > self.size {
+            return Err("Invalid tree sizes");
+        }
+
+        let leaves = self.ordered_leaves()?;
+        let mut nodes = Vec::new();
+        subproof(
+            old_size,
+            &leaves[..new_size],
+            true,
+            &self.hash.as_fn(),
+            &mut nodes,
+        );
+
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            old_root: subtree_root(&leaves[..old_size], &self.hash.as_fn()),
+            new_root: subtree_root(&leaves[..new_size], &self.hash.as_fn()),
+            nodes,
+        })
+    }
+}
+
+/// Appends the nodes of `SUBPROOF(m, leaves, complete)` from RFC 9162.
+fn subproof<N: Clone>(
+    m: usize,
+    leaves: &[N],
+    complete: bool,
+    hash: &impl Fn(Vec<N>) -> N,
+    nodes: &mut Vec<N>,
+) {
+    let n = leaves.len();
+    if m == n {
+        if !complete {
+            nodes.push(subtree_root(leaves, hash));
+        }
+        return;
+    }
+
+    // Largest power of two lower than n
+    let k = 1 << (usize::BITS - 1 - (n - 1).leading_zeros());
+    if m <= k {
+        subproof(m, &leaves[..k], complete, hash, nodes);
+        nodes.push(subtree_root(&leaves[k..], hash));
+    } else {
+        subproof(m - k, &leaves[k..], false, hash, nodes);
+        nodes.push(subtree_root(&leaves[..k], hash));
+    }
+}
+
+/// Computes the root of a non-empty list of leaves.
+fn subtree_root<N: Clone>(leaves: &[N], hash: &impl Fn(Vec<N>) -> N) -> N {
+    let mut nodes = leaves.to_vec();
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| match pair {
+                [left] => left.clone(),
+                _ => hash(pair.to_vec()),
+            })
+            .collect();
+    }
+    nodes.swap_remove(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        format!("({})", nodes.join(","))
+    }
+
+    #[test]
+    fn test_consistency_proof() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        let mut roots = Vec::new();
+        for i in 0..12 {
+            imt.insert(format!("leaf{}", i)).unwrap();
+            roots.push(imt.root().unwrap());
+        }
+
+        for old_size in 1..=12 {
+            for new_size in old_size..=12 {
+                let proof = imt.consistency_proof(old_size, new_size).unwrap();
+                assert_eq!(proof.old_root, roots[old_size - 1]);
+                assert_eq!(proof.new_root, roots[new_size - 1]);
+                assert!(proof.verify(hash));
+            }
+        }
+
+        let proof = imt.consistency_proof(3, 7).unwrap();
+        assert_eq!(proof.nodes.len(), 4);
+
+        assert_eq!(
+            imt.consistency_proof(0, 3).unwrap_err(),
+            "Invalid tree sizes"
+        );
+        assert!(imt.consistency_proof(5, 4).is_err());
+        assert!(imt.consistency_proof(5, 13).is_err());
+    }
+
+    #[test]
+    fn test_inconsistent_trees() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((0..6).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let old_root = imt.consistency_proof(3, 6).unwrap().old_root;
+
+        let mut proof = imt.consistency_proof(3, 6).unwrap();
+        proof.nodes[1] = "leaf9".to_string();
+        assert!(!proof.verify(hash));
+
+        let mut proof = imt.consistency_proof(3, 6).unwrap();
+        proof.new_size = 12;
+        assert!(!proof.verify(hash));
+
+        let mut proof = imt.consistency_proof(3, 6).unwrap();
+        proof.nodes.pop();
+        assert!(!proof.verify(hash));
+
+        // Rewriting history is detected against the old root
+        let siblings = imt.generate_proof(1).unwrap().siblings;
+        imt.remove(&"leaf1".to_string(), &siblings).unwrap();
+        let proof = imt.consistency_proof(3, 6).unwrap();
+        assert!(proof.verify(hash));
+        assert_ne!(proof.old_root, old_root);
+    }
+}
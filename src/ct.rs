@@ -0,0 +1,65 @@
+//! Constant-time comparison of nodes.
+//!
+//! Trees compare roots with `==` by default, which returns at the first
+//! differing byte. Where the timing of a proof check or of the root check of
+//! an update is observable, the functions here, set with
+//! `LeanIMT::set_node_eq` or the `node_eq` option of the builder, compare
+//! nodes with `subtle` instead. Only the contents of the nodes are protected:
+//! nodes of different lengths are unequal without comparing their bytes.
+
+use subtle::ConstantTimeEq;
+
+use crate::codec::NodeCodec;
+
+/// Compares the bytes of two nodes in constant time, for string and byte
+/// nodes.
+pub fn bytes_eq<N: AsRef<[u8]>>(a: &N, b: &N) -> bool {
+    a.as_ref().ct_eq(b.as_ref()).into()
+}
+
+/// Compares the encodings of two nodes by `C` in constant time, e.g.
+/// `codec_eq::<Fr, FieldCodec>` for field elements.
+pub fn codec_eq<N, C: NodeCodec<N>>(a: &N, b: &N) -> bool {
+    C::encode(a).ct_eq(&C::encode(b)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(bytes_eq(&"a,b".to_string(), &"a,b".to_string()));
+        assert!(!bytes_eq(&"a,b".to_string(), &"a,c".to_string()));
+        assert!(!bytes_eq(&"a".to_string(), &"a,b".to_string()));
+        assert!(codec_eq::<String, StringCodec>(
+            &"a".to_string(),
+            &"a".to_string()
+        ));
+
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_node_eq(Some(bytes_eq));
+        imt.insert_many(["a", "b", "c"].map(String::from).to_vec())
+            .unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        assert!(imt.verify_proof(&proof));
+        let mut forged = proof.clone();
+        forged.root = "a,b,d".to_string();
+        assert!(!imt.verify_proof(&forged));
+
+        let root = imt.update(&"b".to_string(), "e".to_string(), &proof.siblings);
+        assert_eq!(root, Ok("a,e,c".to_string()));
+        assert!(imt
+            .remove(&"e".to_string(), &["b".to_string(), "c".to_string()])
+            .is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Differences between two trees.
+//!
+//! Leaves are compared index by index: an index past the size of the old tree
+//! is an addition, and a leaf set to zero, or past the size of the new tree,
+//! is a removal. Any other change of the leaf at an index is an update.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+use core::ops::Range;
+
+use crate::{LeanIMT, LeanIMTSnapshot, LeanIMTStore};
+
+/// Changes that turn the leaves of an old tree into those of a new tree.
+///
+/// Each list is ordered by index. `changed_ranges` merges the indices of all
+/// the changes into contiguous ranges: the internal nodes above the other
+/// leaves are the same in both trees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeDiff<N> {
+    pub old_size: usize,
+    pub new_size: usize,
+    /// `(index, leaf)` of the leaves past the old size; leaves removed since
+    /// they were added hold the zero value.
+    pub added: Vec<(usize, N)>,
+    /// `(index, old leaf)` of the leaves set to zero or past the new size.
+    pub removed: Vec<(usize, N)>,
+    /// `(index, old leaf, new leaf)` of the other changed leaves.
+    pub updated: Vec<(usize, N, N)>,
+    pub changed_ranges: Vec<Range<usize>>,
+}
+
+impl<N> TreeDiff<N> {
+    /// Returns true if both trees have the same leaves.
+    pub fn is_empty(&self) -> bool {
+        self.changed_ranges.is_empty()
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Returns the changes from the leaves of this tree to those of `other`.
+    pub fn diff<T: LeanIMTStore<N>>(
+        &self,
+        other: &LeanIMT<N, T>,
+    ) -> Result<TreeDiff<N>, &'static str> {
+        Ok(diff_leaves(
+            &self.ordered_leaves()?,
+            &other.ordered_leaves()?,
+            &self.zero,
+        ))
+    }
+}
+
+impl<N: Clone + Eq> LeanIMTSnapshot<N> {
+    /// Returns the changes from the leaves of this snapshot to those of
+    /// `other`.
+    pub fn diff(&self, other: &LeanIMTSnapshot<N>) -> Result<TreeDiff<N>, &'static str> {
+        Ok(diff_leaves(
+            &self.ordered_leaves()?,
+            &other.ordered_leaves()?,
+            &self.zero,
+        ))
+    }
+
+    fn ordered_leaves(&self) -> Result<Vec<N>, &'static str> {
+        let mut nodes = Vec::new();
+        nodes
+            .try_reserve(self.size)
+            .map_err(|_| "Tree is too large")?;
+        nodes.resize(self.size, self.zero.clone());
+        for (leaf, index) in &self.leaves {
+            *nodes.get_mut(*index).ok_or("Invalid leaf")? = leaf.clone();
+        }
+        Ok(nodes)
+    }
+}
+
+fn diff_leaves<N: Clone + Eq>(old: &[N], new: &[N], zero: &N) -> TreeDiff<N> {
+    let mut diff = TreeDiff {
+        old_size: old.len(),
+        new_size: new.len(),
+        added: Vec::new(),
+        removed: Vec::new(),
+        updated: Vec::new(),
+        changed_ranges: Vec::new(),
+    };
+
+    for index in 0..old.len().max(new.len()) {
+        match (old.get(index), new.get(index)) {
+            (Some(old_leaf), Some(new_leaf)) if old_leaf == new_leaf => continue,
+            (None, Some(new_leaf)) => diff.added.push((index, new_leaf.clone())),
+            (Some(old_leaf), new_leaf) if new_leaf.is_none_or(|leaf| leaf == zero) => {
+                if old_leaf == zero {
+                    continue;
+                }
+                diff.removed.push((index, old_leaf.clone()));
+            }
+            (Some(old_leaf), Some(new_leaf)) => {
+                diff.updated
+                    .push((index, old_leaf.clone(), new_leaf.clone()))
+            }
+            // Only reached past the end of both trees, which the range excludes
+            (_, None) => continue,
+        }
+
+        match diff.changed_ranges.last_mut() {
+            Some(range) if range.end == index => range.end += 1,
+            _ => diff.changed_ranges.push(index..index + 1),
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_diff() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut replica = LeanIMT::new(hash);
+        replica
+            .insert_many((0..6).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let mut canonical = replica.fork();
+        assert!(replica.diff(&canonical).unwrap().is_empty());
+
+        let siblings = canonical.generate_proof(1).unwrap().siblings;
+        canonical.remove(&"leaf1".to_string(), &siblings).unwrap();
+        let siblings = canonical.generate_proof(2).unwrap().siblings;
+        canonical
+            .update(&"leaf2".to_string(), "new2".to_string(), &siblings)
+            .unwrap();
+        canonical
+            .insert_many(vec!["leaf6".to_string(), "leaf7".to_string()])
+            .unwrap();
+
+        let diff = replica.diff(&canonical).unwrap();
+        assert_eq!((diff.old_size, diff.new_size), (6, 8));
+        assert_eq!(
+            diff.added,
+            vec![(6, "leaf6".to_string()), (7, "leaf7".to_string())]
+        );
+        assert_eq!(diff.removed, vec![(1, "leaf1".to_string())]);
+        assert_eq!(
+            diff.updated,
+            vec![(2, "leaf2".to_string(), "new2".to_string())]
+        );
+        assert_eq!(diff.changed_ranges, vec![1..3, 6..8]);
+
+        // Leaves past the size of the new tree are removed
+        let reverse = canonical.diff(&replica).unwrap();
+        assert_eq!(
+            reverse.removed,
+            vec![(6, "leaf6".to_string()), (7, "leaf7".to_string())]
+        );
+        assert_eq!(
+            reverse.updated,
+            vec![
+                (1, "0".to_string(), "leaf1".to_string()),
+                (2, "new2".to_string(), "leaf2".to_string())
+            ]
+        );
+
+        let snapshot_diff = replica
+            .snapshot()
+            .unwrap()
+            .diff(&canonical.snapshot().unwrap())
+            .unwrap();
+        assert_eq!(snapshot_diff, diff);
+    }
+}
@@ -0,0 +1,302 @@
+//! Trees rotating at epoch boundaries.
+//!
+//! RLN-style rate limiting and rotating allowlists accept members for an
+//! epoch, then start over. An [`EpochLeanIMT`] inserts into the tree of the
+//! current epoch until the [`EpochPolicy`] says it is due, i.e. when it is
+//! full or old enough, then seals it, archiving its root, and starts a fresh
+//! tree. Sealed epochs stay queryable, so membership can be checked in the
+//! current epoch or in any retained one.
+//!
+//! Times are given by the caller, in any unit as long as it is consistent,
+//! e.g. seconds or block numbers.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::{IMTNode, LeanIMT};
+
+/// When the tree of the current epoch is sealed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EpochPolicy {
+    /// Number of leaves after which the epoch is sealed.
+    pub max_size: Option<usize>,
+    /// Time after which the epoch is sealed, counted from its start.
+    pub max_duration: Option<u64>,
+}
+
+/// A sealed epoch and its tree.
+#[derive(Debug)]
+pub struct SealedEpoch<N = IMTNode> {
+    pub number: u64,
+    /// Root of the tree when it was sealed, `None` if it was empty.
+    pub root: Option<N>,
+    pub started_at: u64,
+    pub sealed_at: u64,
+    tree: LeanIMT<N>,
+}
+
+impl<N> SealedEpoch<N> {
+    /// Returns the tree of the epoch, e.g. to generate proofs against the
+    /// archived root.
+    pub fn tree(&self) -> &LeanIMT<N> {
+        &self.tree
+    }
+}
+
+/// Trees of successive epochs, inserting into the current one.
+#[derive(Debug)]
+pub struct EpochLeanIMT<N = IMTNode> {
+    policy: EpochPolicy,
+    /// Empty tree copied to start each epoch.
+    template: LeanIMT<N>,
+    current: LeanIMT<N>,
+    number: u64,
+    started_at: u64,
+    sealed: VecDeque<SealedEpoch<N>>,
+    /// Maximum number of sealed epochs kept, if limited.
+    retention: Option<usize>,
+}
+
+impl<N: Clone + Eq + Hash> EpochLeanIMT<N> {
+    /// Starts epoch 0 at `now`, with trees configured like `tree`, which
+    /// must be empty.
+    pub fn new(mut tree: LeanIMT<N>, policy: EpochPolicy, now: u64) -> Result<Self, &'static str> {
+        if tree.size > 0 {
+            return Err("Tree is not empty");
+        }
+        if policy.max_size == Some(0) || policy.max_duration == Some(0) {
+            return Err("Invalid epoch policy");
+        }
+        if policy.max_size.is_some() {
+            tree.set_max_size(policy.max_size)?;
+        }
+
+        Ok(EpochLeanIMT {
+            policy,
+            current: tree.fork(),
+            template: tree,
+            number: 0,
+            started_at: now,
+            sealed: VecDeque::new(),
+            retention: None,
+        })
+    }
+
+    /// Keeps at most `retention` sealed epochs, dropping the oldest ones, or
+    /// all of them with `None` (the default).
+    pub fn set_retention(&mut self, retention: Option<usize>) {
+        self.retention = retention;
+        self.prune();
+    }
+
+    /// Inserts `leaf` in the current epoch, first sealing it if it is due
+    /// at `now`, and returns the new root of the current epoch.
+    pub fn insert(&mut self, leaf: N, now: u64) -> Result<N, &'static str> {
+        self.advance(now);
+        self.current.insert(leaf)
+    }
+
+    /// Inserts `leaves` in order, sealing the current epoch each time it is
+    /// due, and returns the root of the epoch holding the last leaf.
+    ///
+    /// If an insert fails, the epochs sealed before it stay sealed.
+    pub fn insert_many(&mut self, mut leaves: Vec<N>, now: u64) -> Result<N, &'static str> {
+        self.advance(now);
+        loop {
+            let room = self
+                .policy
+                .max_size
+                .map_or(leaves.len(), |max_size| max_size - self.current.size);
+            if room >= leaves.len() {
+                return self.current.insert_many(leaves);
+            }
+            let rest = leaves.split_off(room);
+            self.current.insert_many(leaves)?;
+            self.seal(now);
+            leaves = rest;
+        }
+    }
+
+    /// Seals the current epoch if it is due at `now`, returning the number
+    /// of the sealed epoch.
+    pub fn advance(&mut self, now: u64) -> Option<u64> {
+        let full = self
+            .policy
+            .max_size
+            .is_some_and(|max_size| self.current.size >= max_size);
+        let expired = self
+            .policy
+            .max_duration
+            .is_some_and(|duration| now.saturating_sub(self.started_at) >= duration);
+        if !(full || expired) {
+            return None;
+        }
+        Some(self.seal(now))
+    }
+
+    /// Seals the current epoch at `now` and starts the next one, returning
+    /// the number of the sealed epoch.
+    pub fn seal(&mut self, now: u64) -> u64 {
+        let tree = core::mem::replace(&mut self.current, self.template.fork());
+        self.sealed.push_back(SealedEpoch {
+            number: self.number,
+            root: tree.root(),
+            started_at: self.started_at,
+            sealed_at: now,
+            tree,
+        });
+        self.number += 1;
+        self.started_at = now;
+        self.prune();
+        self.number - 1
+    }
+
+    fn prune(&mut self) {
+        if let Some(retention) = self.retention {
+            while self.sealed.len() > retention {
+                self.sealed.pop_front();
+            }
+        }
+    }
+
+    /// Returns the number of the current epoch.
+    pub fn get_epoch(&self) -> u64 {
+        self.number
+    }
+
+    /// Returns the time at which the current epoch started.
+    pub fn get_started_at(&self) -> u64 {
+        self.started_at
+    }
+
+    pub fn get_policy(&self) -> EpochPolicy {
+        self.policy
+    }
+
+    pub fn current(&self) -> &LeanIMT<N> {
+        &self.current
+    }
+
+    /// Returns the root of the current epoch, or `None` if it is empty.
+    pub fn root(&self) -> Option<N> {
+        self.current.root()
+    }
+
+    /// Returns the sealed epoch `number`, if it is retained.
+    pub fn epoch(&self, number: u64) -> Option<&SealedEpoch<N>> {
+        let first = self.sealed.front()?.number;
+        self.sealed
+            .get(usize::try_from(number.checked_sub(first)?).ok()?)
+    }
+
+    /// Iterates over the retained sealed epochs, oldest first.
+    pub fn sealed(&self) -> impl Iterator<Item = &SealedEpoch<N>> {
+        self.sealed.iter()
+    }
+
+    /// Tells whether `leaf` is in the current epoch or in a retained sealed
+    /// one.
+    pub fn is_member_in_any_epoch(&self, leaf: &N) -> bool {
+        self.current.has(leaf) || self.sealed.iter().any(|epoch| epoch.tree.has(leaf))
+    }
+
+    /// Returns the numbers of the epochs holding `leaf`, in ascending order,
+    /// including the current one.
+    pub fn epochs_of(&self, leaf: &N) -> Vec<u64> {
+        let mut epochs: Vec<u64> = self
+            .sealed
+            .iter()
+            .filter(|epoch| epoch.tree.has(leaf))
+            .map(|epoch| epoch.number)
+            .collect();
+        if self.current.has(leaf) {
+            epochs.push(self.number);
+        }
+        epochs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_epochs_rotate_by_size() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let policy = EpochPolicy {
+            max_size: Some(2),
+            max_duration: None,
+        };
+        let mut epochs = EpochLeanIMT::new(LeanIMT::new(hash), policy, 0).unwrap();
+
+        epochs.insert("a".to_string(), 1).unwrap();
+        epochs.insert("b".to_string(), 2).unwrap();
+        assert_eq!(epochs.get_epoch(), 0);
+        // The full epoch is sealed before the next insert
+        assert_eq!(epochs.insert("a".to_string(), 3), Ok("a".to_string()));
+        assert_eq!(epochs.get_epoch(), 1);
+
+        let root = epochs
+            .insert_many(vec!["c".into(), "d".into(), "e".into()], 4)
+            .unwrap();
+        assert_eq!(root, "d,e");
+        assert_eq!(epochs.get_epoch(), 2);
+        let sealed: Vec<_> = epochs.sealed().map(|epoch| epoch.root.clone()).collect();
+        assert_eq!(
+            sealed,
+            vec![Some("a,b".to_string()), Some("a,c".to_string())]
+        );
+        assert_eq!(epochs.epoch(1).unwrap().tree().get_size(), 2);
+
+        assert!(epochs.is_member_in_any_epoch(&"b".to_string()));
+        assert!(!epochs.is_member_in_any_epoch(&"f".to_string()));
+        assert_eq!(epochs.epochs_of(&"a".to_string()), vec![0, 1]);
+        assert_eq!(epochs.epochs_of(&"e".to_string()), vec![2]);
+
+        epochs.set_retention(Some(1));
+        assert!(epochs.epoch(0).is_none());
+        assert!(!epochs.is_member_in_any_epoch(&"b".to_string()));
+    }
+
+    #[test]
+    fn test_epochs_rotate_by_time() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let policy = EpochPolicy {
+            max_size: None,
+            max_duration: Some(10),
+        };
+        let mut epochs = EpochLeanIMT::new(LeanIMT::new(hash), policy, 100).unwrap();
+
+        epochs.insert("a".to_string(), 105).unwrap();
+        assert_eq!(epochs.advance(109), None);
+        assert_eq!(epochs.advance(110), Some(0));
+        assert_eq!(epochs.get_started_at(), 110);
+        assert_eq!(epochs.root(), None);
+
+        // Duplicates are rejected within an epoch only
+        epochs.insert("a".to_string(), 111).unwrap();
+        assert!(epochs.insert("a".to_string(), 112).is_err());
+        let sealed = epochs.epoch(0).unwrap();
+        assert_eq!((sealed.started_at, sealed.sealed_at), (100, 110));
+
+        assert_eq!(
+            EpochLeanIMT::new(LeanIMT::new(hash), EpochPolicy::default(), 0)
+                .map(|epochs| epochs.get_epoch()),
+            Ok(0)
+        );
+        let mut tree = LeanIMT::new(hash);
+        tree.insert("a".to_string()).unwrap();
+        assert_eq!(
+            EpochLeanIMT::new(tree, policy, 0).unwrap_err(),
+            "Tree is not empty"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Calldata for updating and removing leaves of an on-chain LeanIMT.
+//!
+//! The Solidity library only stores side nodes, so its `update` and `remove`
+//! functions take the siblings of the leaf from the caller. A tree mirroring
+//! the contract, e.g. with [`LogSync`](crate::eth_sync::LogSync), holds every
+//! leaf and computes these siblings: [`LeafCalldata`] carries the arguments
+//! of both functions, ABI-encoded as contracts wrapping the library expect
+//! them after their own arguments.
+
+use alloy_primitives::U256;
+use alloy_sol_types::SolValue;
+use core::hash::Hash;
+
+use crate::{LeanIMT, LeanIMTStore};
+
+/// Arguments of `LeanIMT.update` and `LeanIMT.remove` for one leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafCalldata {
+    pub old_leaf: U256,
+    /// New value of the leaf, zero for a removal.
+    pub new_leaf: U256,
+    pub sibling_nodes: Vec<U256>,
+}
+
+impl LeafCalldata {
+    /// ABI-encodes `(oldLeaf, newLeaf, siblingNodes)`, the arguments of
+    /// `update`.
+    pub fn encode_update(&self) -> Vec<u8> {
+        (self.old_leaf, self.new_leaf, self.sibling_nodes.clone()).abi_encode_params()
+    }
+
+    /// ABI-encodes `(oldLeaf, siblingNodes)`, the arguments of `remove`.
+    pub fn encode_remove(&self) -> Vec<u8> {
+        (self.old_leaf, self.sibling_nodes.clone()).abi_encode_params()
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Returns the calldata updating `old_leaf` to `new_leaf` on-chain, with
+    /// nodes converted to `uint256` by `to_value`.
+    ///
+    /// The tree must be binary and hold the same leaves as the contract.
+    pub fn update_calldata(
+        &self,
+        old_leaf: &N,
+        new_leaf: &N,
+        to_value: fn(&N) -> U256,
+    ) -> Result<LeafCalldata, &'static str> {
+        if self.has(new_leaf) {
+            return Err("Leaf already exists");
+        }
+        let proof = self.generate_proof(self.index_of(old_leaf)?)?;
+        Ok(LeafCalldata {
+            old_leaf: to_value(old_leaf),
+            new_leaf: to_value(new_leaf),
+            sibling_nodes: proof.siblings.iter().map(to_value).collect(),
+        })
+    }
+
+    /// Returns the calldata removing `old_leaf` on-chain, which sets it to
+    /// zero.
+    pub fn remove_calldata(
+        &self,
+        old_leaf: &N,
+        to_value: fn(&N) -> U256,
+    ) -> Result<LeafCalldata, &'static str> {
+        let proof = self.generate_proof(self.index_of(old_leaf)?)?;
+        Ok(LeafCalldata {
+            old_leaf: to_value(old_leaf),
+            new_leaf: U256::ZERO,
+            sibling_nodes: proof.siblings.iter().map(to_value).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloy_sol_types::{sol, SolCall};
+
+    sol! {
+        function updateMember(uint256 oldLeaf, uint256 newLeaf, uint256[] siblingNodes);
+        function removeMember(uint256 oldLeaf, uint256[] siblingNodes);
+    }
+
+    fn sum_hash_function(nodes: Vec<U256>) -> U256 {
+        nodes
+            .into_iter()
+            .fold(U256::from(7), |acc, node| acc * U256::from(31) + node)
+    }
+
+    #[test]
+    fn test_leaf_calldata() {
+        let hash: IMTHashFunction<U256> = sum_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, U256::ZERO);
+        imt.insert_many((1..=5).map(U256::from).collect()).unwrap();
+
+        let calldata = imt
+            .update_calldata(&U256::from(3), &U256::from(9), |node| *node)
+            .unwrap();
+        let call = updateMemberCall {
+            oldLeaf: U256::from(3),
+            newLeaf: U256::from(9),
+            siblingNodes: calldata.sibling_nodes.clone(),
+        };
+        assert_eq!(calldata.encode_update(), call.abi_encode()[4..]);
+
+        // The siblings are those the contract hashes the new leaf with
+        let mut updated = LeanIMT::with_zero(hash, U256::ZERO);
+        updated
+            .insert_many([1, 2, 9, 4, 5].map(U256::from).to_vec())
+            .unwrap();
+        imt.update(&U256::from(3), U256::from(9), &calldata.sibling_nodes)
+            .unwrap();
+        assert_eq!(imt.root(), updated.root());
+
+        let calldata = imt.remove_calldata(&U256::from(5), |node| *node).unwrap();
+        assert_eq!(calldata.new_leaf, U256::ZERO);
+        let call = removeMemberCall {
+            oldLeaf: U256::from(5),
+            siblingNodes: calldata.sibling_nodes.clone(),
+        };
+        assert_eq!(calldata.encode_remove(), call.abi_encode()[4..]);
+
+        assert_eq!(
+            imt.update_calldata(&U256::from(1), &U256::from(2), |node| *node)
+                .unwrap_err(),
+            "Leaf already exists"
+        );
+        assert_eq!(
+            imt.remove_calldata(&U256::from(3), |node| *node)
+                .unwrap_err(),
+            "Leaf does not exist"
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Mirror of an on-chain LeanIMT rebuilt from contract logs.
+//!
+//! A contract maintaining a LeanIMT with the Solidity library emits one of the
+//! events below for each mutation, carrying the root it reached. [`LogSync`]
+//! decodes the logs of that contract block by block, applies them to a local
+//! tree and checks the local root against the root of every event, so proofs
+//! can be served off-chain for exactly the on-chain state.
+//!
+//! Contracts emitting other events (e.g. the Semaphore group events) can be
+//! mirrored by mapping their logs to [`TreeEvent`]s and calling `apply_event`.
+
+use alloy_primitives::{Address, Log, U256};
+use alloy_sol_types::{sol, SolEvent};
+use core::hash::Hash;
+
+use crate::{IMTNode, LeanIMT, LeanIMTStore, MemoryStore};
+
+sol! {
+    /// Emitted when a leaf is appended to the tree.
+    #[derive(Debug, PartialEq, Eq)]
+    event LeafInserted(uint256 index, uint256 leaf, uint256 root);
+
+    /// Emitted when a batch of leaves is appended from `startIndex`.
+    #[derive(Debug, PartialEq, Eq)]
+    event LeavesInserted(uint256 startIndex, uint256[] leaves, uint256 root);
+
+    /// Emitted when the leaf at `index` is updated.
+    #[derive(Debug, PartialEq, Eq)]
+    event LeafUpdated(uint256 index, uint256 oldLeaf, uint256 newLeaf, uint256 root);
+
+    /// Emitted when the leaf at `index` is removed.
+    #[derive(Debug, PartialEq, Eq)]
+    event LeafRemoved(uint256 index, uint256 leaf, uint256 root);
+}
+
+/// Tree mutation decoded from a contract log, with the root it reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeEvent {
+    Inserted {
+        index: U256,
+        leaf: U256,
+        root: U256,
+    },
+    InsertedMany {
+        start_index: U256,
+        leaves: Vec<U256>,
+        root: U256,
+    },
+    Updated {
+        index: U256,
+        old_leaf: U256,
+        new_leaf: U256,
+        root: U256,
+    },
+    Removed {
+        index: U256,
+        leaf: U256,
+        root: U256,
+    },
+}
+
+impl TreeEvent {
+    /// Decodes a log, returning `None` if it is not one of the tree events.
+    pub fn decode(log: &Log) -> Result<Option<Self>, &'static str> {
+        let Some(&topic) = log.data.topics().first() else {
+            return Ok(None);
+        };
+        let event = match topic {
+            LeafInserted::SIGNATURE_HASH => {
+                let event = decode::<LeafInserted>(log)?;
+                TreeEvent::Inserted {
+                    index: event.index,
+                    leaf: event.leaf,
+                    root: event.root,
+                }
+            }
+            LeavesInserted::SIGNATURE_HASH => {
+                let event = decode::<LeavesInserted>(log)?;
+                TreeEvent::InsertedMany {
+                    start_index: event.startIndex,
+                    leaves: event.leaves,
+                    root: event.root,
+                }
+            }
+            LeafUpdated::SIGNATURE_HASH => {
+                let event = decode::<LeafUpdated>(log)?;
+                TreeEvent::Updated {
+                    index: event.index,
+                    old_leaf: event.oldLeaf,
+                    new_leaf: event.newLeaf,
+                    root: event.root,
+                }
+            }
+            LeafRemoved::SIGNATURE_HASH => {
+                let event = decode::<LeafRemoved>(log)?;
+                TreeEvent::Removed {
+                    index: event.index,
+                    leaf: event.leaf,
+                    root: event.root,
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+fn decode<E: SolEvent>(log: &Log) -> Result<E, &'static str> {
+    E::decode_log_data(&log.data).map_err(|_| "Invalid log")
+}
+
+/// Local tree following the logs of a contract.
+///
+/// If a mutation fails or reaches another root than the contract, the error
+/// is returned and the tree is left at that event: the mirror has diverged
+/// (missed logs, different hash function) and must be rebuilt.
+#[derive(Debug)]
+pub struct LogSync<N = IMTNode, S = MemoryStore<N>> {
+    tree: LeanIMT<N, S>,
+    address: Address,
+    to_node: fn(U256) -> N,
+    last_block: Option<u64>,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LogSync<N, S> {
+    /// Mirrors the contract at `address` into `tree`, which must hold the
+    /// state of the contract before the first block applied. Values from the
+    /// logs are converted to nodes with `to_node`.
+    pub fn new(tree: LeanIMT<N, S>, address: Address, to_node: fn(U256) -> N) -> Self {
+        LogSync {
+            tree,
+            address,
+            to_node,
+            last_block: None,
+        }
+    }
+
+    /// Applies the logs of block `number`, in log order, and returns the
+    /// root of the tree.
+    ///
+    /// Logs emitted by other contracts, and logs of other events, are
+    /// skipped. Blocks must be applied in increasing order.
+    pub fn apply_block<'a>(
+        &mut self,
+        number: u64,
+        logs: impl IntoIterator<Item = &'a Log>,
+    ) -> Result<Option<N>, &'static str> {
+        if self.last_block.is_some_and(|last| number <= last) {
+            return Err("Block already applied");
+        }
+        for log in logs {
+            if log.address != self.address {
+                continue;
+            }
+            if let Some(event) = TreeEvent::decode(log)? {
+                self.apply_event(event)?;
+            }
+        }
+        self.last_block = Some(number);
+        Ok(self.tree.root())
+    }
+
+    /// Applies a decoded event and checks the root it reached.
+    pub fn apply_event(&mut self, event: TreeEvent) -> Result<(), &'static str> {
+        let root = match event {
+            TreeEvent::Inserted { index, leaf, root } => {
+                self.expect_size(index)?;
+                self.tree.insert((self.to_node)(leaf))?;
+                root
+            }
+            TreeEvent::InsertedMany {
+                start_index,
+                leaves,
+                root,
+            } => {
+                self.expect_size(start_index)?;
+                self.tree
+                    .insert_many(leaves.into_iter().map(self.to_node).collect())?;
+                root
+            }
+            TreeEvent::Updated {
+                index,
+                old_leaf,
+                new_leaf,
+                root,
+            } => {
+                let index = to_index(index)?;
+                let siblings = self.siblings(index)?;
+                self.tree.update_at(
+                    index,
+                    &(self.to_node)(old_leaf),
+                    (self.to_node)(new_leaf),
+                    &siblings,
+                )?;
+                root
+            }
+            TreeEvent::Removed { index, leaf, root } => {
+                let index = to_index(index)?;
+                let siblings = self.siblings(index)?;
+                self.tree
+                    .remove_at(index, &(self.to_node)(leaf), &siblings)?;
+                root
+            }
+        };
+
+        if self.tree.root() != Some((self.to_node)(root)) {
+            return Err("Root mismatch");
+        }
+        Ok(())
+    }
+
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> LeanIMT<N, S> {
+        self.tree
+    }
+
+    /// Returns the number of the last block applied.
+    pub fn last_block(&self) -> Option<u64> {
+        self.last_block
+    }
+
+    fn expect_size(&self, index: U256) -> Result<(), &'static str> {
+        if to_index(index)? != self.tree.get_size() {
+            return Err("Unexpected leaf index");
+        }
+        Ok(())
+    }
+
+    fn siblings(&self, index: usize) -> Result<Vec<N>, &'static str> {
+        Ok(self.tree.generate_nary_proof(index)?.siblings.concat())
+    }
+}
+
+fn to_index(index: U256) -> Result<usize, &'static str> {
+    usize::try_from(index).map_err(|_| "Invalid leaf index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+
+    fn sum_hash_function(nodes: Vec<String>) -> String {
+        nodes
+            .iter()
+            .fold(U256::from(7), |acc, node| {
+                acc * U256::from(31) + node.parse::<U256>().unwrap()
+            })
+            .to_string()
+    }
+
+    fn to_node(value: U256) -> String {
+        value.to_string()
+    }
+
+    fn root_of(tree: &LeanIMT) -> U256 {
+        tree.root().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn test_mirror_contract_logs() {
+        let hash: IMTHashFunction = sum_hash_function;
+        let address = Address::repeat_byte(0x11);
+        let log = |data| Log { address, data };
+
+        // Simulates the contract, emitting the log of each mutation
+        let mut chain = LeanIMT::new(hash);
+        chain.insert("5".to_string()).unwrap();
+        let block1 = vec![
+            log(LeafInserted {
+                index: U256::ZERO,
+                leaf: U256::from(5),
+                root: root_of(&chain),
+            }
+            .encode_log_data()),
+            Log {
+                address: Address::repeat_byte(0x22),
+                data: LeafInserted {
+                    index: U256::ZERO,
+                    leaf: U256::from(9),
+                    root: U256::ZERO,
+                }
+                .encode_log_data(),
+            },
+        ];
+        chain
+            .insert_many(vec!["6".to_string(), "7".to_string()])
+            .unwrap();
+        let mut block2 = vec![log(LeavesInserted {
+            startIndex: U256::from(1),
+            leaves: vec![U256::from(6), U256::from(7)],
+            root: root_of(&chain),
+        }
+        .encode_log_data())];
+        let siblings = chain.generate_proof(1).unwrap().siblings;
+        chain
+            .update(&"6".to_string(), "8".to_string(), &siblings)
+            .unwrap();
+        block2.push(log(LeafUpdated {
+            index: U256::from(1),
+            oldLeaf: U256::from(6),
+            newLeaf: U256::from(8),
+            root: root_of(&chain),
+        }
+        .encode_log_data()));
+        let siblings = chain.generate_proof(0).unwrap().siblings;
+        chain.remove(&"5".to_string(), &siblings).unwrap();
+        block2.push(log(LeafRemoved {
+            index: U256::ZERO,
+            leaf: U256::from(5),
+            root: root_of(&chain),
+        }
+        .encode_log_data()));
+
+        let mut sync = LogSync::new(LeanIMT::new(hash), address, to_node);
+        sync.apply_block(10, &block1).unwrap();
+        assert_eq!(sync.tree().get_size(), 1);
+        assert_eq!(sync.apply_block(12, &block2).unwrap(), chain.root());
+        assert_eq!(sync.last_block(), Some(12));
+        assert_eq!(
+            sync.apply_block(12, &block2).unwrap_err(),
+            "Block already applied"
+        );
+
+        let tree = sync.into_tree();
+        assert_eq!(tree.get_size(), 3);
+        assert!(tree.has(&"8".to_string()));
+    }
+
+    #[test]
+    fn test_divergence_is_detected() {
+        let hash: IMTHashFunction = sum_hash_function;
+        let mut sync = LogSync::new(LeanIMT::new(hash), Address::ZERO, to_node);
+
+        let event = TreeEvent::Inserted {
+            index: U256::from(1),
+            leaf: U256::from(5),
+            root: U256::from(5),
+        };
+        assert_eq!(
+            sync.apply_event(event).unwrap_err(),
+            "Unexpected leaf index"
+        );
+
+        let event = TreeEvent::Inserted {
+            index: U256::ZERO,
+            leaf: U256::from(5),
+            root: U256::from(6),
+        };
+        assert_eq!(sync.apply_event(event).unwrap_err(), "Root mismatch");
+
+        let log = Log {
+            address: Address::ZERO,
+            data: alloy_primitives::LogData::new_unchecked(
+                vec![LeafInserted::SIGNATURE_HASH],
+                vec![1, 2, 3].into(),
+            ),
+        };
+        assert_eq!(TreeEvent::decode(&log).unwrap_err(), "Invalid log");
+    }
+}
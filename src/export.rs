@@ -0,0 +1,178 @@
+//! Export of the leaves and state of a tree to files.
+//!
+//! Trees distributed to partners are rebuilt on their side from the leaves,
+//! and the root they reach compared with the root of the original tree.
+//! `export_to` writes a header with the size, depth and root of the tree,
+//! followed by every leaf in index order, removed leaves as the zero value,
+//! in one of the [`ExportFormat`]s. Nodes are written with a [`NodeCodec`],
+//! as text in CSV and JSONL files and as bytes in binary files, so equal
+//! trees always produce equal files.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use core::hash::Hash;
+use serde_json::json;
+
+use crate::codec::{write_bytes, write_varint};
+use crate::{LeanIMT, LeanIMTStore, NodeCodec};
+
+/// Version of the binary export format, stored in its first byte.
+pub const EXPORT_FORMAT_VERSION: u8 = 1;
+
+/// Layout of an exported tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A `size,depth,root` table of one row, then an `index,leaf` table.
+    Csv,
+    /// A `{"size", "depth", "root"}` object on the first line, then one
+    /// `{"index", "leaf"}` object per line. The root of an empty tree is
+    /// `null`.
+    Jsonl,
+    /// The format version byte, the size and depth as LEB128 varints, a
+    /// byte set to 1 if the tree has a root followed by the root, then the
+    /// leaves. Nodes are length-prefixed bytes.
+    Binary,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Writes the state and leaves of the tree to the file at `path`,
+    /// replacing it, with nodes encoded by `C`.
+    pub fn export_to<C: NodeCodec<N>>(
+        &self,
+        path: impl AsRef<Path>,
+        format: ExportFormat,
+    ) -> Result<(), &'static str> {
+        let file = File::create(path).map_err(|_| "Failed to create file")?;
+        let mut writer = BufWriter::new(file);
+        self.export_leaves::<C>(&mut writer, format)?;
+        writer.flush().map_err(|_| "Failed to write leaves")
+    }
+
+    /// Writes the state and leaves of the tree to `writer`, with nodes
+    /// encoded by `C`.
+    pub fn export_leaves<C: NodeCodec<N>>(
+        &self,
+        writer: impl Write,
+        format: ExportFormat,
+    ) -> Result<(), &'static str> {
+        let root = self.root();
+        let leaves = (0..self.size).map(|index| self.leaf_at(index));
+
+        match format {
+            ExportFormat::Csv => {
+                let mut rows = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+                let root = root.as_ref().map(C::to_text).unwrap_or_default();
+                rows.write_record(["size", "depth", "root"])
+                    .and_then(|_| {
+                        rows.write_record([&self.size.to_string(), &self.depth.to_string(), &root])
+                    })
+                    .and_then(|_| rows.write_record(["index", "leaf"]))
+                    .map_err(|_| "Failed to write leaves")?;
+                for (index, leaf) in leaves.enumerate() {
+                    rows.write_record([&index.to_string(), &C::to_text(&leaf?)])
+                        .map_err(|_| "Failed to write leaves")?;
+                }
+                rows.flush().map_err(|_| "Failed to write leaves")
+            }
+            ExportFormat::Jsonl => {
+                let mut writer = writer;
+                let header = json!({
+                    "size": self.size,
+                    "depth": self.depth,
+                    "root": root.as_ref().map(C::to_text),
+                });
+                writeln!(writer, "{}", header).map_err(|_| "Failed to write leaves")?;
+                for (index, leaf) in leaves.enumerate() {
+                    let line = json!({ "index": index, "leaf": C::to_text(&leaf?) });
+                    writeln!(writer, "{}", line).map_err(|_| "Failed to write leaves")?;
+                }
+                Ok(())
+            }
+            ExportFormat::Binary => {
+                let mut writer = writer;
+                let mut bytes = vec![EXPORT_FORMAT_VERSION];
+                write_varint(&mut bytes, self.size as u64);
+                write_varint(&mut bytes, self.depth as u64);
+                match &root {
+                    Some(root) => {
+                        bytes.push(1);
+                        write_bytes(&mut bytes, &C::encode(root));
+                    }
+                    None => bytes.push(0),
+                }
+                for leaf in leaves {
+                    write_bytes(&mut bytes, &C::encode(&leaf?));
+                    writer
+                        .write_all(&bytes)
+                        .map_err(|_| "Failed to write leaves")?;
+                    bytes.clear();
+                }
+                writer
+                    .write_all(&bytes)
+                    .map_err(|_| "Failed to write leaves")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::IMTHashFunction;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn export(imt: &LeanIMT, format: ExportFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        imt.export_leaves::<StringCodec>(&mut bytes, format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_export_leaves() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["a".to_string(), "b,c".to_string(), "d".to_string()])
+            .unwrap();
+        let proof = imt.generate_proof(2).unwrap();
+        imt.remove(&"d".to_string(), &proof.siblings).unwrap();
+
+        assert_eq!(
+            String::from_utf8(export(&imt, ExportFormat::Csv)).unwrap(),
+            "size,depth,root\n3,2,\"a,b,c,0\"\nindex,leaf\n0,a\n1,\"b,c\"\n2,0\n"
+        );
+        assert_eq!(
+            String::from_utf8(export(&imt, ExportFormat::Jsonl)).unwrap(),
+            "{\"depth\":2,\"root\":\"a,b,c,0\",\"size\":3}\n\
+             {\"index\":0,\"leaf\":\"a\"}\n\
+             {\"index\":1,\"leaf\":\"b,c\"}\n\
+             {\"index\":2,\"leaf\":\"0\"}\n"
+        );
+        assert_eq!(
+            export(&imt, ExportFormat::Binary),
+            b"\x01\x03\x02\x01\x07a,b,c,0\x01a\x03b,c\x010"
+        );
+
+        let empty = LeanIMT::new(hash);
+        assert_eq!(
+            String::from_utf8(export(&empty, ExportFormat::Jsonl)).unwrap(),
+            "{\"depth\":0,\"root\":null,\"size\":0}\n"
+        );
+        assert_eq!(export(&empty, ExportFormat::Binary), b"\x01\x00\x00\x00");
+
+        let path = std::env::temp_dir().join(format!("lean-imt-{}.jsonl", std::process::id()));
+        imt.export_to::<StringCodec>(&path, ExportFormat::Jsonl)
+            .unwrap();
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            export(&imt, ExportFormat::Jsonl)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,519 @@
+//! C bindings over a stable ABI.
+//!
+//! Nodes are 32-byte values, with the all-zero node as the zero value. The
+//! hash function is a C callback receiving `count` contiguous nodes and
+//! writing the parent node to `out`. Each tree hashes with a shared hash
+//! function calling its callback, which can be used from any thread.
+//!
+//! Functions returning a [`LeanImtStatus`] other than `Ok` record a message
+//! readable with `lean_imt_last_error`. The header `include/lean_imt.h` is
+//! generated from this module with `cbindgen --config cbindgen.toml
+//! src/ffi.rs`, and a test checks that it is up to date.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+use crate::{LeafIndex, LeanIMT, MemoryStore, MerkleProof};
+
+/// Size in bytes of a node.
+pub const LEAN_IMT_NODE_SIZE: usize = 32;
+
+type Node = [u8; LEAN_IMT_NODE_SIZE];
+
+/// Hash callback: hashes the `count` nodes at `nodes` into `out`.
+pub type LeanImtHashFn = extern "C" fn(nodes: *const u8, count: usize, out: *mut u8);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Hashes `nodes` with the callback `hash`.
+fn c_hash(hash: LeanImtHashFn, nodes: Vec<Node>) -> Node {
+    let mut out = [0; LEAN_IMT_NODE_SIZE];
+    hash(nodes.as_ptr().cast(), nodes.len(), out.as_mut_ptr());
+    out
+}
+
+/// Result of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeanImtStatus {
+    Ok = 0,
+    NullPointer = 1,
+    Error = 2,
+    /// The insert would exceed the maximum size of the tree.
+    TreeFull = 3,
+}
+
+fn fail(message: &str) -> LeanImtStatus {
+    let status = match message {
+        "Tree is full" => LeanImtStatus::TreeFull,
+        _ => LeanImtStatus::Error,
+    };
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|error| *error.borrow_mut() = Some(message));
+    status
+}
+
+fn status(result: Result<(), &'static str>) -> LeanImtStatus {
+    match result {
+        Ok(()) => LeanImtStatus::Ok,
+        Err(message) => fail(message),
+    }
+}
+
+/// Opaque tree handle.
+pub struct LeanImt {
+    tree: LeanIMT<Node>,
+}
+
+/// Inclusion proof, whose `siblings` hold `siblings_len` contiguous nodes
+/// owned by the library and released with `lean_imt_proof_free`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct LeanImtProof {
+    pub root: [u8; LEAN_IMT_NODE_SIZE],
+    pub leaf: [u8; LEAN_IMT_NODE_SIZE],
+    pub index: u64,
+    pub siblings: *mut u8,
+    pub siblings_len: usize,
+}
+
+impl LeanImtProof {
+    /// Copies the proof into a `MerkleProof`, failing if its index does not
+    /// fit a `usize`.
+    ///
+    /// # Safety
+    ///
+    /// `siblings` must point to `siblings_len` nodes, or be null if it is zero.
+    unsafe fn to_proof(&self) -> Result<MerkleProof<Node>, &'static str> {
+        let siblings = if self.siblings_len == 0 {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(self.siblings.cast::<Node>(), self.siblings_len).to_vec()
+        };
+        Ok(MerkleProof {
+            root: self.root,
+            leaf: self.leaf,
+            index: LeafIndex(self.index).to_usize()?,
+            siblings,
+        })
+    }
+}
+
+/// Creates an empty tree hashing with `hash`. Release it with `lean_imt_free`.
+///
+/// Returns null, with a message for `lean_imt_last_error`, if the tree
+/// cannot be created.
+#[no_mangle]
+pub extern "C" fn lean_imt_new(hash: LeanImtHashFn) -> *mut LeanImt {
+    let hash = Arc::new(move |nodes| c_hash(hash, nodes));
+    match LeanIMT::with_shared_hash(hash, [0; LEAN_IMT_NODE_SIZE], MemoryStore::default()) {
+        Ok(tree) => Box::into_raw(Box::new(LeanImt { tree })),
+        Err(message) => {
+            fail(message);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Releases a tree.
+///
+/// # Safety
+///
+/// `tree` must be null or a tree returned by `lean_imt_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_free(tree: *mut LeanImt) {
+    if !tree.is_null() {
+        drop(Box::from_raw(tree));
+    }
+}
+
+/// Limits the tree to the `2^max_depth` leaves of a tree of depth
+/// `max_depth`; further inserts fail with `TreeFull`.
+///
+/// # Safety
+///
+/// `tree` must be a live tree.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_set_max_depth(
+    tree: *mut LeanImt,
+    max_depth: usize,
+) -> LeanImtStatus {
+    let Some(imt) = tree.as_mut() else {
+        return LeanImtStatus::NullPointer;
+    };
+    status(imt.tree.set_max_depth(max_depth))
+}
+
+/// Inserts the leaf at `leaf`.
+///
+/// # Safety
+///
+/// `tree` must be a live tree and `leaf` must point to a node.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_insert(tree: *mut LeanImt, leaf: *const u8) -> LeanImtStatus {
+    let (Some(imt), false) = (tree.as_mut(), leaf.is_null()) else {
+        return LeanImtStatus::NullPointer;
+    };
+    let leaf = *leaf.cast::<Node>();
+    status(imt.tree.insert(leaf).map(drop))
+}
+
+/// Inserts the `count` contiguous leaves at `leaves`.
+///
+/// # Safety
+///
+/// `tree` must be a live tree and `leaves` must point to `count` nodes.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_insert_many(
+    tree: *mut LeanImt,
+    leaves: *const u8,
+    count: usize,
+) -> LeanImtStatus {
+    let (Some(imt), false) = (tree.as_mut(), leaves.is_null()) else {
+        return LeanImtStatus::NullPointer;
+    };
+    let leaves = slice::from_raw_parts(leaves.cast::<Node>(), count).to_vec();
+    status(imt.tree.insert_many(leaves).map(drop))
+}
+
+/// Writes the root of the tree to `out`, failing if the tree is empty.
+///
+/// # Safety
+///
+/// `tree` must be a live tree and `out` must point to a writable node.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_root(tree: *const LeanImt, out: *mut u8) -> LeanImtStatus {
+    let (Some(imt), false) = (tree.as_ref(), out.is_null()) else {
+        return LeanImtStatus::NullPointer;
+    };
+    match imt.tree.root() {
+        Some(root) => {
+            *out.cast::<Node>() = root;
+            LeanImtStatus::Ok
+        }
+        None => fail("Tree is empty"),
+    }
+}
+
+/// Returns the number of leaves of the tree, or 0 if `tree` is null.
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_size(tree: *const LeanImt) -> u64 {
+    tree.as_ref()
+        .map_or(0, |imt| LeafIndex::from(imt.tree.get_size()).get())
+}
+
+/// Writes the proof of the leaf at `index` to `out`.
+///
+/// # Safety
+///
+/// `tree` must be a live tree and `out` must point to a writable proof,
+/// whose previous siblings are not released.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_generate_proof(
+    tree: *const LeanImt,
+    index: u64,
+    out: *mut LeanImtProof,
+) -> LeanImtStatus {
+    let (Some(imt), false) = (tree.as_ref(), out.is_null()) else {
+        return LeanImtStatus::NullPointer;
+    };
+    let proof = LeafIndex(index)
+        .to_usize()
+        .and_then(|index| imt.tree.generate_proof(index));
+    let proof = match proof {
+        Ok(proof) => proof,
+        Err(message) => return fail(message),
+    };
+
+    let siblings_len = proof.siblings.len();
+    let siblings: Box<[u8]> = proof.siblings.concat().into_boxed_slice();
+    out.write(LeanImtProof {
+        root: proof.root,
+        leaf: proof.leaf,
+        index: LeafIndex::from(proof.index).get(),
+        siblings: Box::into_raw(siblings).cast(),
+        siblings_len,
+    });
+    LeanImtStatus::Ok
+}
+
+/// Releases the siblings of a proof filled by `lean_imt_generate_proof`.
+///
+/// # Safety
+///
+/// `proof` must be null or a proof filled by `lean_imt_generate_proof`
+/// whose siblings were not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_proof_free(proof: *mut LeanImtProof) {
+    let Some(proof) = proof.as_mut() else {
+        return;
+    };
+    if !proof.siblings.is_null() {
+        let len = proof.siblings_len * LEAN_IMT_NODE_SIZE;
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            proof.siblings,
+            len,
+        )));
+    }
+    proof.siblings = ptr::null_mut();
+    proof.siblings_len = 0;
+}
+
+/// Checks that a proof hashes up to its root with `hash`.
+///
+/// # Safety
+///
+/// `proof` must be null or point to a proof whose siblings are valid.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_proof_verify(
+    proof: *const LeanImtProof,
+    hash: LeanImtHashFn,
+) -> bool {
+    let Some(proof) = proof.as_ref() else {
+        return false;
+    };
+    let Ok(proof) = proof.to_proof() else {
+        return false;
+    };
+    proof.verify(|nodes| c_hash(hash, nodes))
+}
+
+/// Checks that a proof is valid against the current root of the tree.
+///
+/// # Safety
+///
+/// `tree` must be null or a live tree, and `proof` null or a proof whose
+/// siblings are valid.
+#[no_mangle]
+pub unsafe extern "C" fn lean_imt_verify_proof(
+    tree: *const LeanImt,
+    proof: *const LeanImtProof,
+) -> bool {
+    let (Some(imt), Some(proof)) = (tree.as_ref(), proof.as_ref()) else {
+        return false;
+    };
+    let Ok(proof) = proof.to_proof() else {
+        return false;
+    };
+    imt.tree.verify_proof(&proof)
+}
+
+/// Returns the message of the last error on this thread, or null. The
+/// string stays valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn lean_imt_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| {
+        error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    /// Sums the nodes byte by byte, adding the number of nodes.
+    extern "C" fn sum_hash(nodes: *const u8, count: usize, out: *mut u8) {
+        let nodes = unsafe { slice::from_raw_parts(nodes, count * LEAN_IMT_NODE_SIZE) };
+        let out = unsafe { slice::from_raw_parts_mut(out, LEAN_IMT_NODE_SIZE) };
+        out.fill(count as u8);
+        for node in nodes.chunks(LEAN_IMT_NODE_SIZE) {
+            for (byte, value) in out.iter_mut().zip(node) {
+                *byte = byte.wrapping_add(*value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ffi_tree() {
+        unsafe {
+            let tree = lean_imt_new(sum_hash);
+            let leaves: Vec<u8> = (1..=3).flat_map(|i| [i; LEAN_IMT_NODE_SIZE]).collect();
+            assert_eq!(
+                lean_imt_insert_many(tree, leaves.as_ptr(), 3),
+                LeanImtStatus::Ok
+            );
+            assert_eq!(
+                lean_imt_insert(tree, [4; LEAN_IMT_NODE_SIZE].as_ptr()),
+                LeanImtStatus::Ok
+            );
+            assert_eq!(lean_imt_size(tree), 4);
+
+            let mut root = [0; LEAN_IMT_NODE_SIZE];
+            assert_eq!(lean_imt_root(tree, root.as_mut_ptr()), LeanImtStatus::Ok);
+            // (2 + 1 + 2) + (2 + 3 + 4) + 2
+            assert_eq!(root, [16; LEAN_IMT_NODE_SIZE]);
+
+            let mut proof = std::mem::MaybeUninit::<LeanImtProof>::uninit();
+            assert_eq!(
+                lean_imt_generate_proof(tree, 2, proof.as_mut_ptr()),
+                LeanImtStatus::Ok
+            );
+            let mut proof = proof.assume_init();
+            assert_eq!(proof.siblings_len, 2);
+            assert!(lean_imt_proof_verify(&proof, sum_hash));
+            assert!(lean_imt_verify_proof(tree, &proof));
+
+            proof.leaf = [5; LEAN_IMT_NODE_SIZE];
+            assert!(!lean_imt_proof_verify(&proof, sum_hash));
+            lean_imt_proof_free(&mut proof);
+            assert!(proof.siblings.is_null());
+
+            lean_imt_free(tree);
+        }
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        unsafe {
+            let tree = lean_imt_new(sum_hash);
+            let mut root = [0; LEAN_IMT_NODE_SIZE];
+            assert_eq!(lean_imt_root(tree, root.as_mut_ptr()), LeanImtStatus::Error);
+            assert_eq!(
+                CStr::from_ptr(lean_imt_last_error()).to_str(),
+                Ok("Tree is empty")
+            );
+
+            assert_eq!(
+                lean_imt_insert(tree, [0; LEAN_IMT_NODE_SIZE].as_ptr()),
+                LeanImtStatus::Error
+            );
+            assert_eq!(
+                CStr::from_ptr(lean_imt_last_error()).to_str(),
+                Ok("Leaf cannot be zero")
+            );
+            assert_eq!(
+                lean_imt_insert(ptr::null_mut(), root.as_ptr()),
+                LeanImtStatus::NullPointer
+            );
+            assert_eq!(lean_imt_size(ptr::null()), 0);
+
+            // Indices are u64 whatever the width of usize
+            let mut proof = std::mem::MaybeUninit::<LeanImtProof>::uninit();
+            assert_eq!(
+                lean_imt_generate_proof(tree, u64::MAX, proof.as_mut_ptr()),
+                LeanImtStatus::Error
+            );
+            assert_eq!(
+                CStr::from_ptr(lean_imt_last_error()).to_str(),
+                Ok("Leaf index out of range")
+            );
+            lean_imt_free(tree);
+        }
+    }
+
+    #[test]
+    fn test_ffi_full_tree() {
+        unsafe {
+            let tree = lean_imt_new(sum_hash);
+            assert_eq!(lean_imt_set_max_depth(tree, 1), LeanImtStatus::Ok);
+            let leaves: Vec<u8> = (1..=2).flat_map(|i| [i; LEAN_IMT_NODE_SIZE]).collect();
+            assert_eq!(
+                lean_imt_insert_many(tree, leaves.as_ptr(), 2),
+                LeanImtStatus::Ok
+            );
+            assert_eq!(
+                lean_imt_insert(tree, [3; LEAN_IMT_NODE_SIZE].as_ptr()),
+                LeanImtStatus::TreeFull
+            );
+            assert_eq!(
+                CStr::from_ptr(lean_imt_last_error()).to_str(),
+                Ok("Tree is full")
+            );
+            assert_eq!(lean_imt_size(tree), 2);
+            assert_eq!(
+                lean_imt_set_max_depth(ptr::null_mut(), 1),
+                LeanImtStatus::NullPointer
+            );
+            lean_imt_free(tree);
+        }
+    }
+
+    #[test]
+    fn test_ffi_null_pointers() {
+        unsafe {
+            let tree = lean_imt_new(sum_hash);
+            assert_eq!(
+                lean_imt_insert_many(tree, ptr::null(), 1),
+                LeanImtStatus::NullPointer
+            );
+            assert_eq!(
+                lean_imt_root(tree, ptr::null_mut()),
+                LeanImtStatus::NullPointer
+            );
+            assert_eq!(
+                lean_imt_generate_proof(tree, 0, ptr::null_mut()),
+                LeanImtStatus::NullPointer
+            );
+            assert!(!lean_imt_proof_verify(ptr::null(), sum_hash));
+            assert!(!lean_imt_verify_proof(tree, ptr::null()));
+            lean_imt_proof_free(ptr::null_mut());
+            lean_imt_free(tree);
+            lean_imt_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_ffi_trees_across_threads() {
+        /// Hashes like `sum_hash`, plus one.
+        extern "C" fn other_hash(nodes: *const u8, count: usize, out: *mut u8) {
+            sum_hash(nodes, count, out);
+            let out = unsafe { slice::from_raw_parts_mut(out, LEAN_IMT_NODE_SIZE) };
+            out.iter_mut().for_each(|byte| *byte += 1);
+        }
+
+        // Each tree keeps its own callback, on whichever thread it is used
+        let trees = [lean_imt_new(sum_hash), lean_imt_new(other_hash)].map(|tree| tree as usize);
+        let roots: Vec<_> = trees
+            .iter()
+            .map(|&tree| {
+                std::thread::spawn(move || unsafe {
+                    let tree = tree as *mut LeanImt;
+                    let leaves: Vec<u8> = (1..=2).flat_map(|i| [i; LEAN_IMT_NODE_SIZE]).collect();
+                    assert_eq!(
+                        lean_imt_insert_many(tree, leaves.as_ptr(), 2),
+                        LeanImtStatus::Ok
+                    );
+                    let mut root = [0; LEAN_IMT_NODE_SIZE];
+                    assert_eq!(lean_imt_root(tree, root.as_mut_ptr()), LeanImtStatus::Ok);
+                    root
+                })
+            })
+            .map(|thread| thread.join().unwrap())
+            .collect();
+        assert_eq!(roots, [[5; LEAN_IMT_NODE_SIZE], [6; LEAN_IMT_NODE_SIZE]]);
+
+        for tree in trees {
+            unsafe { lean_imt_free(tree as *mut LeanImt) };
+        }
+    }
+
+    #[test]
+    fn test_header_is_up_to_date() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{dir}/cbindgen.toml")).unwrap();
+        let bindings = cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{dir}/src/ffi.rs"))
+            .generate()
+            .unwrap();
+        let mut header = Vec::new();
+        bindings.write(&mut header);
+        assert!(
+            header == include_bytes!("../include/lean_imt.h"),
+            "include/lean_imt.h is out of date, regenerate it with cbindgen"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Deterministic fingerprint of the state of a tree.
+//!
+//! Replicas comparing roots and sizes trust their side nodes: a tree restored
+//! from a snapshot keeps the root it was given, even if its leaves differ.
+//! `state_fingerprint` digests the leaves as well, by index, so it does not
+//! depend on the order in which the store iterates over its maps, and two
+//! replicas hold identical trees if (barring collisions) their fingerprints
+//! match.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use ::digest::{Digest, Output};
+
+use crate::codec::{write_bytes, write_varint};
+use crate::{LeanIMT, LeanIMTStore, NodeCodec};
+
+/// Tag digested first, identifying the fingerprint format.
+const FINGERPRINT_TAG: &[u8] = b"lean-imt/state/v1";
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Digests the state of the tree with `D`, nodes being encoded by `C`.
+    ///
+    /// The digest covers, in order, a format tag, the size, the depth, the
+    /// arity, the zero value, the root and the leaves by index, each preceded
+    /// by a byte telling whether it is there (removed leaves and the root of
+    /// an empty tree are not). Integers are LEB128 varints and nodes
+    /// length-prefixed bytes.
+    pub fn state_fingerprint<C: NodeCodec<N>, D: Digest>(&self) -> Result<Output<D>, &'static str> {
+        let mut bytes = Vec::new();
+        write_bytes(&mut bytes, FINGERPRINT_TAG);
+        write_varint(&mut bytes, self.size as u64);
+        write_varint(&mut bytes, self.depth as u64);
+        write_varint(&mut bytes, self.arity as u64);
+        write_bytes(&mut bytes, &C::encode(&self.zero));
+        match self.root() {
+            Some(root) => {
+                bytes.push(1);
+                write_bytes(&mut bytes, &C::encode(&root));
+            }
+            None => bytes.push(0),
+        }
+
+        let mut digest = D::new();
+        digest.update(&bytes);
+        for index in 0..self.size {
+            bytes.clear();
+            match self.store.leaf_at(index)? {
+                Some(leaf) => {
+                    bytes.push(1);
+                    write_bytes(&mut bytes, &C::encode(&leaf));
+                }
+                None => bytes.push(0),
+            }
+            digest.update(&bytes);
+        }
+        Ok(digest.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::StringCodec;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use sha2::Sha256;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_state_fingerprint() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["leaf1".into(), "leaf2".into(), "leaf3".into()])
+            .unwrap();
+        let mut replica = LeanIMT::new(hash);
+        for leaf in ["leaf1", "leaf2", "leaf3"] {
+            replica.insert(leaf.to_string()).unwrap();
+        }
+        let fingerprint = imt.state_fingerprint::<StringCodec, Sha256>().unwrap();
+        assert_eq!(
+            replica.state_fingerprint::<StringCodec, Sha256>(),
+            Ok(fingerprint)
+        );
+
+        // A replica restored from a snapshot with a wrong leaf keeps the root
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.leaves[0].0 = "forged".to_string();
+        let forged = LeanIMT::from_snapshot(snapshot, hash).unwrap();
+        assert_eq!(forged.root(), imt.root());
+        assert_ne!(
+            forged.state_fingerprint::<StringCodec, Sha256>(),
+            Ok(fingerprint)
+        );
+
+        let siblings = imt.generate_proof(2).unwrap().siblings;
+        imt.remove(&"leaf3".to_string(), &siblings).unwrap();
+        assert_ne!(
+            imt.state_fingerprint::<StringCodec, Sha256>(),
+            Ok(fingerprint)
+        );
+    }
+}
@@ -0,0 +1,520 @@
+//! Binary trees padded with zeros to a fixed depth.
+//!
+//! Classic incremental Merkle trees, such as those of Tornado Cash or the
+//! Semaphore v3 contracts, have a fixed depth: empty leaves are zero, and an
+//! empty subtree at level `l` hashes to `zeros[l]`, where `zeros[0]` is the
+//! zero value and `zeros[l + 1] = hash([zeros[l], zeros[l]])`.
+//! [`FixedDepthLeanIMT`] keeps a lean tree but returns the roots and proofs
+//! of the padded tree. The padded root is recomputed from the side nodes and
+//! the last leaf with `depth` hashes, so appends keep their lean cost.
+//!
+//! [`FixedLeanIMT`] takes the depth as a const parameter instead, for
+//! circuits compiled for one depth: its proofs hold an array of exactly
+//! `DEPTH` siblings, and a depth that cannot be padded to fails to compile.
+
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::{
+    max_size_of, IMTHashFunction, IMTNode, LeanIMT, LeanIMTStore, MemoryStore, MerkleProof,
+};
+
+/// Lean tree exposing the roots and proofs of a tree padded to `depth`.
+///
+/// The tree is binary and holds at most `2^depth` leaves.
+#[derive(Debug)]
+pub struct FixedDepthLeanIMT<N = IMTNode, S = MemoryStore<N>> {
+    tree: LeanIMT<N, S>,
+    /// Roots of the empty subtrees of each level, up to `depth`.
+    zeros: Vec<N>,
+}
+
+impl FixedDepthLeanIMT {
+    /// Creates a tree of string nodes of depth `depth`, using `"0"` as the
+    /// zero value.
+    pub fn new(hash: IMTHashFunction, depth: usize) -> Result<Self, &'static str> {
+        Self::from_tree(LeanIMT::new(hash), depth)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> FixedDepthLeanIMT<N, S> {
+    /// Pads `tree` to `depth`, limiting it to `2^depth` leaves.
+    ///
+    /// Fails if the tree is not binary or holds more leaves.
+    pub fn from_tree(mut tree: LeanIMT<N, S>, depth: usize) -> Result<Self, &'static str> {
+        tree.ensure_binary()?;
+        if depth >= usize::BITS as usize {
+            return Err("Invalid tree depth");
+        }
+        tree.set_max_depth(depth)?;
+
+        let zeros = Self::zeros_of(&tree, depth);
+        Ok(FixedDepthLeanIMT { tree, zeros })
+    }
+
+    /// Computes the roots of the empty subtrees of `tree` up to `depth`.
+    fn zeros_of(tree: &LeanIMT<N, S>, depth: usize) -> Vec<N> {
+        let mut zeros = Vec::with_capacity(depth + 1);
+        zeros.push(tree.zero.clone());
+        for level in 0..depth {
+            let zero = zeros[level].clone();
+            zeros.push(tree.hash.call(vec![zero.clone(), zero]));
+        }
+        zeros
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.zeros.len() - 1
+    }
+
+    /// Returns the roots of the empty subtrees of each level, from the zero
+    /// value to the root of the empty tree.
+    pub fn zeros(&self) -> &[N] {
+        &self.zeros
+    }
+
+    /// Returns the lean tree, whose root is not padded.
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> LeanIMT<N, S> {
+        self.tree
+    }
+
+    /// Returns the root of the padded tree, that of the empty tree if no leaf
+    /// was inserted.
+    pub fn root(&self) -> Result<N, &'static str> {
+        let size = self.tree.size;
+        if size == 0 {
+            return Ok(self.zeros[self.get_depth()].clone());
+        }
+
+        // Climb from the last leaf: complete subtrees on its left are the
+        // same in the lean tree, and empty ones on its right are zeros
+        let mut node = self.tree.leaf_at(size - 1)?;
+        let mut position = size - 1;
+        for level in 0..self.get_depth() {
+            node = if position & 1 == 1 {
+                self.tree.hash_children(vec![self.side_node(level)?, node])
+            } else {
+                self.tree
+                    .hash_children(vec![node, self.zeros[level].clone()])
+            };
+            position >>= 1;
+        }
+        Ok(node)
+    }
+
+    /// Inserts a leaf, returning the new padded root.
+    pub fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        self.tree.insert(leaf)?;
+        self.root()
+    }
+
+    /// Inserts multiple leaves, returning the new padded root.
+    pub fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        self.tree.insert_many(leaves)?;
+        self.root()
+    }
+
+    /// Updates an existing leaf, given the `depth` siblings of its padded
+    /// proof, and returns the new padded root.
+    pub fn update(
+        &mut self,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        let index = self.tree.index_of(old_leaf)?;
+        let siblings = self.lean_siblings(index, sibling_nodes)?;
+        self.tree.update_at(index, old_leaf, new_leaf, &siblings)?;
+        self.root()
+    }
+
+    /// Removes a leaf, given the `depth` siblings of its padded proof, and
+    /// returns the new padded root.
+    pub fn remove(&mut self, old_leaf: &N, sibling_nodes: &[N]) -> Result<N, &'static str> {
+        self.update(old_leaf, self.tree.zero.clone(), sibling_nodes)
+    }
+
+    /// Generates the proof of the leaf at `index` in the padded tree, with
+    /// one sibling per level.
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        if index >= self.tree.size {
+            return Err("Leaf index out of range");
+        }
+
+        // Padding the leaves to an even count at each level makes every
+        // node hashed, up to the lean depth
+        let mut nodes = self.tree.ordered_leaves()?;
+        let mut siblings = Vec::with_capacity(self.get_depth());
+        let mut position = index;
+        for level in 0..self.get_depth() {
+            if nodes.len() & 1 == 1 {
+                nodes.push(self.zeros[level].clone());
+            }
+            siblings.push(nodes[position ^ 1].clone());
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| self.tree.hash_children(pair.to_vec()))
+                .collect();
+            position >>= 1;
+        }
+        self.tree.stats.add_proof();
+
+        Ok(MerkleProof {
+            root: nodes.swap_remove(0),
+            leaf: self.tree.leaf_at(index)?,
+            index,
+            siblings,
+        })
+    }
+
+    /// Checks a padded proof against the current padded root.
+    pub fn verify_proof(&self, proof: &MerkleProof<N>) -> bool {
+        proof.siblings.len() == self.get_depth()
+            && self
+                .root()
+                .is_ok_and(|root| self.tree.nodes_eq(&root, &proof.root))
+            && proof.verify_with(self.tree.hash.as_fn(), |a, b| self.tree.nodes_eq(a, b))
+    }
+
+    /// Converts the siblings of a padded proof to those of the lean tree,
+    /// checking the siblings that are not nodes of the lean tree.
+    ///
+    /// Complete subtrees are the same in both trees. The incomplete subtree
+    /// on the right edge differs, as the lean tree does not hash a single
+    /// child with a zero, and levels without a sibling in the lean tree must
+    /// have an empty subtree in the padded one.
+    fn lean_siblings(&self, index: usize, sibling_nodes: &[N]) -> Result<Vec<N>, &'static str> {
+        if sibling_nodes.len() != self.get_depth() {
+            return Err("Wrong sibling nodes");
+        }
+
+        let last = self.tree.size - 1;
+        let edge = self.right_edge()?;
+        let mut siblings = Vec::with_capacity(self.tree.depth);
+        let mut level_size = self.tree.size;
+        for (level, sibling) in sibling_nodes.iter().enumerate() {
+            let position = (index >> level) ^ 1;
+            if position >= level_size {
+                if !self.tree.nodes_eq(sibling, &self.zeros[level]) {
+                    return Err("Wrong sibling nodes");
+                }
+            } else if position == last >> level {
+                let (padded, lean) = &edge[level];
+                if !self.tree.nodes_eq(sibling, padded) {
+                    return Err("Wrong sibling nodes");
+                }
+                siblings.push(lean.clone());
+            } else {
+                siblings.push(sibling.clone());
+            }
+            level_size = level_size.div_ceil(2);
+        }
+        Ok(siblings)
+    }
+
+    /// Returns the padded and lean nodes on the path of the last leaf, for
+    /// each level of the lean tree.
+    fn right_edge(&self) -> Result<Vec<(N, N)>, &'static str> {
+        let mut padded = self.tree.leaf_at(self.tree.size - 1)?;
+        let mut lean = padded.clone();
+        let mut edge = Vec::with_capacity(self.tree.depth);
+        let mut position = self.tree.size - 1;
+        for level in 0..self.tree.depth {
+            edge.push((padded.clone(), lean.clone()));
+            if position & 1 == 1 {
+                let left = self.side_node(level)?;
+                padded = self.tree.hash_children(vec![left.clone(), padded]);
+                lean = self.tree.hash_children(vec![left, lean]);
+            } else {
+                padded = self
+                    .tree
+                    .hash_children(vec![padded, self.zeros[level].clone()]);
+            }
+            position >>= 1;
+        }
+        Ok(edge)
+    }
+
+    /// Returns the left node of the last group at `level`.
+    fn side_node(&self, level: usize) -> Result<N, &'static str> {
+        self.tree
+            .store
+            .side_node(self.tree.side_node_key(level, 0))?
+            .ok_or("No side node at this level")
+    }
+}
+
+impl<N: Clone + Eq + Hash> FixedDepthLeanIMT<N> {
+    /// Creates a tree of depth `depth` using `zero` as the value of empty
+    /// leaves.
+    pub fn with_zero(
+        hash: IMTHashFunction<N>,
+        zero: N,
+        depth: usize,
+    ) -> Result<Self, &'static str> {
+        Self::from_tree(LeanIMT::with_zero(hash, zero), depth)
+    }
+}
+
+/// Lean tree padded to the compile-time depth `DEPTH`.
+#[derive(Debug)]
+pub struct FixedLeanIMT<const DEPTH: usize, N = IMTNode, S = MemoryStore<N>> {
+    tree: FixedDepthLeanIMT<N, S>,
+}
+
+/// Proof of a leaf of a [`FixedLeanIMT`], with one sibling per level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedMerkleProof<const DEPTH: usize, N = IMTNode> {
+    pub root: N,
+    pub leaf: N,
+    pub index: usize,
+    pub siblings: [N; DEPTH],
+}
+
+impl<const DEPTH: usize, N: Clone + PartialEq> FixedMerkleProof<DEPTH, N> {
+    /// Checks that the leaf and siblings hash up to the root of the proof.
+    pub fn verify(&self, hash: impl Fn(Vec<N>) -> N) -> bool {
+        self.to_merkle_proof().verify(hash)
+    }
+
+    pub fn to_merkle_proof(&self) -> MerkleProof<N> {
+        MerkleProof {
+            root: self.root.clone(),
+            leaf: self.leaf.clone(),
+            index: self.index,
+            siblings: self.siblings.to_vec(),
+        }
+    }
+}
+
+impl<const DEPTH: usize, N> TryFrom<MerkleProof<N>> for FixedMerkleProof<DEPTH, N> {
+    type Error = &'static str;
+
+    /// Converts a padded proof, failing with "Wrong proof depth" unless it
+    /// has `DEPTH` siblings.
+    fn try_from(proof: MerkleProof<N>) -> Result<Self, Self::Error> {
+        Ok(FixedMerkleProof {
+            root: proof.root,
+            leaf: proof.leaf,
+            index: proof.index,
+            siblings: proof.siblings.try_into().map_err(|_| "Wrong proof depth")?,
+        })
+    }
+}
+
+impl<const DEPTH: usize> FixedLeanIMT<DEPTH> {
+    /// Creates a tree of string nodes, using `"0"` as the zero value.
+    pub fn new(hash: IMTHashFunction) -> Self {
+        Self::with_zero(hash, "0".to_string())
+    }
+}
+
+impl<const DEPTH: usize, N: Clone + Eq + Hash> FixedLeanIMT<DEPTH, N> {
+    /// Creates a tree using `zero` as the value of empty leaves.
+    pub fn with_zero(hash: IMTHashFunction<N>, zero: N) -> Self {
+        let () = Self::VALID_DEPTH;
+        let mut tree = LeanIMT::with_zero(hash, zero);
+        // The tree is new, so the limit is set without checking its size
+        tree.max_size = max_size_of(DEPTH, 2);
+        let zeros = FixedDepthLeanIMT::<N>::zeros_of(&tree, DEPTH);
+        FixedLeanIMT {
+            tree: FixedDepthLeanIMT { tree, zeros },
+        }
+    }
+}
+
+impl<const DEPTH: usize, N: Clone + Eq + Hash, S: LeanIMTStore<N>> FixedLeanIMT<DEPTH, N, S> {
+    /// Fails to compile for depths whose `2^DEPTH` leaves do not fit a
+    /// `usize`.
+    const VALID_DEPTH: () = assert!(DEPTH < usize::BITS as usize, "Invalid tree depth");
+
+    /// Pads `tree` to `DEPTH`, limiting it to `2^DEPTH` leaves.
+    ///
+    /// Fails if the tree is not binary or holds more leaves.
+    pub fn from_tree(tree: LeanIMT<N, S>) -> Result<Self, &'static str> {
+        let () = Self::VALID_DEPTH;
+        Ok(FixedLeanIMT {
+            tree: FixedDepthLeanIMT::from_tree(tree, DEPTH)?,
+        })
+    }
+
+    /// Returns the roots of the empty subtrees of each level, from the zero
+    /// value to the root of the empty tree.
+    pub fn zeros(&self) -> &[N] {
+        self.tree.zeros()
+    }
+
+    /// Returns the lean tree, whose root is not padded.
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        self.tree.tree()
+    }
+
+    pub fn into_tree(self) -> LeanIMT<N, S> {
+        self.tree.into_tree()
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.tree.tree().size
+    }
+
+    /// Returns the root of the padded tree, that of the empty tree if no leaf
+    /// was inserted.
+    pub fn root(&self) -> Result<N, &'static str> {
+        self.tree.root()
+    }
+
+    /// Inserts a leaf, returning the new padded root.
+    pub fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        self.tree.insert(leaf)
+    }
+
+    /// Inserts multiple leaves, returning the new padded root.
+    pub fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        self.tree.insert_many(leaves)
+    }
+
+    /// Updates an existing leaf, given the siblings of its padded proof, and
+    /// returns the new padded root.
+    pub fn update(
+        &mut self,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N; DEPTH],
+    ) -> Result<N, &'static str> {
+        self.tree.update(old_leaf, new_leaf, sibling_nodes)
+    }
+
+    /// Removes a leaf, given the siblings of its padded proof, and returns
+    /// the new padded root.
+    pub fn remove(&mut self, old_leaf: &N, sibling_nodes: &[N; DEPTH]) -> Result<N, &'static str> {
+        self.tree.remove(old_leaf, sibling_nodes)
+    }
+
+    /// Generates the proof of the leaf at `index` in the padded tree.
+    pub fn generate_proof(&self, index: usize) -> Result<FixedMerkleProof<DEPTH, N>, &'static str> {
+        self.tree.generate_proof(index)?.try_into()
+    }
+
+    /// Checks a proof against the current padded root.
+    pub fn verify_proof(&self, proof: &FixedMerkleProof<DEPTH, N>) -> bool {
+        self.tree.verify_proof(&proof.to_merkle_proof())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        format!("({})", nodes.join(","))
+    }
+
+    /// Root of `leaves` padded with zeros to `depth`, computed naively.
+    fn padded_root(mut nodes: Vec<String>, depth: usize) -> String {
+        nodes.resize(1 << depth, "0".to_string());
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| simple_hash_function(pair.to_vec()))
+                .collect();
+        }
+        nodes.swap_remove(0)
+    }
+
+    #[test]
+    fn test_fixed_depth_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = FixedDepthLeanIMT::new(hash, 4).unwrap();
+        assert_eq!(imt.root(), Ok(padded_root(vec![], 4)));
+        assert_eq!(imt.zeros()[1], "(0,0)");
+
+        let mut leaves = Vec::new();
+        for i in 1..=11 {
+            leaves.push(format!("leaf{}", i));
+            let root = imt.insert(format!("leaf{}", i)).unwrap();
+            assert_eq!(root, padded_root(leaves.clone(), 4));
+        }
+
+        for index in [0, 7, 10] {
+            let proof = imt.generate_proof(index).unwrap();
+            assert_eq!(proof.siblings.len(), 4);
+            assert!(imt.verify_proof(&proof));
+
+            let new_leaf = format!("new{}", index);
+            let root = imt
+                .update(&leaves[index], new_leaf.clone(), &proof.siblings)
+                .unwrap();
+            leaves[index] = new_leaf;
+            assert_eq!(root, padded_root(leaves.clone(), 4));
+            let lean_proof = imt.tree().generate_proof(index).unwrap();
+            assert!(imt.tree().verify_proof(&lean_proof));
+        }
+
+        // Siblings outside the lean tree are checked too
+        let proof = imt.generate_proof(10).unwrap();
+        let mut siblings = proof.siblings.clone();
+        siblings[3] = "wrong".to_string();
+        assert_eq!(
+            imt.remove(&leaves[10], &siblings).unwrap_err(),
+            "Wrong sibling nodes"
+        );
+        let root = imt.remove(&leaves[10], &proof.siblings).unwrap();
+        leaves[10] = "0".to_string();
+        assert_eq!(root, padded_root(leaves.clone(), 4));
+
+        let more: Vec<_> = (12..=16).map(|i| format!("leaf{}", i)).collect();
+        leaves.extend(more.clone());
+        assert_eq!(
+            imt.insert_many(more).unwrap(),
+            padded_root(leaves.clone(), 4)
+        );
+        assert_eq!(
+            imt.insert("leaf17".to_string()).unwrap_err(),
+            "Tree is full"
+        );
+
+        assert_eq!(
+            FixedDepthLeanIMT::from_tree(LeanIMT::with_arity(hash, 3).unwrap(), 4).unwrap_err(),
+            "Unsupported arity"
+        );
+    }
+
+    #[test]
+    fn test_const_depth_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = FixedLeanIMT::<4>::new(hash);
+        assert_eq!(imt.root(), Ok(padded_root(vec![], 4)));
+
+        let mut leaves: Vec<String> = (1..=5).map(|i| format!("leaf{}", i)).collect();
+        let root = imt.insert_many(leaves.clone()).unwrap();
+        assert_eq!(root, padded_root(leaves.clone(), 4));
+
+        let proof: FixedMerkleProof<4, String> = imt.generate_proof(2).unwrap();
+        assert!(imt.verify_proof(&proof));
+        let root = imt
+            .update(&leaves[2], "new3".to_string(), &proof.siblings)
+            .unwrap();
+        leaves[2] = "new3".to_string();
+        assert_eq!(root, padded_root(leaves, 4));
+
+        // Proofs of another depth do not convert
+        let lean_proof = imt.tree().generate_proof(2).unwrap();
+        assert_eq!(
+            FixedMerkleProof::<4, String>::try_from(lean_proof).unwrap_err(),
+            "Wrong proof depth"
+        );
+        let padded = FixedDepthLeanIMT::from_tree(imt.into_tree(), 4).unwrap();
+        let proof = padded.generate_proof(2).unwrap();
+        assert!(FixedMerkleProof::<4, String>::try_from(proof)
+            .unwrap()
+            .verify(hash));
+    }
+}
@@ -0,0 +1,219 @@
+//! Many trees sharing one hash function and one storage backend.
+//!
+//! Applications such as Semaphore keep one tree per group, and can have
+//! thousands of groups. A [`Forest`] is the registry of those trees: it
+//! creates, deletes and lists them by id, and returns the root of each one.
+//! Every tree gets its own store from a [`ForestBackend`]: [`MemoryBackend`]
+//! keeps them in memory, and with the `sled` feature
+//! `sled_store::SledForestBackend` keeps them in one sled database, from
+//! which `Forest::open` reopens them.
+
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+
+use crate::{IMTHashFunction, IMTNode, LeanIMT, LeanIMTStore, MemoryStore};
+
+/// Storage shared by the trees of a forest, handing out one store per tree.
+pub trait ForestBackend<K, N> {
+    type Store: LeanIMTStore<N>;
+
+    /// Returns the store of the tree `id`, empty if the tree was never
+    /// created.
+    fn store(&mut self, id: &K) -> Result<Self::Store, &'static str>;
+
+    /// Deletes the nodes of the tree `id`.
+    fn delete(&mut self, id: &K) -> Result<(), &'static str>;
+
+    /// Returns the ids of the trees saved in the backend.
+    ///
+    /// Backends that are not persistent do not keep any.
+    fn ids(&self) -> Result<Vec<K>, &'static str> {
+        Ok(Vec::new())
+    }
+}
+
+/// Backend keeping each tree in its own `MemoryStore`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryBackend;
+
+impl<K, N: Clone + Eq + Hash> ForestBackend<K, N> for MemoryBackend {
+    type Store = MemoryStore<N>;
+
+    fn store(&mut self, _id: &K) -> Result<MemoryStore<N>, &'static str> {
+        Ok(MemoryStore::default())
+    }
+
+    fn delete(&mut self, _id: &K) -> Result<(), &'static str> {
+        Ok(())
+    }
+}
+
+/// Trees identified by ids of type `K`, with nodes kept by the backend `B`.
+pub struct Forest<K, N = IMTNode, B = MemoryBackend>
+where
+    B: ForestBackend<K, N>,
+{
+    hash: IMTHashFunction<N>,
+    zero: N,
+    backend: B,
+    trees: BTreeMap<K, LeanIMT<N, B::Store>>,
+}
+
+impl<K: Ord + Clone> Forest<K> {
+    /// Creates an empty forest of string trees, using `"0"` as the zero value.
+    pub fn new(hash: IMTHashFunction) -> Self {
+        Self::with_backend(hash, "0".to_string(), MemoryBackend)
+    }
+}
+
+impl<K, N, B> Forest<K, N, B>
+where
+    K: Ord + Clone,
+    N: Clone + Eq + Hash,
+    B: ForestBackend<K, N>,
+{
+    /// Creates an empty forest whose new trees use `zero` and keep their
+    /// nodes in `backend`.
+    pub fn with_backend(hash: IMTHashFunction<N>, zero: N, backend: B) -> Self {
+        Forest {
+            hash,
+            zero,
+            backend,
+            trees: BTreeMap::new(),
+        }
+    }
+
+    /// Reopens the trees saved in `backend`.
+    pub fn open(hash: IMTHashFunction<N>, zero: N, mut backend: B) -> Result<Self, &'static str> {
+        let mut trees = BTreeMap::new();
+        for id in backend.ids()? {
+            let store = backend.store(&id)?;
+            trees.insert(id, LeanIMT::open(hash, store)?);
+        }
+        Ok(Forest {
+            hash,
+            zero,
+            backend,
+            trees,
+        })
+    }
+
+    /// Creates the empty tree `id` and returns it.
+    pub fn create(&mut self, id: K) -> Result<&mut LeanIMT<N, B::Store>, &'static str> {
+        if self.trees.contains_key(&id) {
+            return Err("Tree already exists");
+        }
+        let store = self.backend.store(&id)?;
+        let tree = LeanIMT::with_store(self.hash, self.zero.clone(), store)?;
+        Ok(self.trees.entry(id).or_insert(tree))
+    }
+
+    /// Deletes the tree `id` and its nodes.
+    pub fn delete(&mut self, id: &K) -> Result<(), &'static str> {
+        // The tree is dropped first, so that it releases its store
+        self.trees.remove(id).ok_or("Unknown tree")?;
+        self.backend.delete(id)
+    }
+
+    pub fn get(&self, id: &K) -> Option<&LeanIMT<N, B::Store>> {
+        self.trees.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &K) -> Option<&mut LeanIMT<N, B::Store>> {
+        self.trees.get_mut(id)
+    }
+
+    pub fn contains(&self, id: &K) -> bool {
+        self.trees.contains_key(id)
+    }
+
+    /// Iterates over the ids of the trees, in ascending order.
+    pub fn ids(&self) -> impl Iterator<Item = &K> {
+        self.trees.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Returns the root of the tree `id`, or `None` if it is empty.
+    ///
+    /// Fails if there is no tree `id`.
+    pub fn root(&self, id: &K) -> Result<Option<N>, &'static str> {
+        Ok(self.trees.get(id).ok_or("Unknown tree")?.root())
+    }
+
+    /// Iterates over the ids of the trees and their roots, in ascending
+    /// order of ids.
+    pub fn roots(&self) -> impl Iterator<Item = (&K, Option<N>)> {
+        self.trees.iter().map(|(id, tree)| (id, tree.root()))
+    }
+
+    pub fn get_backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+impl<K: fmt::Debug, N, B: ForestBackend<K, N>> fmt::Debug for Forest<K, N, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Forest")
+            .field("ids", &self.trees.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_forest() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut forest = Forest::new(hash);
+        assert!(forest.is_empty());
+
+        for group in [3u64, 1, 2] {
+            let tree = forest.create(group).unwrap();
+            tree.insert_many((1..=group).map(|i| format!("member{}", i)).collect())
+                .unwrap();
+        }
+        assert_eq!(forest.create(2).unwrap_err(), "Tree already exists");
+        assert_eq!(forest.ids().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(forest.root(&1), Ok(Some("member1".to_string())));
+        assert_eq!(forest.root(&4), Err("Unknown tree"));
+
+        forest
+            .get_mut(&1)
+            .unwrap()
+            .insert("member2".to_string())
+            .unwrap();
+        assert_eq!(forest.root(&1), forest.root(&2));
+        assert!(forest.get(&2).unwrap().has(&"member2".to_string()));
+
+        forest.delete(&2).unwrap();
+        assert_eq!(forest.delete(&2).unwrap_err(), "Unknown tree");
+        assert!(!forest.contains(&2));
+        let roots: Vec<_> = forest.roots().collect();
+        assert_eq!(
+            roots,
+            vec![
+                (&1, Some("member1,member2".to_string())),
+                (&3, Some("member1,member2,member3".to_string())),
+            ]
+        );
+    }
+}
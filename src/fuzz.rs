@@ -0,0 +1,343 @@
+//! Generators of tree operations, and a naive model to test trees against.
+//!
+//! Fuzzers and property tests drive a tree with sequences of [`TreeOp`]s,
+//! generated through `arbitrary::Arbitrary` from the raw bytes they are
+//! given. Operations name leaves by index, wrapped around the size of the
+//! tree, so that most of them reach existing leaves instead of failing, and
+//! compute their own sibling nodes.
+//!
+//! [`NaiveTree`] keeps the leaves in a vector and recomputes the root from
+//! them, enforcing the rules of the tree by plain scans. [`OpSequence::check`]
+//! applies the operations to a tree and to its model side by side, and fails
+//! as soon as they disagree on the outcome of an operation, the size, the
+//! root or the leaves.
+
+use arbitrary::Arbitrary;
+use core::hash::Hash;
+use std::collections::HashSet;
+
+use crate::history::compute_root;
+use crate::{
+    DuplicatePolicy, IMTHashFunction, IMTLeafHashFunction, LeanIMT, LeanIMTStore, RemovalPolicy,
+    TreeHash,
+};
+
+/// An operation on a tree.
+#[derive(Debug, Clone, PartialEq, Eq, Arbitrary)]
+pub enum TreeOp<N> {
+    Insert(N),
+    InsertMany(Vec<N>),
+    /// Updates the leaf at `index`, modulo the size of the tree.
+    Update {
+        index: usize,
+        leaf: N,
+    },
+    /// Removes the leaf at `index`, modulo the size of the tree.
+    Remove {
+        index: usize,
+    },
+    /// Truncates the tree to `size`, modulo the size of the tree plus one.
+    Truncate {
+        size: usize,
+    },
+    Pop,
+}
+
+impl<N: Clone + Eq + Hash> TreeOp<N> {
+    /// Applies the operation to `tree`, generating the sibling nodes of
+    /// updates and removals.
+    pub fn apply<S: LeanIMTStore<N>>(&self, tree: &mut LeanIMT<N, S>) -> Result<(), &'static str> {
+        match self {
+            TreeOp::Insert(leaf) => {
+                tree.insert(leaf.clone())?;
+            }
+            TreeOp::InsertMany(leaves) => {
+                tree.insert_many(leaves.clone())?;
+            }
+            TreeOp::Update { index, leaf } => {
+                let index = wrap(*index, tree.size)?;
+                let siblings = tree.generate_nary_proof(index)?.siblings.concat();
+                tree.update_at(index, &tree.leaf_at(index)?, leaf.clone(), &siblings)?;
+            }
+            TreeOp::Remove { index } => {
+                let index = wrap(*index, tree.size)?;
+                let siblings = tree.generate_nary_proof(index)?.siblings.concat();
+                tree.remove_at(index, &tree.leaf_at(index)?, &siblings)?;
+            }
+            TreeOp::Truncate { size } => {
+                tree.truncate(size % (tree.size + 1))?;
+            }
+            TreeOp::Pop => {
+                tree.pop()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps `index` into a tree of `size` leaves.
+fn wrap(index: usize, size: usize) -> Result<usize, &'static str> {
+    match size {
+        0 => Err("Tree is empty"),
+        _ => Ok(index % size),
+    }
+}
+
+/// A sequence of operations, to generate with `arbitrary`.
+#[derive(Debug, Clone, PartialEq, Eq, Arbitrary)]
+pub struct OpSequence<N>(pub Vec<TreeOp<N>>);
+
+impl<N: Clone + Eq + Hash> OpSequence<N> {
+    /// Applies the operations to `tree` and to a model of it, failing with
+    /// "Tree diverged from the model" as soon as an operation succeeds on
+    /// one and fails on the other, or leaves them with different sizes,
+    /// roots or leaves.
+    pub fn check<S: LeanIMTStore<N>>(&self, tree: &mut LeanIMT<N, S>) -> Result<(), &'static str> {
+        let mut model = NaiveTree::of(tree)?;
+        for op in &self.0 {
+            if op.apply(tree).is_ok() != model.apply(op).is_ok()
+                || tree.size != model.leaves.len()
+                || tree.root() != model.root()
+                || tree.ordered_leaves()? != model.leaves
+            {
+                return Err("Tree diverged from the model");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reference implementation of a tree, keeping its leaves in a vector.
+#[derive(Debug, Clone)]
+pub struct NaiveTree<N> {
+    /// Leaves by index, removed leaves being zero.
+    leaves: Vec<N>,
+    zero: N,
+    arity: usize,
+    hash: TreeHash<N>,
+    leaf_hash: Option<IMTLeafHashFunction<N>>,
+    duplicate_policy: DuplicatePolicy,
+    removal_policy: RemovalPolicy,
+    /// Leaves removed since the model was created, if the policy keeps
+    /// tombstones.
+    removed: HashSet<N>,
+    max_size: Option<usize>,
+}
+
+impl<N: Clone + Eq + Hash> NaiveTree<N> {
+    /// Creates an empty binary model rejecting duplicate leaves, as
+    /// `LeanIMT::with_zero` does.
+    pub fn new(hash: IMTHashFunction<N>, zero: N) -> Self {
+        NaiveTree {
+            leaves: Vec::new(),
+            zero,
+            arity: 2,
+            hash: TreeHash::Fn(hash),
+            leaf_hash: None,
+            duplicate_policy: DuplicatePolicy::Reject,
+            removal_policy: RemovalPolicy::Forget,
+            removed: HashSet::new(),
+            max_size: None,
+        }
+    }
+
+    /// Creates a model of the current leaves and configuration of `tree`.
+    ///
+    /// Tombstones kept by the tree before are not part of the model.
+    pub fn of<S: LeanIMTStore<N>>(tree: &LeanIMT<N, S>) -> Result<Self, &'static str> {
+        Ok(NaiveTree {
+            leaves: tree.ordered_leaves()?,
+            zero: tree.zero.clone(),
+            arity: tree.arity,
+            hash: tree.hash.clone(),
+            leaf_hash: tree.leaf_hash,
+            duplicate_policy: tree.duplicate_policy,
+            removal_policy: tree.removal_policy,
+            removed: HashSet::new(),
+            max_size: tree.max_size,
+        })
+    }
+
+    /// Returns the leaves by index, removed leaves being zero.
+    pub fn leaves(&self) -> &[N] {
+        &self.leaves
+    }
+
+    pub fn root(&self) -> Option<N> {
+        compute_root(self.leaves.clone(), self.arity, self.hash.as_fn())
+    }
+
+    /// Applies the operation as a tree does.
+    pub fn apply(&mut self, op: &TreeOp<N>) -> Result<(), &'static str> {
+        match op {
+            TreeOp::Insert(leaf) => self.insert(vec![leaf.clone()]),
+            TreeOp::InsertMany(leaves) if leaves.is_empty() => Err("Empty batch"),
+            TreeOp::InsertMany(leaves) => self.insert(leaves.clone()),
+            TreeOp::Update { index, leaf } => {
+                let index = wrap(*index, self.leaves.len())?;
+                // Updating to zero removes the leaf, whatever the leaf hash
+                let leaf = if *leaf == self.zero {
+                    leaf.clone()
+                } else {
+                    self.hash_leaf(leaf.clone())
+                };
+                if leaf != self.zero && self.rejects(&leaf) {
+                    return Err("New leaf already exists");
+                }
+                if leaf != self.zero && self.rejects_reinsertion(&leaf) {
+                    return Err("Leaf was removed");
+                }
+                self.set(index, leaf)
+            }
+            TreeOp::Remove { index } => {
+                let index = wrap(*index, self.leaves.len())?;
+                self.set(index, self.zero.clone())
+            }
+            TreeOp::Truncate { size } => {
+                self.leaves.truncate(size % (self.leaves.len() + 1));
+                Ok(())
+            }
+            TreeOp::Pop => {
+                self.leaves.pop().ok_or("Tree is empty")?;
+                Ok(())
+            }
+        }
+    }
+
+    fn insert(&mut self, leaves: Vec<N>) -> Result<(), &'static str> {
+        let leaves: Vec<N> = leaves
+            .into_iter()
+            .map(|leaf| self.hash_leaf(leaf))
+            .collect();
+        if self
+            .max_size
+            .is_some_and(|max_size| self.leaves.len() + leaves.len() > max_size)
+        {
+            return Err("Tree is full");
+        }
+        let mut batch = HashSet::new();
+        for leaf in &leaves {
+            if self.rejects(leaf)
+                || (self.duplicate_policy == DuplicatePolicy::Reject && !batch.insert(leaf))
+            {
+                return Err("Leaf already exists");
+            }
+            if *leaf == self.zero {
+                return Err("Leaf cannot be zero");
+            }
+            if self.rejects_reinsertion(leaf) {
+                return Err("Leaf was removed");
+            }
+        }
+        self.leaves.extend(leaves);
+        Ok(())
+    }
+
+    /// Replaces the leaf at `index`, which must not have been removed.
+    fn set(&mut self, index: usize, leaf: N) -> Result<(), &'static str> {
+        if self.leaves[index] == self.zero {
+            return Err("Leaf does not exist");
+        }
+        let old_leaf = core::mem::replace(&mut self.leaves[index], leaf);
+        if self.leaves[index] == self.zero && self.removal_policy != RemovalPolicy::Forget {
+            self.removed.insert(old_leaf);
+        }
+        Ok(())
+    }
+
+    fn hash_leaf(&self, leaf: N) -> N {
+        match self.leaf_hash {
+            Some(leaf_hash) => leaf_hash(leaf),
+            None => leaf,
+        }
+    }
+
+    /// Returns true if `leaf` cannot be added because of the duplicate policy.
+    fn rejects(&self, leaf: &N) -> bool {
+        self.duplicate_policy == DuplicatePolicy::Reject && self.leaves.contains(leaf)
+    }
+
+    /// Returns true if `leaf` cannot be added because it was removed.
+    fn rejects_reinsertion(&self, leaf: &N) -> bool {
+        self.removal_policy == RemovalPolicy::RejectReinsertion && self.removed.contains(leaf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arbitrary::Unstructured;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_model_check() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let ops = OpSequence(vec![
+            TreeOp::InsertMany(vec!["a".into(), "b".into(), "c".into()]),
+            TreeOp::Insert("b".into()),
+            TreeOp::Update {
+                index: 4,
+                leaf: "d".into(),
+            },
+            TreeOp::Remove { index: 0 },
+            TreeOp::Update {
+                index: 3,
+                leaf: "e".into(),
+            },
+            TreeOp::Insert("0".into()),
+            TreeOp::Truncate { size: 3 },
+            TreeOp::Pop,
+            TreeOp::Pop,
+        ]);
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(ops.check(&mut imt), Ok(()));
+        assert_eq!(imt.root(), Some("0".to_string()));
+
+        let mut model = NaiveTree::new(hash, "0".to_string());
+        for op in &ops.0[..3] {
+            let _ = model.apply(op);
+        }
+        assert_eq!(model.leaves(), ["a", "d", "c"]);
+
+        // Removed leaves cannot come back when tombstones reject them
+        let ops = OpSequence(vec![
+            TreeOp::InsertMany(vec!["a".into(), "b".into()]),
+            TreeOp::Remove { index: 0 },
+            TreeOp::Insert("a".into()),
+            TreeOp::Update {
+                index: 1,
+                leaf: "a".into(),
+            },
+        ]);
+        let mut imt = LeanIMT::new(hash);
+        imt.set_removal_policy(RemovalPolicy::RejectReinsertion);
+        assert_eq!(ops.check(&mut imt), Ok(()));
+        assert_eq!(imt.root(), Some("0,b".to_string()));
+
+        // Sequences generated from raw bytes, on binary and ternary trees
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let bytes: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut input = Unstructured::new(&bytes);
+        for arity in [2, 3] {
+            for _ in 0..4 {
+                let ops = (0..64)
+                    .map(|_| TreeOp::<String>::arbitrary(&mut input))
+                    .collect::<Result<_, _>>()
+                    .unwrap();
+                let ops = OpSequence(ops);
+                let mut imt = LeanIMT::with_arity(hash, arity).unwrap();
+                assert_eq!(ops.check(&mut imt), Ok(()));
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Hash functions for the tree, each behind its own feature.
+
+#[cfg(feature = "blake3")]
+pub mod blake3;
+#[cfg(feature = "digest")]
+pub mod digest;
+#[cfg(feature = "mimc")]
+pub mod mimc;
+#[cfg(feature = "pedersen")]
+pub mod pedersen;
+#[cfg(feature = "poseidon-goldilocks")]
+pub mod poseidon_goldilocks;
+#[cfg(feature = "poseidon-halo2")]
+pub mod poseidon_halo2;
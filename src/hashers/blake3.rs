@@ -0,0 +1,132 @@
+//! BLAKE3 hasher, a fast choice for trees that do not need to be proven in
+//! a circuit, such as content-addressed logs.
+//!
+//! Nodes are 32-byte BLAKE3 hashes, and a parent is the hash of its children
+//! concatenated in order. BLAKE3 uses the SIMD instructions of the CPU for
+//! every hash, and the bulk functions of this module spread the work over
+//! the rayon thread pool: [`hash_leaves`] hashes many inputs at once,
+//! [`hash_leaf`] splits large inputs, and [`from_leaves`] and [`insert_many`]
+//! hash the nodes of each level of the tree in parallel.
+
+use alloc::vec::Vec;
+
+use rayon::prelude::*;
+
+use crate::{LeanIMT, LeanIMTStore};
+
+/// Size from which [`hash_leaf`] hashes its input on several threads.
+const PARALLEL_LEAF_LEN: usize = 128 * 1024;
+
+/// Minimum number of nodes hashed by each rayon task.
+const PARALLEL_MIN_GROUPS: usize = 64;
+
+/// Hashes nodes: the BLAKE3 hash of the children concatenated.
+pub fn hash_nodes(nodes: Vec<[u8; 32]>) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    for node in &nodes {
+        hasher.update(node);
+    }
+    hasher.finalize().into()
+}
+
+/// Returns the BLAKE3 hash of `data`, to be inserted as a leaf.
+///
+/// Inputs of 128 KiB or more are hashed on several threads.
+pub fn hash_leaf(data: impl AsRef<[u8]>) -> [u8; 32] {
+    let data = data.as_ref();
+    let mut hasher = blake3::Hasher::new();
+    if data.len() >= PARALLEL_LEAF_LEN {
+        hasher.update_rayon(data);
+    } else {
+        hasher.update(data);
+    }
+    hasher.finalize().into()
+}
+
+/// Returns the BLAKE3 hashes of many inputs, computed in parallel.
+pub fn hash_leaves<T: AsRef<[u8]> + Sync>(data: &[T]) -> Vec<[u8; 32]> {
+    data.par_iter().map(hash_leaf).collect()
+}
+
+/// Builds a BLAKE3 tree of `leaves`, hashing each level in parallel.
+///
+/// The tree uses [`hash_nodes`] and an all-zero zero value.
+pub fn from_leaves(leaves: Vec<[u8; 32]>) -> Result<LeanIMT<[u8; 32]>, &'static str> {
+    let mut imt = LeanIMT::with_zero(hash_nodes, [0; 32]);
+    if !leaves.is_empty() {
+        insert_many(&mut imt, leaves)?;
+    }
+    Ok(imt)
+}
+
+/// Inserts `leaves` like `LeanIMT::insert_many`, hashing the nodes of each
+/// level in parallel with the hash function of the tree.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(leaves = leaves.len()))
+)]
+pub fn insert_many<S: LeanIMTStore<[u8; 32]>>(
+    imt: &mut LeanIMT<[u8; 32], S>,
+    leaves: Vec<[u8; 32]>,
+) -> Result<[u8; 32], &'static str> {
+    let hash = imt.hash.clone();
+    imt.transaction(|imt, changes| {
+        imt.insert_leaves(leaves, changes, |groups| {
+            groups
+                .into_par_iter()
+                .with_min_len(PARALLEL_MIN_GROUPS)
+                .map(|group| hash.call(group))
+                .collect()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{BytesCodec, NodeCodec};
+    use alloc::vec;
+
+    #[test]
+    fn test_blake3_trees() {
+        let leaves: Vec<_> = (0u32..1000).map(|i| hash_leaf(i.to_be_bytes())).collect();
+
+        let mut imt = LeanIMT::with_zero(hash_nodes, [0; 32]);
+        imt.insert(leaves[0]).unwrap();
+        imt.insert_many(leaves[1..].to_vec()).unwrap();
+
+        let mut parallel = from_leaves(leaves[..1].to_vec()).unwrap();
+        insert_many(&mut parallel, leaves[1..].to_vec()).unwrap();
+        assert_eq!(parallel.root(), imt.root());
+        assert_eq!(from_leaves(leaves.clone()).unwrap().root(), imt.root());
+        assert_eq!(from_leaves(vec![]).unwrap().root(), None);
+
+        let proof = parallel.generate_proof(999).unwrap();
+        assert!(proof.verify(hash_nodes));
+        assert_eq!(
+            insert_many(&mut parallel, vec![leaves[3]]).unwrap_err(),
+            "Leaf already exists"
+        );
+        assert_eq!(parallel.get_size(), 1000);
+
+        let mut concatenated = leaves[0].to_vec();
+        concatenated.extend_from_slice(&leaves[1]);
+        assert_eq!(
+            hash_nodes(vec![leaves[0], leaves[1]]),
+            *blake3::hash(&concatenated).as_bytes()
+        );
+
+        let large = vec![7u8; 3 * PARALLEL_LEAF_LEN];
+        assert_eq!(hash_leaf(&large), *blake3::hash(&large).as_bytes());
+        assert_eq!(
+            hash_leaves(&[b"a", b"b"]),
+            [hash_leaf(b"a"), hash_leaf(b"b")]
+        );
+
+        assert_eq!(
+            BytesCodec::to_text(&hash_leaf(b"")),
+            "0xaf1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert!(<BytesCodec as NodeCodec<[u8; 32]>>::decode(&[0; 31]).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Adapter hashing nodes with any RustCrypto [`Digest`] (SHA-2, SHA-3,
+//! BLAKE2, ...).
+//!
+//! Two node encodings are supported, both deterministic:
+//!
+//! - Fixed-size nodes, the `Output<D>` arrays of the digest. A parent is the
+//!   digest of its children concatenated in order. All nodes have the digest
+//!   size, so the input is unambiguous, and with Keccak-256 it matches
+//!   `keccak256(abi.encodePacked(left, right))` over `bytes32` nodes in
+//!   Solidity. Leaves are usually the digest of some data, see [`hash_leaf`].
+//! - Byte vector nodes of any length. Each child is prefixed with its length
+//!   as a big-endian `u64`, so different lists of children never produce the
+//!   same input.
+//!
+//! Fixed-size nodes can also be hashed with domain separation tags, byte
+//! strings hashed before the input that differ for leaves and internal
+//! nodes, as protocols such as RFC 6962 (Certificate Transparency) mandate.
+//! A [`DomainTags`] type chooses the tags, and [`hash_tagged_nodes`],
+//! [`hash_tagged_data`] and [`hash_tagged_leaf`] prefix them. The shape of
+//! the lean tree, where a node without sibling goes up unhashed, is that of
+//! RFC 6962, so with [`Rfc6962`] tags the roots are those of its logs.
+//!
+//! Fixed-size nodes can also be hashed as sorted pairs, the children ordered
+//! by value before hashing, as OpenZeppelin's `MerkleProof` library does.
+//! With [`hash_sorted_nodes`] and Keccak-256, the siblings of a proof verify
+//! with `MerkleProof.verify(proof, root, leaf)` on-chain, without the index,
+//! so the tree builds airdrop and allowlist trees. Their leaves are usually
+//! hashed twice, see [`hash_standard_leaf`].
+//!
+//! The functions are generic over the digest and coerce to
+//! `IMTHashFunction` once it is chosen:
+//!
+//! ```ignore
+//! let hash: IMTHashFunction<Output<Sha256>> = hash_nodes::<Sha256>;
+//! let imt = LeanIMT::with_zero(hash, Output::<Sha256>::default());
+//! ```
+
+use alloc::vec::Vec;
+
+use ::digest::{Digest, Output};
+
+/// Hashes fixed-size nodes: the digest of the children concatenated.
+pub fn hash_nodes<D: Digest>(nodes: Vec<Output<D>>) -> Output<D> {
+    let mut hasher = D::new();
+    for node in &nodes {
+        hasher.update(node);
+    }
+    hasher.finalize()
+}
+
+/// Returns the digest of `data`, to be inserted as a fixed-size leaf.
+pub fn hash_leaf<D: Digest>(data: impl AsRef<[u8]>) -> Output<D> {
+    D::digest(data)
+}
+
+/// Byte hashed before leaves by RFC 6962.
+pub const LEAF_TAG: u8 = 0x00;
+
+/// Byte hashed before internal nodes by RFC 6962.
+pub const NODE_TAG: u8 = 0x01;
+
+/// Tags hashed before leaves and internal nodes, separating their domains.
+///
+/// The tags must differ, and neither be a prefix of the other.
+pub trait DomainTags {
+    const LEAF: &'static [u8];
+    const NODE: &'static [u8];
+}
+
+/// Tags of RFC 6962: [`LEAF_TAG`] before leaves and [`NODE_TAG`] before
+/// internal nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rfc6962;
+
+impl DomainTags for Rfc6962 {
+    const LEAF: &'static [u8] = &[LEAF_TAG];
+    const NODE: &'static [u8] = &[NODE_TAG];
+}
+
+/// Hashes fixed-size nodes: the digest of the node tag of `T` followed by
+/// the children concatenated.
+pub fn hash_tagged_nodes<D: Digest, T: DomainTags>(nodes: Vec<Output<D>>) -> Output<D> {
+    let mut hasher = D::new();
+    hasher.update(T::NODE);
+    for node in &nodes {
+        hasher.update(node);
+    }
+    hasher.finalize()
+}
+
+/// Returns the digest of the leaf tag of `T` followed by `data`, to be
+/// inserted as a leaf: with [`Rfc6962`], the leaf hash of a log entry.
+pub fn hash_tagged_data<D: Digest, T: DomainTags>(data: impl AsRef<[u8]>) -> Output<D> {
+    let mut hasher = D::new();
+    hasher.update(T::LEAF);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Returns the digest of the leaf tag of `T` followed by `leaf`, for
+/// `LeanIMT::set_leaf_hash`.
+///
+/// Leaves and internal nodes never hash the same input: with
+/// [`hash_tagged_nodes`], their tags differ, and with [`hash_nodes`], the
+/// input of a leaf is not a whole number of nodes, for a one-byte tag.
+pub fn hash_tagged_leaf<D: Digest, T: DomainTags>(leaf: Output<D>) -> Output<D> {
+    hash_tagged_data::<D, T>(leaf)
+}
+
+/// Hashes fixed-size nodes as a sorted pair: the digest of the children
+/// concatenated in increasing order. With Keccak-256, this is
+/// `Hashes.commutativeKeccak256` of OpenZeppelin for two children.
+pub fn hash_sorted_nodes<D: Digest>(mut nodes: Vec<Output<D>>) -> Output<D> {
+    nodes.sort_unstable();
+    hash_nodes::<D>(nodes)
+}
+
+/// Returns the digest of the digest of `data`, the leaves of the OpenZeppelin
+/// `StandardMerkleTree` when `data` is the ABI encoding of the leaf values.
+pub fn hash_standard_leaf<D: Digest>(data: impl AsRef<[u8]>) -> Output<D> {
+    D::digest(D::digest(data))
+}
+
+/// Hashes nodes of any length: the digest of the children, each prefixed
+/// with its length as a big-endian `u64`.
+pub fn hash_byte_nodes<D: Digest>(nodes: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut hasher = D::new();
+    for node in &nodes {
+        hasher.update((node.len() as u64).to_be_bytes());
+        hasher.update(node);
+    }
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{BytesCodec, NodeCodec};
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::vec;
+    use sha2::Sha256;
+    use sha3::Keccak256;
+
+    #[test]
+    fn test_digest_trees() {
+        let hash: IMTHashFunction<Output<Sha256>> = hash_nodes::<Sha256>;
+        let mut imt = LeanIMT::with_zero(hash, Output::<Sha256>::default());
+        let leaves: Vec<_> = ["a", "b", "c"].iter().map(hash_leaf::<Sha256>).collect();
+        imt.insert_many(leaves.clone()).unwrap();
+
+        let mut left = Sha256::new();
+        left.update(leaves[0]);
+        left.update(leaves[1]);
+        let mut root = Sha256::new();
+        root.update(left.finalize());
+        root.update(leaves[2]);
+        assert_eq!(imt.root(), Some(root.finalize()));
+
+        let proof = imt.generate_proof(1).unwrap();
+        assert!(proof.verify(hash));
+        let text = BytesCodec::to_text(&proof.leaf);
+        assert_eq!(
+            text,
+            "0x3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d"
+        );
+        assert_eq!(BytesCodec::from_text(&text), Ok(proof.leaf));
+        assert!(<BytesCodec as NodeCodec<Output<Sha256>>>::decode(&[1, 2]).is_err());
+
+        // Keccak-256 over two 32-byte nodes, as abi.encodePacked in Solidity
+        let node = hash_nodes::<Keccak256>(vec![Output::<Keccak256>::default(); 2]);
+        assert_eq!(
+            BytesCodec::to_text(&node),
+            "0xad3228b676f7d3cd4284a5443f17f1962b36e491b30a40b2405849e597ba5fb5"
+        );
+
+        let hash: IMTHashFunction<Output<blake2::Blake2b512>> = hash_nodes::<blake2::Blake2b512>;
+        let mut imt = LeanIMT::with_zero(hash, Default::default());
+        imt.insert(hash_leaf::<blake2::Blake2b512>("a")).unwrap();
+        imt.insert(hash_leaf::<blake2::Blake2b512>("b")).unwrap();
+        assert_eq!(imt.root().unwrap().len(), 64);
+    }
+
+    #[test]
+    fn test_tagged_leaves() {
+        let hash: IMTHashFunction<Output<Sha256>> = hash_nodes::<Sha256>;
+        let mut imt = LeanIMT::with_zero(hash, Output::<Sha256>::default());
+        imt.set_leaf_hash(Some(hash_tagged_leaf::<Sha256, Rfc6962>));
+        let data = hash_leaf::<Sha256>("a");
+        imt.insert(data).unwrap();
+
+        let mut expected = Sha256::new();
+        expected.update([LEAF_TAG]);
+        expected.update(data);
+        assert_eq!(imt.root(), Some(expected.finalize()));
+        assert!(imt.has(&imt.hash_leaf(data)));
+        assert!(!imt.has(&data));
+    }
+
+    #[test]
+    fn test_rfc6962_roots() {
+        let hash: IMTHashFunction<Output<Sha256>> = hash_tagged_nodes::<Sha256, Rfc6962>;
+        let mut imt = LeanIMT::with_zero(hash, Output::<Sha256>::default());
+        let entries: [&[u8]; 8] = [
+            b"",
+            &[0x00],
+            &[0x10],
+            &[0x20, 0x21],
+            &[0x30, 0x31],
+            &[0x40, 0x41, 0x42, 0x43],
+            &[0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57],
+            &[
+                0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d,
+                0x6e, 0x6f,
+            ],
+        ];
+
+        // The test vectors of the Certificate Transparency logs
+        let roots = [
+            "0x6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "0xfac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "0xaeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "0xd37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "0x4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "0x76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "0xddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "0x5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+        for (entry, root) in entries.iter().zip(roots) {
+            imt.insert(hash_tagged_data::<Sha256, Rfc6962>(entry))
+                .unwrap();
+            assert_eq!(BytesCodec::to_text(&imt.root().unwrap()), root);
+        }
+
+        let proof = imt.generate_proof(5).unwrap();
+        assert!(proof.verify(hash));
+    }
+
+    #[test]
+    fn test_sorted_pair_proofs() {
+        let hash: IMTHashFunction<Output<Keccak256>> = hash_sorted_nodes::<Keccak256>;
+        let mut imt = LeanIMT::with_zero(hash, Output::<Keccak256>::default());
+        let leaves: Vec<_> = (0u8..5)
+            .map(|i| hash_standard_leaf::<Keccak256>([i; 32]))
+            .collect();
+        imt.insert_many(leaves.clone()).unwrap();
+
+        // processProof of OpenZeppelin, which hashes each sibling with the
+        // node as a sorted pair and has no use for the index
+        let process_proof = |leaf: Output<Keccak256>, proof: &[Output<Keccak256>]| {
+            proof.iter().fold(leaf, |node, sibling| {
+                let (a, b) = if node < *sibling {
+                    (node, *sibling)
+                } else {
+                    (*sibling, node)
+                };
+                let mut hasher = Keccak256::new();
+                hasher.update(a);
+                hasher.update(b);
+                hasher.finalize()
+            })
+        };
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = imt.generate_proof(index).unwrap();
+            assert_eq!(process_proof(*leaf, &proof.siblings), proof.root);
+            assert!(proof.verify(hash));
+        }
+        assert_eq!(imt.generate_proof(4).unwrap().siblings.len(), 1);
+    }
+
+    #[test]
+    fn test_byte_nodes_are_length_prefixed() {
+        let hash: IMTHashFunction<Vec<u8>> = hash_byte_nodes::<Sha256>;
+        assert_ne!(
+            hash(vec![b"ab".to_vec(), b"c".to_vec()]),
+            hash(vec![b"a".to_vec(), b"bc".to_vec()])
+        );
+
+        let mut imt = LeanIMT::with_zero(hash, vec![]);
+        imt.insert_many(vec![b"leaf1".to_vec(), b"leaf2".to_vec()])
+            .unwrap();
+        let mut expected = Sha256::new();
+        expected.update([0, 0, 0, 0, 0, 0, 0, 5]);
+        expected.update(b"leaf1");
+        expected.update([0, 0, 0, 0, 0, 0, 0, 5]);
+        expected.update(b"leaf2");
+        assert_eq!(imt.root(), Some(expected.finalize().to_vec()));
+    }
+}
@@ -0,0 +1,237 @@
+//! Pedersen hasher over Baby Jubjub, compatible with the `Pedersen` template
+//! and `pedersenHash` of circomlib.
+//!
+//! The message bits are split into segments of 200 bits, each committed to
+//! with its own generator: every window of 4 bits gives a signed multiplier,
+//! from 1 to 8 by the first three bits and negated by the fourth. The
+//! generators are the circomlib ones, derived from BLAKE-256 hashes of
+//! `"PedersenGenerator_<index>_<try>"` and multiplied by the cofactor.
+//!
+//! [`hash_nodes`] commits to the 254 little-endian bits of each child and
+//! returns the `x` coordinate of the result, as a circuit feeding
+//! `Num2Bits(254)` of the children to `Pedersen(254 * n)` and using `out[0]`.
+//! Messages of up to 2000 bits, or seven children, are supported.
+
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use ark_ff::{AdditiveGroup, BigInteger, Field, MontFp, PrimeField};
+
+/// Number of bits committed to by each generator.
+const SEGMENT_BITS: usize = 200;
+
+/// Number of bits of a window.
+const WINDOW_BITS: usize = 4;
+
+/// Number of bits of a node.
+const NODE_BITS: usize = 254;
+
+/// Maximum number of bits of a message.
+pub const MAX_BITS: usize = SEGMENT_BITS * BASES.len();
+
+/// Coefficients of the Baby Jubjub curve, `a x^2 + y^2 = 1 + d x^2 y^2`.
+const A: Fr = MontFp!("168700");
+const D: Fr = MontFp!("168696");
+
+/// Hashes nodes: the `x` coordinate of the Pedersen hash of the 254 bits of
+/// each child, in little-endian order.
+///
+/// # Panics
+///
+/// Panics with more than seven children.
+pub fn hash_nodes(nodes: Vec<Fr>) -> Fr {
+    let mut bits = Vec::with_capacity(nodes.len() * NODE_BITS);
+    for node in &nodes {
+        bits.extend(node.into_bigint().to_bits_le().into_iter().take(NODE_BITS));
+    }
+    pedersen_hash(&bits).0
+}
+
+/// Returns the Pedersen hash of the bits of `data`, least significant bit of
+/// each byte first, as `pedersenHash` in circomlib before packing.
+///
+/// # Panics
+///
+/// Panics if `data` is longer than 250 bytes.
+pub fn pedersen_hash_bytes(data: &[u8]) -> (Fr, Fr) {
+    let bits: Vec<bool> = data
+        .iter()
+        .flat_map(|byte| (0..8).map(move |bit| byte >> bit & 1 == 1))
+        .collect();
+    pedersen_hash(&bits)
+}
+
+/// Returns the Pedersen hash of `bits` as the affine point `(x, y)`.
+///
+/// # Panics
+///
+/// Panics if there are more than [`MAX_BITS`] bits.
+pub fn pedersen_hash(bits: &[bool]) -> (Fr, Fr) {
+    assert!(
+        bits.len() <= MAX_BITS,
+        "Too many bits for the Pedersen hash"
+    );
+
+    let mut result = Point::IDENTITY;
+    for (segment, base) in bits.chunks(SEGMENT_BITS).zip(BASES) {
+        // Each window is worth 2^5 times the previous one
+        let mut window_base = Point::from_affine(base);
+        for window in segment.chunks(WINDOW_BITS) {
+            // The window adds 1 to 8 times its base, negated by the last bit
+            let mut multiplier = window_base;
+            let mut power = window_base;
+            for &bit in window.iter().take(WINDOW_BITS - 1) {
+                if bit {
+                    multiplier = multiplier.add(&power);
+                }
+                power = power.double();
+            }
+            if window.get(WINDOW_BITS - 1) == Some(&true) {
+                multiplier = multiplier.neg();
+            }
+            result = result.add(&multiplier);
+
+            for _ in 0..=WINDOW_BITS {
+                window_base = window_base.double();
+            }
+        }
+    }
+    result.to_affine()
+}
+
+/// Baby Jubjub point in projective coordinates `(X : Y : Z)`.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fr,
+    y: Fr,
+    z: Fr,
+}
+
+impl Point {
+    const IDENTITY: Point = Point {
+        x: Fr::ZERO,
+        y: Fr::ONE,
+        z: Fr::ONE,
+    };
+
+    fn from_affine((x, y): (Fr, Fr)) -> Self {
+        Point { x, y, z: Fr::ONE }
+    }
+
+    fn to_affine(self) -> (Fr, Fr) {
+        let z_inverse = self.z.inverse().expect("Edwards addition is complete");
+        (self.x * z_inverse, self.y * z_inverse)
+    }
+
+    /// Adds two points with the complete twisted Edwards formulas.
+    fn add(&self, other: &Point) -> Point {
+        let a = self.z * other.z;
+        let b = a.square();
+        let c = self.x * other.x;
+        let d = self.y * other.y;
+        let e = D * c * d;
+        let f = b - e;
+        let g = b + e;
+        Point {
+            x: a * f * ((self.x + self.y) * (other.x + other.y) - c - d),
+            y: a * g * (d - A * c),
+            z: f * g,
+        }
+    }
+
+    fn double(&self) -> Point {
+        self.add(self)
+    }
+
+    fn neg(&self) -> Point {
+        Point {
+            x: -self.x,
+            ..*self
+        }
+    }
+}
+
+/// Generators of the segments, as in circomlib's `pedersen.circom`.
+const BASES: [(Fr, Fr); 10] = [
+    (
+        MontFp!("10457101036533406547632367118273992217979173478358440826365724437999023779287"),
+        MontFp!("19824078218392094440610104313265183977899662750282163392862422243483260492317"),
+    ),
+    (
+        MontFp!("2671756056509184035029146175565761955751135805354291559563293617232983272177"),
+        MontFp!("2663205510731142763556352975002641716101654201788071096152948830924149045094"),
+    ),
+    (
+        MontFp!("5802099305472655231388284418920769829666717045250560929368476121199858275951"),
+        MontFp!("5980429700218124965372158798884772646841287887664001482443826541541529227896"),
+    ),
+    (
+        MontFp!("7107336197374528537877327281242680114152313102022415488494307685842428166594"),
+        MontFp!("2857869773864086953506483169737724679646433914307247183624878062391496185654"),
+    ),
+    (
+        MontFp!("20265828622013100949498132415626198973119240347465898028410217039057588424236"),
+        MontFp!("1160461593266035632937973507065134938065359936056410650153315956301179689506"),
+    ),
+    (
+        MontFp!("1487999857809287756929114517587739322941449154962237464737694709326309567994"),
+        MontFp!("14017256862867289575056460215526364897734808720610101650676790868051368668003"),
+    ),
+    (
+        MontFp!("14618644331049802168996997831720384953259095788558646464435263343433563860015"),
+        MontFp!("13115243279999696210147231297848654998887864576952244320558158620692603342236"),
+    ),
+    (
+        MontFp!("6814338563135591367010655964669793483652536871717891893032616415581401894627"),
+        MontFp!("13660303521961041205824633772157003587453809761793065294055279768121314853695"),
+    ),
+    (
+        MontFp!("3571615583211663069428808372184817973703476260057504149923239576077102575715"),
+        MontFp!("11981351099832644138306422070127357074117642951423551606012551622164230222506"),
+    ),
+    (
+        MontFp!("18597552580465440374022635246985743886550544261632147935254624835147509493269"),
+        MontFp!("6753322320275422086923032033899357299485124665258735666995435957890214041481"),
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::vec;
+
+    #[test]
+    fn test_pedersen_hash() {
+        assert_eq!(
+            pedersen_hash_bytes(b"Hello"),
+            (
+                MontFp!(
+                    "13057869703420394250544403835227057665059779354002305870213426705081885688482"
+                ),
+                MontFp!(
+                    "5422822308853265117631996831487612352180561624992420021537578261723609534478"
+                )
+            )
+        );
+
+        let hash: IMTHashFunction<Fr> = hash_nodes;
+        let mut imt = LeanIMT::with_zero(hash, Fr::ZERO);
+        imt.insert_many(vec![Fr::from(1u64), Fr::from(2u64)])
+            .unwrap();
+        assert_eq!(
+            imt.root(),
+            Some(MontFp!(
+                "11454185318328669137695294241820922652272811765749293900752375045586364709713"
+            ))
+        );
+        imt.insert(Fr::from(3u64)).unwrap();
+        assert!(imt.generate_proof(2).unwrap().verify(hash));
+
+        // Every generator is on the curve
+        for (x, y) in BASES {
+            let (x2, y2) = (x.square(), y.square());
+            assert_eq!(A * x2 + y2, Fr::ONE + D * x2 * y2);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Poseidon hasher with the `P128Pow5T3` parameters of halo2, compatible with
+//! the Poseidon chip of `halo2_gadgets`.
+//!
+//! The permutation has a width of 3 and a rate of 2, 8 full rounds and 56
+//! partial rounds, and the S-box `x^5`. Its round constants and its Cauchy MDS
+//! matrix are derived from the Grain LFSR of the Poseidon reference
+//! implementation, seeded with the size of the field, so the same parameters
+//! are generated for the Pallas and Vesta fields of the Zcash halo2 and for
+//! the BN254 scalar field of the halo2 forks proving on Ethereum.
+//!
+//! A parent is hashed as `Hash<F, P128Pow5T3, ConstantLength<L>, 3, 2>` does
+//! for its `L` children: the state starts as zeros with `L * 2^64` in its
+//! capacity element, the children are absorbed two at a time, padded with
+//! zeros to an even count, and the hash is the first element of the state
+//! after the last permutation. A binary tree hashed with [`hash_nodes`] has
+//! the root computed by a halo2 Merkle chip hashing `[left, right]` with
+//! `ConstantLength<2>`.
+//!
+//! Field elements are encoded as halo2 does with `PrimeField::to_repr`: 32
+//! bytes in little-endian order. [`to_repr`] and [`from_repr`] convert roots
+//! and leaves to and from that encoding, e.g. to pass a root as a public
+//! input.
+
+use alloc::vec::Vec;
+use std::sync::OnceLock;
+
+use ark_ff::{BigInteger, PrimeField};
+
+/// Number of elements of the state.
+pub const WIDTH: usize = 3;
+
+/// Number of elements absorbed per permutation.
+pub const RATE: usize = 2;
+
+/// Number of full rounds, half of them before the partial rounds.
+pub const FULL_ROUNDS: usize = 8;
+
+/// Number of partial rounds.
+pub const PARTIAL_ROUNDS: usize = 56;
+
+/// Number of bits of the state of the Grain LFSR.
+const GRAIN_BITS: usize = 80;
+
+/// Fields of the Pasta curves, the fields of the Zcash halo2.
+pub mod pasta {
+    // The derived configurations check for an `asm` feature of ark-ff
+    #![allow(unexpected_cfgs)]
+
+    use ark_ff::fields::{Fp256, MontBackend, MontConfig};
+
+    #[derive(MontConfig)]
+    #[modulus = "28948022309329048855892746252171976963363056481941560715954676764349967630337"]
+    #[generator = "5"]
+    pub struct FpMontConfig;
+
+    #[derive(MontConfig)]
+    #[modulus = "28948022309329048855892746252171976963363056481941647379679742748393362948097"]
+    #[generator = "5"]
+    pub struct FqMontConfig;
+
+    /// Base field of Pallas and scalar field of Vesta, `pasta_curves::Fp`.
+    pub type Fp = Fp256<MontBackend<FpMontConfig, 4>>;
+
+    /// Base field of Vesta and scalar field of Pallas, `pasta_curves::Fq`.
+    pub type Fq = Fp256<MontBackend<FqMontConfig, 4>>;
+}
+
+/// Round constants and MDS matrix of a Poseidon permutation of width 3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Poseidon<F> {
+    round_constants: Vec<[F; WIDTH]>,
+    mds: [[F; WIDTH]; WIDTH],
+    full_rounds: usize,
+}
+
+impl<F: PrimeField> Poseidon<F> {
+    /// Generates the `P128Pow5T3` parameters over `F`.
+    pub fn p128_pow5_t3() -> Self {
+        Self::generate(FULL_ROUNDS, PARTIAL_ROUNDS)
+    }
+
+    /// Generates the parameters of a permutation of width 3 with the S-box
+    /// `x^5` and the given numbers of rounds, as the reference
+    /// implementation does, taking the first MDS matrix sampled.
+    pub fn generate(full_rounds: usize, partial_rounds: usize) -> Self {
+        let mut grain = Grain::new(F::MODULUS_BIT_SIZE, full_rounds, partial_rounds);
+        let round_constants = (0..full_rounds + partial_rounds)
+            .map(|_| core::array::from_fn(|_| grain.next_field_element()))
+            .collect();
+
+        // The 2 * WIDTH elements of a Cauchy matrix must be distinct
+        let elements = loop {
+            let elements: Vec<F> = (0..2 * WIDTH)
+                .map(|_| grain.next_field_element_reduced())
+                .collect();
+            if (1..elements.len()).all(|i| !elements[..i].contains(&elements[i])) {
+                break elements;
+            }
+        };
+        let (xs, ys) = elements.split_at(WIDTH);
+        let mds = core::array::from_fn(|i| {
+            core::array::from_fn(|j| (xs[i] + ys[j]).inverse().expect("MDS elements sum to zero"))
+        });
+
+        Poseidon {
+            round_constants,
+            mds,
+            full_rounds,
+        }
+    }
+
+    /// Returns the round constants, one row per round.
+    pub fn round_constants(&self) -> &[[F; WIDTH]] {
+        &self.round_constants
+    }
+
+    pub fn mds(&self) -> &[[F; WIDTH]; WIDTH] {
+        &self.mds
+    }
+
+    /// Applies the permutation to `state`.
+    pub fn permute(&self, state: &mut [F; WIDTH]) {
+        let half_full = self.full_rounds / 2;
+        let partial_end = self.round_constants.len() - half_full;
+        for (round, constants) in self.round_constants.iter().enumerate() {
+            for (element, constant) in state.iter_mut().zip(constants) {
+                *element += constant;
+            }
+            if round < half_full || round >= partial_end {
+                state
+                    .iter_mut()
+                    .for_each(|element| *element = sbox(*element));
+            } else {
+                state[0] = sbox(state[0]);
+            }
+            *state = core::array::from_fn(|i| {
+                self.mds[i]
+                    .iter()
+                    .zip(state.iter())
+                    .map(|(entry, element)| *entry * element)
+                    .sum()
+            });
+        }
+    }
+
+    /// Hashes `inputs` with the `ConstantLength` domain of their length.
+    pub fn hash(&self, inputs: &[F]) -> F {
+        let mut state = [F::ZERO; WIDTH];
+        state[RATE] = F::from((inputs.len() as u128) << 64);
+        for chunk in inputs.chunks(RATE) {
+            for (element, input) in state.iter_mut().zip(chunk) {
+                *element += input;
+            }
+            self.permute(&mut state);
+        }
+        if inputs.is_empty() {
+            self.permute(&mut state);
+        }
+        state[0]
+    }
+}
+
+fn sbox<F: PrimeField>(element: F) -> F {
+    element.square().square() * element
+}
+
+/// Fields with cached `P128Pow5T3` parameters, to hash nodes with a plain
+/// function.
+pub trait PoseidonField: PrimeField {
+    fn poseidon() -> &'static Poseidon<Self>;
+}
+
+macro_rules! impl_poseidon_field {
+    ($field:ty) => {
+        impl PoseidonField for $field {
+            fn poseidon() -> &'static Poseidon<Self> {
+                static PARAMETERS: OnceLock<Poseidon<$field>> = OnceLock::new();
+                PARAMETERS.get_or_init(Poseidon::p128_pow5_t3)
+            }
+        }
+    };
+}
+
+impl_poseidon_field!(ark_bn254::Fr);
+impl_poseidon_field!(pasta::Fp);
+impl_poseidon_field!(pasta::Fq);
+
+/// Hashes nodes with the `P128Pow5T3` parameters over their field.
+///
+/// The parameters are generated on the first call for each field.
+pub fn hash_nodes<F: PoseidonField>(nodes: Vec<F>) -> F {
+    F::poseidon().hash(&nodes)
+}
+
+/// Encodes a field element as its 32 little-endian bytes, as `to_repr` in
+/// halo2.
+///
+/// # Panics
+///
+/// Panics with fields of more than 256 bits.
+pub fn to_repr<F: PrimeField>(element: &F) -> [u8; 32] {
+    let mut repr = [0; 32];
+    let bytes = element.into_bigint().to_bytes_le();
+    repr[..bytes.len()].copy_from_slice(&bytes);
+    repr
+}
+
+/// Decodes a field element from its 32 little-endian bytes.
+///
+/// Fails with "Invalid field element" if the bytes are not the canonical
+/// encoding of an element, as `from_repr` in halo2.
+pub fn from_repr<F: PrimeField>(repr: &[u8; 32]) -> Result<F, &'static str> {
+    let element = F::from_le_bytes_mod_order(repr);
+    if to_repr(&element) != *repr {
+        return Err("Invalid field element");
+    }
+    Ok(element)
+}
+
+/// Grain LFSR of the Poseidon reference implementation, generating the
+/// parameters of a permutation.
+struct Grain {
+    state: [bool; GRAIN_BITS],
+    /// Position of the oldest bit of the state.
+    head: usize,
+    field_bits: usize,
+}
+
+impl Grain {
+    fn new(field_bits: u32, full_rounds: usize, partial_rounds: usize) -> Self {
+        // Prime field, S-box x^alpha, then the sizes, most significant bit
+        // first, and ones
+        let fields = [
+            (2, 1),
+            (4, 0),
+            (12, field_bits as usize),
+            (12, WIDTH),
+            (10, full_rounds),
+            (10, partial_rounds),
+        ];
+        let mut state = [true; GRAIN_BITS];
+        let mut offset = 0;
+        for (len, value) in fields {
+            for i in 0..len {
+                state[offset + i] = value >> (len - 1 - i) & 1 == 1;
+            }
+            offset += len;
+        }
+
+        let mut grain = Grain {
+            state,
+            head: 0,
+            field_bits: field_bits as usize,
+        };
+        for _ in 0..160 {
+            grain.step();
+        }
+        grain
+    }
+
+    /// Shifts the register, returning the new bit.
+    fn step(&mut self) -> bool {
+        let bit = |i: usize| self.state[(self.head + i) % GRAIN_BITS];
+        let new_bit = bit(62) ^ bit(51) ^ bit(38) ^ bit(23) ^ bit(13) ^ bit(0);
+        self.state[self.head] = new_bit;
+        self.head = (self.head + 1) % GRAIN_BITS;
+        new_bit
+    }
+
+    /// Returns the next output bit: bits are drawn in pairs, the second one
+    /// being output if the first one is set.
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let keep = self.step();
+            let bit = self.step();
+            if keep {
+                return bit;
+            }
+        }
+    }
+
+    /// Returns the next `field_bits` bits as an integer, most significant bit
+    /// first.
+    fn next_bits<B: BigInteger>(&mut self) -> B {
+        let bits: Vec<bool> = (0..self.field_bits).map(|_| self.next_bit()).collect();
+        B::from_bits_be(&bits)
+    }
+
+    /// Samples a field element, drawing again while the bits exceed the
+    /// modulus.
+    fn next_field_element<F: PrimeField>(&mut self) -> F {
+        loop {
+            if let Some(element) = F::from_bigint(self.next_bits()) {
+                return element;
+            }
+        }
+    }
+
+    /// Samples a field element, reducing the bits modulo the field order.
+    fn next_field_element_reduced<F: PrimeField>(&mut self) -> F {
+        let bits: F::BigInt = self.next_bits();
+        F::from_be_bytes_mod_order(&bits.to_bytes_be())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pasta::Fp;
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::vec;
+    use ark_bn254::Fr;
+    use ark_ff::MontFp;
+
+    #[test]
+    fn test_grain_parameters() {
+        // circomlib generated its BN254 parameters of width 3, with 57
+        // partial rounds, with the same reference implementation
+        let poseidon = Poseidon::<Fr>::generate(8, 57);
+        assert_eq!(poseidon.round_constants().len(), 65);
+        assert_eq!(
+            poseidon.round_constants()[0][..2],
+            [
+                MontFp!(
+                    "6745197990210204598374042828761989596302876299545964402857411729872131034734"
+                ),
+                MontFp!(
+                    "426281677759936592021316809065178817848084678679510574715894138690250139748"
+                ),
+            ]
+        );
+        assert_eq!(
+            poseidon.round_constants()[64][2],
+            MontFp!(
+                "13409242754315411433193860530743374419854094495153957441316635981078068351329"
+            )
+        );
+        assert_eq!(
+            poseidon.mds()[0][0],
+            MontFp!("7511745149465107256748700652201246547602992235352608707588321460060273774987")
+        );
+        assert_eq!(
+            poseidon.mds()[2][2],
+            MontFp!(
+                "11597556804922396090267472882856054602429588299176362916247939723151043581408"
+            )
+        );
+    }
+
+    #[test]
+    fn test_poseidon_halo2_tree() {
+        let hash: IMTHashFunction<Fp> = hash_nodes;
+        let leaves: Vec<Fp> = (1..=3u64).map(Fp::from).collect();
+        let mut imt = LeanIMT::with_zero(hash, Fp::from(0u64));
+        imt.insert_many(leaves.clone()).unwrap();
+
+        let poseidon = Fp::poseidon();
+        assert_eq!(
+            poseidon.round_constants().len(),
+            FULL_ROUNDS + PARTIAL_ROUNDS
+        );
+        let mut state = [leaves[0], leaves[1], Fp::from(2u128 << 64)];
+        poseidon.permute(&mut state);
+        let root = hash(vec![state[0], leaves[2]]);
+        assert_eq!(imt.root(), Some(root));
+        assert!(imt.generate_proof(1).unwrap().verify(hash));
+
+        // Odd inputs are padded with zeros, under their own length
+        assert_ne!(
+            poseidon.hash(&leaves),
+            poseidon.hash(&[leaves[0], leaves[1], leaves[2], Fp::from(0u64)])
+        );
+
+        let repr = to_repr(&root);
+        assert_eq!(from_repr::<Fp>(&repr), Ok(root));
+        assert_eq!(to_repr(&Fp::from(258u64))[..3], [2, 1, 0]);
+        assert_eq!(from_repr::<Fp>(&[0xff; 32]), Err("Invalid field element"));
+    }
+}
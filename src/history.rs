@@ -0,0 +1,235 @@
+//! Queries against past versions of a tree.
+//!
+//! Every mutation increments the version of the tree, and the changelog keeps
+//! the leaves it changed and the root it reached. The leaves at an older
+//! version are rebuilt by undoing the newer changes, so past roots and proofs
+//! can be served for as long as the changelog retains them: enable it with
+//! `set_changelog_capacity`, and drop old versions with `prune_history`.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::proof::build_proof;
+use crate::{HashSet, LeanIMT, LeanIMTStore, MerkleProof};
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Returns the root of the tree at `version`.
+    pub fn root_at(&self, version: u64) -> Result<Option<N>, &'static str> {
+        if version > self.version {
+            return Err("Unknown version");
+        }
+        if version == self.version {
+            return Ok(self.root());
+        }
+        // Older roots are recorded by the changelog, or rebuilt from the leaves
+        // once the record of that version has been pruned
+        if let Some(root) = self.changelog.root_at(version) {
+            return Ok(Some(root.clone()));
+        }
+        let leaves = self.leaves_at(version)?;
+        Ok(compute_root(leaves, self.arity, self.hash.as_fn()))
+    }
+
+    /// Generates the proof of `leaf` against the root of the tree at `version`.
+    ///
+    /// If the leaf appeared several times at that version, its lowest index
+    /// is used.
+    pub fn proof_at(&self, version: u64, leaf: &N) -> Result<MerkleProof<N>, &'static str> {
+        self.ensure_binary()?;
+        if version > self.version {
+            return Err("Unknown version");
+        }
+
+        let leaves = self.leaves_at(version)?;
+        let index = leaves
+            .iter()
+            .position(|other| other == leaf && *leaf != self.zero)
+            .ok_or("Leaf does not exist")?;
+        build_proof(leaves, index, self.hash.as_fn())
+    }
+
+    /// Returns the distinct roots of the latest `count` versions, newest
+    /// first, as a contract accepting the last `count` roots keeps them.
+    ///
+    /// Versions of an empty tree have no root and are skipped. Fails like
+    /// `root_at` if some of the versions are no longer retained.
+    pub fn recent_roots(&self, count: usize) -> Result<Vec<N>, &'static str> {
+        let mut roots: Vec<N> = Vec::new();
+        let Some(back) = count.checked_sub(1) else {
+            return Ok(roots);
+        };
+        let oldest = self.version.saturating_sub(back as u64);
+        for version in (oldest..=self.version).rev() {
+            if let Some(root) = self.root_at(version)? {
+                if roots.last() != Some(&root) {
+                    roots.push(root);
+                }
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Checks a proof against the roots of the latest `count` versions, so
+    /// that proofs generated shortly before a change are still accepted.
+    pub fn verify_against_recent(
+        &self,
+        proof: &MerkleProof<N>,
+        count: usize,
+    ) -> Result<bool, &'static str> {
+        let roots: HashSet<N> = self.recent_roots(count)?.into_iter().collect();
+        Ok(self.verify_against(proof, &roots))
+    }
+
+    /// Forgets the versions older than `version`, which can no longer be
+    /// queried.
+    pub fn prune_history(&mut self, version: u64) {
+        self.changelog.prune_through(version);
+    }
+
+    /// Returns the leaves of the tree at `version`, ordered by index.
+    pub(crate) fn leaves_at(&self, version: u64) -> Result<Vec<N>, &'static str> {
+        let changes: Vec<_> = self.changes_since(version)?.collect();
+        let mut leaves = self.ordered_leaves()?;
+        for change in changes.into_iter().rev() {
+            match &change.old_leaf {
+                Some(old_leaf) => leaves[change.index] = old_leaf.clone(),
+                None if change.appended => leaves.truncate(change.index),
+                None => leaves[change.index] = self.zero.clone(),
+            }
+        }
+        Ok(leaves)
+    }
+}
+
+/// Computes the root of a list of leaves.
+pub(crate) fn compute_root<N: Clone>(
+    mut nodes: Vec<N>,
+    arity: usize,
+    hash: impl Fn(Vec<N>) -> N,
+) -> Option<N> {
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(arity)
+            .map(|children| match children {
+                [child] => child.clone(),
+                _ => hash(children.to_vec()),
+            })
+            .collect();
+    }
+    nodes.pop()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_time_travel() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_changelog_capacity(16);
+
+        let mut roots = vec![None];
+        let mut proofs = vec![];
+        for i in 1..=4 {
+            imt.insert(format!("leaf{}", i)).unwrap();
+            roots.push(imt.root());
+            proofs.push(imt.generate_proof(0).unwrap());
+        }
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+        imt.insert_many(vec!["leaf5".to_string(), "leaf6".to_string()])
+            .unwrap();
+        assert_eq!(imt.get_version(), 6);
+
+        for version in 0..=4 {
+            assert_eq!(imt.root_at(version).unwrap(), roots[version as usize]);
+        }
+        assert_eq!(imt.root_at(6).unwrap(), imt.root());
+        assert_eq!(imt.root_at(7).unwrap_err(), "Unknown version");
+
+        for version in 1..=4 {
+            let proof = imt.proof_at(version, &"leaf1".to_string()).unwrap();
+            assert_eq!(proof, proofs[version as usize - 1]);
+            assert!(proof.verify(hash));
+        }
+        assert!(imt.proof_at(4, &"leaf2".to_string()).is_ok());
+        assert_eq!(
+            imt.proof_at(5, &"leaf2".to_string()).unwrap_err(),
+            "Leaf does not exist"
+        );
+        assert_eq!(
+            imt.proof_at(2, &"leaf3".to_string()).unwrap_err(),
+            "Leaf does not exist"
+        );
+
+        imt.prune_history(3);
+        assert_eq!(imt.root_at(3).unwrap(), roots[3]);
+        assert_eq!(imt.root_at(4).unwrap(), roots[4]);
+        assert!(imt.proof_at(3, &"leaf1".to_string()).is_ok());
+        assert_eq!(imt.root_at(2).unwrap_err(), "Changelog has been pruned");
+        assert!(imt.proof_at(2, &"leaf1".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_history_needs_changelog() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.set_changelog_capacity(2);
+        imt.insert("leaf2".to_string()).unwrap();
+        imt.insert("leaf3".to_string()).unwrap();
+
+        // Versions are kept from the moment the changelog is enabled
+        assert_eq!(imt.root_at(2).unwrap(), Some("leaf1,leaf2".to_string()));
+        assert_eq!(imt.root_at(1).unwrap(), Some("leaf1".to_string()));
+        assert!(imt.root_at(0).is_err());
+    }
+
+    #[test]
+    fn test_verify_against_recent_roots() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_changelog_capacity(16);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        let proof = imt.generate_proof(0).unwrap();
+        imt.insert("leaf3".to_string()).unwrap();
+        imt.insert("leaf4".to_string()).unwrap();
+
+        assert_eq!(
+            imt.recent_roots(3).unwrap(),
+            vec![
+                "leaf1,leaf2,leaf3,leaf4".to_string(),
+                "leaf1,leaf2,leaf3".to_string(),
+                "leaf1,leaf2".to_string(),
+            ]
+        );
+        assert!(imt.recent_roots(0).unwrap().is_empty());
+        assert_eq!(imt.recent_roots(16).unwrap().len(), 3);
+
+        // The proof is only accepted while its root is recent enough
+        assert!(!imt.verify_proof(&proof));
+        assert_eq!(imt.verify_against_recent(&proof, 3), Ok(true));
+        assert_eq!(imt.verify_against_recent(&proof, 2), Ok(false));
+        let roots = imt.recent_roots(3).unwrap().into_iter().collect();
+        assert!(imt.verify_against(&proof, &roots));
+        let mut tampered = proof.clone();
+        tampered.leaf = "leaf2".to_string();
+        assert!(!imt.verify_against(&tampered, &roots));
+
+        imt.prune_history(2);
+        assert_eq!(
+            imt.verify_against_recent(&proof, 4),
+            Err("Changelog has been pruned")
+        );
+    }
+}
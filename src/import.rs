@@ -0,0 +1,323 @@
+//! Streaming import of leaves from text files.
+//!
+//! Allowlists and membership sets are often shipped as files of several
+//! gigabytes: one leaf per line, a CSV column or a JSONL field. A
+//! [`LeafReader`] parses such a file lazily, and `import_leaves` inserts the
+//! leaves it yields in chunks of [`ImportOptions::chunk_size`] with
+//! `insert_many`, so only one chunk is held in memory. Files ending in `.gz`
+//! are decompressed on the fly with the `gzip` feature.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines, Read};
+use std::path::Path;
+
+use core::hash::Hash;
+use serde_json::Value;
+
+use crate::{LeanIMT, LeanIMTStore};
+
+/// Layout of a file of leaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafFormat {
+    /// One leaf per line; blank lines are skipped.
+    Lines,
+    /// One leaf per row, in the configured column.
+    Csv,
+    /// One JSON string, number or object per line, objects holding the leaf
+    /// in the configured field.
+    Jsonl,
+}
+
+impl LeafFormat {
+    /// Guesses the format from the extension of `path`, ignoring a `.gz`
+    /// suffix: `.csv` files are CSV, `.jsonl` and `.ndjson` files JSONL,
+    /// and other files hold one leaf per line.
+    pub fn from_path(path: &Path) -> Self {
+        let path = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => path.file_stem().map(Path::new).unwrap_or(path),
+            _ => path,
+        };
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => LeafFormat::Csv,
+            Some("jsonl") | Some("ndjson") => LeafFormat::Jsonl,
+            _ => LeafFormat::Lines,
+        }
+    }
+}
+
+/// How leaves are read from a file and inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub format: LeafFormat,
+    /// CSV column holding the leaves, 0 by default.
+    pub column: usize,
+    /// Whether the first row of a CSV file is a header to skip.
+    pub header: bool,
+    /// JSONL object field holding the leaves, `"leaf"` by default.
+    pub field: String,
+    /// Number of leaves inserted by each `insert_many` call.
+    pub chunk_size: usize,
+}
+
+impl ImportOptions {
+    pub fn new(format: LeafFormat) -> Self {
+        ImportOptions {
+            format,
+            column: 0,
+            header: false,
+            field: "leaf".to_string(),
+            chunk_size: 65_536,
+        }
+    }
+}
+
+enum Rows<R: Read> {
+    Lines(Lines<BufReader<R>>),
+    Csv(csv::StringRecordsIntoIter<R>),
+    Jsonl(Lines<BufReader<R>>),
+}
+
+/// Iterator over the leaves of a file, as text, parsed as it is read.
+pub struct LeafReader<R: Read> {
+    rows: Rows<R>,
+    column: usize,
+    field: String,
+}
+
+impl<R: Read> LeafReader<R> {
+    pub fn new(reader: R, options: &ImportOptions) -> Self {
+        let rows = match options.format {
+            LeafFormat::Lines => Rows::Lines(BufReader::new(reader).lines()),
+            LeafFormat::Csv => Rows::Csv(
+                csv::ReaderBuilder::new()
+                    .has_headers(options.header)
+                    .flexible(true)
+                    .from_reader(reader)
+                    .into_records(),
+            ),
+            LeafFormat::Jsonl => Rows::Jsonl(BufReader::new(reader).lines()),
+        };
+        LeafReader {
+            rows,
+            column: options.column,
+            field: options.field.clone(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for LeafReader<R> {
+    type Item = Result<String, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.rows {
+            Rows::Lines(lines) => loop {
+                let line = match lines.next()? {
+                    Ok(line) => line,
+                    Err(_) => return Some(Err("Failed to read leaves")),
+                };
+                if !line.trim().is_empty() {
+                    return Some(Ok(line.trim().to_string()));
+                }
+            },
+            Rows::Csv(records) => Some(match records.next()? {
+                Ok(row) => row
+                    .get(self.column)
+                    .map(|leaf| leaf.trim().to_string())
+                    .ok_or("Missing column"),
+                Err(_) => Err("Invalid CSV"),
+            }),
+            Rows::Jsonl(lines) => loop {
+                let line = match lines.next()? {
+                    Ok(line) => line,
+                    Err(_) => return Some(Err("Failed to read leaves")),
+                };
+                if !line.trim().is_empty() {
+                    return Some(parse_json_leaf(&line, &self.field));
+                }
+            },
+        }
+    }
+}
+
+fn parse_json_leaf(line: &str, field: &str) -> Result<String, &'static str> {
+    let value = match serde_json::from_str(line).map_err(|_| "Invalid JSON")? {
+        Value::Object(mut object) => object.remove(field).ok_or("Missing field")?,
+        value => value,
+    };
+    match value {
+        Value::String(leaf) => Ok(leaf),
+        Value::Number(leaf) => Ok(leaf.to_string()),
+        _ => Err("Invalid leaf"),
+    }
+}
+
+/// Opens the file at `path`, `-` being stdin, decompressing it if its name
+/// ends in `.gz`.
+///
+/// Compressed files need the `gzip` feature.
+pub fn open_leaves(path: &Path) -> Result<Box<dyn Read>, &'static str> {
+    if path == Path::new("-") {
+        return Ok(Box::new(io::stdin()));
+    }
+    let file = File::open(path).map_err(|_| "Failed to open file")?;
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        #[cfg(feature = "gzip")]
+        return Ok(Box::new(flate2::read::MultiGzDecoder::new(file)));
+        #[cfg(not(feature = "gzip"))]
+        return Err("Compressed files need the gzip feature");
+    }
+    Ok(Box::new(file))
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Inserts the leaves read from `reader`, each converted to a node by
+    /// `parse`, and returns the number of leaves inserted.
+    ///
+    /// Leaves are inserted in chunks of `options.chunk_size`, and `progress`
+    /// is called with the number of leaves inserted so far after each one.
+    /// Each chunk is inserted atomically, but the chunks before a failing
+    /// one stay in the tree.
+    pub fn import_leaves<R: Read>(
+        &mut self,
+        reader: R,
+        options: &ImportOptions,
+        parse: impl Fn(String) -> Result<N, &'static str>,
+        mut progress: impl FnMut(usize),
+    ) -> Result<usize, &'static str> {
+        let chunk_size = options.chunk_size.max(1);
+        let mut leaves = LeafReader::new(reader, options);
+        let mut imported = 0;
+
+        loop {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            for leaf in leaves.by_ref().take(chunk_size) {
+                chunk.push(parse(leaf?)?);
+            }
+            if chunk.is_empty() {
+                return Ok(imported);
+            }
+            let inserted = chunk.len();
+            self.insert_many(chunk)?;
+            imported += inserted;
+            progress(imported);
+        }
+    }
+
+    /// Inserts the leaves of the file at `path`, opened with `open_leaves`,
+    /// like `import_leaves`.
+    pub fn import_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &ImportOptions,
+        parse: impl Fn(String) -> Result<N, &'static str>,
+        progress: impl FnMut(usize),
+    ) -> Result<usize, &'static str> {
+        self.import_leaves(open_leaves(path.as_ref())?, options, parse, progress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn read(text: &str, options: &ImportOptions) -> Result<Vec<String>, &'static str> {
+        LeafReader::new(text.as_bytes(), options).collect()
+    }
+
+    #[test]
+    fn test_leaf_reader() {
+        let lines = "alice\n\n  bob \n";
+        let options = ImportOptions::new(LeafFormat::Lines);
+        assert_eq!(
+            read(lines, &options),
+            Ok(vec!["alice".into(), "bob".into()])
+        );
+
+        let csv = "amount,address\n10,alice\n20,\"b,ob\"\n";
+        let mut options = ImportOptions::new(LeafFormat::Csv);
+        options.column = 1;
+        options.header = true;
+        assert_eq!(read(csv, &options), Ok(vec!["alice".into(), "b,ob".into()]));
+        options.column = 2;
+        assert_eq!(read(csv, &options), Err("Missing column"));
+
+        let jsonl = "{\"address\": \"alice\"}\n\"bob\"\n42\n";
+        let mut options = ImportOptions::new(LeafFormat::Jsonl);
+        options.field = "address".to_string();
+        assert_eq!(
+            read(jsonl, &options),
+            Ok(vec!["alice".into(), "bob".into(), "42".into()])
+        );
+        assert_eq!(read("{}", &options), Err("Missing field"));
+        assert_eq!(read("[1]", &options), Err("Invalid leaf"));
+
+        assert_eq!(LeafFormat::from_path(Path::new("a.csv")), LeafFormat::Csv);
+        assert_eq!(
+            LeafFormat::from_path(Path::new("a.ndjson.gz")),
+            LeafFormat::Jsonl
+        );
+        assert_eq!(LeafFormat::from_path(Path::new("a.txt")), LeafFormat::Lines);
+    }
+
+    #[test]
+    fn test_import_leaves() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let text: String = (1..=10).map(|i| format!("leaf{}\n", i)).collect();
+        let mut options = ImportOptions::new(LeafFormat::Lines);
+        options.chunk_size = 4;
+
+        let mut imt = LeanIMT::new(hash);
+        let mut reported = Vec::new();
+        let imported = imt
+            .import_leaves(text.as_bytes(), &options, Ok, |n| reported.push(n))
+            .unwrap();
+        assert_eq!(imported, 10);
+        assert_eq!(reported, vec![4, 8, 10]);
+
+        let mut reference = LeanIMT::new(hash);
+        reference
+            .insert_many((1..=10).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        assert_eq!(imt.root(), reference.root());
+
+        // Chunks before a failing one stay in the tree
+        let mut imt = LeanIMT::new(hash);
+        let result = imt.import_leaves(
+            "a\nb\nc\na\n".as_bytes(),
+            &ImportOptions {
+                chunk_size: 2,
+                ..ImportOptions::new(LeafFormat::Lines)
+            },
+            Ok,
+            |_| {},
+        );
+        assert_eq!(result, Err("Leaf already exists"));
+        assert_eq!(imt.get_size(), 2);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_import_gzip_file() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let path = std::env::temp_dir().join(format!("lean-imt-{}.jsonl.gz", std::process::id()));
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Default::default());
+        encoder
+            .write_all(b"{\"leaf\": \"a\"}\n{\"leaf\": \"b\"}\n")
+            .unwrap();
+        encoder.finish().unwrap();
+
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        let options = ImportOptions::new(LeafFormat::from_path(&path));
+        assert_eq!(imt.import_file(&path, &options, Ok, |_| {}), Ok(2));
+        assert_eq!(imt.root(), Some("a,b".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+}
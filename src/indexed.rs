@@ -0,0 +1,308 @@
+//! Indexed Merkle trees, which prove that a value is not in a set.
+//!
+//! As in the Aztec nullifier trees, each leaf holds a value with the index
+//! and value of the next larger one, so the append-only leaves form a sorted
+//! linked list. A value is absent if its low leaf, the leaf with the largest
+//! smaller value, points to a larger value or to no value at all: proving
+//! the low leaf proves the absence. The list starts with a leaf holding the
+//! zero value, inserted with the tree, and the last leaf of the list points
+//! to index 0, standing for no next value.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{IMTHashFunction, IMTNode, LeanIMT, MerkleProof};
+
+/// Hashes an indexed leaf into a node of the tree.
+pub type IndexedLeafHashFunction<N = IMTNode> = fn(&IndexedLeaf<N>) -> N;
+
+/// Leaf of an indexed tree, pointing to the leaf with the next larger value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IndexedLeaf<N = IMTNode> {
+    pub value: N,
+    /// Index of the next leaf, or 0 for the largest value.
+    pub next_index: usize,
+    /// Value of the next leaf, or the zero value for the largest value.
+    pub next_value: N,
+}
+
+impl<N: PartialOrd> IndexedLeaf<N> {
+    /// Returns true if `value` falls strictly between the value of this leaf
+    /// and the next one.
+    pub fn is_low_leaf_of(&self, value: &N) -> bool {
+        self.value < *value && (self.next_index == 0 || *value < self.next_value)
+    }
+}
+
+/// Proof that `value` is not in an indexed tree: the inclusion proof of its
+/// low leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NonMembershipProof<N = IMTNode> {
+    pub value: N,
+    pub low_leaf: IndexedLeaf<N>,
+    pub proof: MerkleProof<N>,
+}
+
+impl<N: Clone + PartialOrd> NonMembershipProof<N> {
+    /// Checks that the low leaf brackets the value and hashes up to the root
+    /// of the proof.
+    pub fn verify(
+        &self,
+        hash: impl Fn(Vec<N>) -> N,
+        hash_leaf: IndexedLeafHashFunction<N>,
+    ) -> bool {
+        self.verify_with(hash, hash_leaf, N::eq)
+    }
+
+    /// Checks the proof like `verify`, comparing the hashed low leaf and the
+    /// computed root with `eq`.
+    pub fn verify_with(
+        &self,
+        hash: impl Fn(Vec<N>) -> N,
+        hash_leaf: IndexedLeafHashFunction<N>,
+        eq: impl Fn(&N, &N) -> bool,
+    ) -> bool {
+        self.low_leaf.is_low_leaf_of(&self.value)
+            && eq(&hash_leaf(&self.low_leaf), &self.proof.leaf)
+            && self.proof.verify_with(hash, eq)
+    }
+}
+
+/// Indexed Merkle tree, keeping its leaves in memory.
+#[derive(Debug)]
+pub struct IndexedLeanIMT<N = IMTNode> {
+    tree: LeanIMT<N>,
+    hash_leaf: IndexedLeafHashFunction<N>,
+    /// Leaves in insertion order, as hashed in the tree.
+    leaves: Vec<IndexedLeaf<N>>,
+    /// Index of each value, in value order.
+    indices: BTreeMap<N, usize>,
+}
+
+impl IndexedLeanIMT {
+    /// Creates a tree of string nodes using `"0"` as the zero value.
+    pub fn new(
+        hash: IMTHashFunction,
+        hash_leaf: IndexedLeafHashFunction,
+    ) -> Result<Self, &'static str> {
+        Self::with_zero(hash, hash_leaf, "0".into())
+    }
+}
+
+impl<N: Clone + Ord + Hash> IndexedLeanIMT<N> {
+    /// Creates a tree holding the first leaf of the list, whose value is
+    /// `zero`. Inserted values must be greater than `zero`.
+    pub fn with_zero(
+        hash: IMTHashFunction<N>,
+        hash_leaf: IndexedLeafHashFunction<N>,
+        zero: N,
+    ) -> Result<Self, &'static str> {
+        let first = IndexedLeaf {
+            value: zero.clone(),
+            next_index: 0,
+            next_value: zero.clone(),
+        };
+        let mut tree = LeanIMT::with_zero(hash, zero.clone());
+        tree.insert(hash_leaf(&first))?;
+
+        Ok(IndexedLeanIMT {
+            tree,
+            hash_leaf,
+            leaves: vec![first],
+            indices: BTreeMap::from([(zero, 0)]),
+        })
+    }
+
+    /// Inserts `value`, pointing its low leaf to it, and returns the new
+    /// root.
+    ///
+    /// Both leaves are written in one operation, which fails as a whole.
+    pub fn insert(&mut self, value: N) -> Result<N, &'static str> {
+        let (low_index, low_leaf) = self.low_leaf(&value)?;
+        let index = self.leaves.len();
+        let leaf = IndexedLeaf {
+            value: value.clone(),
+            next_index: low_leaf.next_index,
+            next_value: low_leaf.next_value.clone(),
+        };
+        let new_low_leaf = IndexedLeaf {
+            value: low_leaf.value.clone(),
+            next_index: index,
+            next_value: value.clone(),
+        };
+
+        let siblings = self.tree.generate_proof(low_index)?.siblings;
+        let old_node = (self.hash_leaf)(low_leaf);
+        let new_low_node = (self.hash_leaf)(&new_low_leaf);
+        let node = (self.hash_leaf)(&leaf);
+        let root = self.tree.transaction(|imt, changes| {
+            // The low leaf is only updated if the new leaf fits
+            imt.check_capacity(1)?;
+            imt.update_leaf(low_index, &old_node, new_low_node, &siblings, changes)?;
+            imt.insert_leaf(node, changes)
+        })?;
+
+        self.leaves[low_index] = new_low_leaf;
+        self.leaves.push(leaf);
+        self.indices.insert(value, index);
+        Ok(root)
+    }
+
+    /// Returns the index and leaf of the largest value lower than `value`.
+    ///
+    /// Fails if `value` is in the tree or not greater than the zero value.
+    pub fn low_leaf(&self, value: &N) -> Result<(usize, &IndexedLeaf<N>), &'static str> {
+        if self.indices.contains_key(value) {
+            return Err("Leaf already exists");
+        }
+        let (_, &index) = self
+            .indices
+            .range(..value)
+            .next_back()
+            .ok_or("Value out of range")?;
+        Ok((index, &self.leaves[index]))
+    }
+
+    /// Checks if `value` is in the tree.
+    pub fn has(&self, value: &N) -> bool {
+        self.indices.contains_key(value)
+    }
+
+    /// Returns the index of `value` in the tree.
+    pub fn index_of(&self, value: &N) -> Result<usize, &'static str> {
+        self.indices
+            .get(value)
+            .copied()
+            .ok_or("Leaf does not exist")
+    }
+
+    /// Returns the leaf at `index`, with its current next pointer.
+    pub fn get_leaf(&self, index: usize) -> Option<&IndexedLeaf<N>> {
+        self.leaves.get(index)
+    }
+
+    pub fn root(&self) -> Option<N> {
+        self.tree.root()
+    }
+
+    /// Returns the number of leaves, including the first one.
+    pub fn get_size(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns the tree of the hashed leaves.
+    pub fn tree(&self) -> &LeanIMT<N> {
+        &self.tree
+    }
+
+    /// Generates the inclusion proof of the leaf holding `value`.
+    pub fn generate_proof(&self, value: &N) -> Result<MerkleProof<N>, &'static str> {
+        self.tree.generate_proof(self.index_of(value)?)
+    }
+
+    /// Generates the proof that `value` is not in the tree.
+    pub fn generate_non_membership_proof(
+        &self,
+        value: &N,
+    ) -> Result<NonMembershipProof<N>, &'static str> {
+        let (index, low_leaf) = self.low_leaf(value)?;
+        Ok(NonMembershipProof {
+            value: value.clone(),
+            low_leaf: low_leaf.clone(),
+            proof: self.tree.generate_proof(index)?,
+        })
+    }
+
+    /// Checks a non-membership proof against the current root of the tree.
+    pub fn verify_non_membership_proof(&self, proof: &NonMembershipProof<N>) -> bool {
+        let eq = |a: &N, b: &N| self.tree.nodes_eq(a, b);
+        self.root().is_some_and(|root| eq(&root, &proof.proof.root))
+            && proof.verify_with(self.tree.hash.as_fn(), self.hash_leaf, eq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn hash_leaf(leaf: &IndexedLeaf<String>) -> String {
+        format!("{}:{}:{}", leaf.value, leaf.next_index, leaf.next_value)
+    }
+
+    #[test]
+    fn test_indexed_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = IndexedLeanIMT::new(hash, hash_leaf).unwrap();
+        assert_eq!(imt.root(), Some("0:0:0".to_string()));
+
+        for value in ["b", "d", "c"] {
+            imt.insert(value.to_string()).unwrap();
+        }
+        assert_eq!(imt.root(), Some("0:1:b,b:3:c,d:0:0,c:2:d".to_string()));
+        assert_eq!(
+            imt.insert("c".to_string()).unwrap_err(),
+            "Leaf already exists"
+        );
+        assert_eq!(
+            imt.insert("0".to_string()).unwrap_err(),
+            "Leaf already exists"
+        );
+        assert_eq!(imt.get_size(), 4);
+
+        // A full tree rejects the value before its low leaf is updated
+        imt.tree.set_max_size(Some(4)).unwrap();
+        let root = imt.root();
+        assert_eq!(imt.insert("e".to_string()).unwrap_err(), "Tree is full");
+        assert_eq!(imt.root(), root);
+        imt.tree.set_max_size(None).unwrap();
+
+        // A value between two leaves, and one past the largest leaf
+        for (value, low_value) in [("bb", "b"), ("e", "d")] {
+            let proof = imt
+                .generate_non_membership_proof(&value.to_string())
+                .unwrap();
+            assert_eq!(proof.low_leaf.value, low_value);
+            assert!(imt.verify_non_membership_proof(&proof));
+
+            let mut forged = proof.clone();
+            forged.value = "cc".to_string();
+            assert!(!imt.verify_non_membership_proof(&forged));
+        }
+        assert_eq!(
+            imt.generate_non_membership_proof(&"d".to_string())
+                .unwrap_err(),
+            "Leaf already exists"
+        );
+
+        let proof = imt.generate_proof(&"c".to_string()).unwrap();
+        assert_eq!(proof.leaf, hash_leaf(imt.get_leaf(3).unwrap()));
+        assert!(imt.tree().verify_proof(&proof));
+
+        // Non-membership proofs are checked with the comparison of the tree
+        imt.tree
+            .set_node_eq(Some(|a: &String, b: &String| a.eq_ignore_ascii_case(b)));
+        let mut proof = imt
+            .generate_non_membership_proof(&"bb".to_string())
+            .unwrap();
+        proof.proof.root = proof.proof.root.to_uppercase();
+        assert!(imt.verify_non_membership_proof(&proof));
+        assert!(!proof.verify(simple_hash_function, hash_leaf));
+    }
+}
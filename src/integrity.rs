@@ -0,0 +1,218 @@
+//! Integrity audit of a tree against its leaves.
+//!
+//! A tree restored from a snapshot or a store trusts its side nodes, which
+//! are not recomputed from the leaves. `verify_integrity` streams the leaves
+//! of the tree, kept elsewhere, recomputes every node above them and compares
+//! the nodes the tree keeps, i.e. the stored leaf indices, the side nodes of
+//! the last group of each level and the root, with the recomputed ones.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+
+use crate::{depth_of, LeanIMT, LeanIMTStore};
+
+/// Mismatch between a tree and the leaves it is audited against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// The leaves are not as many as the leaves of the tree.
+    SizeMismatch { expected: usize, found: usize },
+    /// The tree does not store the leaf at this index.
+    LeafMismatch { index: usize },
+    /// The stored node at this level and index, 0 being the leaves, is
+    /// missing or differs from the recomputed one.
+    NodeMismatch { level: usize, index: usize },
+    /// The store failed to read the tree.
+    Store(&'static str),
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityError::SizeMismatch { expected, found } => {
+                write!(f, "Expected {} leaves, found {}", expected, found)
+            }
+            IntegrityError::LeafMismatch { index } => write!(f, "Leaf mismatch at index {}", index),
+            IntegrityError::NodeMismatch { level, index } => {
+                write!(f, "Node mismatch at level {}, index {}", level, index)
+            }
+            IntegrityError::Store(message) => f.write_str(message),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for IntegrityError {}
+
+impl From<&'static str> for IntegrityError {
+    fn from(message: &'static str) -> Self {
+        IntegrityError::Store(message)
+    }
+}
+
+/// Nodes of a level still waiting for the rest of their group.
+struct Level<N> {
+    group: Vec<N>,
+    /// Number of nodes of the level seen so far.
+    count: usize,
+    /// Index of the first node of the last group of the level.
+    last_group: usize,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Recomputes the tree from `leaves`, ordered by index with removed
+    /// leaves set to zero, and checks the stored nodes against it.
+    ///
+    /// The leaves are those stored in the tree, i.e. already hashed by the
+    /// leaf hash, if any. They are streamed: only the nodes of the group
+    /// being hashed at each level are kept. The first mismatch found, from
+    /// the leaves up to the root, is returned.
+    pub fn verify_integrity<I: IntoIterator<Item = N>>(
+        &self,
+        leaves: I,
+    ) -> Result<(), IntegrityError> {
+        let arity = self.arity;
+        let depth = depth_of(self.size, arity);
+
+        let mut levels = Vec::with_capacity(depth + 1);
+        let mut level_size = self.size;
+        for _ in 0..=depth {
+            let last_group = level_size.saturating_sub(1) / arity * arity;
+            levels.push(Level {
+                group: Vec::with_capacity(arity),
+                count: 0,
+                last_group,
+            });
+            level_size = level_size.div_ceil(arity);
+        }
+
+        let mut leaves = leaves.into_iter();
+        for index in 0..self.size {
+            let leaf = leaves.next().ok_or(IntegrityError::SizeMismatch {
+                expected: self.size,
+                found: index,
+            })?;
+            if leaf != self.zero
+                && !self
+                    .store
+                    .leaf_indices(&leaf)?
+                    .is_some_and(|indices| indices.contains(&index))
+            {
+                return Err(IntegrityError::LeafMismatch { index });
+            }
+            self.push_audited_node(&mut levels, 0, leaf)?;
+        }
+        let extra = leaves.count();
+        if extra > 0 {
+            return Err(IntegrityError::SizeMismatch {
+                expected: self.size,
+                found: self.size + extra,
+            });
+        }
+
+        // Close the incomplete last group of each level
+        for level in 0..depth {
+            let mut group = core::mem::take(&mut levels[level].group);
+            let node = match group.len() {
+                0 => continue,
+                1 => group.swap_remove(0),
+                _ => self.hash.call(group),
+            };
+            self.push_audited_node(&mut levels, level + 1, node)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds `node` to `level`, checking it against the side node storing it,
+    /// if any, and hashes its group into the next level once full.
+    fn push_audited_node(
+        &self,
+        levels: &mut [Level<N>],
+        level: usize,
+        node: N,
+    ) -> Result<(), IntegrityError> {
+        let arity = self.arity;
+        let current = &mut levels[level];
+        let index = current.count;
+        let slot = index % arity;
+        current.count += 1;
+
+        // Side nodes hold the nodes of the last group of each level but its
+        // last slot, and the root
+        if index >= current.last_group && slot < arity - 1 {
+            let stored = self.store.side_node(self.side_node_key(level, slot))?;
+            if stored.as_ref() != Some(&node) {
+                return Err(IntegrityError::NodeMismatch { level, index });
+            }
+        }
+
+        current.group.push(node);
+        if current.group.len() == arity {
+            let group = core::mem::take(&mut current.group);
+            self.push_audited_node(levels, level + 1, self.hash.call(group))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_verify_integrity() {
+        let hash: IMTHashFunction = simple_hash_function;
+        for arity in [2, 3] {
+            let mut imt = LeanIMT::with_arity(hash, arity).unwrap();
+            for size in 1..=10 {
+                imt.insert(format!("leaf{}", size)).unwrap();
+                let leaves: Vec<_> = imt.leaves_iter().cloned().collect();
+                assert_eq!(imt.verify_integrity(leaves), Ok(()));
+            }
+        }
+
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(imt.verify_integrity(Vec::new()), Ok(()));
+        imt.insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+        let leaves: Vec<_> = imt.leaves_iter().cloned().collect();
+        assert_eq!(imt.verify_integrity(leaves.clone()), Ok(()));
+
+        assert_eq!(
+            imt.verify_integrity(leaves[..4].to_vec()),
+            Err(IntegrityError::SizeMismatch {
+                expected: 5,
+                found: 4
+            })
+        );
+        let mut swapped = leaves.clone();
+        swapped.swap(0, 2);
+        assert_eq!(
+            imt.verify_integrity(swapped),
+            Err(IntegrityError::LeafMismatch { index: 0 })
+        );
+
+        // A corrupted side node is reported at its level and index
+        imt.store.put_side_node(1, "corrupted".to_string()).unwrap();
+        assert_eq!(
+            imt.verify_integrity(leaves.clone()),
+            Err(IntegrityError::NodeMismatch { level: 1, index: 2 })
+        );
+        imt.store.put_side_node(1, "leaf5".to_string()).unwrap();
+        imt.store.delete_side_node(3).unwrap();
+        assert_eq!(
+            imt.verify_integrity(leaves),
+            Err(IntegrityError::NodeMismatch { level: 3, index: 0 })
+        );
+    }
+}
@@ -0,0 +1,526 @@
+//! Store interning node values, for trees with many repeated nodes.
+//!
+//! [`InternedStore`] keeps every distinct node value once, in an [`Arena`],
+//! and refers to it by a small [`NodeId`] from the side nodes and the leaf
+//! maps. A leaf, which `MemoryStore` keeps both in its value map and by
+//! index, is stored once, and equal side nodes, such as the roots of zeroed
+//! subtrees after removals, share their value.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::hash::{BuildHasher, Hash};
+
+use hashbrown::hash_table::Entry;
+#[cfg(not(feature = "zkvm"))]
+use hashbrown::DefaultHashBuilder;
+use hashbrown::HashTable;
+
+#[cfg(feature = "zkvm")]
+use crate::zkvm::FixedState as DefaultHashBuilder;
+
+use crate::store::StoreIter;
+use crate::{HashMap, LeanIMTStore};
+
+/// Identifier of a node interned in an [`Arena`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+/// Reference-counted storage of distinct node values.
+///
+/// Each value is kept once, however many times it is interned, and freed
+/// once every reference to it has been released.
+#[derive(Debug, Clone)]
+pub struct Arena<N> {
+    /// Values and reference counts, indexed by id; `None` marks a free slot.
+    nodes: Vec<Option<(N, usize)>>,
+    /// Ids of the values, looked up by the hash of the value.
+    ids: HashTable<NodeId>,
+    hasher: DefaultHashBuilder,
+    free: Vec<NodeId>,
+}
+
+impl<N> Default for Arena<N> {
+    fn default() -> Self {
+        Arena {
+            nodes: Vec::new(),
+            ids: HashTable::new(),
+            hasher: DefaultHashBuilder::default(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<N: Eq + Hash> Arena<N> {
+    /// Returns the value of `id`.
+    ///
+    /// Fails if `id` has been freed.
+    pub fn get(&self, id: NodeId) -> Result<&N, &'static str> {
+        Ok(&self.slot(id)?.0)
+    }
+
+    /// Returns the id of `node`, if it is interned.
+    pub fn find(&self, node: &N) -> Option<NodeId> {
+        let hash = self.hasher.hash_one(node);
+        self.ids
+            .find(hash, |&id| self.get(id).is_ok_and(|value| value == node))
+            .copied()
+    }
+
+    /// Interns `node`, adding a reference to it, and returns its id.
+    ///
+    /// Fails if the arena already holds `u32::MAX + 1` values.
+    pub fn intern(&mut self, node: N) -> Result<NodeId, &'static str> {
+        let hash = self.hasher.hash_one(&node);
+        let nodes = &mut self.nodes;
+        let entry = self.ids.entry(
+            hash,
+            |&id| {
+                nodes[id.0 as usize]
+                    .as_ref()
+                    .is_some_and(|(value, _)| *value == node)
+            },
+            |&id| match &nodes[id.0 as usize] {
+                Some((value, _)) => self.hasher.hash_one(value),
+                None => 0,
+            },
+        );
+        match entry {
+            Entry::Occupied(entry) => {
+                let id = *entry.get();
+                if let Some((_, references)) = &mut self.nodes[id.0 as usize] {
+                    *references += 1;
+                }
+                Ok(id)
+            }
+            Entry::Vacant(entry) => {
+                let id = match self.free.pop() {
+                    Some(id) => {
+                        self.nodes[id.0 as usize] = Some((node, 1));
+                        id
+                    }
+                    None => {
+                        let id = NodeId(u32::try_from(self.nodes.len()).or(Err("Arena is full"))?);
+                        self.nodes.push(Some((node, 1)));
+                        id
+                    }
+                };
+                entry.insert(id);
+                Ok(id)
+            }
+        }
+    }
+
+    /// Releases a reference to `id`, freeing its value after the last one.
+    pub fn release(&mut self, id: NodeId) {
+        let Some(Some((node, references))) = self.nodes.get_mut(id.0 as usize) else {
+            return;
+        };
+        *references -= 1;
+        if *references > 0 {
+            return;
+        }
+
+        let hash = self.hasher.hash_one(&*node);
+        if let Ok(entry) = self.ids.find_entry(hash, |&other| other == id) {
+            entry.remove();
+        }
+        self.nodes[id.0 as usize] = None;
+        self.free.push(id);
+    }
+
+    /// Returns the number of distinct values.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns whether the arena holds no value.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    fn slot(&self, id: NodeId) -> Result<&(N, usize), &'static str> {
+        self.nodes
+            .get(id.0 as usize)
+            .and_then(Option::as_ref)
+            .ok_or("Node has been freed")
+    }
+}
+
+/// Write made since the last commit, undone by `rollback`.
+///
+/// Entries hold the ids that writes replaced or deleted, with the reference
+/// of the slot they were removed from, until the commit releases them.
+#[derive(Debug, Clone)]
+enum Undo<N> {
+    SideNode(usize, Option<NodeId>),
+    /// Leaf at an index, with the number of indices before the write.
+    LeafAt(usize, Option<NodeId>, usize),
+    /// Indices of a leaf before they were written, `None` for a new leaf.
+    Leaf(NodeId, Option<Vec<usize>>),
+    DeleteLeaf(NodeId, Vec<usize>),
+    Payload(usize, Option<Vec<u8>>),
+    Tombstone(N, Option<Vec<usize>>),
+}
+
+/// In-memory store keeping each distinct node value once.
+///
+/// Writes are made in place and undone from a log on rollback. The log
+/// holds node ids, so it costs no copy of the nodes.
+#[derive(Debug, Clone)]
+pub struct InternedStore<N> {
+    arena: Arena<N>,
+    side_nodes: Vec<Option<NodeId>>,
+    leaves: HashMap<NodeId, Vec<usize>>,
+    leaves_by_index: Vec<Option<NodeId>>,
+    /// Payloads are not interned, as they are rarely shared.
+    payloads: BTreeMap<usize, Vec<u8>>,
+    /// Removed values are no longer nodes, so tombstones are not interned.
+    tombstones: HashMap<N, Vec<usize>>,
+    undo: Vec<Undo<N>>,
+}
+
+impl<N> Default for InternedStore<N> {
+    fn default() -> Self {
+        InternedStore {
+            arena: Arena::default(),
+            side_nodes: Vec::new(),
+            leaves: HashMap::default(),
+            leaves_by_index: Vec::new(),
+            payloads: BTreeMap::new(),
+            tombstones: HashMap::default(),
+            undo: Vec::new(),
+        }
+    }
+}
+
+impl<N> InternedStore<N> {
+    /// Returns the arena holding the node values.
+    pub fn arena(&self) -> &Arena<N> {
+        &self.arena
+    }
+}
+
+impl<N: Clone + Eq + Hash> InternedStore<N> {
+    /// Returns the value of the node of a slot.
+    fn value(&self, id: Option<NodeId>) -> Result<Option<N>, &'static str> {
+        id.map(|id| self.arena.get(id).cloned()).transpose()
+    }
+
+    /// Releases the id of a slot, if any.
+    fn release(arena: &mut Arena<N>, id: Option<NodeId>) {
+        if let Some(id) = id {
+            arena.release(id);
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMTStore<N> for InternedStore<N> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        self.value(self.side_nodes.get(level).copied().flatten())
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        let id = self.arena.intern(node)?;
+        if level >= self.side_nodes.len() {
+            self.side_nodes.resize(level + 1, None);
+        }
+        let old = self.side_nodes[level].replace(id);
+        self.undo.push(Undo::SideNode(level, old));
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        if let Some(old) = self.side_nodes.get_mut(level).and_then(Option::take) {
+            self.undo.push(Undo::SideNode(level, Some(old)));
+        }
+        while let Some(None) = self.side_nodes.last() {
+            self.side_nodes.pop();
+        }
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        Box::new(
+            self.side_nodes
+                .iter()
+                .enumerate()
+                .filter_map(|(level, id)| {
+                    Some(self.arena.get((*id)?).map(|node| (level, node.clone())))
+                }),
+        )
+    }
+
+    fn reserve(&mut self, leaves: usize, side_nodes: usize) {
+        self.side_nodes
+            .reserve(side_nodes.saturating_sub(self.side_nodes.len()));
+        self.leaves.reserve(leaves);
+        self.leaves_by_index.reserve(leaves);
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self
+            .arena
+            .find(leaf)
+            .and_then(|id| self.leaves.get(&id))
+            .cloned())
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        if let Some((id, entry)) = self
+            .arena
+            .find(&leaf)
+            .and_then(|id| Some((id, self.leaves.get_mut(&id)?)))
+        {
+            let old = core::mem::replace(entry, indices);
+            self.undo.push(Undo::Leaf(id, Some(old)));
+        } else {
+            let id = self.arena.intern(leaf)?;
+            self.leaves.insert(id, indices);
+            self.undo.push(Undo::Leaf(id, None));
+        }
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        if let Some(id) = self.arena.find(leaf) {
+            if let Some(indices) = self.leaves.remove(&id) {
+                self.undo.push(Undo::DeleteLeaf(id, indices));
+            }
+        }
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        Box::new(
+            self.leaves
+                .iter()
+                .map(|(&id, indices)| Ok((self.arena.get(id)?.clone(), indices.clone()))),
+        )
+    }
+
+    fn has_leaf(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self
+            .arena
+            .find(leaf)
+            .is_some_and(|id| self.leaves.contains_key(&id)))
+    }
+
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        self.value(self.leaves_by_index.get(index).copied().flatten())
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        let id = leaf.map(|leaf| self.arena.intern(leaf)).transpose()?;
+        let len = self.leaves_by_index.len();
+        if index >= len {
+            self.leaves_by_index.resize(index + 1, None);
+        }
+        let old = core::mem::replace(&mut self.leaves_by_index[index], id);
+        self.undo.push(Undo::LeafAt(index, old, len));
+        Ok(())
+    }
+
+    fn leaves_by_index(&self) -> Result<Vec<Option<N>>, &'static str>
+    where
+        N: Clone,
+    {
+        self.leaves_by_index
+            .iter()
+            .map(|&id| self.value(id))
+            .collect()
+    }
+
+    fn payload(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        Ok(self.payloads.get(&index).cloned())
+    }
+
+    fn put_payload(&mut self, index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        let old = match payload {
+            Some(payload) => self.payloads.insert(index, payload),
+            None => self.payloads.remove(&index),
+        };
+        self.undo.push(Undo::Payload(index, old));
+        Ok(())
+    }
+
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        Box::new(
+            self.payloads
+                .iter()
+                .map(|(&index, payload)| Ok((index, payload.clone()))),
+        )
+    }
+
+    fn tombstone(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self.tombstones.get(leaf).cloned())
+    }
+
+    fn put_tombstone(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        let old = self.tombstones.insert(leaf.clone(), indices);
+        self.undo.push(Undo::Tombstone(leaf, old));
+        Ok(())
+    }
+
+    fn tombstones<'a>(&'a self) -> StoreIter<'a, (N, Vec<usize>)>
+    where
+        N: 'a,
+    {
+        Box::new(
+            self.tombstones
+                .iter()
+                .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone()))),
+        )
+    }
+
+    fn commit(&mut self) -> Result<(), &'static str> {
+        for write in self.undo.drain(..) {
+            match write {
+                Undo::SideNode(_, old) | Undo::LeafAt(_, old, _) => {
+                    Self::release(&mut self.arena, old)
+                }
+                Undo::DeleteLeaf(id, _) => self.arena.release(id),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        while let Some(write) = self.undo.pop() {
+            match write {
+                Undo::SideNode(level, old) => {
+                    if level >= self.side_nodes.len() {
+                        self.side_nodes.resize(level + 1, None);
+                    }
+                    let current = core::mem::replace(&mut self.side_nodes[level], old);
+                    Self::release(&mut self.arena, current);
+                }
+                Undo::LeafAt(index, old, len) => {
+                    let current = core::mem::replace(&mut self.leaves_by_index[index], old);
+                    Self::release(&mut self.arena, current);
+                    self.leaves_by_index.truncate(len);
+                }
+                Undo::Leaf(id, Some(indices)) => {
+                    self.leaves.insert(id, indices);
+                }
+                Undo::Leaf(id, None) => {
+                    self.leaves.remove(&id);
+                    self.arena.release(id);
+                }
+                Undo::DeleteLeaf(id, indices) => {
+                    self.leaves.insert(id, indices);
+                }
+                Undo::Payload(index, Some(payload)) => {
+                    self.payloads.insert(index, payload);
+                }
+                Undo::Payload(index, None) => {
+                    self.payloads.remove(&index);
+                }
+                Undo::Tombstone(leaf, Some(indices)) => {
+                    self.tombstones.insert(leaf, indices);
+                }
+                Undo::Tombstone(leaf, None) => {
+                    self.tombstones.remove(&leaf);
+                }
+            }
+        }
+        while let Some(None) = self.side_nodes.last() {
+            self.side_nodes.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_interned_store() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_store(hash, "0".to_string(), InternedStore::default()).unwrap();
+        let mut reference = LeanIMT::new(hash);
+        let leaves: Vec<_> = (1..=8).map(|i| format!("leaf{}", i)).collect();
+        imt.insert_many(leaves.clone()).unwrap();
+        reference.insert_many(leaves.clone()).unwrap();
+
+        // Each leaf is stored once for both leaf maps, and the side nodes of
+        // levels 1 to 3 are the only other values
+        assert_eq!(imt.get_store().arena().len(), 11);
+
+        for leaf in &leaves[..4] {
+            let proof = imt.generate_proof(imt.index_of(leaf).unwrap()).unwrap();
+            imt.remove(leaf, &proof.siblings).unwrap();
+            reference.remove(leaf, &proof.siblings).unwrap();
+        }
+        assert_eq!(imt.root(), reference.root());
+        assert_eq!(imt.snapshot(), reference.snapshot());
+        assert_eq!(imt.leaf_at(1), Ok("0".to_string()));
+        assert_eq!(imt.leaf_at(5), Ok("leaf6".to_string()));
+        assert!(!imt.has(&"leaf1".to_string()));
+        assert_eq!(imt.index_of(&"leaf8".to_string()), Ok(7));
+
+        // Removed leaves are freed, and their slots reused. Values replaced
+        // by an operation are only freed once it is committed.
+        assert_eq!(imt.get_store().arena().len(), 7);
+        imt.insert("leaf9".to_string()).unwrap();
+        let store = imt.get_store();
+        assert_eq!(store.arena().len(), 7);
+        assert_eq!(store.arena().nodes.len(), 13);
+
+        // The new leaf is the side node of levels 0 to 2, stored once
+        let id = store.arena().find(&"leaf9".to_string()).unwrap();
+        assert_eq!(store.side_nodes[..3], [Some(id); 3]);
+        assert_eq!(store.arena().slot(id).map(|slot| slot.1), Ok(5));
+
+        // Freed ids are reported rather than read
+        let freed = store.arena().find(&"leaf8".to_string()).unwrap();
+        let proof = imt.generate_proof(7).unwrap();
+        imt.remove(&"leaf8".to_string(), &proof.siblings).unwrap();
+        assert_eq!(
+            imt.get_store().arena().get(freed),
+            Err("Node has been freed")
+        );
+    }
+
+    #[test]
+    fn test_interned_store_rollback() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_store(hash, "0".to_string(), InternedStore::default()).unwrap();
+        imt.insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let snapshot = imt.snapshot();
+        let values = imt.get_store().arena().len();
+
+        // Writes replacing, adding and deleting nodes are all undone, and
+        // the values they interned are freed
+        let store = &mut imt.store;
+        store.put_side_node(0, "partial".to_string()).unwrap();
+        store.put_side_node(9, "partial".to_string()).unwrap();
+        store.delete_side_node(2).unwrap();
+        store.put_leaf_at(7, Some("leaf8".to_string())).unwrap();
+        store.put_leaf_at(4, None).unwrap();
+        store
+            .put_leaf_indices("leaf8".to_string(), vec![7])
+            .unwrap();
+        store.delete_leaf(&"leaf1".to_string()).unwrap();
+        store.put_leaf_indices("leaf2".to_string(), vec![]).unwrap();
+        store.rollback();
+        assert_eq!(imt.snapshot(), snapshot);
+        assert_eq!(imt.get_store().arena().len(), values);
+        assert_eq!(imt.get_store().leaves_by_index.len(), 5);
+
+        imt.insert("leaf6".to_string()).unwrap();
+        let store = imt.get_store();
+        let id = store.arena().find(&"leaf6".to_string()).unwrap();
+        assert_eq!(store.arena().slot(id).map(|slot| slot.1), Ok(2));
+    }
+}
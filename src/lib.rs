@@ -1,273 +1,1799 @@
-use std::collections::HashMap;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+
+#[cfg(feature = "rkyv")]
+mod archive;
+#[cfg(feature = "async")]
+pub mod async_tree;
+#[cfg(feature = "num-bigint")]
+pub mod bigint;
+mod builder;
+mod changelog;
+#[cfg(feature = "circom")]
+pub mod circom;
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod compression;
+#[cfg(feature = "concurrent")]
+pub mod concurrent;
+mod consistency;
+#[cfg(feature = "subtle")]
+pub mod ct;
+mod diff;
+pub mod epoch;
+#[cfg(feature = "alloy")]
+pub mod eth_calldata;
+#[cfg(feature = "alloy")]
+pub mod eth_sync;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "digest")]
+mod fingerprint;
+pub mod fixed_depth;
+pub mod forest;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod hashers;
+mod history;
+#[cfg(feature = "import")]
+pub mod import;
+pub mod indexed;
+mod integrity;
+pub mod interned_store;
+#[cfg(feature = "maci")]
+pub mod maci;
+#[cfg(feature = "uniffi")]
+pub mod mobile;
+#[cfg(feature = "mmap")]
+pub mod mmap_store;
+mod multiproof;
+#[cfg(feature = "noir")]
+pub mod noir;
+#[cfg(feature = "object-store")]
+pub mod object_snapshots;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "imbl")]
+pub mod persistent_store;
+mod proof;
+pub mod proof_cache;
+#[cfg(feature = "postgres")]
+pub mod postgres_store;
+mod pretty;
+pub mod rln;
+#[cfg(feature = "std")]
+pub mod root_stream;
+#[cfg(feature = "semaphore")]
+pub mod semaphore;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "sled")]
+pub mod sled_store;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+mod snapshot;
+pub mod sparse;
+mod stats;
+pub mod store;
+mod subtree;
+pub mod sync;
+#[cfg(feature = "tornado")]
+pub mod tornado;
+mod txn;
+mod typed;
+#[cfg(feature = "json")]
+pub mod vectors;
+pub mod wal;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "zkvm")]
+pub mod zkvm;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
+
+#[cfg(feature = "std")]
+use root_stream::RootStream;
+#[cfg(feature = "std")]
+use std::sync::mpsc::Receiver;
+
+use changelog::Changelog;
+use stats::Counters;
+pub use builder::LeanIMTBuilder;
+pub use changelog::{ChangeRecord, HookId, Operation, RootChange};
+pub use codec::NodeCodec;
+pub use consistency::ConsistencyProof;
+pub use diff::TreeDiff;
+pub use integrity::IntegrityError;
+pub use multiproof::MultiProof;
+pub use proof::{prove_from_leaves, MerkleProof, NaryMerkleProof, PaddedMerkleProof};
+#[cfg(feature = "rkyv")]
+pub use snapshot::ArchivedLeanIMTSnapshot;
+pub use snapshot::LeanIMTSnapshot;
+pub use stats::TreeStats;
+pub use store::{LeanIMTStore, MemoryStore, StoreMetadata};
+pub use subtree::Subtree;
+pub use txn::TreeTxn;
+pub use typed::{Leaf, LeafIndex, Root};
+
+#[cfg(not(any(feature = "std", feature = "zkvm")))]
+pub use hashbrown::HashMap;
+#[cfg(not(any(feature = "std", feature = "zkvm")))]
+pub use hashbrown::HashSet;
+#[cfg(all(feature = "std", not(feature = "zkvm")))]
+pub use std::collections::HashMap;
+#[cfg(all(feature = "std", not(feature = "zkvm")))]
+pub use std::collections::HashSet;
+/// Hash map with a fixed seed, so that guests run deterministically.
+#[cfg(feature = "zkvm")]
+pub type HashMap<K, V> = hashbrown::HashMap<K, V, zkvm::FixedState>;
+/// Hash set with a fixed seed, so that guests run deterministically.
+#[cfg(feature = "zkvm")]
+pub type HashSet<T> = hashbrown::HashSet<T, zkvm::FixedState>;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 pub type IMTNode = String;
-pub type IMTHashFunction = fn(Vec<IMTNode>) -> IMTNode;
+pub type IMTHashFunction<N = IMTNode> = fn(Vec<N>) -> N;
+/// Hashes two nodes by reference, e.g. a binary hash over byte slices, so
+/// the hash never takes ownership of the nodes.
+pub type IMTPairHashFunction<N = IMTNode> = fn(&N, &N) -> N;
+/// Hashes a raw leaf into the leaf stored in the tree.
+pub type IMTLeafHashFunction<N = IMTNode> = fn(N) -> N;
+/// Compares two nodes, e.g. in constant time.
+pub type IMTNodeEqFunction<N = IMTNode> = fn(&N, &N) -> bool;
+/// Hash function that can capture state, such as the parameters of a hash or
+/// a client of a hashing service, shared by the trees and threads using it.
+pub type SharedHashFunction<N = IMTNode> = Arc<dyn Fn(Vec<N>) -> N + Send + Sync>;
+
+/// Hash function of a tree.
+#[derive(Clone)]
+pub(crate) enum TreeHash<N> {
+    Fn(IMTHashFunction<N>),
+    Shared(SharedHashFunction<N>),
+    Pair(IMTPairHashFunction<N>),
+}
+
+impl<N> TreeHash<N> {
+    pub(crate) fn call(&self, nodes: Vec<N>) -> N {
+        match self {
+            TreeHash::Fn(hash) => hash(nodes),
+            TreeHash::Shared(hash) => hash(nodes),
+            TreeHash::Pair(hash) => {
+                // Wider groups, in trees of higher arity, are hashed from the
+                // left
+                let mut nodes = nodes.into_iter();
+                let first = nodes.next().expect("Hashed an empty group");
+                nodes.fold(first, |left, right| hash(&left, &right))
+            }
+        }
+    }
+
+    /// Returns the hash function as a closure, for the helpers generic over
+    /// hash functions.
+    pub(crate) fn as_fn(&self) -> impl Fn(Vec<N>) -> N + Copy + '_ {
+        move |nodes| self.call(nodes)
+    }
+}
+
+impl<N> fmt::Debug for TreeHash<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TreeHash::Fn(hash) => f.debug_tuple("Fn").field(hash).finish(),
+            TreeHash::Shared(_) => f.write_str("Shared"),
+            TreeHash::Pair(hash) => f.debug_tuple("Pair").field(hash).finish(),
+        }
+    }
+}
+
+/// How a tree handles leaves whose value is already in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(compare(PartialEq), derive(Debug))
+)]
+pub enum DuplicatePolicy {
+    /// Duplicate leaves are rejected with "Leaf already exists".
+    #[default]
+    Reject,
+    /// Duplicate leaves are accepted; lookups by value use the lowest index.
+    Allow,
+}
 
+/// How a tree remembers the leaves it removed.
+///
+/// Whatever the policy, a removed leaf is gone from the tree: `has` and
+/// `index_of` no longer find it, and its index is only reused by
+/// `insert_into_gap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(compare(PartialEq), derive(Debug))
+)]
+pub enum RemovalPolicy {
+    /// Removed leaves are forgotten, and can be inserted again at a new
+    /// index.
+    #[default]
+    Forget,
+    /// Removed leaves are kept by the store as tombstones, with the indices
+    /// they were removed from, and can be inserted again at a new index.
+    Tombstone,
+    /// Removed leaves are kept as tombstones, and inserting them again fails
+    /// with "Leaf was removed", e.g. for commitments that must not rejoin a
+    /// group once revoked.
+    RejectReinsertion,
+}
+
+/// Lean incremental Merkle tree, keeping its side nodes and leaves in `S`.
 #[derive(Debug)]
-pub struct LeanIMT {
+pub struct LeanIMT<N = IMTNode, S = MemoryStore<N>> {
     size: usize,
     depth: usize,
-    side_nodes: HashMap<usize, IMTNode>,
-    leaves: HashMap<IMTNode, usize>,
-    hash: IMTHashFunction,
+    /// Maximum number of children hashed together.
+    arity: usize,
+    store: S,
+    duplicate_policy: DuplicatePolicy,
+    removal_policy: RemovalPolicy,
+    /// Indices of the removed leaves, tracked while compaction is enabled.
+    gaps: Option<BTreeSet<usize>>,
+    /// Maximum number of leaves, if the tree is limited.
+    max_size: Option<usize>,
+    hash: TreeHash<N>,
+    /// Hash applied to the leaves entering the tree, if any.
+    leaf_hash: Option<IMTLeafHashFunction<N>>,
+    /// Comparison of roots and proof nodes, if not `==`.
+    node_eq: Option<IMTNodeEqFunction<N>>,
+    zero: N,
+    version: u64,
+    changelog: Changelog<N>,
+    stats: Counters,
 }
 
 impl LeanIMT {
+    /// Creates a tree of string nodes using `"0"` as the zero value.
     pub fn new(hash: IMTHashFunction) -> Self {
+        Self::with_zero(hash, "0".to_string())
+    }
+
+    /// Creates a tree of string nodes hashing up to `arity` children per node.
+    pub fn with_arity(hash: IMTHashFunction, arity: usize) -> Result<Self, &'static str> {
+        let mut imt = Self::new(hash);
+        imt.set_arity(arity)?;
+        Ok(imt)
+    }
+
+    /// Creates a tree of string nodes holding at most the `2^max_depth`
+    /// leaves of a binary tree of depth `max_depth`.
+    pub fn with_max_depth(hash: IMTHashFunction, max_depth: usize) -> Self {
+        let mut imt = Self::new(hash);
+        imt.max_size = max_size_of(max_depth, 2);
+        imt
+    }
+
+    /// Creates a tree of string nodes with room for `capacity` leaves, so a
+    /// bulk load of that size does not grow the store as it goes.
+    pub fn with_capacity(hash: IMTHashFunction, capacity: usize) -> Self {
+        let mut imt = Self::new(hash);
+        imt.reserve(capacity);
+        imt
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMT<N> {
+    /// Creates a tree using `zero` as the value of empty (removed) leaves.
+    pub fn with_zero(hash: IMTHashFunction<N>, zero: N) -> Self {
         LeanIMT {
             size: 0,
             depth: 0,
-            side_nodes: HashMap::new(),
-            leaves: HashMap::new(),
+            arity: 2,
+            store: MemoryStore::default(),
+            duplicate_policy: DuplicatePolicy::Reject,
+            removal_policy: RemovalPolicy::Forget,
+            gaps: None,
+            max_size: None,
+            hash: TreeHash::Fn(hash),
+            leaf_hash: None,
+            node_eq: None,
+            zero,
+            version: 0,
+            changelog: Changelog::new(),
+            stats: Counters::default(),
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Creates an empty tree keeping its nodes in `store`.
+    ///
+    /// Fails with "Store is not empty" if `store` already holds nodes.
+    pub fn with_store(hash: IMTHashFunction<N>, zero: N, store: S) -> Result<Self, &'static str> {
+        Self::create(TreeHash::Fn(hash), zero, store)
+    }
+
+    /// Creates an empty tree like `with_store`, hashing with a function that
+    /// can capture state.
+    pub fn with_shared_hash(
+        hash: SharedHashFunction<N>,
+        zero: N,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        Self::create(TreeHash::Shared(hash), zero, store)
+    }
+
+    /// Creates an empty tree like `with_store`, hashing pairs of nodes by
+    /// reference. Trees of higher arity hash their groups from the left,
+    /// e.g. `hash(&hash(&a, &b), &c)`.
+    pub fn with_pair_hash(
+        hash: IMTPairHashFunction<N>,
+        zero: N,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        Self::create(TreeHash::Pair(hash), zero, store)
+    }
+
+    pub(crate) fn create(hash: TreeHash<N>, zero: N, store: S) -> Result<Self, &'static str> {
+        if store.side_nodes().next().is_some()
+            || store.leaves().next().is_some()
+            || store.payloads().next().is_some()
+            || store.metadata()?.is_some()
+        {
+            return Err("Store is not empty");
+        }
+
+        let mut imt = LeanIMT {
+            size: 0,
+            depth: 0,
+            arity: 2,
+            store,
+            duplicate_policy: DuplicatePolicy::Reject,
+            removal_policy: RemovalPolicy::Forget,
+            gaps: None,
+            max_size: None,
+            hash,
+            leaf_hash: None,
+            node_eq: None,
+            zero,
+            version: 0,
+            changelog: Changelog::new(),
+            stats: Counters::default(),
+        };
+        imt.persist()?;
+        Ok(imt)
+    }
+
+    /// Reopens the tree saved in a persistent store.
+    ///
+    /// Fails with "Missing tree metadata" if the store does not hold a tree.
+    pub fn open(hash: IMTHashFunction<N>, store: S) -> Result<Self, &'static str> {
+        Self::reopen(TreeHash::Fn(hash), store)
+    }
+
+    /// Reopens the tree saved in a persistent store like `open`, hashing with
+    /// a function that can capture state.
+    pub fn open_with_shared_hash(
+        hash: SharedHashFunction<N>,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        Self::reopen(TreeHash::Shared(hash), store)
+    }
+
+    /// Reopens the tree saved in a persistent store like `open`, hashing
+    /// pairs of nodes by reference.
+    pub fn open_with_pair_hash(
+        hash: IMTPairHashFunction<N>,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        Self::reopen(TreeHash::Pair(hash), store)
+    }
+
+    fn reopen(hash: TreeHash<N>, store: S) -> Result<Self, &'static str> {
+        let metadata = store.metadata()?.ok_or("Missing tree metadata")?;
+        if metadata.arity < 2 {
+            return Err("Invalid arity");
+        }
+
+        let depth = depth_of(metadata.size, metadata.arity);
+        let root_key = depth
+            .checked_mul(metadata.arity - 1)
+            .ok_or("Invalid arity")?;
+        if metadata.size > 0 && store.side_node(root_key)?.is_none() {
+            return Err("Missing root");
+        }
+
+        Ok(LeanIMT {
+            size: metadata.size,
+            depth,
+            arity: metadata.arity,
+            store,
+            duplicate_policy: metadata.duplicate_policy,
+            removal_policy: RemovalPolicy::Forget,
+            gaps: None,
+            max_size: metadata.max_size,
             hash,
+            leaf_hash: None,
+            node_eq: None,
+            zero: metadata.zero,
+            version: metadata.version,
+            changelog: Changelog::new(),
+            stats: Counters::default(),
+        })
+    }
+
+    /// Returns an independent copy of the tree, which can be changed without
+    /// affecting this one.
+    ///
+    /// The copy shares as much as the store allows: `MemoryStore` is copied
+    /// entry by entry, while stores with structural sharing, such as
+    /// `PersistentStore`, make forks cheap. Forks start with an empty
+    /// changelog.
+    pub fn fork(&self) -> Self
+    where
+        S: Clone,
+    {
+        LeanIMT {
+            size: self.size,
+            depth: self.depth,
+            arity: self.arity,
+            store: self.store.clone(),
+            duplicate_policy: self.duplicate_policy,
+            removal_policy: self.removal_policy,
+            gaps: self.gaps.clone(),
+            max_size: self.max_size,
+            hash: self.hash.clone(),
+            leaf_hash: self.leaf_hash,
+            node_eq: self.node_eq,
+            zero: self.zero.clone(),
+            version: self.version,
+            changelog: Changelog::new(),
+            stats: Counters::default(),
         }
     }
 
+    /// Resets the tree to `other`, a fork of one of its earlier versions. The
+    /// tree keeps its changelog, minus the records after that version, and
+    /// its statistics.
+    #[cfg(feature = "concurrent")]
+    pub(crate) fn reset_to(&mut self, other: &Self)
+    where
+        S: Clone,
+    {
+        let changelog = core::mem::replace(&mut self.changelog, Changelog::new());
+        let stats = core::mem::take(&mut self.stats);
+        *self = other.fork();
+        self.changelog = changelog;
+        self.changelog.discard_after(self.version);
+        self.stats = stats;
+    }
+
+    /// Reserves room in the store for `additional` more leaves, and for the
+    /// side nodes of the deeper tree they make.
+    pub fn reserve(&mut self, additional: usize) {
+        let depth = depth_of(self.size.saturating_add(additional), self.arity);
+        self.store.reserve(additional, self.side_node_key(depth, 0) + 1);
+    }
+
     /// Inserts a new leaf into the tree.
-    pub fn insert(&mut self, leaf: IMTNode) -> Result<IMTNode, &'static str> {
-        if self.leaves.contains_key(&leaf) {
+    pub fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        self.transaction(|imt, changes| imt.insert_leaf(leaf, changes))
+    }
+
+    /// Inserts a new leaf with `value` attached to it as a payload.
+    ///
+    /// The payload stays with the index of the leaf: updates keep it, and
+    /// removing the leaf drops it. Payloads are saved in snapshots.
+    pub fn insert_with_value(&mut self, leaf: N, value: Vec<u8>) -> Result<N, &'static str> {
+        self.transaction(|imt, changes| {
+            // The leaf is checked before the payload is written, and the
+            // payload before the leaf, so that a rejected leaf or a store
+            // without payloads leaves no write behind
+            let leaf = imt.check_new_leaf(leaf)?;
+            imt.store.put_payload(imt.size, Some(value))?;
+            imt.append_leaf(leaf, changes)
+        })
+    }
+
+    /// Inserts a new leaf into the lowest removed slot, or appends it if no
+    /// leaf was removed, and returns the new root.
+    ///
+    /// Reusing the slots of removed leaves keeps the tree dense, and its depth
+    /// minimal, under heavy churn. The leaf is checked as by `insert`, and
+    /// the change is recorded as an insertion at the reused index. Filling a
+    /// slot recomputes the tree from its leaves to find its sibling nodes.
+    ///
+    /// Fails with "Compaction is disabled" unless `set_compaction` enabled
+    /// tracking the removed slots.
+    pub fn insert_into_gap(&mut self, leaf: N) -> Result<N, &'static str> {
+        let gaps = self.gaps.as_ref().ok_or("Compaction is disabled")?;
+        let Some(&index) = gaps.first() else {
+            return self.insert(leaf);
+        };
+        let sibling_nodes = self.nary_proof(index)?.siblings.concat();
+
+        self.transaction(|imt, changes| {
+            let leaf = imt.prehash(leaf);
+            if imt.rejects_duplicate(&leaf)? {
+                return Err("Leaf already exists");
+            }
+            if imt.rejects_reinsertion(&leaf)? {
+                return Err("Leaf was removed");
+            }
+            if leaf == imt.zero {
+                return Err("Leaf cannot be zero");
+            }
+
+            let zero = imt.zero.clone();
+            let root = imt.write_leaf(index, &zero, leaf, &sibling_nodes, changes)?;
+            imt.stats.add_inserts(1);
+            Ok(root)
+        })
+    }
+
+    fn insert_leaf(
+        &mut self,
+        leaf: N,
+        changes: &mut Vec<ChangeRecord<N>>,
+    ) -> Result<N, &'static str> {
+        let leaf = self.check_new_leaf(leaf)?;
+        self.append_leaf(leaf, changes)
+    }
+
+    /// Hashes a leaf to insert and checks that the tree accepts it, returning
+    /// the hashed leaf.
+    fn check_new_leaf(&self, leaf: N) -> Result<N, &'static str> {
+        let leaf = self.prehash(leaf);
+        self.check_capacity(1)?;
+        if self.rejects_duplicate(&leaf)? {
             return Err("Leaf already exists");
         }
-        if leaf == "0" {
+        if self.rejects_reinsertion(&leaf)? {
+            return Err("Leaf was removed");
+        }
+        if leaf == self.zero {
             return Err("Leaf cannot be zero");
         }
+        Ok(leaf)
+    }
+
+    /// Appends a leaf checked by `check_new_leaf`.
+    fn append_leaf(
+        &mut self,
+        leaf: N,
+        changes: &mut Vec<ChangeRecord<N>>,
+    ) -> Result<N, &'static str> {
+        let index = self.size;
+        let node = self.append_node(leaf.clone(), 0, index + 1)?;
+
+        self.version += 1;
+        self.stats.add_inserts(1);
+        if self.changelog.is_active() {
+            changes.push(ChangeRecord {
+                version: self.version,
+                index,
+                old_leaf: None,
+                appended: true,
+                new_leaf: leaf.clone(),
+                new_root: node.clone(),
+            });
+        }
 
-        let mut index = self.size;
-        let mut tree_depth = self.depth;
+        self.add_leaf_index(leaf, index)?;
+
+        Ok(node)
+    }
+
+    /// Appends `node` at `level`, right after the nodes covering the current
+    /// leaves, and updates the side nodes and root for a tree of `new_size`
+    /// leaves.
+    ///
+    /// The current size must be a multiple of the number of leaves covered by
+    /// a node at `level`.
+    pub(crate) fn append_node(
+        &mut self,
+        mut node: N,
+        level: usize,
+        new_size: usize,
+    ) -> Result<N, &'static str> {
+        let arity = self.arity;
 
         // Increase tree depth if necessary
-        if (1 << tree_depth) < index + 1 {
-            tree_depth += 1;
-            self.depth = tree_depth;
-        }
+        let tree_depth = depth_of(new_size, arity);
+        self.depth = tree_depth;
 
-        let mut node = leaf.clone();
+        // A level past the width of the index has a single node, at position 0
+        let mut position = LeafIndex::from(self.size)
+            .ancestor(level, arity)
+            .to_usize()?;
 
-        for level in 0..tree_depth {
-            if ((index >> level) & 1) == 1 {
-                // If the bit at position `level` is 1, hash with the side node
-                let side_node = self
-                    .side_nodes
-                    .get(&level)
-                    .cloned()
-                    .expect("No side node at this level");
-                node = (self.hash)(vec![side_node, node]);
-            } else {
-                // Else, store the node as side node
-                self.side_nodes.insert(level, node.clone());
-                break;
+        for level in level..tree_depth {
+            let slot = position % arity;
+
+            // Save the node while later nodes can still join its group, and
+            // keep climbing so the root includes the left subtrees at the
+            // upper levels
+            if slot < arity - 1 {
+                self.put_side_node(self.side_node_key(level, slot), node.clone())?;
             }
+
+            // Hash with the side nodes on the left of the node, if any
+            if slot > 0 {
+                let mut children = Vec::with_capacity(slot + 1);
+                for left_slot in 0..slot {
+                    children.push(
+                        self.store
+                            .side_node(self.side_node_key(level, left_slot))?
+                            .ok_or("No side node at this level")?,
+                    );
+                }
+                children.push(node);
+                node = self.hash_children(children);
+            }
+
+            position /= arity;
         }
 
-        index += 1;
-        self.size = index;
+        self.size = new_size;
 
         // Update the root node
-        self.side_nodes.insert(tree_depth, node.clone());
-        self.leaves.insert(leaf, index);
+        self.put_side_node(self.side_node_key(tree_depth, 0), node.clone())?;
 
         Ok(node)
     }
 
     /// Inserts multiple leaves into the tree.
-    pub fn insert_many(&mut self, leaves: Vec<IMTNode>) -> Result<IMTNode, &'static str> {
-        // Validate leaves
-        for leaf in &leaves {
-            if self.leaves.contains_key(leaf) {
-                return Err("Leaf already exists");
-            }
-            if leaf == "0" {
-                return Err("Leaf cannot be zero");
-            }
-        }
+    ///
+    /// An empty batch is rejected, leaving the tree untouched.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(leaves = leaves.len()))
+    )]
+    pub fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        let hash = self.hash.clone();
+        self.transaction(|imt, changes| {
+            imt.insert_leaves(leaves, changes, |groups| {
+                groups.into_iter().map(|group| hash.call(group)).collect()
+            })
+        })
+    }
 
-        let mut current_level_new_nodes = leaves.clone();
+    /// Inserts `leaves`, computing the parents of each level with
+    /// `hash_groups`, which hashes every group of children it is given.
+    pub(crate) fn insert_leaves(
+        &mut self,
+        leaves: Vec<N>,
+        changes: &mut Vec<ChangeRecord<N>>,
+        hash_groups: impl Fn(Vec<Vec<N>>) -> Vec<N>,
+    ) -> Result<N, &'static str> {
+        if leaves.is_empty() {
+            return Err("Empty batch");
+        }
+        let leaves = match self.leaf_hash {
+            Some(_) => leaves.into_iter().map(|leaf| self.prehash(leaf)).collect(),
+            None => leaves,
+        };
+        self.check_new_leaves(&leaves)?;
+        self.reserve(leaves.len());
 
         let tree_size = self.size;
-        let mut tree_depth = self.depth;
+        let arity = self.arity;
 
         // Calculate new tree depth
-        while (1 << tree_depth) < tree_size + leaves.len() {
-            tree_depth += 1;
-        }
+        let tree_depth = depth_of(tree_size + leaves.len(), arity);
         self.depth = tree_depth;
 
+        // The leaves are copied once into the first level; the nodes of each
+        // level are then moved into their parents' groups
+        let mut current_level_new_nodes = leaves.clone();
+        let mut side_nodes = Vec::new();
         let mut current_level_start_index = tree_size;
         let mut current_level_size = tree_size + leaves.len();
-        let mut next_level_start_index = current_level_start_index >> 1;
-        let mut next_level_size = ((current_level_size - 1) >> 1) + 1;
 
         for level in 0..tree_depth {
-            let number_of_new_nodes = next_level_size - next_level_start_index;
-            let mut next_level_new_nodes = Vec::with_capacity(number_of_new_nodes);
-
-            for i in 0..number_of_new_nodes {
-                let left_index = (i + next_level_start_index) * 2 - current_level_start_index;
-                let right_index = left_index + 1;
-
-                let left_node = if left_index < current_level_new_nodes.len() {
-                    current_level_new_nodes[left_index].clone()
-                } else {
-                    self.side_nodes.get(&level).cloned().unwrap_or("0".to_string())
-                };
+            let next_level_start_index = current_level_start_index / arity;
+            let next_level_size = (current_level_size - 1) / arity + 1;
+
+            // The new nodes of the last group become side nodes, which later
+            // nodes can still join. They are written once every level is
+            // hashed, as the first group reads the side nodes they replace
+            // and hashing the groups can fail.
+            let last_group_start = (current_level_size - 1) / arity * arity;
+            for position in last_group_start.max(current_level_start_index)..current_level_size {
+                let slot = position - last_group_start;
+                if slot < arity - 1 {
+                    side_nodes.push((
+                        self.side_node_key(level, slot),
+                        current_level_new_nodes[position - current_level_start_index].clone(),
+                    ));
+                }
+            }
 
-                let right_node = if right_index < current_level_new_nodes.len() {
-                    current_level_new_nodes[right_index].clone()
-                } else {
-                    "0".to_string()
-                };
+            let mut new_nodes = current_level_new_nodes.into_iter();
+            let mut groups = Vec::with_capacity(next_level_size - next_level_start_index);
+            let mut last_node = None;
+
+            for parent in next_level_start_index..next_level_size {
+                let first_position = parent * arity;
+                let last_position = (first_position + arity).min(current_level_size);
+
+                // The children are new nodes or, for the first parent of a
+                // partially filled level, the saved side nodes
+                let mut children = Vec::with_capacity(last_position - first_position);
+                for position in first_position..last_position.min(current_level_start_index) {
+                    children.push(
+                        self.store
+                            .side_node(self.side_node_key(level, position - first_position))?
+                            .ok_or("No side node at this level")?,
+                    );
+                }
+                let new_children = last_position - first_position.max(current_level_start_index);
+                children.extend(new_nodes.by_ref().take(new_children));
 
-                let parent_node = if right_node != "0" {
-                    (self.hash)(vec![left_node.clone(), right_node])
+                // Only the last group can have a single child, which goes up
+                // unhashed
+                if children.len() == 1 {
+                    last_node = children.pop();
                 } else {
-                    left_node.clone()
-                };
-
-                next_level_new_nodes.push(parent_node);
+                    groups.push(children);
+                }
             }
 
-            // Update side nodes
-            if current_level_size & 1 == 1 {
-                self.side_nodes
-                    .insert(level, current_level_new_nodes.last().cloned().unwrap());
-            } else if current_level_new_nodes.len() > 1 {
-                self.side_nodes.insert(
-                    level,
-                    current_level_new_nodes
-                        .get(current_level_new_nodes.len() - 2)
-                        .cloned()
-                        .unwrap(),
-                );
+            self.stats.add_hashes(groups.len());
+            let group_count = groups.len();
+            let mut next_level_new_nodes = hash_groups(groups);
+            if next_level_new_nodes.len() != group_count {
+                return Err("Wrong number of hashed nodes");
             }
+            next_level_new_nodes.extend(last_node);
 
             current_level_start_index = next_level_start_index;
-            next_level_start_index >>= 1;
-
             current_level_new_nodes = next_level_new_nodes;
             current_level_size = next_level_size;
-            next_level_size = ((next_level_size - 1) >> 1) + 1;
         }
 
         // Update tree size and root
+        let root = current_level_new_nodes.pop().ok_or("Empty batch")?;
+        for (key, node) in side_nodes {
+            self.put_side_node(key, node)?;
+        }
         self.size = tree_size + leaves.len();
-        self.side_nodes
-            .insert(tree_depth, current_level_new_nodes[0].clone());
-
-        // Update leaves mapping
-        for (i, leaf) in leaves.iter().enumerate() {
-            self.leaves.insert(leaf.clone(), tree_size + i + 1);
+        self.put_side_node(self.side_node_key(tree_depth, 0), root.clone())?;
+
+        // Update leaves mapping, moving the leaves unless the changelog
+        // records them
+        self.version += 1;
+        self.stats.add_inserts(leaves.len());
+        let record_changes = self.changelog.is_active();
+        for (i, leaf) in leaves.into_iter().enumerate() {
+            if record_changes {
+                changes.push(ChangeRecord {
+                    version: self.version,
+                    index: tree_size + i,
+                    old_leaf: None,
+                    appended: true,
+                    new_leaf: leaf.clone(),
+                    new_root: root.clone(),
+                });
+            }
+            self.add_leaf_index(leaf, tree_size + i)?;
         }
 
-        Ok(current_level_new_nodes[0].clone())
+        Ok(root)
     }
 
     /// Updates an existing leaf in the tree.
+    ///
+    /// If the leaf appears several times, its first occurrence is updated.
     pub fn update(
         &mut self,
-        old_leaf: &IMTNode,
-        new_leaf: IMTNode,
-        sibling_nodes: &[IMTNode],
-    ) -> Result<IMTNode, &'static str> {
-        if !self.leaves.contains_key(old_leaf) {
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        let index = self.index_of(old_leaf)?;
+        self.update_at(index, old_leaf, new_leaf, sibling_nodes)
+    }
+
+    /// Updates the leaf at `index`, which must currently be `old_leaf`.
+    ///
+    /// This selects a specific occurrence of a leaf when duplicates are allowed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(index))
+    )]
+    pub fn update_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.transaction(|imt, changes| {
+            imt.update_leaf(index, old_leaf, new_leaf, sibling_nodes, changes)
+        })
+    }
+
+    fn update_leaf(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+        changes: &mut Vec<ChangeRecord<N>>,
+    ) -> Result<N, &'static str> {
+        if index >= self.size
+            || !self
+                .store
+                .leaf_indices(old_leaf)?
+                .is_some_and(|indices| indices.contains(&index))
+        {
             return Err("Leaf does not exist");
         }
-        if self.leaves.contains_key(&new_leaf) && new_leaf != "0" {
+        // Updating to zero removes the leaf, whatever the leaf hash
+        let new_leaf = if new_leaf == self.zero {
+            new_leaf
+        } else {
+            self.prehash(new_leaf)
+        };
+        if new_leaf != self.zero && self.rejects_duplicate(&new_leaf)? {
             return Err("New leaf already exists");
         }
+        if new_leaf != self.zero && self.rejects_reinsertion(&new_leaf)? {
+            return Err("Leaf was removed");
+        }
 
-        let index = self.index_of(old_leaf)?;
+        let root = self.write_leaf(index, old_leaf, new_leaf, sibling_nodes, changes)?;
+        self.stats.add_update();
+        Ok(root)
+    }
+
+    /// Replaces `old_leaf` at `index` with `new_leaf`, already hashed and
+    /// checked, recomputing the path to the root from the sibling nodes.
+    ///
+    /// `old_leaf` is the zero value when a removed slot is filled.
+    fn write_leaf(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+        changes: &mut Vec<ChangeRecord<N>>,
+    ) -> Result<N, &'static str> {
         let mut node = new_leaf.clone();
         let mut old_root = old_leaf.clone();
+        let mut sibling_nodes = sibling_nodes.iter().cloned();
+        let mut side_nodes = Vec::new();
 
-        let last_index = self.size - 1;
-        let mut i = 0;
-
+        let arity = self.arity;
         let tree_depth = self.depth;
+        let mut level_size = self.size;
 
         for level in 0..tree_depth {
-            if ((index >> level) & 1) == 1 {
-                let sibling_node = sibling_nodes
-                    .get(i)
-                    .cloned()
-                    .ok_or("Not enough sibling nodes")?;
-                node = (self.hash)(vec![sibling_node.clone(), node]);
-                old_root = (self.hash)(vec![sibling_node, old_root]);
-                i += 1;
-            } else {
-                if (index >> level) != (last_index >> level) {
-                    let sibling_node = sibling_nodes
-                        .get(i)
-                        .cloned()
-                        .ok_or("Not enough sibling nodes")?;
-                    node = (self.hash)(vec![node, sibling_node.clone()]);
-                    old_root = (self.hash)(vec![old_root, sibling_node]);
-                    i += 1;
-                } else {
-                    self.side_nodes.insert(level, node.clone());
+            let position = LeafIndex::from(index).ancestor(level, arity).to_usize()?;
+            let group_start = position / arity * arity;
+            let group_end = (group_start + arity).min(level_size);
+
+            // The nodes of the last group are side nodes, which later nodes
+            // can still hash with, except a last node completing the group
+            if group_end == level_size && position - group_start < arity - 1 {
+                side_nodes.push((
+                    self.side_node_key(level, position - group_start),
+                    node.clone(),
+                ));
+            }
+
+            if group_end - group_start > 1 {
+                let mut children = Vec::with_capacity(group_end - group_start);
+                let mut old_children = Vec::with_capacity(group_end - group_start);
+                // The siblings in order, with the node at its position
+                for _ in 1..group_end - group_start {
+                    old_children.push(sibling_nodes.next().ok_or("Not enough sibling nodes")?);
                 }
+                children.extend(old_children.iter().cloned());
+                children.insert(position - group_start, node);
+                old_children.insert(position - group_start, old_root);
+                node = self.hash_children(children);
+                old_root = self.hash_children(old_children);
             }
+
+            level_size = (level_size - 1) / arity + 1;
         }
 
-        if Some(old_root) != self.root() {
+        let root_key = self.side_node_key(tree_depth, 0);
+        let root = self.store.side_node(root_key)?;
+        if !root.is_some_and(|root| self.nodes_eq(&old_root, &root)) {
             return Err("Wrong sibling nodes");
         }
+        if new_leaf == self.zero && self.removal_policy != RemovalPolicy::Forget {
+            let mut indices = self.store.tombstone(old_leaf)?.unwrap_or_default();
+            let position = indices.partition_point(|&other| other < index);
+            indices.insert(position, index);
+            self.store.put_tombstone(old_leaf.clone(), indices)?;
+        }
 
-        self.side_nodes.insert(tree_depth, node.clone());
+        for (key, side_node) in side_nodes {
+            self.put_side_node(key, side_node)?;
+        }
+        self.put_side_node(root_key, node.clone())?;
+
+        self.remove_leaf_index(old_leaf, index)?;
+        if new_leaf != self.zero {
+            self.add_leaf_index(new_leaf.clone(), index)?;
+        } else {
+            self.store.put_leaf_at(index, None)?;
+            self.store.put_payload(index, None)?;
+        }
 
-        if new_leaf != "0" {
-            let leaf_index = *self.leaves.get(old_leaf).unwrap();
-            self.leaves.insert(new_leaf.clone(), leaf_index);
+        if let Some(gaps) = &mut self.gaps {
+            if new_leaf == self.zero {
+                gaps.insert(index);
+            } else {
+                gaps.remove(&index);
+            }
         }
 
-        self.leaves.remove(old_leaf);
+        self.version += 1;
+        if self.changelog.is_active() {
+            changes.push(ChangeRecord {
+                version: self.version,
+                index,
+                // Filling a removed slot is recorded as an insertion
+                old_leaf: (*old_leaf != self.zero).then(|| old_leaf.clone()),
+                appended: false,
+                new_leaf,
+                new_root: node.clone(),
+            });
+        }
 
         Ok(node)
     }
 
-    /// Removes a leaf from the tree.
-    pub fn remove(&mut self, old_leaf: &IMTNode, sibling_nodes: &[IMTNode]) -> Result<IMTNode, &'static str> {
-        self.update(old_leaf, "0".to_string(), sibling_nodes)
+    /// Removes a leaf from the tree.
+    pub fn remove(&mut self, old_leaf: &N, sibling_nodes: &[N]) -> Result<N, &'static str> {
+        self.update(old_leaf, self.zero.clone(), sibling_nodes)
+    }
+
+    /// Removes the leaf at `index`, which must currently be `old_leaf`.
+    pub fn remove_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.update_at(index, old_leaf, self.zero.clone(), sibling_nodes)
+    }
+
+    /// Removes the last leaf and returns it, the zero value if it was
+    /// removed already.
+    ///
+    /// This is `truncate` to one leaf less.
+    pub fn pop(&mut self) -> Result<N, &'static str> {
+        let index = self.size.checked_sub(1).ok_or("Tree is empty")?;
+        let leaf = self.leaf_at(index)?;
+        self.truncate(index)?;
+        Ok(leaf)
+    }
+
+    /// Drops the leaves from index `new_size` on, rewinding the tree to that
+    /// size, and returns the new root.
+    ///
+    /// The side nodes of the smaller tree are recomputed from its leaves,
+    /// with one hash per node. Change records cannot express dropped
+    /// leaves, so the changelog is pruned up to the truncation: older
+    /// versions and the changes before it are no longer served, and root
+    /// change hooks are called with `Operation::Truncate` and no record.
+    pub fn truncate(&mut self, new_size: usize) -> Result<Option<N>, &'static str> {
+        if new_size > self.size {
+            return Err("Index out of range");
+        }
+        if new_size == self.size {
+            return Ok(self.root());
+        }
+
+        let old_root = self.root();
+        self.transaction(|imt, _| imt.truncate_leaves(new_size))?;
+        self.changelog.prune_through(self.version);
+        let new_root = self.root();
+        if self.changelog.has_hooks() {
+            self.changelog.notify_operation(RootChange {
+                version: self.version,
+                operation: Operation::Truncate,
+                old_root: old_root.as_ref(),
+                new_root: new_root.as_ref(),
+                size: self.size,
+                changes: &[],
+            });
+        }
+        Ok(new_root)
+    }
+
+    fn truncate_leaves(&mut self, new_size: usize) -> Result<(), &'static str> {
+        let mut nodes = self.ordered_leaves()?;
+        for (index, leaf) in nodes.drain(new_size..).enumerate() {
+            let index = new_size + index;
+            if leaf != self.zero {
+                self.remove_leaf_index(&leaf, index)?;
+                self.store.put_leaf_at(index, None)?;
+            }
+            self.store.put_payload(index, None)?;
+        }
+
+        self.write_side_nodes(nodes)?;
+        self.size = new_size;
+        if let Some(gaps) = &mut self.gaps {
+            gaps.split_off(&new_size);
+        }
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Replaces the leaves of the tree with `leaves`, ordered by index with
+    /// removed leaves set to zero, and returns the new root.
+    ///
+    /// Repairs a tree whose store lost or corrupted some of its state,
+    /// keeping its configuration and hooks: every leaf index and side node
+    /// is rewritten from the leaves, with one hash per node. The leaves are
+    /// taken as stored, not hashed again by the leaf hash. Payloads of the
+    /// indices still holding a leaf are kept. Like `truncate`, the changelog
+    /// is pruned up to the rebuild, and root change hooks are called with
+    /// `Operation::Rebuild` and no record.
+    pub fn rebuild_from_leaves(&mut self, leaves: Vec<N>) -> Result<Option<N>, &'static str> {
+        if self
+            .max_size
+            .is_some_and(|max_size| leaves.len() > max_size)
+        {
+            return Err("Tree is full");
+        }
+        if self.duplicate_policy == DuplicatePolicy::Reject {
+            let mut seen: HashSet<_> =
+                HashSet::with_capacity_and_hasher(leaves.len(), Default::default());
+            if leaves
+                .iter()
+                .any(|leaf| *leaf != self.zero && !seen.insert(leaf))
+            {
+                return Err("Leaf already exists");
+            }
+        }
+
+        let old_root = self.root();
+        self.transaction(|imt, _| imt.rebuild_leaves(leaves))?;
+        self.changelog.prune_through(self.version);
+        let new_root = self.root();
+        if self.changelog.has_hooks() {
+            self.changelog.notify_operation(RootChange {
+                version: self.version,
+                operation: Operation::Rebuild,
+                old_root: old_root.as_ref(),
+                new_root: new_root.as_ref(),
+                size: self.size,
+                changes: &[],
+            });
+        }
+        Ok(new_root)
+    }
+
+    fn rebuild_leaves(&mut self, leaves: Vec<N>) -> Result<(), &'static str> {
+        // The store may hold leaves past the size of the tree if corrupted
+        let stale_size = self.store.leaves_by_index()?.len().max(self.size);
+        let stored = self
+            .store
+            .leaves()
+            .map(|entry| entry.map(|(leaf, _)| leaf))
+            .collect::<Result<Vec<_>, _>>()?;
+        for leaf in stored {
+            self.store.delete_leaf(&leaf)?;
+        }
+        for index in leaves.len()..stale_size {
+            self.store.put_leaf_at(index, None)?;
+        }
+        let payloads = self
+            .store
+            .payloads()
+            .map(|entry| entry.map(|(index, _)| index))
+            .collect::<Result<Vec<_>, _>>()?;
+        for index in payloads {
+            if leaves.get(index).is_none_or(|leaf| *leaf == self.zero) {
+                self.store.put_payload(index, None)?;
+            }
+        }
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            if *leaf == self.zero {
+                self.store.put_leaf_at(index, None)?;
+            } else {
+                self.add_leaf_index(leaf.clone(), index)?;
+            }
+        }
+
+        if self.gaps.is_some() {
+            self.gaps = Some(
+                (0..leaves.len())
+                    .filter(|&index| leaves[index] == self.zero)
+                    .collect(),
+            );
+        }
+        self.size = leaves.len();
+        self.write_side_nodes(leaves)?;
+        self.version += 1;
+        Ok(())
+    }
+
+    /// Replaces the side nodes with those of the tree whose leaves are
+    /// `nodes`, and sets its depth.
+    fn write_side_nodes(&mut self, mut nodes: Vec<N>) -> Result<(), &'static str> {
+        // Only the nodes of the last group of each level are kept as side
+        // nodes, as after building the tree in one batch
+        let arity = self.arity;
+        let depth = depth_of(nodes.len(), arity);
+        let mut side_nodes = Vec::new();
+        for level in 0..depth {
+            let last_group_start = (nodes.len() - 1) / arity * arity;
+            for (slot, node) in nodes[last_group_start..].iter().enumerate() {
+                if slot < arity - 1 {
+                    side_nodes.push((self.side_node_key(level, slot), node.clone()));
+                }
+            }
+            nodes = nodes
+                .chunks(arity)
+                .map(|children| match children {
+                    [child] => child.clone(),
+                    _ => self.hash_children(children.to_vec()),
+                })
+                .collect();
+        }
+        if let Some(root) = nodes.pop() {
+            side_nodes.push((self.side_node_key(depth, 0), root));
+        }
+
+        let stale = self
+            .store
+            .side_nodes()
+            .map(|entry| entry.map(|(key, _)| key))
+            .collect::<Result<Vec<_>, _>>()?;
+        for key in stale {
+            self.store.delete_side_node(key)?;
+        }
+        for (key, node) in side_nodes {
+            self.put_side_node(key, node)?;
+        }
+
+        self.depth = depth;
+        Ok(())
+    }
+
+    /// Checks if a leaf exists in the tree.
+    ///
+    /// A leaf that cannot be read from the store is reported as missing.
+    pub fn has(&self, leaf: &N) -> bool {
+        self.store.has_leaf(leaf).unwrap_or(false)
+    }
+
+    /// Returns the index of a leaf in the tree.
+    ///
+    /// If the leaf appears several times, the lowest index is returned.
+    pub fn index_of(&self, leaf: &N) -> Result<usize, &'static str> {
+        self.store
+            .leaf_indices(leaf)?
+            .and_then(|indices| indices.first().copied())
+            .ok_or("Leaf does not exist")
+    }
+
+    /// Returns the indices a leaf was removed from, in ascending order, if
+    /// the tree keeps tombstones. A leaf inserted again after its removal
+    /// keeps its tombstone.
+    pub fn removed_indices_of(&self, leaf: &N) -> Vec<usize> {
+        self.store
+            .tombstone(leaf)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Returns every index of a leaf in the tree, in ascending order.
+    pub fn indices_of(&self, leaf: &N) -> Vec<usize> {
+        self.store
+            .leaf_indices(leaf)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Returns the leaf at `index`, the zero value if it was removed.
+    pub fn leaf_at(&self, index: usize) -> Result<N, &'static str> {
+        if index >= self.size {
+            return Err("Index out of range");
+        }
+        Ok(self
+            .store
+            .leaf_at(index)?
+            .unwrap_or_else(|| self.zero.clone()))
+    }
+
+    /// Returns the payload attached to the leaf at `index`, if any.
+    pub fn value_at(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        if index >= self.size {
+            return Err("Index out of range");
+        }
+        self.store.payload(index)
+    }
+
+    /// Returns the payload attached to a leaf, if any.
+    ///
+    /// If the leaf appears several times, the payload of its lowest index is
+    /// returned.
+    pub fn value_of(&self, leaf: &N) -> Result<Option<Vec<u8>>, &'static str> {
+        self.store.payload(self.index_of(leaf)?)
+    }
+
+    /// Attaches `value` to the leaf at `index`, replacing its payload, or
+    /// detaches the payload with `None`.
+    ///
+    /// Removed leaves cannot hold a payload.
+    pub fn set_value(&mut self, index: usize, value: Option<Vec<u8>>) -> Result<(), &'static str> {
+        let leaf = self.leaf_at(index)?;
+        if value.is_some() && leaf == self.zero {
+            return Err("Leaf does not exist");
+        }
+        self.transaction(|imt, _| imt.store.put_payload(index, value))
+    }
+
+    /// Returns the root of the tree.
+    ///
+    /// A root that cannot be read from the store is reported as missing.
+    pub fn root(&self) -> Option<N> {
+        self.store
+            .side_node(self.side_node_key(self.depth, 0))
+            .ok()
+            .flatten()
+    }
+
+    /// Returns the store key of the side node at `slot` of the last group of
+    /// `level`.
+    ///
+    /// A level holds up to `arity - 1` side nodes, so binary trees keep one
+    /// side node per level, and the root is the only node at `depth`.
+    pub(crate) fn side_node_key(&self, level: usize, slot: usize) -> usize {
+        level * (self.arity - 1) + slot
+    }
+
+    /// Returns the leaves ordered by index, with removed leaves set to zero.
+    pub(crate) fn ordered_leaves(&self) -> Result<Vec<N>, &'static str> {
+        let mut leaves = self.store.leaves_by_index()?;
+        if leaves[self.size.min(leaves.len())..]
+            .iter()
+            .any(Option::is_some)
+        {
+            return Err("Invalid leaf index");
+        }
+        // The size may come from a corrupted store, so a failed allocation is
+        // reported rather than aborting
+        leaves
+            .try_reserve(self.size.saturating_sub(leaves.len()))
+            .map_err(|_| "Tree is too large")?;
+        leaves.resize(self.size, None);
+        Ok(leaves
+            .into_iter()
+            .map(|leaf| leaf.unwrap_or_else(|| self.zero.clone()))
+            .collect())
+    }
+
+    pub fn get_duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    pub fn get_arity(&self) -> usize {
+        self.arity
+    }
+
+    /// Sets the maximum number of children hashed together into a node.
+    ///
+    /// The arity can only be changed while the tree is empty.
+    pub fn set_arity(&mut self, arity: usize) -> Result<(), &'static str> {
+        if arity < 2 {
+            return Err("Invalid arity");
+        }
+        if self.size > 0 {
+            return Err("Tree is not empty");
+        }
+        self.transaction(|imt, _| {
+            imt.arity = arity;
+            Ok(())
+        })
+    }
+
+    /// Limits the tree to `max_size` leaves, or lifts the limit with `None`.
+    ///
+    /// Inserts that would grow the tree past the limit fail with "Tree is
+    /// full". Fails if the tree already holds more leaves.
+    pub fn set_max_size(&mut self, max_size: Option<usize>) -> Result<(), &'static str> {
+        if max_size.is_some_and(|max_size| self.size > max_size) {
+            return Err("Tree exceeds max size");
+        }
+        self.transaction(|imt, _| {
+            imt.max_size = max_size;
+            Ok(())
+        })
+    }
+
+    /// Limits the tree to the leaves of a tree of depth `max_depth` with the
+    /// current arity, as circuits with a fixed depth require.
+    pub fn set_max_depth(&mut self, max_depth: usize) -> Result<(), &'static str> {
+        self.set_max_size(max_size_of(max_depth, self.arity))
+    }
+
+    /// Returns the maximum number of leaves, if the tree is limited.
+    pub fn get_max_size(&self) -> Option<usize> {
+        self.max_size
+    }
+
+    /// Hashes the leaves entering the tree with `leaf_hash`, or stores them
+    /// as given with `None`.
+    ///
+    /// Inserted leaves and the new leaves of updates are then raw data,
+    /// while the tree, its proofs and the `old_leaf` arguments of updates
+    /// and removals hold the hashed leaves. A leaf hash that
+    /// domain-separates leaves from internal nodes, such as
+    /// `hashers::digest::hash_tagged_leaf`, keeps a node from being passed
+    /// off as a leaf. The function is not saved with the tree, so it has to
+    /// be set again on restored or reopened trees.
+    pub fn set_leaf_hash(&mut self, leaf_hash: Option<IMTLeafHashFunction<N>>) {
+        self.leaf_hash = leaf_hash;
+    }
+
+    /// Returns the leaf stored in the tree for the raw leaf `leaf`, to look
+    /// it up with `has` or `index_of`.
+    pub fn hash_leaf(&self, leaf: N) -> N {
+        match self.leaf_hash {
+            Some(leaf_hash) => leaf_hash(leaf),
+            None => leaf,
+        }
+    }
+
+    /// Compares roots and proof nodes with `node_eq`, or with `==` with
+    /// `None`.
+    ///
+    /// Proof checks and the root check of updates and removals then compare
+    /// with it, so a constant-time comparison such as `ct::bytes_eq` keeps
+    /// their timing from telling how much of a forged node matches. The
+    /// function is not saved with the tree, so it has to be set again on
+    /// restored or reopened trees.
+    pub fn set_node_eq(&mut self, node_eq: Option<IMTNodeEqFunction<N>>) {
+        self.node_eq = node_eq;
+    }
+
+    /// Compares two nodes with the comparison of the tree.
+    pub(crate) fn nodes_eq(&self, a: &N, b: &N) -> bool {
+        match self.node_eq {
+            Some(node_eq) => node_eq(a, b),
+            None => a == b,
+        }
+    }
+
+    /// Fails on the operations only defined for binary trees.
+    pub(crate) fn ensure_binary(&self) -> Result<(), &'static str> {
+        if self.arity != 2 {
+            return Err("Unsupported arity");
+        }
+        Ok(())
+    }
+
+    /// Sets how leaves already in the tree are handled from now on.
+    ///
+    /// Switching to `DuplicatePolicy::Reject` fails if the tree already holds
+    /// duplicate leaves.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) -> Result<(), &'static str> {
+        if policy == DuplicatePolicy::Reject {
+            for entry in self.store.leaves() {
+                if entry?.1.len() > 1 {
+                    return Err("Tree contains duplicate leaves");
+                }
+            }
+        }
+        self.transaction(|imt, _| {
+            imt.duplicate_policy = policy;
+            Ok(())
+        })
+    }
+
+    /// Sets how leaves removed from now on are remembered.
+    ///
+    /// Tombstones are kept by the store, so removing a leaf fails with "Store
+    /// does not keep tombstones" if the policy keeps them and the store does
+    /// not. The policy and the tombstones are saved in snapshots, but not in
+    /// the metadata of persistent stores, which do not keep tombstones.
+    /// Tombstones already kept stay in the store whatever the policy.
+    pub fn set_removal_policy(&mut self, policy: RemovalPolicy) {
+        self.removal_policy = policy;
+    }
+
+    pub fn get_removal_policy(&self) -> RemovalPolicy {
+        self.removal_policy
+    }
+
+    /// Enables or disables compaction, which tracks the slots of removed
+    /// leaves so that `insert_into_gap` can fill them.
+    ///
+    /// Enabling it scans the leaves for the slots removed so far. The
+    /// removed slots are kept in memory, not in the store, so compaction has
+    /// to be enabled again on restored or reopened trees.
+    pub fn set_compaction(&mut self, enabled: bool) -> Result<(), &'static str> {
+        self.gaps = if enabled {
+            Some(self.find_gaps()?)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    pub fn is_compacting(&self) -> bool {
+        self.gaps.is_some()
+    }
+
+    /// Returns the indices of the removed leaves in ascending order, none if
+    /// compaction is disabled.
+    pub fn gaps(&self) -> impl Iterator<Item = usize> + '_ {
+        self.gaps.iter().flatten().copied()
+    }
+
+    /// Lists the indices of the removed leaves from the store.
+    fn find_gaps(&self) -> Result<BTreeSet<usize>, &'static str> {
+        let leaves = self.store.leaves_by_index()?;
+        Ok((0..self.size)
+            .filter(|&index| leaves.get(index).is_none_or(Option::is_none))
+            .collect())
+    }
+
+    /// Checks that a batch of leaves can be appended to the tree.
+    pub(crate) fn check_new_leaves(&self, leaves: &[N]) -> Result<(), &'static str> {
+        self.check_capacity(leaves.len())?;
+        let mut batch: HashSet<_> =
+            HashSet::with_capacity_and_hasher(leaves.len(), Default::default());
+        for leaf in leaves {
+            if self.rejects_duplicate(leaf)?
+                || (self.duplicate_policy == DuplicatePolicy::Reject && !batch.insert(leaf))
+            {
+                return Err("Leaf already exists");
+            }
+            if *leaf == self.zero {
+                return Err("Leaf cannot be zero");
+            }
+            if self.rejects_reinsertion(leaf)? {
+                return Err("Leaf was removed");
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with "Tree is full" if `additional` leaves would exceed the
+    /// maximum size of the tree.
+    fn check_capacity(&self, additional: usize) -> Result<(), &'static str> {
+        if self.size.checked_add(additional).is_none()
+            || self
+                .max_size
+                .is_some_and(|max_size| additional > max_size.saturating_sub(self.size))
+        {
+            return Err("Tree is full");
+        }
+        Ok(())
+    }
+
+    /// Returns true if `leaf` cannot be added because of the duplicate policy.
+    fn rejects_duplicate(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self.duplicate_policy == DuplicatePolicy::Reject && self.store.has_leaf(leaf)?)
+    }
+
+    /// Returns true if `leaf` cannot be added because it was removed.
+    fn rejects_reinsertion(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self.removal_policy == RemovalPolicy::RejectReinsertion
+            && self.store.tombstone(leaf)?.is_some())
+    }
+
+    /// Records `leaf` at `index` in both leaf maps of the store.
+    fn add_leaf_index(&mut self, leaf: N, index: usize) -> Result<(), &'static str> {
+        let mut indices = self.store.leaf_indices(&leaf)?.unwrap_or_default();
+        let position = indices.partition_point(|&other| other < index);
+        indices.insert(position, index);
+        self.store.put_leaf_at(index, Some(leaf.clone()))?;
+        self.stats.add_nodes_stored(1);
+        self.store.put_leaf_indices(leaf, indices)
+    }
+
+    /// Writes a side node to the store, counting it in the statistics.
+    fn put_side_node(&mut self, key: usize, node: N) -> Result<(), &'static str> {
+        self.stats.add_nodes_stored(1);
+        self.store.put_side_node(key, node)
+    }
+
+    /// Hashes a leaf entering the tree, counting the call in the statistics.
+    fn prehash(&self, leaf: N) -> N {
+        if self.leaf_hash.is_some() {
+            self.stats.add_hashes(1);
+        }
+        self.hash_leaf(leaf)
+    }
+
+    /// Hashes `children`, counting the call in the statistics.
+    fn hash_children(&self, children: Vec<N>) -> N {
+        self.stats.add_hashes(1);
+        self.hash.call(children)
+    }
+
+    fn remove_leaf_index(&mut self, leaf: &N, index: usize) -> Result<(), &'static str> {
+        if let Some(mut indices) = self.store.leaf_indices(leaf)? {
+            indices.retain(|&other| other != index);
+            if indices.is_empty() {
+                self.store.delete_leaf(leaf)?;
+            } else {
+                self.store.put_leaf_indices(leaf.clone(), indices)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs an operation, then saves the metadata and commits the store.
+    ///
+    /// If the operation or the commit fails, the store is rolled back and the
+    /// tree fields are restored. Only stores that buffer their writes, or
+    /// keep a checkpoint or an undo log, undo them, so operations make every
+    /// check that can fail before their first write: `MemoryStore`, whose
+    /// writes do not fail, is then never left half written. The change
+    /// records collected by the operation are only published once it is
+    /// committed.
+    fn transaction<T>(
+        &mut self,
+        operation: impl FnOnce(&mut Self, &mut Vec<ChangeRecord<N>>) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        let (size, depth, version) = (self.size, self.depth, self.version);
+        let (arity, duplicate_policy) = (self.arity, self.duplicate_policy);
+        let max_size = self.max_size;
+        let old_root = if self.changelog.has_hooks() {
+            self.root()
+        } else {
+            None
+        };
+
+        let mut changes = Vec::new();
+        let result = operation(self, &mut changes).and_then(|value| {
+            self.persist()?;
+            Ok(value)
+        });
+
+        match result {
+            Ok(value) => {
+                if self.changelog.has_hooks() {
+                    let new_root = self.root();
+                    self.changelog.notify(
+                        old_root.as_ref(),
+                        new_root.as_ref(),
+                        self.size,
+                        &changes,
+                        &self.zero,
+                    );
+                }
+                for change in changes {
+                    self.changelog.push(change);
+                }
+                Ok(value)
+            }
+            Err(error) => {
+                self.store.rollback();
+                self.size = size;
+                self.depth = depth;
+                self.version = version;
+                self.arity = arity;
+                self.duplicate_policy = duplicate_policy;
+                self.max_size = max_size;
+                // The removed slots are rescanned from the rolled back store
+                if self.gaps.is_some() {
+                    self.gaps = self.find_gaps().ok();
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Saves the metadata of the tree and commits the store.
+    fn persist(&mut self) -> Result<(), &'static str> {
+        self.store.put_metadata(StoreMetadata {
+            size: self.size,
+            version: self.version,
+            zero: self.zero.clone(),
+            duplicate_policy: self.duplicate_policy,
+            arity: self.arity,
+            max_size: self.max_size,
+        })?;
+        self.store.commit()
+    }
+
+    /// Retains up to `capacity` change records for `changes_since`.
+    ///
+    /// A capacity of zero (the default) disables the pull-based changelog.
+    pub fn set_changelog_capacity(&mut self, capacity: usize) {
+        self.changelog.set_capacity(capacity, self.version);
+    }
+
+    /// Returns the changes applied after `version`, oldest first.
+    ///
+    /// Fails if some of those changes are no longer retained, in which case the
+    /// caller has to resynchronize from a full snapshot.
+    pub fn changes_since(
+        &self,
+        version: u64,
+    ) -> Result<impl Iterator<Item = &ChangeRecord<N>>, &'static str> {
+        self.changelog.since(version)
+    }
+
+    /// Returns a channel receiving every change applied from now on.
+    #[cfg(feature = "std")]
+    pub fn subscribe_changes(&mut self) -> Receiver<ChangeRecord<N>> {
+        self.changelog.subscribe()
+    }
+
+    /// Returns a stream of the version, size and root reached by every
+    /// mutation that changes the leaves, from now on.
+    #[cfg(feature = "std")]
+    pub fn subscribe_roots(&mut self) -> RootStream<N> {
+        self.changelog.subscribe_roots()
+    }
+
+    /// Registers `hook` to be called after every mutation that changes the
+    /// leaves, once it is committed, with the old and new roots.
+    ///
+    /// Hooks run synchronously on the thread applying the mutation; forks do
+    /// not inherit them.
+    pub fn on_root_change(
+        &mut self,
+        hook: impl FnMut(&RootChange<'_, N>) + Send + Sync + 'static,
+    ) -> HookId {
+        self.changelog.add_hook(hook)
+    }
+
+    /// Unregisters a hook, returning false if it was not registered.
+    pub fn remove_root_change_hook(&mut self, id: HookId) -> bool {
+        self.changelog.remove_hook(id)
+    }
+
+    /// Getter Functions for Debugging
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the number of mutations applied to the tree.
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn get_zero(&self) -> &N {
+        &self.zero
+    }
+
+    pub fn get_store(&self) -> &S {
+        &self.store
     }
 
-    /// Checks if a leaf exists in the tree.
-    pub fn has(&self, leaf: &IMTNode) -> bool {
-        self.leaves.contains_key(leaf)
+    /// Returns a copy of the side nodes, skipping those that cannot be read
+    /// from the store.
+    ///
+    /// This copies every side node; `side_nodes` borrows them from a
+    /// `MemoryStore`.
+    pub fn get_side_nodes(&self) -> HashMap<usize, N> {
+        self.store.side_nodes().filter_map(Result::ok).collect()
     }
 
-    /// Returns the index of a leaf in the tree.
-    pub fn index_of(&self, leaf: &IMTNode) -> Result<usize, &'static str> {
-        self.leaves
-            .get(leaf)
-            .map(|&index| index - 1)
-            .ok_or("Leaf does not exist")
+    /// Returns a copy of each leaf with its lowest index plus one, skipping
+    /// those that cannot be read from the store.
+    ///
+    /// The indices are 1-based for compatibility with earlier versions. This
+    /// copies every leaf; `leaves_iter` borrows them from a `MemoryStore`.
+    pub fn get_leaves(&self) -> HashMap<N, usize> {
+        self.store
+            .leaves()
+            .filter_map(|entry| {
+                let (leaf, indices) = entry.ok()?;
+                Some((leaf, indices.first()? + 1))
+            })
+            .collect()
     }
+}
 
-    /// Returns the root of the tree.
-    pub fn root(&self) -> Option<IMTNode> {
-        self.side_nodes.get(&self.depth).cloned()
+impl<N> LeanIMT<N, MemoryStore<N>> {
+    /// Iterates over the `(key, node)` side nodes by reference, ordered by
+    /// key.
+    pub fn side_nodes(&self) -> impl Iterator<Item = (usize, &N)> {
+        self.store.side_node_refs()
     }
 
-    /// Getter Functions for Debugging
-    pub fn get_size(&self) -> usize {
-        self.size
+    /// Iterates over the leaves by reference, ordered by index, with removed
+    /// leaves set to zero.
+    pub fn leaves_iter(&self) -> impl Iterator<Item = &N> {
+        self.store
+            .leaf_refs()
+            .chain(core::iter::repeat(None))
+            .take(self.size)
+            .map(|leaf| leaf.unwrap_or(&self.zero))
     }
+}
 
-    pub fn get_depth(&self) -> usize {
-        self.depth
+/// Clones the tree like `fork`, also retaining the change records, so the
+/// clone serves the same past roots and proofs. Root change hooks, change
+/// subscribers and root streams stay with the original tree.
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N> + Clone> Clone for LeanIMT<N, S> {
+    fn clone(&self) -> Self {
+        let mut tree = self.fork();
+        tree.changelog = self.changelog.clone_records();
+        tree
     }
+}
 
-    pub fn get_side_nodes(&self) -> HashMap<usize, IMTNode> {
-        self.side_nodes.clone()
+/// Trees are equal if they have the same arity, size, depth and leaves by
+/// index, whatever their stores. Trees hashing with the same function then
+/// have the same root; the hash functions themselves are not compared, nor
+/// the settings such as the duplicate policy or the size limit.
+///
+/// A tree whose leaves cannot be read is not equal to any tree.
+impl<N, S, T> PartialEq<LeanIMT<N, T>> for LeanIMT<N, S>
+where
+    N: Clone + Eq + Hash,
+    S: LeanIMTStore<N>,
+    T: LeanIMTStore<N>,
+{
+    fn eq(&self, other: &LeanIMT<N, T>) -> bool {
+        self.arity == other.arity
+            && self.size == other.size
+            && self.depth == other.depth
+            && matches!(
+                (self.ordered_leaves(), other.ordered_leaves()),
+                (Ok(leaves), Ok(other_leaves)) if leaves == other_leaves
+            )
     }
+}
+
+/// Returns the number of leaves of a full tree of `depth`, or `None` if it
+/// does not fit in a `usize`, in which case the tree cannot be limited.
+pub(crate) fn max_size_of(depth: usize, arity: usize) -> Option<usize> {
+    arity.checked_pow(u32::try_from(depth).ok()?)
+}
 
-    pub fn get_leaves(&self) -> HashMap<IMTNode, usize> {
-        self.leaves.clone()
+/// Returns the depth of a tree of `size` leaves hashing up to `arity`
+/// children per node.
+pub(crate) fn depth_of(size: usize, arity: usize) -> usize {
+    let mut depth = 0;
+    let mut capacity: usize = 1;
+    while capacity < size {
+        capacity = capacity.saturating_mul(arity);
+        depth += 1;
     }
+    depth
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::format;
+    use alloc::vec;
 
     fn simple_hash_function(nodes: Vec<String>) -> String {
         nodes.join(",")
@@ -341,6 +1867,120 @@ mod tests {
         assert_eq!(result.unwrap_err(), "Leaf already exists");
     }
 
+    #[test]
+    fn test_insert_many_with_duplicate_in_batch() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        let leaves = vec!["leaf1".to_string(), "leaf1".to_string()];
+        let result = imt.insert_many(leaves);
+        assert_eq!(result.unwrap_err(), "Leaf already exists");
+        assert_eq!(imt.size, 0);
+    }
+
+    #[test]
+    fn test_allow_duplicate_leaves() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_duplicate_policy(DuplicatePolicy::Allow).unwrap();
+
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.insert("leaf1".to_string()).unwrap();
+        assert_eq!(imt.size, 4);
+        assert_eq!(imt.index_of(&"leaf1".to_string()).unwrap(), 0);
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![0, 2, 3]);
+        assert_eq!(imt.root().unwrap(), "leaf1,leaf2,leaf1,leaf1".to_string());
+
+        // Switching back is refused while duplicates remain
+        let result = imt.set_duplicate_policy(DuplicatePolicy::Reject);
+        assert_eq!(result.unwrap_err(), "Tree contains duplicate leaves");
+
+        // Update a specific occurrence
+        let proof = imt.generate_proof(2).unwrap();
+        imt.update_at(2, &"leaf1".to_string(), "leaf2".to_string(), &proof.siblings)
+            .unwrap();
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![0, 3]);
+        assert_eq!(imt.indices_of(&"leaf2".to_string()), vec![1, 2]);
+
+        // `remove` targets the first occurrence
+        let proof = imt.generate_proof(0).unwrap();
+        imt.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![3]);
+        assert_eq!(imt.root().unwrap(), "0,leaf2,leaf2,leaf1".to_string());
+
+        let result = imt.update_at(0, &"leaf2".to_string(), "leaf3".to_string(), &[]);
+        assert_eq!(result.unwrap_err(), "Leaf does not exist");
+    }
+
+    #[test]
+    fn test_insert_many_empty_batch() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        assert_eq!(imt.insert_many(vec![]).unwrap_err(), "Empty batch");
+        assert_eq!(imt.size, 0);
+        assert!(imt.root().is_none());
+
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        let root = imt.root();
+        assert_eq!(imt.insert_many(vec![]).unwrap_err(), "Empty batch");
+        assert_eq!(imt.size, 2);
+        assert_eq!(imt.root(), root);
+        assert_eq!(imt.get_version(), 1);
+    }
+
+    #[test]
+    fn test_insert_many_single_leaf_matches_insert() {
+        let hash: IMTHashFunction = simple_hash_function;
+
+        for size in 0..9 {
+            let mut batched = LeanIMT::new(hash);
+            let mut sequential = LeanIMT::new(hash);
+            for i in 0..size {
+                batched.insert(format!("leaf{}", i)).unwrap();
+                sequential.insert(format!("leaf{}", i)).unwrap();
+            }
+
+            let batch_root = batched.insert_many(vec!["new".to_string()]).unwrap();
+            let root = sequential.insert("new".to_string()).unwrap();
+            assert_eq!(batch_root, root);
+            assert_eq!(batched.get_depth(), sequential.get_depth());
+            assert_eq!(batched.get_side_nodes(), sequential.get_side_nodes());
+        }
+    }
+
+    #[test]
+    fn test_insert_many_into_partially_filled_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+
+        for size in 1..9 {
+            for batch_size in 1..6 {
+                let mut batched = LeanIMT::new(hash);
+                let mut sequential = LeanIMT::new(hash);
+                let leaves: Vec<_> = (0..size).map(|i| format!("leaf{}", i)).collect();
+                batched.insert_many(leaves.clone()).unwrap();
+                sequential.insert_many(leaves).unwrap();
+
+                let batch: Vec<_> = (0..batch_size).map(|i| format!("new{}", i)).collect();
+                batched.insert_many(batch.clone()).unwrap();
+                for leaf in batch {
+                    sequential.insert(leaf).unwrap();
+                }
+
+                assert_eq!(batched.root(), sequential.root());
+                assert_eq!(batched.get_side_nodes(), sequential.get_side_nodes());
+
+                // The saved side nodes keep later insertions consistent
+                batched.insert("last".to_string()).unwrap();
+                sequential.insert("last".to_string()).unwrap();
+                assert_eq!(batched.root(), sequential.root());
+            }
+        }
+    }
+
     #[test]
     fn test_update() {
         let hash: IMTHashFunction = simple_hash_function;
@@ -387,6 +2027,129 @@ mod tests {
         assert_eq!(imt.root().unwrap(), "0".to_string());
     }
 
+    #[test]
+    fn test_reinsert_removed_leaf() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+
+        // Removed leaves are forgotten and come back at a new index
+        let proof = imt.generate_proof(0).unwrap();
+        imt.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+        imt.insert("leaf1".to_string()).unwrap();
+        assert_eq!(imt.index_of(&"leaf1".to_string()), Ok(2));
+        assert_eq!(imt.leaf_at(0), Ok("0".to_string()));
+        assert!(imt.removed_indices_of(&"leaf1".to_string()).is_empty());
+
+        imt.set_removal_policy(RemovalPolicy::Tombstone);
+        let proof = imt.generate_proof(2).unwrap();
+        imt.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+        imt.insert("leaf1".to_string()).unwrap();
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![3]);
+        assert_eq!(imt.removed_indices_of(&"leaf1".to_string()), vec![2]);
+
+        // Tombstones can also reject reinsertions, in transactions as well
+        imt.set_removal_policy(RemovalPolicy::RejectReinsertion);
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+        assert_eq!(imt.insert("leaf2".to_string()), Err("Leaf was removed"));
+        assert_eq!(
+            imt.insert_many(vec!["leaf3".to_string(), "leaf2".to_string()]),
+            Err("Leaf was removed")
+        );
+        let proof = imt.generate_proof(3).unwrap();
+        assert_eq!(
+            imt.update(&"leaf1".to_string(), "leaf2".to_string(), &proof.siblings),
+            Err("Leaf was removed")
+        );
+        let mut txn = imt.begin();
+        let proof = txn.generate_proof(3).unwrap();
+        txn.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+        assert_eq!(txn.insert("leaf1".to_string()), Err("Leaf was removed"));
+        txn.commit().unwrap();
+        assert_eq!(imt.removed_indices_of(&"leaf1".to_string()), vec![2, 3]);
+        assert_eq!(imt.insert("leaf1".to_string()), Err("Leaf was removed"));
+        assert_eq!(imt.get_size(), 4);
+    }
+
+    #[test]
+    fn test_insert_into_gap() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_arity(hash, 3).unwrap();
+        let leaves: Vec<String> = (0..5).map(|i| format!("leaf{}", i)).collect();
+        imt.insert_many(leaves.clone()).unwrap();
+        assert_eq!(
+            imt.insert_into_gap("leaf5".to_string()),
+            Err("Compaction is disabled")
+        );
+
+        let proof = imt.generate_nary_proof(1).unwrap();
+        imt.remove(&leaves[1], &proof.siblings.concat()).unwrap();
+        imt.set_compaction(true).unwrap();
+        let proof = imt.generate_nary_proof(3).unwrap();
+        imt.remove(&leaves[3], &proof.siblings.concat()).unwrap();
+        assert_eq!(imt.gaps().collect::<Vec<_>>(), vec![1, 3]);
+
+        // Gaps are filled from the lowest, then leaves are appended
+        assert_eq!(
+            imt.insert_into_gap("leaf0".to_string()),
+            Err("Leaf already exists")
+        );
+        imt.insert_into_gap("leaf5".to_string()).unwrap();
+        imt.insert_into_gap("leaf6".to_string()).unwrap();
+        imt.insert_into_gap("leaf7".to_string()).unwrap();
+        assert_eq!(imt.gaps().count(), 0);
+        assert_eq!(imt.index_of(&"leaf6".to_string()), Ok(3));
+        let mut expected = LeanIMT::with_arity(hash, 3).unwrap();
+        expected
+            .insert_many(
+                ["leaf0", "leaf5", "leaf2", "leaf6", "leaf4", "leaf7"]
+                    .map(String::from)
+                    .to_vec(),
+            )
+            .unwrap();
+        assert_eq!(imt, expected);
+        assert_eq!(imt.root(), expected.root());
+
+        // Truncations drop the gaps past the new size
+        let proof = imt.generate_nary_proof(4).unwrap();
+        imt.remove(&"leaf4".to_string(), &proof.siblings.concat())
+            .unwrap();
+        imt.truncate(4).unwrap();
+        assert_eq!(imt.gaps().count(), 0);
+    }
+
+    #[test]
+    fn test_insert_into_gap_changelog() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_changelog_capacity(16);
+        imt.set_compaction(true).unwrap();
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+        let proof = imt.generate_proof(2).unwrap();
+        let removed_root = imt.remove(&"leaf3".to_string(), &proof.siblings).unwrap();
+        let removed = imt.get_version();
+
+        // Filling the last slot is recorded as an insertion, not an append
+        imt.insert_into_gap("leaf4".to_string()).unwrap();
+        let change = imt.changes_since(removed).unwrap().next().unwrap().clone();
+        assert_eq!(change.index, 2);
+        assert_eq!(change.old_leaf, None);
+        assert!(!change.appended);
+        assert_eq!(change.new_leaf, "leaf4");
+
+        // and undone by restoring the removed slot
+        let proof = imt.proof_at(removed, &"leaf1".to_string()).unwrap();
+        assert_eq!(proof.root, removed_root);
+        assert_eq!(imt.root_at(removed), Ok(Some(removed_root)));
+    }
+
     #[test]
     fn test_remove_nonexistent_leaf() {
         let hash: IMTHashFunction = simple_hash_function;
@@ -398,6 +2161,30 @@ mod tests {
         assert_eq!(result.unwrap_err(), "Leaf does not exist");
     }
 
+    #[test]
+    fn test_custom_zero_value() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let zero = "0x00".to_string();
+        let mut imt = LeanIMT::with_zero(hash, zero.clone());
+
+        // "0" is a regular leaf once another zero value is configured
+        imt.insert("0".to_string()).unwrap();
+        imt.insert("leaf2".to_string()).unwrap();
+
+        let result = imt.insert(zero.clone());
+        assert_eq!(result.unwrap_err(), "Leaf cannot be zero");
+        let result = imt.insert_many(vec!["leaf3".to_string(), zero.clone()]);
+        assert_eq!(result.unwrap_err(), "Leaf cannot be zero");
+
+        let sibling_nodes = vec!["leaf2".to_string()];
+        imt.remove(&"0".to_string(), &sibling_nodes).unwrap();
+        assert_eq!(imt.root().unwrap(), "0x00,leaf2".to_string());
+        assert_eq!(imt.get_zero(), &zero);
+
+        let proof = imt.generate_proof(0).unwrap();
+        assert_eq!(proof.leaf, zero);
+    }
+
     #[test]
     fn test_has_and_index_of() {
         let hash: IMTHashFunction = simple_hash_function;
@@ -411,6 +2198,114 @@ mod tests {
         assert_eq!(imt.index_of(&"leaf1".to_string()).unwrap(), 0);
     }
 
+    #[test]
+    fn test_leaf_at() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+
+        assert_eq!(imt.leaf_at(0).unwrap(), "leaf1");
+        assert_eq!(imt.leaf_at(1).unwrap(), "0");
+        assert_eq!(imt.leaf_at(2).unwrap(), "leaf3");
+        assert_eq!(imt.leaf_at(3).unwrap_err(), "Index out of range");
+        assert_eq!(imt.index_of(&"leaf3".to_string()).unwrap(), 2);
+        assert_eq!(imt.get_leaves()[&"leaf3".to_string()], 3);
+    }
+
+    #[test]
+    fn test_insert_leaves_with_failing_hash() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((1..=3).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let snapshot = imt.snapshot();
+
+        // Hashing the second level fails after the first one was hashed,
+        // and no side node is written
+        let levels = core::cell::Cell::new(0);
+        let leaves = (4..=8).map(|i| format!("leaf{}", i)).collect();
+        let result = imt.transaction(|imt, changes| {
+            imt.insert_leaves(leaves, changes, |groups| {
+                levels.set(levels.get() + 1);
+                match levels.get() {
+                    1 => groups.into_iter().map(simple_hash_function).collect(),
+                    _ => Vec::new(),
+                }
+            })
+        });
+        assert_eq!(result.unwrap_err(), "Wrong number of hashed nodes");
+        assert_eq!(imt.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_insert_many_clones() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct CountedNode(String);
+
+        impl Clone for CountedNode {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::Relaxed);
+                CountedNode(self.0.clone())
+            }
+        }
+
+        fn counted_hash(nodes: Vec<CountedNode>) -> CountedNode {
+            CountedNode(nodes.into_iter().map(|node| node.0).collect())
+        }
+
+        let mut imt = LeanIMT::with_zero(counted_hash, CountedNode("0".to_string()));
+        let leaves: Vec<_> = (0..64)
+            .map(|i| CountedNode(format!("leaf{}", i)))
+            .collect();
+        imt.insert_many(leaves).unwrap();
+        assert!(imt.root().is_some());
+
+        // Each leaf is copied into the first level and into the store by
+        // index, and only a few side nodes and the root are copied besides
+        let clones = CLONES.load(Ordering::Relaxed);
+        assert!(clones < 3 * 64, "{} clones", clones);
+    }
+
+    #[test]
+    fn test_borrowed_nodes() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+        let proof = imt.generate_proof(2).unwrap();
+        imt.remove(&"leaf3".to_string(), &proof.siblings).unwrap();
+
+        let leaves: Vec<&String> = imt.leaves_iter().collect();
+        assert_eq!(leaves, ["leaf1", "leaf2", "0"]);
+        let mut side_nodes: Vec<(usize, String)> = imt
+            .side_nodes()
+            .map(|(key, node)| (key, node.clone()))
+            .collect();
+        side_nodes.sort();
+        let mut copied: Vec<_> = imt.get_side_nodes().into_iter().collect();
+        copied.sort();
+        assert_eq!(side_nodes, copied);
+        assert_eq!(
+            imt.get_store().leaf_index_refs().count(),
+            imt.get_leaves().len()
+        );
+    }
+
     #[test]
     fn test_root_after_operations() {
         let hash: IMTHashFunction = simple_hash_function;
@@ -514,7 +2409,7 @@ mod tests {
         }
 
         // Check that the tree depth is correct
-        let expected_depth = (100 as f64).log2().ceil() as usize;
+        let expected_depth = 100_f64.log2().ceil() as usize;
         assert_eq!(imt.depth, expected_depth);
     }
 
@@ -641,4 +2536,455 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err(), "Wrong sibling nodes");
     }
+
+    #[test]
+    fn test_arity() {
+        fn nested_hash_function(nodes: Vec<String>) -> String {
+            format!("({})", nodes.join(","))
+        }
+
+        fn expected_root(mut nodes: Vec<String>, arity: usize) -> String {
+            while nodes.len() > 1 {
+                nodes = nodes
+                    .chunks(arity)
+                    .map(|children| match children {
+                        [child] => child.clone(),
+                        _ => nested_hash_function(children.to_vec()),
+                    })
+                    .collect();
+            }
+            nodes.swap_remove(0)
+        }
+
+        let hash: IMTHashFunction = nested_hash_function;
+        for arity in [3, 5] {
+            let mut imt = LeanIMT::with_arity(hash, arity).unwrap();
+            let mut batch = LeanIMT::with_arity(hash, arity).unwrap();
+            let mut leaves = Vec::new();
+
+            for i in 0..30 {
+                leaves.push(format!("leaf{}", i));
+                imt.insert(leaves[i].clone()).unwrap();
+                assert_eq!(imt.root(), Some(expected_root(leaves.clone(), arity)));
+            }
+            batch.insert_many(leaves[..7].to_vec()).unwrap();
+            batch.insert_many(leaves[7..].to_vec()).unwrap();
+            assert_eq!(batch.root(), imt.root());
+            batch.insert_many(vec!["leaf30".to_string()]).unwrap();
+            assert_eq!(
+                batch.root(),
+                Some(expected_root(
+                    [&leaves[..], &["leaf30".to_string()]].concat(),
+                    arity
+                ))
+            );
+            assert_eq!(imt.get_depth(), if arity == 3 { 4 } else { 3 });
+
+            for index in [0, 13, 29] {
+                let proof = imt.generate_nary_proof(index).unwrap();
+                assert!(imt.verify_nary_proof(&proof));
+                let siblings = proof.siblings.concat();
+                imt.update(&leaves[index], format!("new{}", index), &siblings)
+                    .unwrap();
+                leaves[index] = format!("new{}", index);
+                assert_eq!(imt.root(), Some(expected_root(leaves.clone(), arity)));
+            }
+
+            // Later insertions use the side nodes refreshed by the updates
+            leaves.push("leaf30".to_string());
+            imt.insert("leaf30".to_string()).unwrap();
+            assert_eq!(imt.root(), Some(expected_root(leaves.clone(), arity)));
+            assert_eq!(imt.generate_proof(0).unwrap_err(), "Unsupported arity");
+        }
+
+        assert_eq!(LeanIMT::with_arity(hash, 1).unwrap_err(), "Invalid arity");
+        let mut imt = LeanIMT::new(hash);
+        imt.insert("leaf1".to_string()).unwrap();
+        assert_eq!(imt.set_arity(4).unwrap_err(), "Tree is not empty");
+    }
+
+    #[test]
+    fn test_max_size() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_max_depth(hash, 2);
+        assert_eq!(imt.get_max_size(), Some(4));
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+
+        let root = imt.root();
+        assert_eq!(
+            imt.insert_many(vec!["leaf4".to_string(), "leaf5".to_string()])
+                .unwrap_err(),
+            "Tree is full"
+        );
+        assert_eq!(imt.root(), root);
+        imt.insert("leaf4".to_string()).unwrap();
+        assert_eq!(imt.insert("leaf5".to_string()).unwrap_err(), "Tree is full");
+        assert_eq!(imt.get_size(), 4);
+
+        // Removed leaves still count, as their index stays taken
+        let proof = imt.generate_proof(0).unwrap();
+        imt.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+        assert_eq!(imt.insert("leaf5".to_string()).unwrap_err(), "Tree is full");
+
+        assert_eq!(
+            imt.set_max_size(Some(3)).unwrap_err(),
+            "Tree exceeds max size"
+        );
+        imt.set_max_size(None).unwrap();
+        imt.insert("leaf5".to_string()).unwrap();
+
+        let mut imt = LeanIMT::with_arity(hash, 3).unwrap();
+        imt.set_max_depth(2).unwrap();
+        assert_eq!(imt.get_max_size(), Some(9));
+        imt.set_max_depth(usize::MAX).unwrap();
+        assert_eq!(imt.get_max_size(), None);
+    }
+
+    #[test]
+    fn test_wrong_siblings_leave_side_nodes_unchanged() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+        let side_nodes = imt.get_side_nodes();
+
+        let sibling_nodes = vec!["wrong".to_string()];
+        let result = imt.update(&"leaf3".to_string(), "leaf4".to_string(), &sibling_nodes);
+        assert_eq!(result.unwrap_err(), "Wrong sibling nodes");
+        assert_eq!(imt.get_side_nodes(), side_nodes);
+
+        // The tree can still grow from the saved side nodes
+        imt.insert("leaf4".to_string()).unwrap();
+        assert_eq!(imt.root(), Some("leaf1,leaf2,leaf3,leaf4".to_string()));
+    }
+
+    #[test]
+    fn test_insert_after_updating_full_last_group() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+
+        // The first pair is the left node of the last group of level 1, which
+        // the next insert hashes with
+        let proof = imt.generate_proof(0).unwrap();
+        imt.update(&"leaf1".to_string(), "new1".to_string(), &proof.siblings)
+            .unwrap();
+        imt.insert("leaf4".to_string()).unwrap();
+        assert_eq!(imt.root(), Some("new1,leaf2,leaf3,leaf4".to_string()));
+    }
+
+    #[test]
+    fn test_fork() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+
+        let mut fork = imt.fork();
+        fork.insert("leaf3".to_string()).unwrap();
+        assert_eq!(fork.root(), Some("leaf1,leaf2,leaf3".to_string()));
+        assert_eq!(imt.root(), Some("leaf1,leaf2".to_string()));
+        assert_eq!(fork.get_version(), imt.get_version() + 1);
+    }
+
+    #[test]
+    fn test_clone_and_eq() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_changelog_capacity(16);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        imt.insert("leaf3".to_string()).unwrap();
+
+        let mut clone = imt.clone();
+        assert!(clone == imt);
+        assert_eq!(clone.root_at(1), Ok(Some("leaf1,leaf2".to_string())));
+        let proof = clone.generate_proof(1).unwrap();
+        clone.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+        assert!(clone != imt);
+        assert_eq!(imt.root(), Some("leaf1,leaf2,leaf3".to_string()));
+
+        // Trees are compared by their leaves, whatever their stores
+        let store = interned_store::InternedStore::default();
+        let mut interned = LeanIMT::with_store(hash, String::new(), store).unwrap();
+        for leaf in ["leaf1", "leaf2", "leaf3"] {
+            interned.insert(leaf.to_string()).unwrap();
+        }
+        assert!(imt == interned);
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.leaves[0].0 = "forged".to_string();
+        assert!(LeanIMT::from_snapshot(snapshot, hash).unwrap() != imt);
+    }
+
+    #[test]
+    fn test_leaf_payloads() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_with_value("leaf1".to_string(), b"one".to_vec())
+            .unwrap();
+        imt.insert("leaf2".to_string()).unwrap();
+        imt.insert_with_value("leaf3".to_string(), b"three".to_vec())
+            .unwrap();
+        assert_eq!(imt.value_of(&"leaf1".to_string()), Ok(Some(b"one".to_vec())));
+        assert_eq!(imt.value_at(1), Ok(None));
+
+        // Updates keep the payload, removals drop it
+        let proof = imt.generate_proof(0).unwrap();
+        imt.update(&"leaf1".to_string(), "new1".to_string(), &proof.siblings)
+            .unwrap();
+        assert_eq!(imt.value_of(&"new1".to_string()), Ok(Some(b"one".to_vec())));
+        let proof = imt.generate_proof(0).unwrap();
+        imt.remove(&"new1".to_string(), &proof.siblings).unwrap();
+        assert_eq!(imt.value_at(0), Ok(None));
+        assert_eq!(
+            imt.set_value(0, Some(b"zero".to_vec())).unwrap_err(),
+            "Leaf does not exist"
+        );
+
+        imt.set_value(1, Some(b"two".to_vec())).unwrap();
+        let snapshot = imt.snapshot().unwrap();
+        assert_eq!(
+            snapshot.payloads,
+            vec![(1, b"two".to_vec()), (2, b"three".to_vec())]
+        );
+        let bytes = imt.to_bytes::<codec::StringCodec>().unwrap();
+        let restored = LeanIMT::from_bytes::<codec::StringCodec>(&bytes, hash).unwrap();
+        assert_eq!(restored.value_of(&"leaf2".to_string()), Ok(Some(b"two".to_vec())));
+        assert_eq!(restored.snapshot(), Ok(snapshot));
+
+        // A rejected leaf leaves no payload for the next one
+        assert_eq!(
+            imt.insert_with_value("leaf2".to_string(), b"secret".to_vec()),
+            Err("Leaf already exists")
+        );
+        imt.insert("leaf4".to_string()).unwrap();
+        assert_eq!(imt.value_of(&"leaf4".to_string()), Ok(None));
+
+        // Stores without payloads reject the leaf along with its payload
+        let mut imt =
+            LeanIMT::with_store(hash, "0".to_string(), sparse::SparseStore::default()).unwrap();
+        assert_eq!(
+            imt.insert_with_value("a".to_string(), b"one".to_vec()),
+            Err("Store does not keep payloads")
+        );
+        assert!(!imt.has(&"a".to_string()));
+        assert_eq!(imt.root(), None);
+        assert_eq!(imt.insert("a".to_string()), Ok("a".to_string()));
+    }
+
+    #[test]
+    fn test_leaf_hash() {
+        fn tag_leaf(leaf: String) -> String {
+            format!("leaf:{}", leaf)
+        }
+
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_leaf_hash(Some(tag_leaf));
+        imt.insert("a".to_string()).unwrap();
+        imt.insert_many(vec!["b".to_string(), "c".to_string()])
+            .unwrap();
+        assert_eq!(imt.root(), Some("leaf:a,leaf:b,leaf:c".to_string()));
+        assert_eq!(imt.index_of(&imt.hash_leaf("b".to_string())), Ok(1));
+        assert_eq!(imt.stats().hashes, 5);
+
+        // New leaves of updates are hashed, but not the zero of removals
+        let proof = imt.generate_proof(0).unwrap();
+        imt.update(&"leaf:a".to_string(), "d".to_string(), &proof.siblings)
+            .unwrap();
+        assert_eq!(imt.leaf_at(0), Ok("leaf:d".to_string()));
+        let proof = imt.generate_proof(0).unwrap();
+        imt.remove(&"leaf:d".to_string(), &proof.siblings).unwrap();
+        assert_eq!(imt.root(), Some("0,leaf:b,leaf:c".to_string()));
+    }
+
+    #[test]
+    fn test_truncate() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let leaves: Vec<_> = (1..=7).map(|i| format!("leaf{}", i)).collect();
+
+        for arity in [2, 3] {
+            let mut imt = LeanIMT::with_arity(hash, arity).unwrap();
+            for leaf in &leaves {
+                imt.insert_with_value(leaf.clone(), vec![1]).unwrap();
+            }
+            imt.set_changelog_capacity(16);
+            let version = imt.get_version();
+
+            assert_eq!(imt.pop(), Ok("leaf7".to_string()));
+            let root = imt.truncate(4).unwrap();
+            let mut reference = LeanIMT::with_arity(hash, arity).unwrap();
+            reference.insert_many(leaves[..4].to_vec()).unwrap();
+            assert_eq!(root, reference.root());
+            assert_eq!(imt.get_depth(), reference.get_depth());
+            assert!(!imt.has(&"leaf5".to_string()));
+            assert_eq!(imt.value_at(3), Ok(Some(vec![1])));
+            assert!(imt.changes_since(version).is_err());
+            if arity == 2 {
+                assert_eq!(imt.get_side_nodes(), reference.get_side_nodes());
+            }
+
+            // The tree grows again from the recomputed side nodes
+            for leaf in ["new5", "new6"] {
+                imt.insert(leaf.to_string()).unwrap();
+                reference.insert(leaf.to_string()).unwrap();
+            }
+            assert_eq!(imt.root(), reference.root());
+            assert_eq!(imt.changes_since(imt.get_version() - 2).unwrap().count(), 2);
+        }
+
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(leaves.clone()).unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&leaves[1], &proof.siblings).unwrap();
+        assert_eq!(imt.truncate(8).unwrap_err(), "Index out of range");
+        assert_eq!(imt.truncate(2), Ok(Some("leaf1,0".to_string())));
+        assert_eq!(imt.pop(), Ok("0".to_string()));
+        assert_eq!(imt.truncate(0), Ok(None));
+        assert_eq!(imt.pop().unwrap_err(), "Tree is empty");
+        assert!(imt.get_side_nodes().is_empty());
+    }
+
+    #[test]
+    fn test_rebuild_from_leaves() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_max_size(Some(8)).unwrap();
+        for i in 1..=5 {
+            imt.insert_with_value(format!("leaf{}", i), vec![i])
+                .unwrap();
+        }
+        let mut reference = LeanIMT::new(hash);
+        reference
+            .insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = reference.generate_proof(1).unwrap();
+        reference
+            .remove(&"leaf2".to_string(), &proof.siblings)
+            .unwrap();
+
+        // Lost side nodes and a leaf index left by a corrupted store
+        imt.store.delete_side_node(0).unwrap();
+        imt.store.delete_side_node(3).unwrap();
+        imt.store
+            .put_leaf_indices("stale".to_string(), vec![9])
+            .unwrap();
+
+        let leaves: Vec<_> = reference.leaves_iter().cloned().collect();
+        assert_eq!(
+            imt.rebuild_from_leaves(leaves.clone()),
+            Ok(reference.root())
+        );
+        assert_eq!(imt.get_max_size(), Some(8));
+        assert_eq!(imt.get_depth(), 3);
+        assert!(!imt.has(&"stale".to_string()));
+        assert!(!imt.has(&"leaf2".to_string()));
+        assert_eq!(imt.value_at(1), Ok(None));
+        assert_eq!(imt.value_at(2), Ok(Some(vec![3])));
+        assert_eq!(imt.verify_integrity(leaves), Ok(()));
+
+        imt.insert("leaf6".to_string()).unwrap();
+        reference.insert("leaf6".to_string()).unwrap();
+        assert_eq!(imt.root(), reference.root());
+
+        let duplicates = vec!["leaf1".to_string(), "leaf1".to_string()];
+        assert_eq!(
+            imt.rebuild_from_leaves(duplicates).unwrap_err(),
+            "Leaf already exists"
+        );
+        let too_many = (1..=9).map(|i| format!("leaf{}", i)).collect();
+        assert_eq!(
+            imt.rebuild_from_leaves(too_many).unwrap_err(),
+            "Tree is full"
+        );
+        assert_eq!(imt.rebuild_from_leaves(Vec::new()), Ok(None));
+        assert!(imt.get_side_nodes().is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_shared_hash() {
+        use std::sync::RwLock;
+        use std::thread;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<LeanIMT>();
+        assert_send_sync::<LeanIMTBuilder<[u8; 32]>>();
+
+        // The hash function captures its separator
+        let separator = "|".to_string();
+        let hash: SharedHashFunction = Arc::new(move |nodes: Vec<String>| nodes.join(&separator));
+        let imt = LeanIMT::with_shared_hash(hash.clone(), "0".to_string(), MemoryStore::default())
+            .unwrap();
+        let imt = Arc::new(RwLock::new(imt));
+
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let imt = Arc::clone(&imt);
+                thread::spawn(move || imt.write().unwrap().insert(format!("leaf{}", i)))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+
+        let imt = imt.read().unwrap();
+        assert_eq!(imt.root().unwrap().matches('|').count(), 3);
+        let proof = imt.generate_proof(2).unwrap();
+        assert!(imt.verify_proof(&proof));
+        assert!(proof.verify(&*hash));
+
+        let built = LeanIMTBuilder::with_shared_hash(hash, "0".to_string())
+            .build()
+            .unwrap();
+        assert_eq!(built.get_arity(), 2);
+    }
+
+    #[test]
+    fn test_pair_hash() {
+        fn pair_hash(left: &u64, right: &u64) -> u64 {
+            left.wrapping_mul(31).wrapping_add(*right)
+        }
+
+        fn group_hash(nodes: Vec<u64>) -> u64 {
+            nodes
+                .into_iter()
+                .reduce(|left, right| pair_hash(&left, &right))
+                .unwrap()
+        }
+
+        let leaves: Vec<u64> = (1..=7).collect();
+        let mut imt = LeanIMT::with_pair_hash(pair_hash, 0, MemoryStore::default()).unwrap();
+        let mut expected = LeanIMT::with_zero(group_hash, 0);
+        imt.insert_many(leaves[..4].to_vec()).unwrap();
+        expected.insert_many(leaves[..4].to_vec()).unwrap();
+        for &leaf in &leaves[4..] {
+            assert_eq!(imt.insert(leaf), expected.insert(leaf));
+        }
+        let proof = imt.generate_proof(5).unwrap();
+        assert!(imt.verify_proof(&proof));
+        assert!(proof.verify(group_hash));
+
+        // Groups of three are hashed from the left
+        let mut ternary = LeanIMTBuilder::with_pair_hash(pair_hash, 0)
+            .arity(3)
+            .build()
+            .unwrap();
+        ternary.insert_many(leaves[..3].to_vec()).unwrap();
+        assert_eq!(ternary.root(), Some(pair_hash(&pair_hash(&1, &2), &3)));
+    }
 }
@@ -0,0 +1,284 @@
+//! Quinary trees of MACI.
+//!
+//! MACI (Minimal Anti-Collusion Infrastructure) keeps its state, ballot, vote
+//! option and message trees as quinary trees of fixed depth, the
+//! `IncrementalQuinTree` of maci-crypto: every node hashes 5 children with
+//! circomlib's Poseidon of 5 inputs (`hash5`), and empty leaves hold a zero
+//! value that depends on the tree. Messages use [`NOTHING_UP_MY_SLEEVE`],
+//! vote options 0, and the state and ballot trees the hash of a blank state
+//! leaf or ballot. [`MaciTree`] keeps a lean quinary tree but returns the
+//! roots and proofs of the padded tree, so a coordinator can build its trees
+//! here instead of in TypeScript.
+//!
+//! Poseidon is not a dependency: the tree hashes with the function it is
+//! given, which must be `hash5` for the roots to match those of MACI. Proofs
+//! are `NaryMerkleProof`s with the 4 other children of every level, the
+//! `pathElements` of maci-crypto, and the position of the path among them,
+//! its `pathIndices`.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use ark_bn254::Fr;
+use ark_ff::{AdditiveGroup, MontFp};
+
+use crate::{
+    max_size_of, DuplicatePolicy, IMTHashFunction, LeanIMT, LeanIMTStore, MemoryStore,
+    NaryMerkleProof,
+};
+
+/// Number of children hashed by each node.
+pub const ARITY: usize = 5;
+
+/// Zero value of the message tree, `keccak256("Maci")` reduced modulo the
+/// BN254 scalar field order.
+pub const NOTHING_UP_MY_SLEEVE: Fr =
+    MontFp!("8370432830353022751713833565135785980866757267633941821328460903436894336785");
+
+/// Lean quinary tree exposing the roots and proofs of a MACI tree of fixed
+/// depth.
+///
+/// The tree holds at most `5^depth` leaves.
+#[derive(Debug)]
+pub struct MaciTree<N = Fr, S = MemoryStore<N>> {
+    tree: LeanIMT<N, S>,
+    /// Roots of the empty subtrees of each level, up to `depth`.
+    zeros: Vec<N>,
+}
+
+impl MaciTree {
+    /// Creates a message tree of depth `depth`, whose empty leaves are
+    /// `NOTHING_UP_MY_SLEEVE`.
+    pub fn message_tree(hash: IMTHashFunction<Fr>, depth: usize) -> Result<Self, &'static str> {
+        Self::with_zero(hash, NOTHING_UP_MY_SLEEVE, depth)
+    }
+
+    /// Creates a vote option tree of depth `depth`, whose empty leaves are
+    /// zero.
+    pub fn vote_option_tree(hash: IMTHashFunction<Fr>, depth: usize) -> Result<Self, &'static str> {
+        Self::with_zero(hash, Fr::ZERO, depth)
+    }
+}
+
+impl<N: Clone + Eq + Hash> MaciTree<N> {
+    /// Creates a tree of depth `depth` using `zero` as the value of empty
+    /// leaves, e.g. the hash of a blank state leaf for a state tree.
+    ///
+    /// The tree accepts duplicate leaves, as MACI trees hold equal ballots
+    /// and vote counts.
+    pub fn with_zero(
+        hash: IMTHashFunction<N>,
+        zero: N,
+        depth: usize,
+    ) -> Result<Self, &'static str> {
+        let mut tree = LeanIMT::with_zero(hash, zero);
+        tree.set_duplicate_policy(DuplicatePolicy::Allow)?;
+        Self::from_tree(tree, depth)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> MaciTree<N, S> {
+    /// Pads `tree` to `depth`, limiting it to `5^depth` leaves. An empty
+    /// tree is made quinary.
+    ///
+    /// Fails if the tree has another arity or holds more leaves.
+    pub fn from_tree(mut tree: LeanIMT<N, S>, depth: usize) -> Result<Self, &'static str> {
+        if tree.get_arity() != ARITY {
+            tree.set_arity(ARITY)?;
+        }
+        let max_size = max_size_of(depth, ARITY).ok_or("Invalid tree depth")?;
+        tree.set_max_size(Some(max_size))?;
+
+        let mut zeros = Vec::with_capacity(depth + 1);
+        zeros.push(tree.zero.clone());
+        for level in 0..depth {
+            zeros.push(tree.hash.call(vec![zeros[level].clone(); ARITY]));
+        }
+        Ok(MaciTree { tree, zeros })
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.zeros.len() - 1
+    }
+
+    /// Returns the roots of the empty subtrees of each level, from the zero
+    /// value to the root of the empty tree.
+    pub fn zeros(&self) -> &[N] {
+        &self.zeros
+    }
+
+    /// Returns the lean tree, whose root is not padded.
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> LeanIMT<N, S> {
+        self.tree
+    }
+
+    /// Returns the root of the padded tree, that of the empty tree if no leaf
+    /// was inserted.
+    pub fn root(&self) -> Result<N, &'static str> {
+        let size = self.tree.size;
+        if size == 0 {
+            return Ok(self.zeros[self.get_depth()].clone());
+        }
+
+        // Climb from the last leaf: the side nodes on its left are complete
+        // subtrees, and the subtrees on its right are empty
+        let mut node = self.tree.leaf_at(size - 1)?;
+        let mut position = size - 1;
+        for level in 0..self.get_depth() {
+            let slot = position % ARITY;
+            let mut children = Vec::with_capacity(ARITY);
+            for left_slot in 0..slot {
+                children.push(
+                    self.tree
+                        .store
+                        .side_node(self.tree.side_node_key(level, left_slot))?
+                        .ok_or("No side node at this level")?,
+                );
+            }
+            children.push(node);
+            children.resize(ARITY, self.zeros[level].clone());
+            node = self.tree.hash_children(children);
+            position /= ARITY;
+        }
+        Ok(node)
+    }
+
+    /// Inserts a leaf, returning the new padded root.
+    pub fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        self.tree.insert(leaf)?;
+        self.root()
+    }
+
+    /// Inserts multiple leaves, returning the new padded root.
+    pub fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        self.tree.insert_many(leaves)?;
+        self.root()
+    }
+
+    /// Sets the leaf at `index` to `leaf`, as `update` of maci-crypto does,
+    /// and returns the new padded root.
+    ///
+    /// Setting a leaf to the zero value removes it, and removed leaves cannot
+    /// be updated.
+    pub fn update(&mut self, index: usize, leaf: N) -> Result<N, &'static str> {
+        if index >= self.tree.size {
+            return Err("Leaf index out of range");
+        }
+        let proof = self.tree.nary_proof(index)?;
+        self.tree
+            .update_at(index, &proof.leaf, leaf, &proof.siblings.concat())?;
+        self.root()
+    }
+
+    /// Generates the proof of the leaf at `index` in the padded tree, with
+    /// the 4 other children of every level.
+    pub fn generate_proof(&self, index: usize) -> Result<NaryMerkleProof<N>, &'static str> {
+        if index >= self.tree.size {
+            return Err("Leaf index out of range");
+        }
+
+        let mut nodes = self.tree.ordered_leaves()?;
+        let leaf = nodes[index].clone();
+        let mut positions = Vec::with_capacity(self.get_depth());
+        let mut siblings = Vec::with_capacity(self.get_depth());
+        let mut position = index;
+        for level in 0..self.get_depth() {
+            // Padding the nodes to full groups makes every node hashed
+            nodes.resize(
+                nodes.len().div_ceil(ARITY) * ARITY,
+                self.zeros[level].clone(),
+            );
+            let group_start = position / ARITY * ARITY;
+            let mut group = nodes[group_start..group_start + ARITY].to_vec();
+            group.remove(position - group_start);
+            positions.push(position - group_start);
+            siblings.push(group);
+            nodes = nodes
+                .chunks(ARITY)
+                .map(|children| self.tree.hash_children(children.to_vec()))
+                .collect();
+            position /= ARITY;
+        }
+        self.tree.stats.add_proof();
+
+        Ok(NaryMerkleProof {
+            root: nodes.swap_remove(0),
+            leaf,
+            index,
+            positions,
+            siblings,
+        })
+    }
+
+    /// Checks a padded proof against the current padded root.
+    pub fn verify_proof(&self, proof: &NaryMerkleProof<N>) -> bool {
+        proof.siblings.len() == self.get_depth()
+            && proof
+                .siblings
+                .iter()
+                .all(|siblings| siblings.len() == ARITY - 1)
+            && self
+                .root()
+                .is_ok_and(|root| self.tree.nodes_eq(&root, &proof.root))
+            && proof.verify_with(self.tree.hash.as_fn(), |a, b| self.tree.nodes_eq(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_ff::PrimeField;
+
+    fn simple_hash_function(nodes: Vec<Fr>) -> Fr {
+        nodes
+            .into_iter()
+            .fold(Fr::from(7u64), |node, child| node * Fr::from(3u64) + child)
+    }
+
+    #[test]
+    fn test_maci_tree() {
+        let hash: IMTHashFunction<Fr> = simple_hash_function;
+        let keccak: [u8; 32] = [
+            0x73, 0x4a, 0x1c, 0x27, 0x24, 0x56, 0x36, 0x32, 0x43, 0xdf, 0xb2, 0x32, 0x5a, 0x38,
+            0x58, 0x38, 0xd1, 0x4c, 0xc9, 0x28, 0x47, 0xf6, 0xa9, 0xe4, 0xf8, 0x0b, 0x30, 0x3a,
+            0x64, 0x17, 0xf7, 0x13,
+        ];
+        assert_eq!(NOTHING_UP_MY_SLEEVE, Fr::from_be_bytes_mod_order(&keccak));
+
+        let mut tree = MaciTree::message_tree(hash, 2).unwrap();
+        assert_eq!(tree.root(), Ok(tree.zeros()[2]));
+        let leaves: Vec<Fr> = (1..=7).map(Fr::from).collect();
+        let root = tree.insert_many(leaves.clone()).unwrap();
+
+        // The padded tree hashes every group of 5, empty leaves included
+        let mut padded = leaves;
+        padded.resize(25, NOTHING_UP_MY_SLEEVE);
+        let level: Vec<Fr> = padded
+            .chunks(ARITY)
+            .map(|children| simple_hash_function(children.to_vec()))
+            .collect();
+        assert_eq!(root, simple_hash_function(level));
+
+        let proof = tree.generate_proof(6).unwrap();
+        assert_eq!(proof.positions, [1, 1]);
+        assert_eq!(proof.siblings[0][1], NOTHING_UP_MY_SLEEVE);
+        assert!(tree.verify_proof(&proof));
+
+        // Duplicate leaves are accepted, and updates recompute the root
+        tree.insert(Fr::from(1u64)).unwrap();
+        let root = tree.update(6, Fr::from(9u64)).unwrap();
+        let proof = tree.generate_proof(6).unwrap();
+        assert_eq!((proof.leaf, proof.root), (Fr::from(9u64), root));
+        assert!(tree.verify_proof(&proof));
+        assert!(!tree.verify_proof(&tree.tree().generate_nary_proof(6).unwrap()));
+
+        let mut votes = MaciTree::vote_option_tree(hash, 1).unwrap();
+        votes.insert_many((1..=5).map(Fr::from).collect()).unwrap();
+        assert_eq!(votes.insert(Fr::from(6u64)), Err("Tree is full"));
+    }
+}
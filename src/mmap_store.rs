@@ -0,0 +1,723 @@
+//! Store keeping the nodes of a tree in memory-mapped files.
+//!
+//! Trees larger than the available memory can live in an [`MmapStore`]
+//! without a database: its nodes are fixed-width records, read and written
+//! by position in files mapped into memory, and paged in and out by the OS
+//! as they are touched. A directory holds four files:
+//!
+//! - `side_nodes`: the side node of each key;
+//! - `leaves`: the leaf at each index, followed by the next index holding the
+//!   same leaf, which chains the indices of duplicated leaves;
+//! - `index`: an open-addressing hash table from each leaf to its first index;
+//! - `metadata`: the format version, the node width and the tree metadata.
+//!
+//! Records start with the length of their node plus one as a little-endian
+//! `u16`, zero marking an empty record, followed by the node encoded by the
+//! codec and padded to the node width. Indices are little-endian `u64`s.
+//!
+//! The writes of a failed operation are undone, but the files only reach the
+//! disk when the OS writes the mapped pages back, and the metadata when
+//! [`MmapStore::flush`] is called or the store is dropped: a crash can lose
+//! the operations since the last flush.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use core::hash::Hash;
+use memmap2::MmapMut;
+
+use crate::codec::{write_varint, ByteReader};
+use crate::store::{StoreIter, StoreMetadata};
+use crate::{prove_from_leaves, LeanIMT, LeanIMTStore, MerkleProof, NodeCodec};
+
+/// Version of the file format, stored in the first byte of the metadata.
+const FORMAT_VERSION: u8 = 1;
+
+/// Number of records of new files.
+const INITIAL_RECORDS: usize = 1024;
+
+/// Header of an empty record.
+const EMPTY: u16 = 0;
+/// Header of an index slot whose leaf was deleted.
+const TOMBSTONE: u16 = u16::MAX;
+
+const STORAGE_ERROR: &str = "Storage error";
+
+/// File of fixed-size records mapped into memory.
+#[derive(Debug)]
+struct RecordFile {
+    file: File,
+    map: MmapMut,
+    record_size: usize,
+}
+
+impl RecordFile {
+    /// Opens the file at `path`, creating it with `INITIAL_RECORDS` empty
+    /// records if it does not exist, or replacing it if `truncate` is set.
+    fn open(path: &Path, record_size: usize, truncate: bool) -> Result<Self, &'static str> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .open(path)
+            .map_err(|_| STORAGE_ERROR)?;
+        let length = file.metadata().map_err(|_| STORAGE_ERROR)?.len();
+        if length == 0 {
+            file.set_len((INITIAL_RECORDS * record_size) as u64)
+                .map_err(|_| STORAGE_ERROR)?;
+        } else if length % record_size as u64 != 0 {
+            return Err("Invalid record file");
+        }
+
+        Ok(RecordFile {
+            map: Self::map(&file)?,
+            file,
+            record_size,
+        })
+    }
+
+    fn map(file: &File) -> Result<MmapMut, &'static str> {
+        // SAFETY: the files belong to the store, which is their only user
+        // while it is open
+        unsafe { MmapMut::map_mut(file) }.map_err(|_| STORAGE_ERROR)
+    }
+
+    /// Returns the number of records of the file.
+    fn len(&self) -> usize {
+        self.map.len() / self.record_size
+    }
+
+    fn record(&self, position: usize) -> Option<&[u8]> {
+        let start = position.checked_mul(self.record_size)?;
+        self.map.get(start..start.checked_add(self.record_size)?)
+    }
+
+    /// Returns the record at `position`, doubling the file until it holds it.
+    fn record_mut(&mut self, position: usize) -> Result<&mut [u8], &'static str> {
+        if position >= self.len() {
+            let records = position
+                .checked_add(1)
+                .and_then(usize::checked_next_power_of_two)
+                .ok_or("Tree is too large")?;
+            self.resize(records)?;
+        }
+        let start = position * self.record_size;
+        Ok(&mut self.map[start..start + self.record_size])
+    }
+
+    fn resize(&mut self, records: usize) -> Result<(), &'static str> {
+        let length = records
+            .checked_mul(self.record_size)
+            .ok_or("Tree is too large")?;
+        self.file
+            .set_len(length as u64)
+            .map_err(|_| STORAGE_ERROR)?;
+        self.map = Self::map(&self.file)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), &'static str> {
+        self.map.flush().map_err(|_| STORAGE_ERROR)
+    }
+}
+
+/// Write made by the current operation, undone if it fails.
+#[derive(Debug)]
+enum Undo {
+    /// Previous contents of a side node record.
+    SideNode(usize, Vec<u8>),
+    /// Previous contents of a leaf record.
+    Leaf(usize, Vec<u8>),
+    /// Previous first index of an encoded leaf in the hash table.
+    Index(Vec<u8>, Option<usize>),
+}
+
+/// Store keeping the nodes of a tree in memory-mapped files, encoded with
+/// the codec `C` into records of a fixed width.
+#[derive(Debug)]
+pub struct MmapStore<N, C> {
+    directory: PathBuf,
+    node_width: usize,
+    side_nodes: RecordFile,
+    leaves: RecordFile,
+    index: RecordFile,
+    /// Number of occupied or deleted slots of the hash table.
+    index_used: usize,
+    /// Encoded metadata, as written by the current operation.
+    metadata: Option<Vec<u8>>,
+    /// Encoded metadata, as of the last commit.
+    committed_metadata: Option<Vec<u8>>,
+    undo: Vec<Undo>,
+    codec: PhantomData<fn() -> (N, C)>,
+}
+
+impl<N, C: NodeCodec<N>> MmapStore<N, C> {
+    /// Opens (or creates) the store in `directory`, with records holding
+    /// encoded nodes of up to `node_width` bytes.
+    ///
+    /// Fails if the store was created with another node width.
+    pub fn open(directory: impl AsRef<Path>, node_width: usize) -> Result<Self, &'static str> {
+        if node_width >= usize::from(TOMBSTONE) - 1 {
+            return Err("Invalid node width");
+        }
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory).map_err(|_| STORAGE_ERROR)?;
+
+        let metadata = match fs::read(directory.join("metadata")) {
+            Ok(bytes) => {
+                let mut reader = ByteReader::new(&bytes);
+                if reader.read_byte()? != FORMAT_VERSION {
+                    return Err("Unsupported store version");
+                }
+                if reader.read_usize()? != node_width {
+                    return Err("Node width mismatch");
+                }
+                let metadata = reader.read_bytes()?;
+                (!metadata.is_empty()).then(|| metadata.to_vec())
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(_) => return Err(STORAGE_ERROR),
+        };
+
+        let record_size = node_width + 2;
+        let side_nodes = RecordFile::open(&directory.join("side_nodes"), record_size, false)?;
+        let leaves = RecordFile::open(&directory.join("leaves"), record_size + 8, false)?;
+        let index = RecordFile::open(&directory.join("index"), record_size + 8, false)?;
+        if !index.len().is_power_of_two() {
+            return Err("Invalid record file");
+        }
+        let index_used = (0..index.len())
+            .filter(|&slot| {
+                index
+                    .record(slot)
+                    .is_some_and(|record| header(record) != EMPTY)
+            })
+            .count();
+
+        let store = MmapStore {
+            directory,
+            node_width,
+            side_nodes,
+            leaves,
+            index,
+            index_used,
+            metadata: metadata.clone(),
+            committed_metadata: metadata,
+            undo: Vec::new(),
+            codec: PhantomData,
+        };
+        store.save_metadata()?;
+        Ok(store)
+    }
+
+    fn encode(&self, node: &N) -> Result<Vec<u8>, &'static str> {
+        let bytes = C::encode(node);
+        if bytes.len() > self.node_width {
+            return Err("Node is wider than the records");
+        }
+        Ok(bytes)
+    }
+
+    fn write_side_node(&mut self, key: usize, node: Option<&[u8]>) -> Result<(), &'static str> {
+        let record = self.side_nodes.record_mut(key)?;
+        self.undo.push(Undo::SideNode(key, record.to_vec()));
+        write_node(record, node);
+        Ok(())
+    }
+
+    fn write_leaf(&mut self, index: usize, leaf: Option<&[u8]>) -> Result<(), &'static str> {
+        let node_size = self.node_width + 2;
+        let record = self.leaves.record_mut(index)?;
+        self.undo.push(Undo::Leaf(index, record.to_vec()));
+        write_node(&mut record[..node_size], leaf);
+        Ok(())
+    }
+
+    /// Chains the leaf at `index` to the leaf at `next`, or ends the chain.
+    fn write_next(&mut self, index: usize, next: Option<usize>) -> Result<(), &'static str> {
+        let node_size = self.node_width + 2;
+        let record = self.leaves.record_mut(index)?;
+        self.undo.push(Undo::Leaf(index, record.to_vec()));
+        let next = next.map_or(0, |next| next as u64 + 1);
+        record[node_size..].copy_from_slice(&next.to_le_bytes());
+        Ok(())
+    }
+
+    /// Returns the indices chained from `first`, in ascending order.
+    fn chain(&self, first: usize) -> Result<Vec<usize>, &'static str> {
+        let node_size = self.node_width + 2;
+        let mut indices = vec![first];
+        let mut index = first;
+        loop {
+            let record = self.leaves.record(index).ok_or("Invalid leaf index")?;
+            match read_index(&record[node_size..])? {
+                0 => return Ok(indices),
+                next if next - 1 > index => index = next - 1,
+                _ => return Err("Invalid leaf index"),
+            }
+            indices.push(index);
+        }
+    }
+
+    /// Finds `key` in the hash table, returning its slot if it is there, or
+    /// else the slot where to insert it.
+    fn find_slot(&self, key: &[u8]) -> Result<Result<usize, usize>, &'static str> {
+        let mask = self.index.len() - 1;
+        let mut slot = fnv1a(key) as usize & mask;
+        let mut free = None;
+        for _ in 0..self.index.len() {
+            let record = self.index.record(slot).ok_or(STORAGE_ERROR)?;
+            match header(record) {
+                EMPTY => return Ok(Err(free.unwrap_or(slot))),
+                TOMBSTONE => {
+                    free.get_or_insert(slot);
+                }
+                _ => {
+                    if read_node(record)? == Some(key) {
+                        return Ok(Ok(slot));
+                    }
+                }
+            }
+            slot = (slot + 1) & mask;
+        }
+        free.map(Err).ok_or("Invalid record file")
+    }
+
+    fn first_index(&self, key: &[u8]) -> Result<Option<usize>, &'static str> {
+        let node_size = self.node_width + 2;
+        match self.find_slot(key)? {
+            Ok(slot) => {
+                let record = self.index.record(slot).ok_or(STORAGE_ERROR)?;
+                Ok(Some(read_index(&record[node_size..])?))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Sets the first index of `key` in the hash table, `None` deleting it.
+    fn set_first_index(&mut self, key: &[u8], first: Option<usize>) -> Result<(), &'static str> {
+        let node_size = self.node_width + 2;
+        let slot = match (self.find_slot(key)?, first) {
+            (Ok(slot), _) => slot,
+            (Err(_), None) => return Ok(()),
+            (Err(slot), Some(_)) => {
+                let reused = self
+                    .index
+                    .record(slot)
+                    .is_some_and(|r| header(r) == TOMBSTONE);
+                if reused {
+                    slot
+                } else if (self.index_used + 1) * 2 > self.index.len() {
+                    self.rehash()?;
+                    return self.set_first_index(key, first);
+                } else {
+                    self.index_used += 1;
+                    slot
+                }
+            }
+        };
+
+        let record = self.index.record_mut(slot)?;
+        match first {
+            Some(first) => {
+                write_node(&mut record[..node_size], Some(key));
+                record[node_size..].copy_from_slice(&(first as u64).to_le_bytes());
+            }
+            None => record[..2].copy_from_slice(&TOMBSTONE.to_le_bytes()),
+        }
+        Ok(())
+    }
+
+    /// Moves the leaves of the hash table to a new one, sized for twice as
+    /// many leaves and without deleted slots.
+    fn rehash(&mut self) -> Result<(), &'static str> {
+        let node_size = self.node_width + 2;
+        let live = (0..self.index.len())
+            .filter(|&slot| self.index.record(slot).is_some_and(|r| !is_free(r)))
+            .count();
+        let capacity = (live + 1)
+            .checked_mul(4)
+            .and_then(usize::checked_next_power_of_two)
+            .ok_or("Tree is too large")?
+            .max(INITIAL_RECORDS);
+
+        let path = self.directory.join("index.new");
+        let mut index = RecordFile::open(&path, node_size + 8, true)?;
+        index.resize(capacity)?;
+        for slot in 0..self.index.len() {
+            let record = self.index.record(slot).ok_or(STORAGE_ERROR)?;
+            if is_free(record) {
+                continue;
+            }
+            let key = read_node(record)?.ok_or(STORAGE_ERROR)?;
+            let mut target = fnv1a(key) as usize & (capacity - 1);
+            while index.record(target).is_some_and(|r| header(r) != EMPTY) {
+                target = (target + 1) & (capacity - 1);
+            }
+            index.record_mut(target)?.copy_from_slice(record);
+        }
+
+        fs::rename(path, self.directory.join("index")).map_err(|_| STORAGE_ERROR)?;
+        self.index = index;
+        self.index_used = live;
+        Ok(())
+    }
+}
+
+impl<N, C> MmapStore<N, C> {
+    pub fn node_width(&self) -> usize {
+        self.node_width
+    }
+
+    /// Writes the mapped files and the metadata of the last committed
+    /// operation to disk.
+    pub fn flush(&self) -> Result<(), &'static str> {
+        self.side_nodes.flush()?;
+        self.leaves.flush()?;
+        self.index.flush()?;
+        self.save_metadata()
+    }
+
+    /// Replaces the metadata file, writing it next to it first so that it is
+    /// never left partial.
+    fn save_metadata(&self) -> Result<(), &'static str> {
+        let mut bytes = vec![FORMAT_VERSION];
+        write_varint(&mut bytes, self.node_width as u64);
+        let metadata = self.committed_metadata.as_deref().unwrap_or_default();
+        write_varint(&mut bytes, metadata.len() as u64);
+        bytes.extend_from_slice(metadata);
+
+        let path = self.directory.join("metadata");
+        let temporary = self.directory.join("metadata.new");
+        fs::write(&temporary, bytes).map_err(|_| STORAGE_ERROR)?;
+        fs::rename(temporary, path).map_err(|_| STORAGE_ERROR)
+    }
+}
+
+impl<N, C: NodeCodec<N>> LeanIMTStore<N> for MmapStore<N, C> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        self.side_nodes
+            .record(level)
+            .map(read_node)
+            .transpose()?
+            .flatten()
+            .map(C::decode)
+            .transpose()
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        let bytes = self.encode(&node)?;
+        self.write_side_node(level, Some(&bytes))
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        if level < self.side_nodes.len() {
+            self.write_side_node(level, None)?;
+        }
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        Box::new((0..self.side_nodes.len()).filter_map(move |level| {
+            let record = self.side_nodes.record(level)?;
+            match read_node(record) {
+                Ok(Some(bytes)) => Some(C::decode(bytes).map(|node| (level, node))),
+                Ok(None) => None,
+                Err(error) => Some(Err(error)),
+            }
+        }))
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        self.first_index(&C::encode(leaf))?
+            .map(|first| self.chain(first))
+            .transpose()
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        let key = self.encode(&leaf)?;
+        for (position, &index) in indices.iter().enumerate() {
+            self.write_next(index, indices.get(position + 1).copied())?;
+        }
+        let old = self.first_index(&key)?;
+        self.undo.push(Undo::Index(key.clone(), old));
+        self.set_first_index(&key, indices.first().copied())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        let key = C::encode(leaf);
+        let old = self.first_index(&key)?;
+        self.undo.push(Undo::Index(key.clone(), old));
+        self.set_first_index(&key, None)
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        let node_size = self.node_width + 2;
+        Box::new((0..self.index.len()).filter_map(move |slot| {
+            let record = self.index.record(slot)?;
+            if is_free(record) {
+                return None;
+            }
+            let leaf = read_node(record).and_then(|leaf| C::decode(leaf.ok_or(STORAGE_ERROR)?));
+            let indices = read_index(&record[node_size..]).and_then(|first| self.chain(first));
+            Some(leaf.and_then(|leaf| Ok((leaf, indices?))))
+        }))
+    }
+
+    fn has_leaf(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self.first_index(&C::encode(leaf))?.is_some())
+    }
+
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        let node_size = self.node_width + 2;
+        match self.leaves.record(index) {
+            Some(record) => read_node(&record[..node_size])?.map(C::decode).transpose(),
+            None => Ok(None),
+        }
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        match leaf {
+            Some(leaf) => {
+                let bytes = self.encode(&leaf)?;
+                self.write_leaf(index, Some(&bytes))
+            }
+            None if index < self.leaves.len() => self.write_leaf(index, None),
+            None => Ok(()),
+        }
+    }
+
+    fn leaves_by_index(&self) -> Result<Vec<Option<N>>, &'static str> {
+        let mut leaves = (0..self.leaves.len())
+            .map(|index| self.leaf_at(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        while leaves.last().is_some_and(Option::is_none) {
+            leaves.pop();
+        }
+        Ok(leaves)
+    }
+
+    fn metadata(&self) -> Result<Option<StoreMetadata<N>>, &'static str> {
+        self.metadata
+            .as_deref()
+            .map(StoreMetadata::from_bytes::<C>)
+            .transpose()
+    }
+
+    fn put_metadata(&mut self, metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        self.metadata = Some(metadata.to_bytes::<C>());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), &'static str> {
+        self.undo.clear();
+        self.committed_metadata.clone_from(&self.metadata);
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        while let Some(undo) = self.undo.pop() {
+            let _ = match undo {
+                Undo::SideNode(key, bytes) => self
+                    .side_nodes
+                    .record_mut(key)
+                    .map(|record| record.copy_from_slice(&bytes)),
+                Undo::Leaf(index, bytes) => self
+                    .leaves
+                    .record_mut(index)
+                    .map(|record| record.copy_from_slice(&bytes)),
+                Undo::Index(key, first) => self.set_first_index(&key, first),
+            };
+        }
+        self.metadata.clone_from(&self.committed_metadata);
+    }
+}
+
+impl<N, C> Drop for MmapStore<N, C> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl<N: Clone + Eq + Hash, C: NodeCodec<N>> LeanIMT<N, MmapStore<N, C>> {
+    /// Generates the proof of the leaf at `index` by streaming the leaves of
+    /// the mapped file through `prove_from_leaves`.
+    ///
+    /// `generate_proof` loads every leaf in memory, while this keeps a few
+    /// nodes per level, so the leaves are paged in as they are hashed and
+    /// trees larger than memory can be proven.
+    pub fn generate_paged_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.ensure_binary()?;
+        if index >= self.size {
+            return Err("Leaf index out of range");
+        }
+
+        let mut error = None;
+        let leaves = (0..self.size).map_while(|index| match self.store.leaf_at(index) {
+            Ok(leaf) => Some(leaf.unwrap_or_else(|| self.zero.clone())),
+            Err(failure) => {
+                error = Some(failure);
+                None
+            }
+        });
+        let proof = prove_from_leaves(leaves, index, self.hash.as_fn());
+        if let Some(error) = error {
+            return Err(error);
+        }
+        self.stats.add_proof();
+        proof
+    }
+}
+
+fn header(record: &[u8]) -> u16 {
+    u16::from_le_bytes([record[0], record[1]])
+}
+
+/// Tells whether an index slot is empty or deleted.
+fn is_free(record: &[u8]) -> bool {
+    matches!(header(record), EMPTY | TOMBSTONE)
+}
+
+fn read_node(record: &[u8]) -> Result<Option<&[u8]>, &'static str> {
+    match header(record) {
+        EMPTY | TOMBSTONE => Ok(None),
+        length => record
+            .get(2..usize::from(length) + 1)
+            .map(Some)
+            .ok_or("Invalid record"),
+    }
+}
+
+/// Writes `node`, which fits the record, or empties the record.
+fn write_node(record: &mut [u8], node: Option<&[u8]>) {
+    let (header, body) = record.split_at_mut(2);
+    let length = node.map_or(EMPTY, |node| node.len() as u16 + 1);
+    header.copy_from_slice(&length.to_le_bytes());
+    body.fill(0);
+    if let Some(node) = node {
+        body[..node.len()].copy_from_slice(node);
+    }
+}
+
+fn read_index(bytes: &[u8]) -> Result<usize, &'static str> {
+    let bytes = bytes.try_into().map_err(|_| "Invalid record")?;
+    usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| "Value out of range")
+}
+
+/// 64-bit FNV-1a, which keeps the hash table valid across platforms and
+/// versions of Rust.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::{DuplicatePolicy, IMTHashFunction};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    /// Joins the children like `simple_hash_function`, but into nodes of a
+    /// fixed width.
+    fn short_hash_function(nodes: Vec<String>) -> String {
+        format!("{:016x}", fnv1a(nodes.join(",").as_bytes()))
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("lean-imt-mmap-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_reopen_mmap_tree() {
+        let hash: IMTHashFunction = short_hash_function;
+        let directory = temporary_directory("reopen");
+        let mut reference = LeanIMT::new(hash);
+
+        {
+            let store = MmapStore::<String, StringCodec>::open(&directory, 16).unwrap();
+            let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+            imt.set_duplicate_policy(DuplicatePolicy::Allow).unwrap();
+            reference
+                .set_duplicate_policy(DuplicatePolicy::Allow)
+                .unwrap();
+
+            // Enough leaves to grow the files and the hash table
+            let leaves: Vec<_> = (0..3000).map(|i| format!("leaf{}", i)).collect();
+            imt.insert_many(leaves.clone()).unwrap();
+            reference.insert_many(leaves).unwrap();
+            imt.insert("leaf1".to_string()).unwrap();
+            reference.insert("leaf1".to_string()).unwrap();
+
+            let proof = imt.generate_proof(2).unwrap();
+            imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+            reference
+                .remove(&"leaf2".to_string(), &proof.siblings)
+                .unwrap();
+
+            // The side nodes written before the siblings are found wrong are discarded
+            let root = imt.root();
+            let result = imt.update(
+                &"leaf4".to_string(),
+                "new".to_string(),
+                &["wrong".to_string()],
+            );
+            assert!(result.is_err());
+            assert_eq!(imt.root(), root);
+            assert!(!imt.has(&"new".to_string()));
+        }
+
+        let store = MmapStore::<String, StringCodec>::open(&directory, 16).unwrap();
+        let imt = LeanIMT::open(hash, store).unwrap();
+        assert_eq!(imt.root(), reference.root());
+        assert_eq!(imt.get_version(), reference.get_version());
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![1, 3000]);
+        assert!(!imt.has(&"leaf2".to_string()));
+        assert_eq!(imt.index_of(&"leaf2999".to_string()), Ok(2999));
+        for index in [0, 2, 1500, 3000] {
+            assert_eq!(
+                imt.generate_paged_proof(index),
+                reference.generate_proof(index)
+            );
+        }
+        assert_eq!(
+            imt.generate_paged_proof(3001).unwrap_err(),
+            "Leaf index out of range"
+        );
+        drop(imt);
+
+        assert_eq!(
+            MmapStore::<String, StringCodec>::open(&directory, 32).unwrap_err(),
+            "Node width mismatch"
+        );
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_nodes_wider_than_records() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let directory = temporary_directory("width");
+        let store = MmapStore::<String, StringCodec>::open(&directory, 8).unwrap();
+        let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+
+        imt.insert("leaf1".to_string()).unwrap();
+        assert_eq!(
+            imt.insert("a leaf too wide".to_string()).unwrap_err(),
+            "Node is wider than the records"
+        );
+        assert_eq!(imt.get_size(), 1);
+        assert!(!imt.has(&"a leaf too wide".to_string()));
+        drop(imt);
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,243 @@
+//! UniFFI bindings for Swift and Kotlin.
+//!
+//! Mobile clients build the tree from the leaves published by a backend and
+//! generate proofs locally, with the same tree logic. Nodes are strings, as
+//! for [`IMTNode`] trees, and the hash function is implemented on the foreign
+//! side through the [`NodeHasher`] interface. Trees store plain function
+//! pointers, so the hasher of the tree being used is installed in a thread
+//! local for the duration of each call, as in the wasm bindings.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{IMTNode, LeafIndex, LeanIMT, MerkleProof};
+
+/// Hash function implemented by the foreign code.
+#[uniffi::export(with_foreign)]
+pub trait NodeHasher: Send + Sync {
+    fn hash(&self, nodes: Vec<String>) -> String;
+}
+
+thread_local! {
+    static HASHER: RefCell<Option<Arc<dyn NodeHasher>>> = const { RefCell::new(None) };
+}
+
+/// Hash function handed to the wrapped tree, forwarding to the current hasher.
+fn foreign_hash(nodes: Vec<IMTNode>) -> IMTNode {
+    HASHER.with(|hasher| {
+        hasher
+            .borrow()
+            .as_ref()
+            .expect("No hasher installed")
+            .hash(nodes)
+    })
+}
+
+/// Runs `f` with `hasher` installed as the current hash function.
+fn with_hasher<T>(hasher: &Arc<dyn NodeHasher>, f: impl FnOnce() -> T) -> T {
+    let previous = HASHER.with(|current| current.replace(Some(hasher.clone())));
+    let result = f();
+    HASHER.with(|current| *current.borrow_mut() = previous);
+    result
+}
+
+/// Error raised by the tree.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Error)]
+pub enum LeanImtError {
+    Tree {
+        message: String,
+    },
+    /// The insert would exceed the maximum size of the tree.
+    TreeFull,
+}
+
+impl fmt::Display for LeanImtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeanImtError::Tree { message } => f.write_str(message),
+            LeanImtError::TreeFull => f.write_str("Tree is full"),
+        }
+    }
+}
+
+impl std::error::Error for LeanImtError {}
+
+impl From<&'static str> for LeanImtError {
+    fn from(message: &'static str) -> Self {
+        match message {
+            "Tree is full" => LeanImtError::TreeFull,
+            _ => LeanImtError::Tree {
+                message: message.to_string(),
+            },
+        }
+    }
+}
+
+/// Inclusion proof, in the shape of `MerkleProof`.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct Proof {
+    pub root: String,
+    pub leaf: String,
+    pub index: u64,
+    pub siblings: Vec<String>,
+}
+
+impl From<MerkleProof> for Proof {
+    fn from(proof: MerkleProof) -> Self {
+        Proof {
+            root: proof.root,
+            leaf: proof.leaf,
+            index: LeafIndex::from(proof.index).get(),
+            siblings: proof.siblings,
+        }
+    }
+}
+
+impl TryFrom<Proof> for MerkleProof {
+    type Error = LeanImtError;
+
+    fn try_from(proof: Proof) -> Result<Self, Self::Error> {
+        Ok(MerkleProof {
+            root: proof.root,
+            leaf: proof.leaf,
+            index: LeafIndex(proof.index).to_usize()?,
+            siblings: proof.siblings,
+        })
+    }
+}
+
+/// Tree exposed to Swift and Kotlin, safe to share between threads.
+#[derive(uniffi::Object)]
+pub struct MobileLeanIMT {
+    tree: Mutex<LeanIMT>,
+    hasher: Arc<dyn NodeHasher>,
+}
+
+impl MobileLeanIMT {
+    fn tree(&self) -> MutexGuard<'_, LeanIMT> {
+        self.tree
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs `f` on the tree with the hasher installed.
+    fn with_tree<T>(&self, f: impl FnOnce(&mut LeanIMT) -> T) -> T {
+        with_hasher(&self.hasher, || f(&mut self.tree()))
+    }
+}
+
+#[uniffi::export]
+impl MobileLeanIMT {
+    #[uniffi::constructor]
+    pub fn new(hasher: Arc<dyn NodeHasher>) -> Self {
+        MobileLeanIMT {
+            tree: Mutex::new(LeanIMT::new(foreign_hash)),
+            hasher,
+        }
+    }
+
+    pub fn insert(&self, leaf: String) -> Result<String, LeanImtError> {
+        Ok(self.with_tree(|tree| tree.insert(leaf))?)
+    }
+
+    pub fn insert_many(&self, leaves: Vec<String>) -> Result<String, LeanImtError> {
+        Ok(self.with_tree(|tree| tree.insert_many(leaves))?)
+    }
+
+    pub fn update(
+        &self,
+        old_leaf: String,
+        new_leaf: String,
+        siblings: Vec<String>,
+    ) -> Result<String, LeanImtError> {
+        Ok(self.with_tree(|tree| tree.update(&old_leaf, new_leaf, &siblings))?)
+    }
+
+    pub fn remove(&self, leaf: String, siblings: Vec<String>) -> Result<String, LeanImtError> {
+        Ok(self.with_tree(|tree| tree.remove(&leaf, &siblings))?)
+    }
+
+    /// Limits the tree to the `2^max_depth` leaves of a tree of depth
+    /// `max_depth`; further inserts fail with `TreeFull`.
+    pub fn set_max_depth(&self, max_depth: u32) -> Result<(), LeanImtError> {
+        Ok(self.tree().set_max_depth(max_depth as usize)?)
+    }
+
+    pub fn has(&self, leaf: String) -> bool {
+        self.tree().has(&leaf)
+    }
+
+    pub fn index_of(&self, leaf: String) -> Result<u64, LeanImtError> {
+        Ok(self.tree().leaf_index_of(&leaf)?.get())
+    }
+
+    pub fn root(&self) -> Option<String> {
+        self.tree().root()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.tree().get_size() as u64
+    }
+
+    pub fn generate_proof(&self, index: u64) -> Result<Proof, LeanImtError> {
+        let index = LeafIndex(index).to_usize()?;
+        Ok(self.with_tree(|tree| tree.generate_proof(index))?.into())
+    }
+
+    /// Checks that a proof is valid against the current root of the tree.
+    pub fn verify_proof(&self, proof: Proof) -> bool {
+        MerkleProof::try_from(proof)
+            .is_ok_and(|proof| self.with_tree(|tree| tree.verify_proof(&proof)))
+    }
+}
+
+/// Checks that a proof hashes up to its root with `hasher`.
+#[uniffi::export]
+pub fn verify_proof(proof: Proof, hasher: Arc<dyn NodeHasher>) -> bool {
+    MerkleProof::try_from(proof)
+        .is_ok_and(|proof| with_hasher(&hasher, || proof.verify(foreign_hash)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct JoinHasher;
+
+    impl NodeHasher for JoinHasher {
+        fn hash(&self, nodes: Vec<String>) -> String {
+            nodes.join(",")
+        }
+    }
+
+    #[test]
+    fn test_mobile_tree() {
+        let hasher: Arc<dyn NodeHasher> = Arc::new(JoinHasher);
+        let tree = MobileLeanIMT::new(hasher.clone());
+        tree.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        assert_eq!(
+            tree.insert("leaf3".to_string()).unwrap(),
+            "leaf1,leaf2,leaf3"
+        );
+
+        let proof = tree
+            .generate_proof(tree.index_of("leaf2".to_string()).unwrap())
+            .unwrap();
+        assert_eq!(proof.siblings, vec!["leaf1", "leaf3"]);
+        assert!(tree.verify_proof(proof.clone()));
+        assert!(verify_proof(proof.clone(), hasher));
+
+        tree.update("leaf2".to_string(), "new2".to_string(), proof.siblings)
+            .unwrap();
+        assert!(!tree.has("leaf2".to_string()));
+        assert_eq!(tree.size(), 3);
+        assert_eq!(
+            tree.insert("leaf1".to_string()).unwrap_err(),
+            LeanImtError::Tree {
+                message: "Leaf already exists".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{IMTNode, LeanIMT, LeanIMTStore};
+
+/// Inclusion proof of several leaves at once.
+///
+/// `leaves` holds the `(index, leaf)` pairs sorted by index, and `nodes` the
+/// nodes needed to hash them up to the root that cannot be computed from the
+/// leaves themselves, in the order the verifier consumes them: level by level
+/// from the leaves, and by position within a level. Siblings shared by
+/// several leaves only appear once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MultiProof<N = IMTNode> {
+    pub root: N,
+    pub size: usize,
+    pub leaves: Vec<(usize, N)>,
+    pub nodes: Vec<N>,
+}
+
+impl<N: Clone + PartialEq> MultiProof<N> {
+    /// Checks that the leaves and nodes hash up to the root of the proof.
+    pub fn verify(&self, hash: impl Fn(Vec<N>) -> N) -> bool {
+        self.verify_with(hash, N::eq)
+    }
+
+    /// Checks the proof like `verify`, comparing the computed root with
+    /// `eq`.
+    pub fn verify_with(&self, hash: impl Fn(Vec<N>) -> N, eq: impl Fn(&N, &N) -> bool) -> bool {
+        let sorted = self.leaves.windows(2).all(|pair| pair[0].0 < pair[1].0);
+        match self.leaves.last() {
+            Some((index, _)) if sorted && *index < self.size => {}
+            _ => return false,
+        }
+
+        let mut level = self.leaves.clone();
+        let mut nodes = self.nodes.iter();
+        let mut level_size = self.size;
+
+        while level_size > 1 {
+            let mut next_level = Vec::with_capacity(level.len());
+            let mut i = 0;
+
+            while i < level.len() {
+                let (position, node) = &level[i];
+                let parent = if position & 1 == 1 {
+                    // The left sibling would have been paired with this node if known
+                    let Some(left) = nodes.next() else {
+                        return false;
+                    };
+                    hash(vec![left.clone(), node.clone()])
+                } else if position + 1 < level_size {
+                    let right = match level.get(i + 1) {
+                        Some((next, right)) if *next == position + 1 => {
+                            i += 1;
+                            right
+                        }
+                        _ => match nodes.next() {
+                            Some(right) => right,
+                            None => return false,
+                        },
+                    };
+                    hash(vec![node.clone(), right.clone()])
+                } else {
+                    node.clone()
+                };
+
+                next_level.push((position >> 1, parent));
+                i += 1;
+            }
+
+            level = next_level;
+            level_size = level_size.div_ceil(2);
+        }
+
+        nodes.next().is_none() && eq(&level[0].1, &self.root)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Generates a single proof for the leaves at `indices`.
+    ///
+    /// Duplicate indices are ignored.
+    pub fn generate_multiproof(&self, indices: &[usize]) -> Result<MultiProof<N>, &'static str> {
+        self.ensure_binary()?;
+        if indices.is_empty() {
+            return Err("No leaf indices");
+        }
+        if indices.iter().any(|&index| index >= self.size) {
+            return Err("Leaf index out of range");
+        }
+
+        let mut indices = indices.to_vec();
+        indices.sort_unstable();
+        indices.dedup();
+
+        self.stats.add_proof();
+        Ok(build_multiproof(
+            self.ordered_leaves()?,
+            indices,
+            self.stats.counted(&self.hash),
+        ))
+    }
+
+    /// Checks a multiproof against the current root of the tree.
+    pub fn verify_multiproof(&self, proof: &MultiProof<N>) -> bool {
+        self.arity == 2
+            && proof.size == self.size
+            && self
+                .root()
+                .is_some_and(|root| self.nodes_eq(&root, &proof.root))
+            && proof.verify_with(self.hash.as_fn(), |a, b| self.nodes_eq(a, b))
+    }
+}
+
+/// Builds the multiproof of the leaves at `indices` from all the leaves of a
+/// tree.
+///
+/// `indices` must be sorted, deduplicated and lower than the number of leaves.
+fn build_multiproof<N: Clone>(
+    mut level: Vec<N>,
+    mut known: Vec<usize>,
+    hash: impl Fn(Vec<N>) -> N,
+) -> MultiProof<N> {
+    let size = level.len();
+    let leaves = known
+        .iter()
+        .map(|&index| (index, level[index].clone()))
+        .collect();
+    let mut nodes = Vec::new();
+
+    while level.len() > 1 {
+        let mut next_known = Vec::with_capacity(known.len());
+        let mut i = 0;
+
+        while i < known.len() {
+            let position = known[i];
+            if position & 1 == 1 {
+                nodes.push(level[position - 1].clone());
+            } else if position + 1 < level.len() {
+                if known.get(i + 1) == Some(&(position + 1)) {
+                    i += 1;
+                } else {
+                    nodes.push(level[position + 1].clone());
+                }
+            }
+
+            next_known.push(position >> 1);
+            i += 1;
+        }
+
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left] => left.clone(),
+                _ => hash(pair.to_vec()),
+            })
+            .collect();
+        known = next_known;
+    }
+
+    MultiProof {
+        root: level[0].clone(),
+        size,
+        leaves,
+        nodes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_generate_multiproof() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((1..=7).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+
+        let proof = imt.generate_multiproof(&[5, 0, 1, 6, 0]).unwrap();
+        assert_eq!(proof.root, imt.root().unwrap());
+        assert_eq!(
+            proof.leaves,
+            vec![
+                (0, "leaf1".to_string()),
+                (1, "leaf2".to_string()),
+                (5, "leaf6".to_string()),
+                (6, "leaf7".to_string()),
+            ]
+        );
+        // Separate proofs would need 3 + 3 + 2 + 2 siblings
+        assert_eq!(
+            proof.nodes,
+            vec!["leaf5".to_string(), "leaf3,leaf4".to_string()]
+        );
+        assert!(imt.verify_multiproof(&proof));
+
+        // Every subset of leaves of trees of every size verifies
+        for size in 1..=9 {
+            let mut imt = LeanIMT::new(hash);
+            imt.insert_many((0..size).map(|i| format!("leaf{}", i)).collect())
+                .unwrap();
+            for subset in 1..(1u32 << size) {
+                let indices: Vec<_> = (0..size).filter(|i| subset >> i & 1 == 1).collect();
+                let proof = imt.generate_multiproof(&indices).unwrap();
+                assert!(imt.verify_multiproof(&proof));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_multiproof() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        assert_eq!(imt.generate_multiproof(&[]).unwrap_err(), "No leaf indices");
+        imt.insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        assert_eq!(
+            imt.generate_multiproof(&[1, 5]).unwrap_err(),
+            "Leaf index out of range"
+        );
+
+        let proof = imt.generate_multiproof(&[1, 2]).unwrap();
+
+        let mut tampered = proof.clone();
+        tampered.leaves[0].1 = "leaf9".to_string();
+        assert!(!tampered.verify(hash));
+
+        let mut tampered = proof.clone();
+        tampered.nodes.push("leaf9".to_string());
+        assert!(!tampered.verify(hash));
+
+        let mut tampered = proof.clone();
+        tampered.nodes.pop();
+        assert!(!tampered.verify(hash));
+
+        let mut tampered = proof.clone();
+        tampered.leaves.swap(0, 1);
+        assert!(!tampered.verify(hash));
+
+        let mut tampered = proof.clone();
+        tampered.size = 6;
+        assert!(!imt.verify_multiproof(&tampered));
+
+        // Sizes that cannot be rounded up fail instead of overflowing
+        let mut tampered = proof.clone();
+        tampered.size = usize::MAX;
+        assert!(!tampered.verify(hash));
+
+        imt.insert("leaf6".to_string()).unwrap();
+        assert!(proof.verify(hash));
+        assert!(!imt.verify_multiproof(&proof));
+    }
+}
@@ -0,0 +1,171 @@
+//! Inputs of Noir Merkle inclusion circuits.
+//!
+//! Noir circuits take a proof as fixed-size arrays, the length the circuit
+//! is compiled for: the siblings padded with zeros, and the directions of the
+//! path as `u1` values. [`NoirProof`] lays a proof out this way, matching
+//! `binary_merkle_root` of zk-kit.noir, which takes the leaf, the number of
+//! levels actually hashed as `depth`, the path bits as `indices` and the
+//! padded `siblings`, so it accepts the lean proofs of this crate.
+//!
+//! Field elements are written as `0x`-prefixed, 32-byte big-endian hex
+//! strings, as nargo reads them, either as a `Prover.toml` fragment or as the
+//! JSON inputs of nargo and noir_js.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use ark_ff::{BigInteger, PrimeField};
+
+use crate::codec::encode_hex;
+use crate::{LeanIMT, LeanIMTStore, MerkleProof};
+
+/// Proof laid out as the inputs of a Noir circuit compiled for
+/// `siblings.len()` levels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoirProof<F> {
+    pub leaf: F,
+    pub root: F,
+    /// Number of levels hashed by the proof.
+    pub depth: usize,
+    /// Bits of the path, 0 or 1, one per level.
+    pub indices: Vec<u8>,
+    /// Siblings of the proof, padded with zeros to the maximum depth.
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> NoirProof<F> {
+    /// Lays out `proof` for a circuit compiled for `max_depth` levels.
+    ///
+    /// Fails with "Tree is deeper than target depth" if the proof has more
+    /// siblings.
+    pub fn new(proof: &MerkleProof<F>, max_depth: usize) -> Result<Self, &'static str> {
+        let depth = proof.siblings.len();
+        if depth > max_depth {
+            return Err("Tree is deeper than target depth");
+        }
+
+        let path = proof.path();
+        let mut siblings = proof.siblings.clone();
+        siblings.resize(max_depth, F::ZERO);
+        Ok(NoirProof {
+            leaf: proof.leaf,
+            root: proof.root,
+            depth,
+            indices: (0..max_depth)
+                .map(|level| u8::from(path.bit(level)))
+                .collect(),
+            siblings,
+        })
+    }
+
+    /// Writes the `leaf`, `depth`, `indices` and `siblings` inputs as lines
+    /// of a `Prover.toml`, e.g. to append to the other inputs of the circuit.
+    pub fn to_prover_toml(&self) -> String {
+        format!(
+            "leaf = \"{}\"\ndepth = {}\nindices = [{}]\nsiblings = [{}]\n",
+            field_hex(&self.leaf),
+            self.depth,
+            self.indices_list(),
+            self.siblings_list()
+        )
+    }
+
+    /// Writes the same inputs as a JSON object, as read by nargo and
+    /// noir_js.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"leaf\":\"{}\",\"depth\":{},\"indices\":[{}],\"siblings\":[{}]}}",
+            field_hex(&self.leaf),
+            self.depth,
+            self.indices_list(),
+            self.siblings_list()
+        )
+    }
+
+    fn indices_list(&self) -> String {
+        let indices: Vec<String> = self.indices.iter().map(|bit| format!("{}", bit)).collect();
+        indices.join(", ")
+    }
+
+    fn siblings_list(&self) -> String {
+        let siblings: Vec<String> = self
+            .siblings
+            .iter()
+            .map(|sibling| format!("\"{}\"", field_hex(sibling)))
+            .collect();
+        siblings.join(", ")
+    }
+}
+
+/// Writes a field element as `0x`-prefixed big-endian hex, padded to 32
+/// bytes.
+fn field_hex<F: PrimeField>(element: &F) -> String {
+    let bytes = element.into_bigint().to_bytes_be();
+    let mut padded = vec![0; 32usize.saturating_sub(bytes.len())];
+    padded.extend(bytes);
+    encode_hex(&padded)
+}
+
+impl<F: PrimeField + Hash, S: LeanIMTStore<F>> LeanIMT<F, S> {
+    /// Generates the proof of the leaf at `index`, laid out for a Noir
+    /// circuit compiled for `max_depth` levels.
+    pub fn generate_noir_proof(
+        &self,
+        index: usize,
+        max_depth: usize,
+    ) -> Result<NoirProof<F>, &'static str> {
+        NoirProof::new(&self.generate_proof(index)?, max_depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use ark_bn254::Fr;
+
+    fn simple_hash_function(nodes: Vec<Fr>) -> Fr {
+        nodes
+            .into_iter()
+            .fold(Fr::from(7u64), |node, child| node * Fr::from(3u64) + child)
+    }
+
+    #[test]
+    fn test_noir_proof() {
+        let hash: IMTHashFunction<Fr> = simple_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, Fr::from(0u64));
+        imt.insert_many((1..=3).map(Fr::from).collect::<Vec<Fr>>())
+            .unwrap();
+
+        let proof = imt.generate_noir_proof(1, 3).unwrap();
+        assert_eq!(proof.depth, 2);
+        assert_eq!(proof.indices, vec![1, 0, 0]);
+        assert_eq!(proof.siblings[0], Fr::from(1u64));
+        assert_eq!(proof.siblings[2], Fr::from(0u64));
+
+        let zero = format!("0x{}", "0".repeat(64));
+        let one = format!("0x{}1", "0".repeat(63));
+        let two = format!("0x{}2", "0".repeat(63));
+        let three = format!("0x{}3", "0".repeat(63));
+        assert_eq!(
+            proof.to_prover_toml(),
+            format!(
+                "leaf = \"{two}\"\ndepth = 2\nindices = [1, 0, 0]\nsiblings = [\"{one}\", \"{three}\", \"{zero}\"]\n"
+            )
+        );
+        assert_eq!(
+            proof.to_json(),
+            format!(
+                "{{\"leaf\":\"{two}\",\"depth\":2,\"indices\":[1, 0, 0],\"siblings\":[\"{one}\", \"{three}\", \"{zero}\"]}}"
+            )
+        );
+
+        assert_eq!(
+            imt.generate_noir_proof(0, 1).unwrap_err(),
+            "Tree is deeper than target depth"
+        );
+    }
+}
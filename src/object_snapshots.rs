@@ -0,0 +1,363 @@
+//! Snapshots and log segments kept in object storage.
+//!
+//! Stateless replicas bootstrap from a bucket instead of a local disk. An
+//! [`ObjectSnapshotStore`] keeps, under a prefix of any `object_store`
+//! backend (S3, GCS, Azure, local files or memory):
+//!
+//! - `parts/<sha256>`: chunks of the uploaded objects, named by their SHA-256;
+//! - `snapshots/<segment>`: binary snapshots, named by the number of the
+//!   first log segment they do not include;
+//! - `wal/<segment>`: log segments, i.e. sequences of [`wal`](crate::wal)
+//!   frames, numbered from 0.
+//!
+//! Snapshots and segments are manifests listing the parts of their data,
+//! written once every part is uploaded, so an interrupted upload never leaves
+//! a partial object behind. Uploading the object again skips the parts
+//! already stored, resuming where it stopped, and every part is checked
+//! against its hash when downloaded.
+
+use std::sync::Arc;
+
+use core::hash::Hash;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use sha2::{Digest, Sha256};
+
+use crate::codec::{encode_hex, write_bytes, write_varint, ByteReader};
+use crate::compression::Compression;
+use crate::wal::{read_log, replay_onto};
+use crate::{IMTHashFunction, LeanIMT, LeanIMTSnapshot, LeanIMTStore, NodeCodec};
+
+/// Version of the manifest format, stored in its first byte.
+const MANIFEST_FORMAT_VERSION: u8 = 1;
+
+/// Snapshots and log segments of a tree under a prefix of an object store.
+#[derive(Debug, Clone)]
+pub struct ObjectSnapshotStore {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    part_size: usize,
+}
+
+impl ObjectSnapshotStore {
+    /// Keeps snapshots and log segments under `prefix` in `store`, uploaded
+    /// in parts of 8 MiB.
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: impl Into<Path>) -> Self {
+        ObjectSnapshotStore {
+            store,
+            prefix: prefix.into(),
+            part_size: 8 << 20,
+        }
+    }
+
+    /// Uploads objects in parts of `part_size` bytes.
+    pub fn part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Uploads a snapshot of `tree`, with nodes encoded by `C`, including the
+    /// log segments before `next_segment`.
+    pub async fn put_snapshot<N, S, C>(
+        &self,
+        tree: &LeanIMT<N, S>,
+        next_segment: u64,
+        compression: Compression,
+    ) -> Result<(), &'static str>
+    where
+        N: Clone + Eq + Hash,
+        S: LeanIMTStore<N>,
+        C: NodeCodec<N>,
+    {
+        let mut bytes = Vec::new();
+        tree.write_snapshot::<C>(&mut bytes, compression)?;
+        self.put_object(self.key("snapshots", next_segment), &bytes)
+            .await
+    }
+
+    /// Uploads the log segment numbered `segment`.
+    pub async fn put_segment(&self, segment: u64, log: &[u8]) -> Result<(), &'static str> {
+        self.put_object(self.key("wal", segment), log).await
+    }
+
+    /// Restores the latest snapshot and applies the log segments uploaded
+    /// after it, returning the tree and the number of the next segment.
+    pub async fn bootstrap<N, C>(
+        &self,
+        hash: IMTHashFunction<N>,
+    ) -> Result<(LeanIMT<N>, u64), &'static str>
+    where
+        N: Clone + Eq + Hash,
+        C: NodeCodec<N>,
+    {
+        let next_segment = *self.list("snapshots").await?.last().ok_or("No snapshot")?;
+        let bytes = self
+            .get_object(&self.key("snapshots", next_segment))
+            .await?;
+        let snapshot = LeanIMTSnapshot::read_from::<C>(&bytes[..])?;
+        let mut tree = LeanIMT::from_snapshot(snapshot, hash)?;
+        let next_segment = self.catch_up::<N, C>(&mut tree, next_segment, hash).await?;
+        Ok((tree, next_segment))
+    }
+
+    /// Applies the log segments numbered from `next_segment` to `tree`,
+    /// returning the number of the segment after them.
+    ///
+    /// Fails if a segment is missing between `next_segment` and the last one.
+    pub async fn catch_up<N, C>(
+        &self,
+        tree: &mut LeanIMT<N>,
+        next_segment: u64,
+        hash: IMTHashFunction<N>,
+    ) -> Result<u64, &'static str>
+    where
+        N: Clone + Eq + Hash,
+        C: NodeCodec<N>,
+    {
+        let mut next = next_segment;
+        for segment in self.list("wal").await? {
+            if segment < next {
+                continue;
+            }
+            if segment != next {
+                return Err("Missing log segment");
+            }
+            let log = self.get_object(&self.key("wal", segment)).await?;
+            replay_onto(tree, read_log::<N, C>(&log)?, hash)?;
+            next += 1;
+        }
+        Ok(next)
+    }
+
+    fn key(&self, directory: &str, number: u64) -> Path {
+        self.prefix
+            .child(directory)
+            .child(format!("{:020}", number))
+    }
+
+    fn part_key(&self, digest: &[u8]) -> Path {
+        self.prefix.child("parts").child(encode_hex(digest))
+    }
+
+    /// Returns the numbers of the objects in `directory`, in order.
+    async fn list(&self, directory: &str) -> Result<Vec<u64>, &'static str> {
+        let listing = self
+            .store
+            .list_with_delimiter(Some(&self.prefix.child(directory)))
+            .await
+            .map_err(|_| "Failed to list objects")?;
+        let mut numbers: Vec<u64> = listing
+            .objects
+            .iter()
+            .filter_map(|object| object.location.filename()?.parse().ok())
+            .collect();
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+
+    /// Uploads the parts of `data` not stored yet, then its manifest at `key`.
+    ///
+    /// The manifest holds the format version byte, the size of the data and
+    /// the SHA-256 of each part, as a LEB128 varint count followed by
+    /// length-prefixed bytes.
+    async fn put_object(&self, key: Path, data: &[u8]) -> Result<(), &'static str> {
+        let mut manifest = vec![MANIFEST_FORMAT_VERSION];
+        write_varint(&mut manifest, data.len() as u64);
+        write_varint(&mut manifest, data.len().div_ceil(self.part_size) as u64);
+        for part in data.chunks(self.part_size) {
+            let digest = Sha256::digest(part);
+            let location = self.part_key(&digest);
+            match self.store.head(&location).await {
+                Ok(object) if object.size == part.len() as u64 => {}
+                Ok(_) | Err(object_store::Error::NotFound { .. }) => {
+                    self.store
+                        .put(&location, part.to_vec().into())
+                        .await
+                        .map_err(|_| "Failed to upload object")?;
+                }
+                Err(_) => return Err("Failed to upload object"),
+            }
+            write_bytes(&mut manifest, &digest);
+        }
+        self.store
+            .put(&key, manifest.into())
+            .await
+            .map_err(|_| "Failed to upload object")?;
+        Ok(())
+    }
+
+    /// Downloads the parts listed by the manifest at `key`, checking them
+    /// against their hashes.
+    async fn get_object(&self, key: &Path) -> Result<Vec<u8>, &'static str> {
+        let manifest = self.fetch(key).await?;
+        let mut reader = ByteReader::new(&manifest);
+        if reader.read_byte()? != MANIFEST_FORMAT_VERSION {
+            return Err("Unsupported manifest version");
+        }
+        let size = reader.read_usize()?;
+        let count = reader.read_usize()?;
+
+        let mut data = Vec::new();
+        for _ in 0..count {
+            let digest = reader.read_bytes()?;
+            let part = self.fetch(&self.part_key(digest)).await?;
+            if Sha256::digest(&part).as_slice() != digest {
+                return Err("Checksum mismatch");
+            }
+            data.extend_from_slice(&part);
+        }
+        if !reader.is_empty() {
+            return Err("Trailing bytes");
+        }
+        if data.len() != size {
+            return Err("Checksum mismatch");
+        }
+        Ok(data)
+    }
+
+    async fn fetch(&self, location: &Path) -> Result<Vec<u8>, &'static str> {
+        let object = self
+            .store
+            .get(location)
+            .await
+            .map_err(|_| "Failed to download object")?;
+        let bytes = object
+            .bytes()
+            .await
+            .map_err(|_| "Failed to download object")?;
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::wal::{write_record, LogRecord};
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use object_store::memory::InMemory;
+    use std::future::Future;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(value) = future.as_mut().poll(&mut context) {
+                return value;
+            }
+        }
+    }
+
+    fn segment(leaves: &[&str]) -> Vec<u8> {
+        let mut log = Vec::new();
+        for leaf in leaves {
+            write_record::<String, StringCodec>(&mut log, &LogRecord::Insert(leaf.to_string()))
+                .unwrap();
+        }
+        log
+    }
+
+    #[test]
+    fn test_bootstrap_from_object_store() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let objects = Arc::new(InMemory::new());
+        let store = ObjectSnapshotStore::new(objects.clone(), "trees/main").part_size(16);
+
+        block_on(async {
+            assert_eq!(
+                store
+                    .bootstrap::<String, StringCodec>(hash)
+                    .await
+                    .unwrap_err(),
+                "No snapshot"
+            );
+
+            let mut imt = LeanIMT::new(hash);
+            imt.insert_many((1..=10).map(|i| format!("leaf{}", i)).collect())
+                .unwrap();
+            store
+                .put_snapshot::<_, _, StringCodec>(&imt, 0, Compression::None)
+                .await
+                .unwrap();
+            store.put_segment(0, &segment(&["a", "b"])).await.unwrap();
+            store.put_segment(1, &segment(&["c"])).await.unwrap();
+            imt.insert_many(vec!["a".into(), "b".into(), "c".into()])
+                .unwrap();
+
+            let (mut replica, next_segment) =
+                store.bootstrap::<String, StringCodec>(hash).await.unwrap();
+            assert_eq!(replica.root(), imt.root());
+            assert_eq!(next_segment, 2);
+
+            // A newer snapshot replaces the segments it includes
+            store
+                .put_snapshot::<_, _, StringCodec>(&imt, 2, Compression::None)
+                .await
+                .unwrap();
+            store.put_segment(2, &segment(&["d"])).await.unwrap();
+            imt.insert("d".to_string()).unwrap();
+            assert_eq!(
+                store
+                    .catch_up::<String, StringCodec>(&mut replica, 2, hash)
+                    .await,
+                Ok(3)
+            );
+            assert_eq!(replica.root(), imt.root());
+            let (replica, _) = store.bootstrap::<String, StringCodec>(hash).await.unwrap();
+            assert_eq!(replica.root(), imt.root());
+
+            store.put_segment(4, &segment(&["e"])).await.unwrap();
+            assert_eq!(
+                store
+                    .catch_up::<String, StringCodec>(&mut imt, 3, hash)
+                    .await,
+                Err("Missing log segment")
+            );
+        });
+    }
+
+    #[test]
+    fn test_corrupted_part_is_detected() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let objects = Arc::new(InMemory::new());
+        let store = ObjectSnapshotStore::new(objects.clone(), "trees").part_size(8);
+
+        block_on(async {
+            let mut imt = LeanIMT::new(hash);
+            imt.insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+                .unwrap();
+            store
+                .put_snapshot::<_, _, StringCodec>(&imt, 0, Compression::None)
+                .await
+                .unwrap();
+
+            let part = Path::from("trees/parts");
+            let listing = objects.list_with_delimiter(Some(&part)).await.unwrap();
+            let location = &listing.objects[0].location;
+            objects
+                .put(location, b"corrupted".to_vec().into())
+                .await
+                .unwrap();
+            assert_eq!(
+                store
+                    .bootstrap::<String, StringCodec>(hash)
+                    .await
+                    .unwrap_err(),
+                "Checksum mismatch"
+            );
+
+            // Uploading again replaces the corrupted part, whose size differs
+            store
+                .put_snapshot::<_, _, StringCodec>(&imt, 0, Compression::None)
+                .await
+                .unwrap();
+            let (replica, _) = store.bootstrap::<String, StringCodec>(hash).await.unwrap();
+            assert_eq!(replica.root(), imt.root());
+        });
+    }
+}
@@ -0,0 +1,123 @@
+//! Proofs of every leaf, generated on the rayon thread pool.
+//!
+//! An airdrop publishes the proof of each of its recipients, which for
+//! millions of leaves is far too slow with one `generate_proof` per leaf.
+//! `export_proofs` hashes each level of the tree once, spreading the nodes
+//! of the level over the thread pool, then reads the proofs of batches of
+//! consecutive leaves in parallel, one subtree per task, and passes them in
+//! index order to a callback, so only one batch is held in memory.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use rayon::prelude::*;
+
+use crate::proof::proof_from_levels;
+use crate::{LeanIMT, LeanIMTStore, MerkleProof};
+
+/// Number of proofs generated before they are passed to the callback.
+const EXPORT_BATCH: usize = 1 << 16;
+
+/// Number of leaves whose proofs are read by each rayon task.
+const SUBTREE_LEAVES: usize = 1 << 10;
+
+/// Minimum number of nodes hashed by each rayon task.
+const PARALLEL_MIN_PAIRS: usize = 64;
+
+impl<N, S> LeanIMT<N, S>
+where
+    N: Clone + Eq + Hash + Send + Sync,
+    S: LeanIMTStore<N>,
+{
+    /// Generates the proof of every leaf in parallel and calls `write` with
+    /// the index of each leaf and its proof, in index order.
+    ///
+    /// Removed leaves are proven as the zero value. The export stops at the
+    /// first error returned by `write`.
+    pub fn export_proofs(
+        &self,
+        mut write: impl FnMut(usize, MerkleProof<N>) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        self.ensure_binary()?;
+        if self.size == 0 {
+            return Ok(());
+        }
+
+        let levels = self.parallel_levels(self.ordered_leaves()?);
+        for start in (0..self.size).step_by(EXPORT_BATCH) {
+            let end = (start + EXPORT_BATCH).min(self.size);
+            let proofs: Vec<_> = (start..end)
+                .into_par_iter()
+                .with_min_len(SUBTREE_LEAVES)
+                .map(|index| proof_from_levels(&levels, index))
+                .collect();
+            for (index, proof) in (start..end).zip(proofs) {
+                self.stats.add_proof();
+                write(index, proof?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes every level of the tree from its leaves, hashing the nodes
+    /// of each level in parallel.
+    fn parallel_levels(&self, leaves: Vec<N>) -> Vec<Vec<N>> {
+        let hash = self.stats.counted(&self.hash);
+        let mut levels = vec![leaves];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1]
+                .par_chunks(2)
+                .with_min_len(PARALLEL_MIN_PAIRS)
+                .map(|pair| match pair {
+                    [left] => left.clone(),
+                    _ => hash(pair.to_vec()),
+                })
+                .collect();
+            levels.push(parents);
+        }
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IMTHashFunction, LeanIMT};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_export_proofs() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((0..3000).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = imt.generate_proof(1234).unwrap();
+        imt.remove(&"leaf1234".to_string(), &proof.siblings)
+            .unwrap();
+
+        let mut exported = Vec::new();
+        imt.export_proofs(|index, proof| {
+            exported.push((index, proof));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(exported.len(), 3000);
+        for index in [0, 1234, 1999, 2999] {
+            assert_eq!(exported[index], (index, imt.generate_proof(index).unwrap()));
+        }
+
+        let mut written = 0;
+        let result = imt.export_proofs(|index, _| {
+            written += 1;
+            if index == 10 {
+                return Err("Failed to write proof");
+            }
+            Ok(())
+        });
+        assert_eq!(result, Err("Failed to write proof"));
+        assert_eq!(written, 11);
+    }
+}
@@ -0,0 +1,212 @@
+//! Store with structural sharing, for cheap forks.
+//!
+//! [`PersistentStore`] keeps the side nodes, leaves, payloads and tombstones
+//! in the persistent hash maps and vectors of `imbl`. Cloning it, and so
+//! forking a tree that uses it, takes constant time: the clone shares every
+//! entry with the original, and each later write only copies the few map
+//! nodes on its path. For the same reason, rolling back a failed operation
+//! restores a clone of the maps taken at the last commit. A store that was
+//! never committed has no such clone, and keeps its writes.
+
+use std::hash::Hash;
+
+use imbl::{HashMap, Vector};
+
+use crate::store::StoreIter;
+use crate::LeanIMTStore;
+
+/// In-memory store backed by persistent hash maps and vectors.
+#[derive(Debug, Clone)]
+pub struct PersistentStore<N: Clone + Eq + Hash> {
+    side_nodes: HashMap<usize, N>,
+    leaves: HashMap<N, Vec<usize>>,
+    leaves_by_index: Vector<Option<N>>,
+    payloads: HashMap<usize, Vec<u8>>,
+    tombstones: HashMap<N, Vec<usize>>,
+    /// Maps as of the last commit, restored by `rollback`.
+    checkpoint: Option<Box<PersistentStore<N>>>,
+}
+
+impl<N: Clone + Eq + Hash> Default for PersistentStore<N> {
+    fn default() -> Self {
+        PersistentStore {
+            side_nodes: HashMap::new(),
+            leaves: HashMap::new(),
+            leaves_by_index: Vector::new(),
+            payloads: HashMap::new(),
+            tombstones: HashMap::new(),
+            checkpoint: None,
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMTStore<N> for PersistentStore<N> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        Ok(self.side_nodes.get(&level).cloned())
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        self.side_nodes.insert(level, node);
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        self.side_nodes.remove(&level);
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        Box::new(
+            self.side_nodes
+                .iter()
+                .map(|(&level, node)| Ok((level, node.clone()))),
+        )
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self.leaves.get(leaf).cloned())
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.leaves.insert(leaf, indices);
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.leaves.remove(leaf);
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        Box::new(
+            self.leaves
+                .iter()
+                .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone()))),
+        )
+    }
+
+    fn has_leaf(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self.leaves.contains_key(leaf))
+    }
+
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        Ok(self.leaves_by_index.get(index).cloned().flatten())
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        while self.leaves_by_index.len() <= index {
+            self.leaves_by_index.push_back(None);
+        }
+        self.leaves_by_index.set(index, leaf);
+        Ok(())
+    }
+
+    fn leaves_by_index(&self) -> Result<Vec<Option<N>>, &'static str>
+    where
+        N: Clone,
+    {
+        Ok(self.leaves_by_index.iter().cloned().collect())
+    }
+
+    fn payload(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        Ok(self.payloads.get(&index).cloned())
+    }
+
+    fn put_payload(&mut self, index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        match payload {
+            Some(payload) => self.payloads.insert(index, payload),
+            None => self.payloads.remove(&index),
+        };
+        Ok(())
+    }
+
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        Box::new(
+            self.payloads
+                .iter()
+                .map(|(&index, payload)| Ok((index, payload.clone()))),
+        )
+    }
+
+    fn tombstone(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self.tombstones.get(leaf).cloned())
+    }
+
+    fn put_tombstone(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.tombstones.insert(leaf, indices);
+        Ok(())
+    }
+
+    fn tombstones<'a>(&'a self) -> StoreIter<'a, (N, Vec<usize>)>
+    where
+        N: 'a,
+    {
+        Box::new(
+            self.tombstones
+                .iter()
+                .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone()))),
+        )
+    }
+
+    fn commit(&mut self) -> Result<(), &'static str> {
+        self.checkpoint = None;
+        self.checkpoint = Some(Box::new(self.clone()));
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        if let Some(checkpoint) = self.checkpoint.take() {
+            *self = (*checkpoint).clone();
+            self.checkpoint = Some(checkpoint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_forks_are_independent() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt =
+            LeanIMT::with_store(hash, "0".to_string(), PersistentStore::default()).unwrap();
+        imt.insert_many((1..=100).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let root = imt.root();
+
+        let mut fork = imt.fork();
+        assert_eq!(fork.root(), root);
+        fork.insert("leaf101".to_string()).unwrap();
+        let proof = fork.generate_proof(0).unwrap();
+        fork.remove(&"leaf1".to_string(), &proof.siblings).unwrap();
+
+        assert_eq!(imt.root(), root);
+        assert_eq!(imt.get_size(), 100);
+        assert!(imt.has(&"leaf1".to_string()));
+        assert!(!imt.has(&"leaf101".to_string()));
+        assert!(!fork.has(&"leaf1".to_string()));
+
+        // The parent keeps evolving on its own
+        imt.insert("other".to_string()).unwrap();
+        assert_ne!(imt.root(), fork.root());
+        assert_eq!(fork.index_of(&"leaf101".to_string()), Ok(100));
+
+        // Failed operations restore the maps of the last commit
+        let snapshot = imt.snapshot();
+        imt.store.put_side_node(0, "partial".to_string()).unwrap();
+        imt.store.rollback();
+        assert_eq!(imt.snapshot(), snapshot);
+
+        // Without a commit, there is nothing to restore
+        let mut store = PersistentStore::default();
+        store.put_side_node(0, "node".to_string()).unwrap();
+        store.rollback();
+        assert_eq!(store.side_node(0), Ok(Some("node".to_string())));
+    }
+}
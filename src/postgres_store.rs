@@ -0,0 +1,405 @@
+//! Async store backed by a Postgres database.
+//!
+//! Side nodes, leaves and metadata are kept in the tables
+//! `lean_imt_side_nodes(key, node)`, `lean_imt_leaves(leaf, indices)`,
+//! `lean_imt_leaves_by_index(position, leaf)` and `lean_imt_metadata(id,
+//! value)`, nodes encoded with a [`NodeCodec`]. Each operation of an
+//! [`AsyncLeanIMT`](crate::async_tree::AsyncLeanIMT) writes its changes in a
+//! single transaction, so an `insert_many` or `update` is stored entirely or
+//! not at all, and records the root it produces in `lean_imt_roots(version,
+//! root)` in the same transaction.
+//!
+//! Operations can also join a transaction of the application, opened with
+//! [`PostgresStore::begin`] on the connection the store shares with it: they
+//! run in savepoints and only become durable when the application commits.
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Client;
+
+use crate::async_tree::AsyncLeanIMTStore;
+use crate::store::StoreMetadata;
+use crate::{depth_of, NodeCodec};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS lean_imt_side_nodes (key BIGINT PRIMARY KEY, node BYTEA NOT NULL);
+    CREATE TABLE IF NOT EXISTS lean_imt_leaves (leaf BYTEA PRIMARY KEY, indices BIGINT[] NOT NULL);
+    CREATE TABLE IF NOT EXISTS lean_imt_leaves_by_index (position BIGINT PRIMARY KEY, leaf BYTEA NOT NULL);
+    CREATE TABLE IF NOT EXISTS lean_imt_metadata (id INTEGER PRIMARY KEY CHECK (id = 0), value BYTEA NOT NULL);
+    CREATE TABLE IF NOT EXISTS lean_imt_roots (version BIGINT PRIMARY KEY, root BYTEA NOT NULL);
+";
+
+const STORAGE_ERROR: &str = "Storage error";
+
+/// Async store keeping the nodes of a tree in Postgres, encoded with the
+/// codec `C`.
+#[derive(Debug)]
+pub struct PostgresStore<N, C> {
+    client: Client,
+    /// Whether the current operation opened a transaction or savepoint.
+    in_operation: bool,
+    /// Whether the application opened a transaction with `begin`.
+    in_transaction: AtomicBool,
+    /// Version and root key of the tree written by the current operation.
+    pending_root: Option<(u64, usize)>,
+    codec: PhantomData<fn() -> (N, C)>,
+}
+
+impl<N, C: NodeCodec<N>> PostgresStore<N, C> {
+    /// Uses the database of `client`, creating the tables of the store if
+    /// needed.
+    pub async fn new(client: Client) -> Result<Self, &'static str> {
+        client
+            .batch_execute(SCHEMA)
+            .await
+            .map_err(|_| STORAGE_ERROR)?;
+        Ok(PostgresStore {
+            client,
+            in_operation: false,
+            in_transaction: AtomicBool::new(false),
+            pending_root: None,
+            codec: PhantomData,
+        })
+    }
+
+    /// Returns the connection of the store, to run the statements of the
+    /// application in the same transactions as the tree.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Opens a transaction of the application, which the operations of the
+    /// tree join until it is committed or rolled back.
+    pub async fn begin(&self) -> Result<(), &'static str> {
+        if self.in_transaction.swap(true, Ordering::SeqCst) {
+            return Err("Transaction already open");
+        }
+        if self.client.batch_execute("BEGIN").await.is_err() {
+            self.in_transaction.store(false, Ordering::SeqCst);
+            return Err(STORAGE_ERROR);
+        }
+        Ok(())
+    }
+
+    /// Commits the transaction opened by `begin`.
+    pub async fn commit_transaction(&self) -> Result<(), &'static str> {
+        self.end_transaction("COMMIT").await
+    }
+
+    /// Rolls back the transaction opened by `begin`.
+    ///
+    /// Trees over the store still hold the size and version reached in the
+    /// transaction: reopen them with `AsyncLeanIMT::open`.
+    pub async fn rollback_transaction(&self) -> Result<(), &'static str> {
+        self.end_transaction("ROLLBACK").await
+    }
+
+    /// Returns the root recorded for `version`, if any.
+    pub async fn root_at(&self, version: u64) -> Result<Option<N>, &'static str> {
+        let version = i64::try_from(version).map_err(|_| "Value out of range")?;
+        self.query_bytes(
+            "SELECT root FROM lean_imt_roots WHERE version = $1",
+            &[&version],
+        )
+        .await?
+        .map(|bytes| C::decode(&bytes))
+        .transpose()
+    }
+
+    async fn end_transaction(&self, sql: &str) -> Result<(), &'static str> {
+        if !self.in_transaction.swap(false, Ordering::SeqCst) {
+            return Err("No transaction open");
+        }
+        self.client
+            .batch_execute(sql)
+            .await
+            .map_err(|_| STORAGE_ERROR)
+    }
+
+    /// Runs a write statement, opening the transaction or savepoint of the
+    /// operation first.
+    async fn execute(
+        &mut self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<(), &'static str> {
+        if !self.in_operation {
+            let begin = if self.in_transaction.load(Ordering::SeqCst) {
+                "SAVEPOINT lean_imt"
+            } else {
+                "BEGIN"
+            };
+            self.client
+                .batch_execute(begin)
+                .await
+                .map_err(|_| STORAGE_ERROR)?;
+            self.in_operation = true;
+        }
+        self.client
+            .execute(sql, params)
+            .await
+            .map_err(|_| STORAGE_ERROR)?;
+        Ok(())
+    }
+
+    async fn query_bytes(
+        &self,
+        sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        let row = self
+            .client
+            .query_opt(sql, params)
+            .await
+            .map_err(|_| STORAGE_ERROR)?;
+        row.map(|row| row.try_get(0).map_err(|_| STORAGE_ERROR))
+            .transpose()
+    }
+}
+
+fn to_key(index: usize) -> Result<i64, &'static str> {
+    i64::try_from(index).map_err(|_| "Value out of range")
+}
+
+fn from_key(key: i64) -> Result<usize, &'static str> {
+    usize::try_from(key).map_err(|_| "Invalid store key")
+}
+
+impl<N, C> AsyncLeanIMTStore<N> for PostgresStore<N, C>
+where
+    N: Send + Sync,
+    C: NodeCodec<N>,
+{
+    async fn side_node(&self, key: usize) -> Result<Option<N>, &'static str> {
+        self.query_bytes(
+            "SELECT node FROM lean_imt_side_nodes WHERE key = $1",
+            &[&to_key(key)?],
+        )
+        .await?
+        .map(|bytes| C::decode(&bytes))
+        .transpose()
+    }
+
+    async fn put_side_node(&mut self, key: usize, node: N) -> Result<(), &'static str> {
+        self.execute(
+            "INSERT INTO lean_imt_side_nodes (key, node) VALUES ($1, $2)
+             ON CONFLICT (key) DO UPDATE SET node = EXCLUDED.node",
+            &[&to_key(key)?, &C::encode(&node)],
+        )
+        .await
+    }
+
+    async fn delete_side_node(&mut self, key: usize) -> Result<(), &'static str> {
+        self.execute(
+            "DELETE FROM lean_imt_side_nodes WHERE key = $1",
+            &[&to_key(key)?],
+        )
+        .await
+    }
+
+    async fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT indices FROM lean_imt_leaves WHERE leaf = $1",
+                &[&C::encode(leaf)],
+            )
+            .await
+            .map_err(|_| STORAGE_ERROR)?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let indices: Vec<i64> = row.try_get(0).map_err(|_| STORAGE_ERROR)?;
+        indices
+            .into_iter()
+            .map(from_key)
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    async fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        let indices = indices
+            .into_iter()
+            .map(to_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        self.execute(
+            "INSERT INTO lean_imt_leaves (leaf, indices) VALUES ($1, $2)
+             ON CONFLICT (leaf) DO UPDATE SET indices = EXCLUDED.indices",
+            &[&C::encode(&leaf), &indices],
+        )
+        .await
+    }
+
+    async fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.execute(
+            "DELETE FROM lean_imt_leaves WHERE leaf = $1",
+            &[&C::encode(leaf)],
+        )
+        .await
+    }
+
+    async fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        match leaf {
+            Some(leaf) => {
+                self.execute(
+                    "INSERT INTO lean_imt_leaves_by_index (position, leaf) VALUES ($1, $2)
+                     ON CONFLICT (position) DO UPDATE SET leaf = EXCLUDED.leaf",
+                    &[&to_key(index)?, &C::encode(&leaf)],
+                )
+                .await
+            }
+            None => {
+                self.execute(
+                    "DELETE FROM lean_imt_leaves_by_index WHERE position = $1",
+                    &[&to_key(index)?],
+                )
+                .await
+            }
+        }
+    }
+
+    async fn leaves(&self) -> Result<Vec<(N, Vec<usize>)>, &'static str> {
+        let rows = self
+            .client
+            .query("SELECT leaf, indices FROM lean_imt_leaves", &[])
+            .await
+            .map_err(|_| STORAGE_ERROR)?;
+        let mut leaves = Vec::with_capacity(rows.len());
+        for row in rows {
+            let leaf: Vec<u8> = row.try_get(0).map_err(|_| STORAGE_ERROR)?;
+            let indices: Vec<i64> = row.try_get(1).map_err(|_| STORAGE_ERROR)?;
+            let indices = indices
+                .into_iter()
+                .map(from_key)
+                .collect::<Result<_, _>>()?;
+            leaves.push((C::decode(&leaf)?, indices));
+        }
+        Ok(leaves)
+    }
+
+    async fn metadata(&self) -> Result<Option<StoreMetadata<N>>, &'static str> {
+        self.query_bytes("SELECT value FROM lean_imt_metadata WHERE id = 0", &[])
+            .await?
+            .map(|bytes| StoreMetadata::from_bytes::<C>(&bytes))
+            .transpose()
+    }
+
+    async fn put_metadata(&mut self, metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        self.pending_root = (metadata.size > 0 && metadata.arity >= 2).then(|| {
+            let root_key = depth_of(metadata.size, metadata.arity) * (metadata.arity - 1);
+            (metadata.version, root_key)
+        });
+        self.execute(
+            "INSERT INTO lean_imt_metadata (id, value) VALUES (0, $1)
+             ON CONFLICT (id) DO UPDATE SET value = EXCLUDED.value",
+            &[&metadata.to_bytes::<C>()],
+        )
+        .await
+    }
+
+    async fn commit(&mut self) -> Result<(), &'static str> {
+        if !self.in_operation {
+            return Ok(());
+        }
+        if let Some((version, root_key)) = self.pending_root.take() {
+            let version = i64::try_from(version).map_err(|_| "Value out of range")?;
+            self.execute(
+                "INSERT INTO lean_imt_roots (version, root)
+                 SELECT $1, node FROM lean_imt_side_nodes WHERE key = $2
+                 ON CONFLICT (version) DO UPDATE SET root = EXCLUDED.root",
+                &[&version, &to_key(root_key)?],
+            )
+            .await?;
+        }
+        let end = if self.in_transaction.load(Ordering::SeqCst) {
+            "RELEASE SAVEPOINT lean_imt"
+        } else {
+            "COMMIT"
+        };
+        self.in_operation = false;
+        self.client
+            .batch_execute(end)
+            .await
+            .map_err(|_| STORAGE_ERROR)
+    }
+
+    async fn rollback(&mut self) {
+        self.pending_root = None;
+        if !self.in_operation {
+            return;
+        }
+        self.in_operation = false;
+        let rollback = if self.in_transaction.load(Ordering::SeqCst) {
+            "ROLLBACK TO SAVEPOINT lean_imt; RELEASE SAVEPOINT lean_imt"
+        } else {
+            "ROLLBACK"
+        };
+        let _ = self.client.batch_execute(rollback).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_tree::AsyncLeanIMT;
+    use crate::codec::StringCodec;
+    use crate::IMTHashFunction;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    async fn connect() -> Client {
+        let url = std::env::var("LEAN_IMT_POSTGRES_URL").unwrap();
+        let (client, connection) = tokio_postgres::connect(&url, tokio_postgres::NoTls)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database at LEAN_IMT_POSTGRES_URL"]
+    async fn test_postgres_store() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let client = connect().await;
+        client
+            .batch_execute(
+                "DROP TABLE IF EXISTS lean_imt_side_nodes, lean_imt_leaves,
+                 lean_imt_leaves_by_index, lean_imt_metadata, lean_imt_roots",
+            )
+            .await
+            .unwrap();
+        let store = PostgresStore::<String, StringCodec>::new(client)
+            .await
+            .unwrap();
+        let mut imt = AsyncLeanIMT::with_store(hash, "0".to_string(), store)
+            .await
+            .unwrap();
+        let leaves: Vec<_> = (1..=5).map(|i| format!("leaf{}", i)).collect();
+        imt.insert_many(leaves).await.unwrap();
+        let version = imt.get_version();
+        let root = imt.root().await;
+        assert_eq!(imt.get_store().root_at(version).await.unwrap(), root);
+
+        // A failed operation writes nothing
+        let result = imt.insert("leaf1".to_string()).await;
+        assert_eq!(result.unwrap_err(), "Leaf already exists");
+
+        // Operations in a rolled back application transaction are undone
+        imt.get_store().begin().await.unwrap();
+        imt.insert("leaf6".to_string()).await.unwrap();
+        imt.get_store().rollback_transaction().await.unwrap();
+
+        let store = PostgresStore::<String, StringCodec>::new(connect().await)
+            .await
+            .unwrap();
+        let imt = AsyncLeanIMT::open(hash, store).await.unwrap();
+        assert_eq!(imt.get_size(), 5);
+        assert_eq!(imt.get_version(), version);
+        assert_eq!(imt.root().await, root);
+        assert!(!imt.has(&"leaf6".to_string()).await);
+    }
+}
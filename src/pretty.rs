@@ -0,0 +1,290 @@
+//! Text renderings of the nodes of a tree, for debugging small trees.
+//!
+//! The store only keeps side nodes, keyed by level and index, which says
+//! little about the shape of the tree. These renderings recompute every level
+//! from the leaves, each node shortened to a prefix of its `Display` output
+//! and removed leaves marked as zero. `pretty_print` prints the levels from
+//! the root down, one line per level, while `to_dot` and `to_mermaid` emit
+//! diagrams of the whole tree for bug reports and documentation.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Write};
+use core::hash::Hash;
+use core::ops::Range;
+
+use crate::{LeanIMT, LeanIMTStore};
+
+/// Number of characters of each node printed.
+const NODE_PREFIX: usize = 10;
+
+/// Number of nodes printed per level, the others being counted.
+const NODES_PER_LEVEL: usize = 16;
+
+/// Marker printed for nodes equal to the zero value.
+const ZERO_MARKER: &str = "∅";
+
+/// A node of a diagram, named after its level and index.
+struct DiagramNode {
+    level: usize,
+    index: usize,
+    label: String,
+    /// Indices of the children of the node, on the level below.
+    children: Range<usize>,
+}
+
+impl<N: Clone + Eq + Hash + Display, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Renders the size and depth of the tree, then its levels from the root
+    /// down to `max_depth` levels below it, e.g. for the leaves `a`, `b` and
+    /// `c` hashed by joining them with commas:
+    ///
+    /// ```text
+    /// size 3, depth 2
+    /// 2 | a,b,c
+    /// 1 | a,b c
+    /// 0 | a b c
+    /// ```
+    ///
+    /// Nodes are cut to their first 10 characters, followed by `…`, and at
+    /// most 16 nodes are printed per level. Nodes equal to the zero value are
+    /// printed as `∅`.
+    pub fn pretty_print(&self, max_depth: usize) -> Result<String, &'static str> {
+        let mut output = String::new();
+        self.write_levels(&mut output, max_depth)
+            .map_err(|_| "Failed to format tree")?;
+        Ok(output)
+    }
+
+    fn write_levels(&self, f: &mut impl Write, max_depth: usize) -> fmt::Result {
+        writeln!(f, "size {}, depth {}", self.size, self.depth)?;
+        if self.size == 0 {
+            return Ok(());
+        }
+
+        let levels = self.levels().map_err(|_| fmt::Error)?;
+        let shown = levels.len().min(max_depth.saturating_add(1));
+        for (level, nodes) in levels.iter().enumerate().rev().take(shown) {
+            write!(f, "{} |", level)?;
+            for node in nodes.iter().take(NODES_PER_LEVEL) {
+                write!(f, " {}", self.label(node))?;
+            }
+            if nodes.len() > NODES_PER_LEVEL {
+                write!(f, " … {} more", nodes.len() - NODES_PER_LEVEL)?;
+            }
+            f.write_char('\n')?;
+        }
+        match levels.len() - shown {
+            0 => {}
+            1 => writeln!(f, "… 1 more level")?,
+            hidden => writeln!(f, "… {} more levels", hidden)?,
+        }
+        Ok(())
+    }
+
+    /// Renders the tree as a Graphviz DOT digraph, with an edge from every
+    /// node to each of its children. Nodes are named `n<level>_<index>` and
+    /// labeled as by `pretty_print`.
+    pub fn to_dot(&self) -> Result<String, &'static str> {
+        let mut output = String::from("digraph LeanIMT {\n    node [shape=box];\n");
+        for DiagramNode {
+            level,
+            index,
+            label,
+            children,
+        } in self.diagram_nodes()?
+        {
+            let label = label.replace('\\', "\\\\").replace('"', "\\\"");
+            output.push_str(&format!(
+                "    n{}_{} [label=\"{}\"];\n",
+                level, index, label
+            ));
+            for child in children {
+                output.push_str(&format!(
+                    "    n{}_{} -> n{}_{};\n",
+                    level,
+                    index,
+                    level - 1,
+                    child
+                ));
+            }
+        }
+        output.push_str("}\n");
+        Ok(output)
+    }
+
+    /// Renders the tree as a Mermaid flowchart, with nodes named and labeled
+    /// as by `to_dot`.
+    pub fn to_mermaid(&self) -> Result<String, &'static str> {
+        let mut output = String::from("flowchart TD\n");
+        for DiagramNode {
+            level,
+            index,
+            label,
+            children,
+        } in self.diagram_nodes()?
+        {
+            let label = label.replace('"', "#quot;");
+            output.push_str(&format!("    n{}_{}[\"{}\"]\n", level, index, label));
+            for child in children {
+                output.push_str(&format!(
+                    "    n{}_{} --> n{}_{}\n",
+                    level,
+                    index,
+                    level - 1,
+                    child
+                ));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Lists the nodes of the diagrams, from the root down.
+    fn diagram_nodes(&self) -> Result<Vec<DiagramNode>, &'static str> {
+        if self.size == 0 {
+            return Ok(Vec::new());
+        }
+        let levels = self.levels()?;
+        let mut nodes = Vec::new();
+        for (level, level_nodes) in levels.iter().enumerate().rev() {
+            for (index, node) in level_nodes.iter().enumerate() {
+                let children = match level {
+                    0 => 0..0,
+                    _ => {
+                        let first = index * self.arity;
+                        first..(first + self.arity).min(levels[level - 1].len())
+                    }
+                };
+                nodes.push(DiagramNode {
+                    level,
+                    index,
+                    label: self.label(node),
+                    children,
+                });
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Computes every level of the tree from its leaves.
+    fn levels(&self) -> Result<Vec<Vec<N>>, &'static str> {
+        let hash = self.hash.as_fn();
+        let mut levels = vec![self.ordered_leaves()?];
+        while levels[levels.len() - 1].len() > 1 {
+            let parents = levels[levels.len() - 1]
+                .chunks(self.arity)
+                .map(|children| match children {
+                    [child] => child.clone(),
+                    _ => hash(children.to_vec()),
+                })
+                .collect();
+            levels.push(parents);
+        }
+        Ok(levels)
+    }
+
+    fn label(&self, node: &N) -> String {
+        if *node == self.zero {
+            return ZERO_MARKER.to_string();
+        }
+        let text = node.to_string();
+        match text.char_indices().nth(NODE_PREFIX) {
+            Some((end, _)) => format!("{}…", &text[..end]),
+            None => text,
+        }
+    }
+}
+
+/// Renders every level of the tree, as `pretty_print` does.
+impl<N: Clone + Eq + Hash + Display, S: LeanIMTStore<N>> Display for LeanIMT<N, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_levels(f, self.depth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(format!("{}", imt), "size 0, depth 0\n");
+
+        imt.insert_many(["a", "b", "c"].map(String::from).to_vec())
+            .unwrap();
+        assert_eq!(
+            format!("{}", imt),
+            "size 3, depth 2\n2 | a,b,c\n1 | a,b c\n0 | a b c\n"
+        );
+        assert_eq!(
+            imt.pretty_print(1).unwrap(),
+            "size 3, depth 2\n2 | a,b,c\n1 | a,b c\n… 1 more level\n"
+        );
+
+        let siblings = imt.generate_proof(1).unwrap().siblings;
+        imt.remove(&"b".to_string(), &siblings).unwrap();
+        imt.insert("abcdefghijkl".to_string()).unwrap();
+        assert_eq!(
+            imt.pretty_print(0).unwrap(),
+            "size 4, depth 2\n2 | a,0,c,abcd…\n… 2 more levels\n"
+        );
+        assert!(imt
+            .pretty_print(2)
+            .unwrap()
+            .ends_with("0 | a ∅ c abcdefghij…\n"));
+    }
+
+    #[test]
+    fn test_diagrams() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(imt.to_mermaid().unwrap(), "flowchart TD\n");
+
+        imt.insert_many(["a", "b", "\"c\""].map(String::from).to_vec())
+            .unwrap();
+        assert_eq!(
+            imt.to_dot().unwrap(),
+            "digraph LeanIMT {
+    node [shape=box];
+    n2_0 [label=\"a,b,\\\"c\\\"\"];
+    n2_0 -> n1_0;
+    n2_0 -> n1_1;
+    n1_0 [label=\"a,b\"];
+    n1_0 -> n0_0;
+    n1_0 -> n0_1;
+    n1_1 [label=\"\\\"c\\\"\"];
+    n1_1 -> n0_2;
+    n0_0 [label=\"a\"];
+    n0_1 [label=\"b\"];
+    n0_2 [label=\"\\\"c\\\"\"];
+}
+"
+        );
+        assert_eq!(
+            imt.to_mermaid().unwrap(),
+            "flowchart TD
+    n2_0[\"a,b,#quot;c#quot;\"]
+    n2_0 --> n1_0
+    n2_0 --> n1_1
+    n1_0[\"a,b\"]
+    n1_0 --> n0_0
+    n1_0 --> n0_1
+    n1_1[\"#quot;c#quot;\"]
+    n1_1 --> n0_2
+    n0_0[\"a\"]
+    n0_1[\"b\"]
+    n0_2[\"#quot;c#quot;\"]
+"
+        );
+    }
+}
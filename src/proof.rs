@@ -0,0 +1,632 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{HashSet, IMTNode, LeafIndex, LeanIMT, LeanIMTStore};
+
+/// Inclusion proof of a leaf, in the same shape as the zk-kit LeanIMT proofs.
+///
+/// Levels where the path node has no sibling are skipped, so `siblings` only
+/// holds the nodes that are actually hashed, and the bits of `index` are the
+/// directions at those levels (bit `i` set means the path node is the right
+/// child when it is hashed with `siblings[i]`). For a tree without such gaps
+/// this is the leaf index itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MerkleProof<N = IMTNode> {
+    pub root: N,
+    pub leaf: N,
+    pub index: usize,
+    pub siblings: Vec<N>,
+}
+
+/// Proof laid out as the inputs of the Semaphore circuits.
+///
+/// `siblings` holds the `length` siblings of a `MerkleProof` padded with the
+/// zero value of the tree to the depth the circuit is compiled for, and
+/// `path_indices` holds the bits of `index` (0 or 1) over the same depth.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PaddedMerkleProof<N = IMTNode> {
+    pub root: N,
+    pub leaf: N,
+    pub index: usize,
+    pub length: usize,
+    pub path_indices: Vec<u8>,
+    pub siblings: Vec<N>,
+}
+
+/// Inclusion proof of a leaf in a tree of any arity.
+///
+/// At each level where the path node is hashed with other nodes, `siblings`
+/// holds those nodes in order and `positions` the position of the path node
+/// among them. `siblings.concat()` is what `update` and `remove` expect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NaryMerkleProof<N = IMTNode> {
+    pub root: N,
+    pub leaf: N,
+    pub index: usize,
+    pub positions: Vec<usize>,
+    pub siblings: Vec<Vec<N>>,
+}
+
+impl<N: Clone + PartialEq> NaryMerkleProof<N> {
+    /// Checks that the leaf and siblings hash up to the root of the proof.
+    pub fn verify(&self, hash: impl Fn(Vec<N>) -> N) -> bool {
+        self.verify_with(hash, N::eq)
+    }
+
+    /// Checks the proof like `verify`, comparing the computed root with
+    /// `eq`.
+    pub fn verify_with(&self, hash: impl Fn(Vec<N>) -> N, eq: impl Fn(&N, &N) -> bool) -> bool {
+        if self.positions.len() != self.siblings.len() {
+            return false;
+        }
+
+        let mut node = self.leaf.clone();
+        for (&position, siblings) in self.positions.iter().zip(&self.siblings) {
+            if siblings.is_empty() || position > siblings.len() {
+                return false;
+            }
+            let mut children = siblings.clone();
+            children.insert(position, node);
+            node = hash(children);
+        }
+
+        eq(&node, &self.root)
+    }
+}
+
+impl<N: Clone> PaddedMerkleProof<N> {
+    /// Returns the proof without padding.
+    pub fn to_merkle_proof(&self) -> MerkleProof<N> {
+        MerkleProof {
+            root: self.root.clone(),
+            leaf: self.leaf.clone(),
+            index: self.index,
+            siblings: self.siblings[..self.length.min(self.siblings.len())].to_vec(),
+        }
+    }
+}
+
+impl<N: Clone + PartialEq> MerkleProof<N> {
+    /// Checks that the leaf and siblings hash up to the root of the proof.
+    pub fn verify(&self, hash: impl Fn(Vec<N>) -> N) -> bool {
+        self.verify_with(hash, N::eq)
+    }
+
+    /// Checks the proof like `verify`, comparing the computed root with
+    /// `eq`.
+    pub fn verify_with(&self, hash: impl Fn(Vec<N>) -> N, eq: impl Fn(&N, &N) -> bool) -> bool {
+        let mut node = self.leaf.clone();
+
+        for (i, sibling) in self.siblings.iter().enumerate() {
+            if self.path().bit(i) {
+                node = hash(vec![sibling.clone(), node]);
+            } else {
+                node = hash(vec![node, sibling.clone()]);
+            }
+        }
+
+        eq(&node, &self.root)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Generates the inclusion proof of the leaf at `index`.
+    ///
+    /// Only side nodes are stored, so the path is recomputed from the leaves.
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.ensure_binary()?;
+        if index >= self.size {
+            return Err("Leaf index out of range");
+        }
+
+        self.stats.add_proof();
+        build_proof(
+            self.ordered_leaves()?,
+            index,
+            self.stats.counted(&self.hash),
+        )
+    }
+
+    /// Generates the inclusion proofs of the leaves at `indices`, in the
+    /// same order.
+    ///
+    /// The tree is rebuilt from the leaves once for all the proofs, instead
+    /// of once per proof as with `generate_proof`.
+    pub fn generate_proofs(&self, indices: &[usize]) -> Result<Vec<MerkleProof<N>>, &'static str> {
+        self.ensure_binary()?;
+        if indices.iter().any(|&index| index >= self.size) {
+            return Err("Leaf index out of range");
+        }
+        if indices.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let levels = build_levels(self.ordered_leaves()?, self.stats.counted(&self.hash));
+        indices
+            .iter()
+            .map(|&index| {
+                self.stats.add_proof();
+                proof_from_levels(&levels, index)
+            })
+            .collect()
+    }
+
+    /// Generates the proof of `leaf` padded to `target_depth` siblings, as
+    /// expected by the Semaphore circuits.
+    ///
+    /// If the leaf appears several times, its lowest index is used.
+    pub fn generate_proof_padded(
+        &self,
+        leaf: &N,
+        target_depth: usize,
+    ) -> Result<PaddedMerkleProof<N>, &'static str> {
+        let proof = self.generate_proof(self.index_of(leaf)?)?;
+        if proof.siblings.len() > target_depth {
+            return Err("Tree is deeper than target depth");
+        }
+
+        let length = proof.siblings.len();
+        let path = proof.path();
+        let mut siblings = proof.siblings;
+        siblings.resize(target_depth, self.zero.clone());
+        let path_indices = (0..target_depth)
+            .map(|level| u8::from(path.bit(level)))
+            .collect();
+
+        Ok(PaddedMerkleProof {
+            root: proof.root,
+            leaf: proof.leaf,
+            index: proof.index,
+            length,
+            path_indices,
+            siblings,
+        })
+    }
+
+    /// Checks a proof against the current root of the tree.
+    pub fn verify_proof(&self, proof: &MerkleProof<N>) -> bool {
+        self.arity == 2
+            && self
+                .root()
+                .is_some_and(|root| self.nodes_eq(&root, &proof.root))
+            && proof.verify_with(self.hash.as_fn(), |a, b| self.nodes_eq(a, b))
+    }
+
+    /// Checks a proof against a set of acceptable roots, such as the recent
+    /// roots a contract accepts, rather than only the current root.
+    ///
+    /// With a comparison set by `set_node_eq`, every root of the set is
+    /// compared instead of looking the root up.
+    pub fn verify_against(&self, proof: &MerkleProof<N>, roots: &HashSet<N>) -> bool {
+        let known = match self.node_eq {
+            Some(node_eq) => roots
+                .iter()
+                .fold(false, |known, root| node_eq(root, &proof.root) | known),
+            None => roots.contains(&proof.root),
+        };
+        self.arity == 2 && known && proof.verify_with(self.hash.as_fn(), |a, b| self.nodes_eq(a, b))
+    }
+
+    /// Generates the inclusion proof of the leaf at `index`, for trees of any
+    /// arity.
+    pub fn generate_nary_proof(&self, index: usize) -> Result<NaryMerkleProof<N>, &'static str> {
+        if index >= self.size {
+            return Err("Leaf index out of range");
+        }
+
+        self.stats.add_proof();
+        self.nary_proof(index)
+    }
+
+    /// Builds the n-ary proof of the leaf at `index`, which must be lower
+    /// than the size, without counting it in the statistics.
+    pub(crate) fn nary_proof(&self, index: usize) -> Result<NaryMerkleProof<N>, &'static str> {
+        let mut nodes = self.ordered_leaves()?;
+        let leaf = nodes[index].clone();
+        let mut positions = Vec::new();
+        let mut siblings = Vec::new();
+        let mut current_index = index;
+
+        while nodes.len() > 1 {
+            let group_start = current_index / self.arity * self.arity;
+            let group_end = (group_start + self.arity).min(nodes.len());
+            if group_end - group_start > 1 {
+                let mut group = nodes[group_start..group_end].to_vec();
+                group.remove(current_index - group_start);
+                positions.push(current_index - group_start);
+                siblings.push(group);
+            }
+
+            nodes = nodes
+                .chunks(self.arity)
+                .map(|children| match children {
+                    [child] => child.clone(),
+                    _ => self.hash_children(children.to_vec()),
+                })
+                .collect();
+            current_index /= self.arity;
+        }
+
+        Ok(NaryMerkleProof {
+            root: nodes[0].clone(),
+            leaf,
+            index,
+            positions,
+            siblings,
+        })
+    }
+
+    /// Checks an n-ary proof against the current root of the tree.
+    pub fn verify_nary_proof(&self, proof: &NaryMerkleProof<N>) -> bool {
+        self.root()
+            .is_some_and(|root| self.nodes_eq(&root, &proof.root))
+            && proof.verify_with(self.hash.as_fn(), |a, b| self.nodes_eq(a, b))
+    }
+}
+
+/// Builds the proof of the leaf at `index` from all the leaves of a tree.
+///
+/// `index` must be lower than the number of leaves.
+pub(crate) fn build_proof<N: Clone>(
+    mut nodes: Vec<N>,
+    index: usize,
+    hash: impl Fn(Vec<N>) -> N,
+) -> Result<MerkleProof<N>, &'static str> {
+    let leaf = nodes[index].clone();
+    let mut siblings = Vec::new();
+    let mut path = LeafIndex::default();
+    let mut current_index = index;
+
+    while nodes.len() > 1 {
+        let sibling_index = current_index ^ 1;
+        if let Some(sibling) = nodes.get(sibling_index) {
+            path = add_direction(path, current_index, siblings.len())?;
+            siblings.push(sibling.clone());
+        }
+
+        nodes = nodes
+            .chunks(2)
+            .map(|pair| match pair {
+                [left] => left.clone(),
+                _ => hash(pair.to_vec()),
+            })
+            .collect();
+        current_index >>= 1;
+    }
+
+    Ok(MerkleProof {
+        root: nodes[0].clone(),
+        leaf,
+        index: path.to_usize()?,
+        siblings,
+    })
+}
+
+/// Sets bit `bit` of `path` if the node at `position` is a right child.
+fn add_direction(path: LeafIndex, position: usize, bit: usize) -> Result<LeafIndex, &'static str> {
+    if LeafIndex::from(position).bit(0) {
+        path.with_bit(bit).ok_or("Leaf index out of range")
+    } else {
+        Ok(path)
+    }
+}
+
+/// Computes every level of the binary tree made of `leaves`, from the leaves
+/// up to the root.
+pub(crate) fn build_levels<N: Clone>(leaves: Vec<N>, hash: impl Fn(Vec<N>) -> N) -> Vec<Vec<N>> {
+    let mut levels = vec![leaves];
+    while levels[levels.len() - 1].len() > 1 {
+        let parents = levels[levels.len() - 1]
+            .chunks(2)
+            .map(|pair| match pair {
+                [left] => left.clone(),
+                _ => hash(pair.to_vec()),
+            })
+            .collect();
+        levels.push(parents);
+    }
+    levels
+}
+
+/// Reads the proof of the leaf at `index` off the levels of a tree.
+///
+/// `index` must be lower than the number of leaves.
+pub(crate) fn proof_from_levels<N: Clone>(
+    levels: &[Vec<N>],
+    index: usize,
+) -> Result<MerkleProof<N>, &'static str> {
+    let mut siblings = Vec::new();
+    let mut path = LeafIndex::default();
+    let mut current_index = index;
+    for level in &levels[..levels.len() - 1] {
+        if let Some(sibling) = level.get(current_index ^ 1) {
+            path = add_direction(path, current_index, siblings.len())?;
+            siblings.push(sibling.clone());
+        }
+        current_index >>= 1;
+    }
+
+    Ok(MerkleProof {
+        root: levels[levels.len() - 1][0].clone(),
+        leaf: levels[0][index].clone(),
+        index: path.to_usize()?,
+        siblings,
+    })
+}
+
+/// Generates the inclusion proof of the leaf at `index` of the binary tree
+/// made of `leaves`, ordered by index with removed leaves set to zero.
+///
+/// The leaves are streamed without building the tree: only the left node
+/// waiting for its right sibling and the sibling of the path are kept at
+/// each level, so memory grows with the depth of the tree, not its size.
+pub fn prove_from_leaves<N: Clone>(
+    leaves: impl IntoIterator<Item = N>,
+    index: usize,
+    hash: impl Fn(Vec<N>) -> N,
+) -> Result<MerkleProof<N>, &'static str> {
+    let mut leaf = None;
+    let mut pending: Vec<Option<N>> = Vec::new();
+    let mut path_siblings: Vec<Option<N>> = Vec::new();
+    let mut size = 0;
+
+    for mut node in leaves {
+        if size == index {
+            leaf = Some(node.clone());
+        }
+
+        // Complete nodes are final: each one is saved if it is a sibling of
+        // the path, then hashed with the left node waiting at its level
+        let mut position = size;
+        let mut level = 0;
+        size += 1;
+        loop {
+            if level == pending.len() {
+                pending.push(None);
+                path_siblings.push(None);
+            }
+            if position == (index >> level) ^ 1 {
+                path_siblings[level] = Some(node.clone());
+            }
+            match pending[level].take() {
+                Some(left) => node = hash(vec![left, node]),
+                None => {
+                    pending[level] = Some(node);
+                    break;
+                }
+            }
+            position >>= 1;
+            level += 1;
+        }
+    }
+    let leaf = leaf.ok_or("Leaf index out of range")?;
+
+    // The last node of each level covers the leaves left over below it, and
+    // is promoted without hashing when it has no left node to join
+    let mut last: Option<N> = None;
+    for (level, left) in pending.into_iter().enumerate() {
+        if let Some(node) = &last {
+            if size >> level == (index >> level) ^ 1 {
+                path_siblings[level] = Some(node.clone());
+            }
+        }
+        last = match (left, last) {
+            (Some(left), Some(right)) => Some(hash(vec![left, right])),
+            (left, right) => left.or(right),
+        };
+    }
+
+    let mut path = LeafIndex::default();
+    let mut siblings = Vec::new();
+    for (level, sibling) in path_siblings.into_iter().enumerate() {
+        if let Some(sibling) = sibling {
+            if LeafIndex::from(index).bit(level) {
+                path = path
+                    .with_bit(siblings.len())
+                    .ok_or("Leaf index out of range")?;
+            }
+            siblings.push(sibling);
+        }
+    }
+
+    Ok(MerkleProof {
+        root: last.ok_or("Leaf index out of range")?,
+        leaf,
+        index: path.to_usize()?,
+        siblings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_generate_proof() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        for leaf in ["leaf1", "leaf2", "leaf3", "leaf4", "leaf5"] {
+            imt.insert(leaf.to_string()).unwrap();
+        }
+
+        let proof = imt.generate_proof(1).unwrap();
+        assert_eq!(proof.root, imt.root().unwrap());
+        assert_eq!(proof.leaf, "leaf2".to_string());
+        assert_eq!(proof.index, 1);
+        assert_eq!(
+            proof.siblings,
+            vec!["leaf1".to_string(), "leaf3,leaf4".to_string(), "leaf5".to_string()]
+        );
+        assert!(imt.verify_proof(&proof));
+
+        // The last leaf has no sibling on the first two levels
+        let proof = imt.generate_proof(4).unwrap();
+        assert_eq!(proof.index, 1);
+        assert_eq!(proof.siblings, vec!["leaf1,leaf2,leaf3,leaf4".to_string()]);
+        assert!(imt.verify_proof(&proof));
+    }
+
+    #[test]
+    fn test_prove_from_leaves() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        for size in 1..=12 {
+            imt.insert(format!("leaf{}", size)).unwrap();
+            let leaves: Vec<_> = imt.leaves_iter().cloned().collect();
+            for index in 0..size {
+                assert_eq!(
+                    prove_from_leaves(leaves.iter().cloned(), index, hash),
+                    imt.generate_proof(index)
+                );
+            }
+        }
+
+        let proof = imt.generate_proof(5).unwrap();
+        imt.remove(&"leaf6".to_string(), &proof.siblings).unwrap();
+        let leaves = imt.leaves_iter().cloned().collect::<Vec<_>>();
+        assert_eq!(
+            prove_from_leaves(leaves.clone(), 5, hash),
+            imt.generate_proof(5)
+        );
+        assert_eq!(
+            prove_from_leaves(leaves, 12, hash).unwrap_err(),
+            "Leaf index out of range"
+        );
+    }
+
+    #[test]
+    fn test_generate_proofs() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(imt.generate_proofs(&[]), Ok(Vec::new()));
+
+        imt.insert_many((1..=11).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = imt.generate_proof(2).unwrap();
+        imt.remove(&"leaf3".to_string(), &proof.siblings).unwrap();
+        let indices = [10, 0, 2, 7, 0];
+        let expected: Vec<_> = indices
+            .iter()
+            .map(|&index| imt.generate_proof(index).unwrap())
+            .collect();
+        assert_eq!(imt.generate_proofs(&indices), Ok(expected));
+        assert_eq!(
+            imt.generate_proofs(&[1, 11]).unwrap_err(),
+            "Leaf index out of range"
+        );
+    }
+
+    #[test]
+    fn test_proof_siblings_drive_update() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+
+        let proof = imt.generate_proof(2).unwrap();
+        imt.remove(&"leaf3".to_string(), &proof.siblings).unwrap();
+
+        let proof = imt.generate_proof(2).unwrap();
+        assert_eq!(proof.leaf, "0".to_string());
+        assert!(imt.verify_proof(&proof));
+    }
+
+    #[test]
+    fn test_generate_proof_out_of_range() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        assert_eq!(imt.generate_proof(0).unwrap_err(), "Leaf index out of range");
+
+        imt.insert("leaf1".to_string()).unwrap();
+        let proof = imt.generate_proof(0).unwrap();
+        assert!(proof.siblings.is_empty());
+        assert!(imt.verify_proof(&proof));
+        assert!(imt.generate_proof(1).is_err());
+    }
+
+    #[test]
+    fn test_generate_proof_padded() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+
+        let proof = imt.generate_proof_padded(&"leaf2".to_string(), 4).unwrap();
+        assert_eq!(proof.index, 1);
+        assert_eq!(proof.length, 2);
+        assert_eq!(proof.path_indices, vec![1, 0, 0, 0]);
+        assert_eq!(
+            proof.siblings,
+            vec!["leaf1".to_string(), "leaf3".to_string(), "0".to_string(), "0".to_string()]
+        );
+        assert!(imt.verify_proof(&proof.to_merkle_proof()));
+
+        // The last leaf skips the level without sibling
+        let proof = imt.generate_proof_padded(&"leaf3".to_string(), 2).unwrap();
+        assert_eq!(proof.path_indices, vec![1, 0]);
+        assert_eq!(proof.siblings, vec!["leaf1,leaf2".to_string(), "0".to_string()]);
+        assert!(imt.verify_proof(&proof.to_merkle_proof()));
+
+        assert_eq!(
+            imt.generate_proof_padded(&"leaf1".to_string(), 1)
+                .unwrap_err(),
+            "Tree is deeper than target depth"
+        );
+        assert_eq!(
+            imt.generate_proof_padded(&"leaf4".to_string(), 4)
+                .unwrap_err(),
+            "Leaf does not exist"
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered_proof() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.insert("leaf2".to_string()).unwrap();
+
+        let mut proof = imt.generate_proof(0).unwrap();
+        proof.leaf = "leaf3".to_string();
+        assert!(!proof.verify(hash));
+        assert!(!imt.verify_proof(&proof));
+
+        // A valid proof for another tree is not valid for this one
+        let proof = imt.generate_proof(0).unwrap();
+        imt.insert("leaf3".to_string()).unwrap();
+        assert!(proof.verify(hash));
+        assert!(!imt.verify_proof(&proof));
+    }
+}
@@ -0,0 +1,252 @@
+//! Tree caching its nodes to serve proofs without recomputing them.
+//!
+//! Only side nodes are stored, so `generate_proof` rebuilds the tree from its
+//! leaves for every proof. [`CachedLeanIMT`] keeps every level of the tree
+//! instead, and reads the siblings of a proof off those levels. After the
+//! tree changes, the changes recorded by its changelog are replayed on the
+//! cache, rehashing only the paths of the changed leaves. Changes that are no
+//! longer retained, and those the changelog does not record (`truncate`,
+//! `pop` and `rebuild_from_leaves`), rebuild the whole cache.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::proof::{build_levels, proof_from_levels};
+use crate::{IMTNode, LeanIMT, LeanIMTStore, MemoryStore, MerkleProof};
+
+/// A binary tree with a cache of its nodes, to serve many proofs between
+/// writes.
+#[derive(Debug)]
+pub struct CachedLeanIMT<N = IMTNode, S = MemoryStore<N>> {
+    tree: LeanIMT<N, S>,
+    /// Nodes of the tree level by level, from the leaves up to the root.
+    levels: Vec<Vec<N>>,
+    /// Version of the tree the levels were computed at, `None` until they
+    /// are first built.
+    version: Option<u64>,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> CachedLeanIMT<N, S> {
+    /// Wraps a tree, retaining up to `retention` change records so that up
+    /// to that many changed leaves are replayed on the cache instead of
+    /// rebuilding it.
+    pub fn new(mut tree: LeanIMT<N, S>, retention: usize) -> Self {
+        tree.set_changelog_capacity(retention);
+        CachedLeanIMT {
+            tree,
+            levels: Vec::new(),
+            version: None,
+        }
+    }
+
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        &self.tree
+    }
+
+    /// Returns the tree to change it. The cache catches up on the next
+    /// `refresh` or `generate_proof`.
+    pub fn tree_mut(&mut self) -> &mut LeanIMT<N, S> {
+        &mut self.tree
+    }
+
+    pub fn into_inner(self) -> LeanIMT<N, S> {
+        self.tree
+    }
+
+    /// Tells whether the cache is up to date with the tree.
+    pub fn is_fresh(&self) -> bool {
+        self.version == Some(self.tree.version) && self.root() == self.tree.root().as_ref()
+    }
+
+    fn root(&self) -> Option<&N> {
+        self.levels.last().and_then(|level| level.first())
+    }
+
+    /// Brings the cache up to date with the tree.
+    pub fn refresh(&mut self) -> Result<(), &'static str> {
+        self.tree.ensure_binary()?;
+        if self.is_fresh() {
+            return Ok(());
+        }
+
+        let replayed = match self.version {
+            Some(version) => self.replay(version),
+            None => false,
+        };
+        // A tree replaced through `tree_mut` can have a changelog unrelated
+        // to the cache, which the root gives away
+        if !replayed || self.root() != self.tree.root().as_ref() {
+            self.rebuild()?;
+        }
+        self.version = Some(self.tree.version);
+        Ok(())
+    }
+
+    /// Generates the inclusion proof of the leaf at `index`, first bringing
+    /// the cache up to date.
+    pub fn generate_proof(&mut self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.refresh()?;
+        self.cached_proof(index)
+    }
+
+    /// Generates the inclusion proof of the leaf at `index` from the cache,
+    /// e.g. by readers sharing the cached tree behind a lock.
+    ///
+    /// Fails if the tree changed since the cache was last refreshed.
+    pub fn cached_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        if !self.is_fresh() {
+            return Err("Proof cache is stale");
+        }
+        if index >= self.tree.size {
+            return Err("Leaf index out of range");
+        }
+
+        self.tree.stats.add_proof();
+        proof_from_levels(&self.levels, index)
+    }
+
+    /// Returns the sibling nodes of the leaf at `index`, in the order
+    /// `update` and `remove` take them, first bringing the cache up to date.
+    ///
+    /// The siblings are read off the cache, so callers driving the tree with
+    /// explicit siblings, e.g. to mirror the calls made to a contract, do not
+    /// recompute them.
+    pub fn siblings_of(&mut self, index: usize) -> Result<Vec<N>, &'static str> {
+        self.refresh()?;
+        if index >= self.tree.size {
+            return Err("Leaf index out of range");
+        }
+        Ok(proof_from_levels(&self.levels, index)?.siblings)
+    }
+
+    /// Applies the changes made after `version` to the cache, returning
+    /// false if some of them are not retained.
+    fn replay(&mut self, version: u64) -> bool {
+        let Ok(changes) = self.tree.changes_since(version) else {
+            return false;
+        };
+        let changes: Vec<(usize, N)> = changes
+            .map(|change| (change.index, change.new_leaf.clone()))
+            .collect();
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+
+        let mut dirty = Vec::with_capacity(changes.len());
+        for (index, leaf) in changes {
+            let leaves = &mut self.levels[0];
+            if index < leaves.len() {
+                leaves[index] = leaf;
+            } else if index == leaves.len() {
+                leaves.push(leaf);
+            } else {
+                return false;
+            }
+            dirty.push(index);
+        }
+
+        let hash = self.tree.stats.counted(&self.tree.hash);
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            dirty.sort_unstable();
+            dirty.dedup();
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+            let (lower, upper) = self.levels.split_at_mut(level + 1);
+            let (children, parents) = (&lower[level], &mut upper[0]);
+
+            // New parents are dirty and come last, so they are appended in order
+            for parent in dirty.iter_mut() {
+                *parent >>= 1;
+                let node = match children.get(*parent * 2 + 1) {
+                    Some(right) => hash(vec![children[*parent * 2].clone(), right.clone()]),
+                    None => children[*parent * 2].clone(),
+                };
+                if *parent < parents.len() {
+                    parents[*parent] = node;
+                } else {
+                    parents.push(node);
+                }
+            }
+            level += 1;
+        }
+        true
+    }
+
+    fn rebuild(&mut self) -> Result<(), &'static str> {
+        let leaves = self.tree.ordered_leaves()?;
+        self.levels = build_levels(leaves, self.tree.stats.counted(&self.tree.hash));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_cached_proofs() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut tree = LeanIMT::new(hash);
+        tree.insert_many((0..5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let mut cached = CachedLeanIMT::new(tree, 16);
+        assert_eq!(cached.cached_proof(0).unwrap_err(), "Proof cache is stale");
+
+        let check = |cached: &mut CachedLeanIMT| {
+            for index in 0..cached.tree().get_size() {
+                let proof = cached.generate_proof(index).unwrap();
+                assert_eq!(proof, cached.tree().generate_proof(index).unwrap());
+            }
+            assert_eq!(
+                cached.generate_proof(cached.tree().get_size()).unwrap_err(),
+                "Leaf index out of range"
+            );
+        };
+        check(&mut cached);
+
+        // An update rehashes only the path of the leaf
+        let siblings = cached.siblings_of(1).unwrap();
+        assert_eq!(siblings, cached.tree().generate_proof(1).unwrap().siblings);
+        let tree = cached.tree_mut();
+        tree.update(&"leaf1".to_string(), "new1".to_string(), &siblings)
+            .unwrap();
+        let hashes = tree.stats().hashes;
+        cached.refresh().unwrap();
+        assert_eq!(cached.tree().stats().hashes - hashes, 3);
+        check(&mut cached);
+
+        let tree = cached.tree_mut();
+        tree.insert_many(vec![
+            "leaf5".into(),
+            "leaf6".into(),
+            "leaf7".into(),
+            "leaf8".into(),
+        ])
+        .unwrap();
+        let siblings = cached.siblings_of(6).unwrap();
+        let tree = cached.tree_mut();
+        tree.remove(&"leaf6".to_string(), &siblings).unwrap();
+        check(&mut cached);
+        assert_eq!(
+            cached.siblings_of(9).unwrap_err(),
+            "Leaf index out of range"
+        );
+
+        // Truncations are not recorded by the changelog
+        cached.tree_mut().truncate(3).unwrap();
+        check(&mut cached);
+        *cached.tree_mut() = LeanIMT::new(hash);
+        cached.tree_mut().insert("other".to_string()).unwrap();
+        check(&mut cached);
+    }
+}
@@ -0,0 +1,206 @@
+//! Membership trees of RLN (rate-limiting nullifier) deployments.
+//!
+//! RLN members register a rate commitment, `Poseidon(identity_commitment,
+//! user_message_limit)` in RLN v2, as a leaf of a binary tree of fixed depth,
+//! 20 in zerokit and the rln circuits. Each message comes with a proof of
+//! membership against a root, and since members and verifiers do not see the
+//! tree change at the same time, verifiers accept the roots of the last few
+//! epochs. [`RlnMembership`] keeps one tree across epochs, unlike the rotating
+//! trees of `epoch::EpochLeanIMT`, records its root at the end of each epoch
+//! and accepts proofs against the current root or the retained ones.
+//!
+//! The rln circuits hash every level of the tree, and take the path as the
+//! `pathElements` and `identityPathIndex` inputs, one element per level.
+//! [`RlnWitness`] holds a padded proof laid out that way.
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::hash::Hash;
+
+use crate::fixed_depth::FixedDepthLeanIMT;
+use crate::{IMTNode, LeafIndex, MerkleProof};
+
+/// Membership tree of an RLN deployment, with the roots of recent epochs.
+#[derive(Debug)]
+pub struct RlnMembership<N = IMTNode> {
+    tree: FixedDepthLeanIMT<N>,
+    /// Roots recorded at the end of the retained epochs, oldest first.
+    roots: VecDeque<(u64, N)>,
+    /// Number of past epochs whose roots are accepted.
+    window: usize,
+}
+
+/// Path of a member, laid out as the inputs of the rln circuits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RlnWitness<N = IMTNode> {
+    pub root: N,
+    /// Rate commitment of the member.
+    pub leaf: N,
+    /// Siblings of the path, one per level.
+    pub path_elements: Vec<N>,
+    /// Bits of the index of the member, 0 or 1, one per level.
+    pub identity_path_index: Vec<u8>,
+}
+
+impl<N: Clone + Eq + Hash> RlnMembership<N> {
+    /// Manages the members of `tree`, accepting the roots of the last
+    /// `window` epochs besides the current root.
+    pub fn new(tree: FixedDepthLeanIMT<N>, window: usize) -> Self {
+        RlnMembership {
+            tree,
+            roots: VecDeque::new(),
+            window,
+        }
+    }
+
+    pub fn tree(&self) -> &FixedDepthLeanIMT<N> {
+        &self.tree
+    }
+
+    /// Registers a member by its rate commitment, returning its index.
+    pub fn register(&mut self, rate_commitment: N) -> Result<usize, &'static str> {
+        self.tree.insert(rate_commitment)?;
+        Ok(self.tree.tree().get_size() - 1)
+    }
+
+    /// Removes the member at `index`, e.g. once it has been slashed, and
+    /// returns the new root.
+    pub fn remove(&mut self, index: usize) -> Result<N, &'static str> {
+        let proof = self.tree.generate_proof(index)?;
+        self.tree.remove(&proof.leaf, &proof.siblings)
+    }
+
+    /// Records the current root as the root of `epoch`, returning it. Roots
+    /// of epochs older than the window are forgotten.
+    ///
+    /// Fails with "Epoch already recorded" unless `epoch` is later than the
+    /// last recorded one.
+    pub fn end_epoch(&mut self, epoch: u64) -> Result<N, &'static str> {
+        if self.roots.back().is_some_and(|(last, _)| *last >= epoch) {
+            return Err("Epoch already recorded");
+        }
+        let root = self.tree.root()?;
+        self.roots.push_back((epoch, root.clone()));
+        while self.roots.len() > self.window {
+            self.roots.pop_front();
+        }
+        Ok(root)
+    }
+
+    /// Returns the root recorded for `epoch`, if it is still retained.
+    pub fn root_of(&self, epoch: u64) -> Option<&N> {
+        self.roots
+            .iter()
+            .find(|(number, _)| *number == epoch)
+            .map(|(_, root)| root)
+    }
+
+    /// Returns true if `root` is the current root or that of a retained
+    /// epoch.
+    ///
+    /// All the retained roots are compared, with the comparison set on the
+    /// tree by `set_node_eq`, if any.
+    pub fn is_accepted_root(&self, root: &N) -> bool {
+        let lean = self.tree.tree();
+        let current = self
+            .tree
+            .root()
+            .is_ok_and(|current| lean.nodes_eq(&current, root));
+        self.roots.iter().fold(current, |accepted, (_, recorded)| {
+            lean.nodes_eq(recorded, root) | accepted
+        })
+    }
+
+    /// Checks a padded proof against the accepted roots.
+    pub fn verify_proof(&self, proof: &MerkleProof<N>) -> bool {
+        proof.siblings.len() == self.tree.get_depth()
+            && self.is_accepted_root(&proof.root)
+            && proof.verify_with(self.tree.tree().hash.as_fn(), |a, b| {
+                self.tree.tree().nodes_eq(a, b)
+            })
+    }
+
+    /// Generates the witness of the member at `index` against the current
+    /// root.
+    pub fn witness(&self, index: usize) -> Result<RlnWitness<N>, &'static str> {
+        let proof = self.tree.generate_proof(index)?;
+        let path = LeafIndex::from(index);
+        Ok(RlnWitness {
+            root: proof.root,
+            leaf: proof.leaf,
+            identity_path_index: (0..proof.siblings.len())
+                .map(|level| u8::from(path.bit(level)))
+                .collect(),
+            path_elements: proof.siblings,
+        })
+    }
+}
+
+impl<N: Display> RlnWitness<N> {
+    /// Writes the `pathElements` and `identityPathIndex` inputs as a JSON
+    /// object, with elements as strings, to merge with the other inputs of
+    /// the circuit.
+    pub fn to_json(&self) -> String {
+        let elements: Vec<String> = self
+            .path_elements
+            .iter()
+            .map(|element| format!("\"{}\"", element))
+            .collect();
+        let indices: Vec<String> = self
+            .identity_path_index
+            .iter()
+            .map(|bit| format!("\"{}\"", bit))
+            .collect();
+        format!(
+            "{{\"pathElements\":[{}],\"identityPathIndex\":[{}]}}",
+            elements.join(","),
+            indices.join(",")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::string::ToString;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_rln_membership() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let tree = FixedDepthLeanIMT::new(hash, 2).unwrap();
+        let mut rln = RlnMembership::new(tree, 1);
+        assert_eq!(rln.register("a".to_string()), Ok(0));
+        assert_eq!(rln.register("b".to_string()), Ok(1));
+        let first = rln.end_epoch(1).unwrap();
+        assert_eq!(first, "a,b,0,0");
+        let proof = rln.tree().generate_proof(1).unwrap();
+
+        // Roots of the previous epoch are still accepted
+        assert_eq!(rln.register("c".to_string()), Ok(2));
+        assert!(rln.verify_proof(&proof));
+        assert_eq!(rln.end_epoch(1), Err("Epoch already recorded"));
+        rln.end_epoch(2).unwrap();
+        assert_eq!(rln.root_of(1), None);
+        assert!(!rln.verify_proof(&proof));
+
+        let witness = rln.witness(2).unwrap();
+        assert_eq!(witness.root, "a,b,c,0");
+        assert_eq!(witness.identity_path_index, [0, 1]);
+        assert_eq!(
+            witness.to_json(),
+            "{\"pathElements\":[\"0\",\"a,b\"],\"identityPathIndex\":[\"0\",\"1\"]}"
+        );
+
+        assert_eq!(rln.remove(0), Ok("0,b,c,0".to_string()));
+        assert!(rln.is_accepted_root(&"a,b,c,0".to_string()));
+        assert!(rln.remove(0).is_err());
+    }
+}
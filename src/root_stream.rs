@@ -0,0 +1,165 @@
+//! Stream of the roots reached by a tree.
+//!
+//! Relayers publishing roots on chain and websocket servers pushing them to
+//! clients react to every root transition. `subscribe_roots` returns a
+//! [`RootStream`] receiving the version, size and root reached by every
+//! mutation that commits, in order, so they never have to poll `root()`.
+//! Updates are queued until they are read; the stream ends once the tree is
+//! dropped and the queued updates are read.
+//!
+//! `next` awaits the next update on any executor, and with the `stream`
+//! feature the stream implements `futures_core::Stream`.
+
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use crate::IMTNode;
+
+/// The state of a tree after a mutation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootUpdate<N = IMTNode> {
+    pub version: u64,
+    pub size: usize,
+    /// `None` if the mutation emptied the tree.
+    pub root: Option<N>,
+}
+
+#[derive(Debug)]
+struct Queue<N> {
+    updates: VecDeque<RootUpdate<N>>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+fn lock<N>(queue: &Mutex<Queue<N>>) -> MutexGuard<'_, Queue<N>> {
+    queue
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Sending side of a root stream, kept by the changelog of the tree.
+#[derive(Debug)]
+pub(crate) struct RootSender<N>(Arc<Mutex<Queue<N>>>);
+
+impl<N> RootSender<N> {
+    /// Queues `update`, returning false if the stream was dropped.
+    pub(crate) fn send(&self, update: RootUpdate<N>) -> bool {
+        if Arc::strong_count(&self.0) == 1 {
+            return false;
+        }
+        let mut queue = lock(&self.0);
+        queue.updates.push_back(update);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+impl<N> Drop for RootSender<N> {
+    fn drop(&mut self) {
+        let mut queue = lock(&self.0);
+        queue.closed = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Receiving side of a root stream, returned by `subscribe_roots`.
+#[derive(Debug)]
+pub struct RootStream<N = IMTNode>(Arc<Mutex<Queue<N>>>);
+
+impl<N> RootStream<N> {
+    pub(crate) fn new() -> (RootSender<N>, Self) {
+        let queue = Arc::new(Mutex::new(Queue {
+            updates: VecDeque::new(),
+            waker: None,
+            closed: false,
+        }));
+        (RootSender(Arc::clone(&queue)), RootStream(queue))
+    }
+
+    /// Waits for the next update, or returns `None` once the tree is
+    /// dropped and every update has been read.
+    pub async fn next(&mut self) -> Option<RootUpdate<N>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Returns the next queued update without waiting.
+    pub fn try_next(&mut self) -> Option<RootUpdate<N>> {
+        lock(&self.0).updates.pop_front()
+    }
+
+    /// Polls for the next update, registering the waker of `cx` to be woken
+    /// when one is queued.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<RootUpdate<N>>> {
+        let mut queue = lock(&self.0);
+        if let Some(update) = queue.updates.pop_front() {
+            return Poll::Ready(Some(update));
+        }
+        if queue.closed {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<N> futures_core::Stream for RootStream<N> {
+    type Item = RootUpdate<N>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<RootUpdate<N>>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{IMTHashFunction, LeanIMT};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[tokio::test]
+    async fn test_root_stream() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert("leaf1".to_string()).unwrap();
+        let mut roots = imt.subscribe_roots();
+        assert_eq!(roots.try_next(), None);
+
+        let consumer = tokio::spawn(async move {
+            let mut updates = Vec::new();
+            while let Some(update) = roots.next().await {
+                updates.push((update.size, update.root));
+            }
+            updates
+        });
+        tokio::task::yield_now().await;
+
+        imt.insert_many(vec!["leaf2".into(), "leaf3".into()])
+            .unwrap();
+        assert!(imt.insert("leaf1".to_string()).is_err());
+        imt.truncate(1).unwrap();
+        tokio::task::yield_now().await;
+        imt.pop().unwrap();
+        drop(imt);
+
+        assert_eq!(
+            consumer.await.unwrap(),
+            vec![
+                (3, Some("leaf1,leaf2,leaf3".to_string())),
+                (1, Some("leaf1".to_string())),
+                (0, None),
+            ]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Conversions to and from the types of semaphore-rs.
+//!
+//! semaphore-rs, the Rust implementation of Semaphore v4, keeps the members
+//! of a group in a lean IMT of zk-kit and exchanges roots, members and proofs
+//! as `Element`s: BN254 scalars as 32 little-endian bytes. The functions here
+//! convert the `ark_bn254::Fr` trees of this crate to and from that encoding,
+//! so a group managed here gives the members of `Group::new`, or the
+//! `MerkleProof` passed to `Proof::generate_proof`, directly.
+//!
+//! A proof of `Element`s has the `root`, `leaf`, `index` and `siblings` of the
+//! `MerkleProof` of zk-kit's lean IMT. Removed members are zero, as they are
+//! in a semaphore-rs group. The tree must hash its nodes with the Poseidon of
+//! Semaphore for the roots to match.
+
+use alloc::vec::Vec;
+
+use ark_bn254::Fr;
+use ark_ff::AdditiveGroup;
+
+use crate::codec::{FieldCodec, NodeCodec};
+use crate::{IMTHashFunction, LeanIMT, LeanIMTStore, MerkleProof};
+
+/// A group member or node of semaphore-rs, as 32 little-endian bytes.
+pub type Element = [u8; 32];
+
+/// Encodes a field element as an `Element`.
+pub fn to_element(node: &Fr) -> Element {
+    let mut element = [0; 32];
+    element.copy_from_slice(&FieldCodec::encode(node));
+    element
+}
+
+/// Decodes an `Element`.
+///
+/// Fails with "Invalid field element" if it is not reduced modulo the field
+/// order.
+pub fn from_element(element: &Element) -> Result<Fr, &'static str> {
+    FieldCodec::decode(element)
+}
+
+impl<S: LeanIMTStore<Fr>> LeanIMT<Fr, S> {
+    /// Returns the members of the group as `Element`s, ordered by index, with
+    /// removed members set to zero.
+    pub fn semaphore_members(&self) -> Result<Vec<Element>, &'static str> {
+        Ok(self.ordered_leaves()?.iter().map(to_element).collect())
+    }
+
+    pub fn semaphore_root(&self) -> Option<Element> {
+        self.root().as_ref().map(to_element)
+    }
+
+    /// Generates the proof of the member at `index`, as a proof of
+    /// `Element`s.
+    pub fn generate_semaphore_proof(
+        &self,
+        index: usize,
+    ) -> Result<MerkleProof<Element>, &'static str> {
+        Ok(self.generate_proof(index)?.to_semaphore())
+    }
+}
+
+impl LeanIMT<Fr> {
+    /// Creates a group from the members of a semaphore-rs group, removed
+    /// members being zero.
+    ///
+    /// Fails with "Invalid field element" if a member is not reduced.
+    pub fn from_semaphore_members(
+        hash: IMTHashFunction<Fr>,
+        members: &[Element],
+    ) -> Result<Self, &'static str> {
+        let leaves = members
+            .iter()
+            .map(from_element)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut imt = LeanIMT::with_zero(hash, Fr::ZERO);
+        imt.rebuild_from_leaves(leaves)?;
+        Ok(imt)
+    }
+}
+
+impl MerkleProof<Fr> {
+    /// Encodes the nodes of the proof as `Element`s.
+    pub fn to_semaphore(&self) -> MerkleProof<Element> {
+        MerkleProof {
+            root: to_element(&self.root),
+            leaf: to_element(&self.leaf),
+            index: self.index,
+            siblings: self.siblings.iter().map(to_element).collect(),
+        }
+    }
+
+    /// Decodes a proof of `Element`s, e.g. one generated by semaphore-rs.
+    ///
+    /// Fails with "Invalid field element" if a node is not reduced.
+    pub fn from_semaphore(proof: &MerkleProof<Element>) -> Result<Self, &'static str> {
+        Ok(MerkleProof {
+            root: from_element(&proof.root)?,
+            leaf: from_element(&proof.leaf)?,
+            index: proof.index,
+            siblings: proof
+                .siblings
+                .iter()
+                .map(from_element)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<Fr>) -> Fr {
+        nodes
+            .into_iter()
+            .fold(Fr::from(7u64), |node, child| node * Fr::from(3u64) + child)
+    }
+
+    #[test]
+    fn test_semaphore_group() {
+        let hash: IMTHashFunction<Fr> = simple_hash_function;
+        let mut imt = LeanIMT::with_zero(hash, Fr::ZERO);
+        imt.insert_many((1..=3).map(Fr::from).collect::<Vec<Fr>>())
+            .unwrap();
+        let siblings = imt.generate_proof(1).unwrap().siblings;
+        imt.remove(&Fr::from(2u64), &siblings).unwrap();
+
+        let members = imt.semaphore_members().unwrap();
+        let mut one = [0; 32];
+        one[0] = 1;
+        assert_eq!(members, vec![one, [0; 32], to_element(&Fr::from(3u64))]);
+
+        let group = LeanIMT::from_semaphore_members(hash, &members).unwrap();
+        assert_eq!(group.semaphore_root(), imt.semaphore_root());
+        assert!(!group.has(&Fr::ZERO));
+
+        let proof = group.generate_semaphore_proof(2).unwrap();
+        assert_eq!(proof.leaf, members[2]);
+        let decoded = MerkleProof::from_semaphore(&proof).unwrap();
+        assert_eq!(decoded, imt.generate_proof(2).unwrap());
+        assert!(imt.verify_proof(&decoded));
+
+        assert_eq!(from_element(&[0xff; 32]), Err("Invalid field element"));
+        assert!(LeanIMT::from_semaphore_members(hash, &[[0xff; 32]]).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+//! JSON-RPC 2.0 service exposing a shared tree over HTTP.
+//!
+//! [`TreeRpc`] answers the methods `insert`, `insert_many`, `root`, `size`,
+//! `proof` and `verify` against a [`SharedLeanIMT`], so the process embedding
+//! the tree keeps using it directly while other services reach the same
+//! instance over the network. Nodes are exchanged as the text produced by the
+//! codec `C`, and proofs as objects with `root`, `leaf`, `index` and
+//! `siblings` fields. `handle` is independent of the transport; `serve` runs
+//! it behind a blocking HTTP server accepting `POST` requests, whose bodies
+//! are limited to [`DEFAULT_MAX_BODY_SIZE`] bytes unless set otherwise.
+
+use std::hash::Hash;
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::net::ToSocketAddrs;
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::codec::StringCodec;
+use crate::shared::SharedLeanIMT;
+use crate::{IMTNode, LeanIMTStore, MemoryStore, MerkleProof, NodeCodec};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Code of the errors returned by the tree itself.
+const TREE_ERROR: i64 = -32000;
+
+/// Default size limit of request bodies, in bytes.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 1 << 20;
+
+/// Error of a call, turned into a JSON-RPC error object.
+struct RpcError {
+    code: i64,
+    message: &'static str,
+}
+
+impl RpcError {
+    fn params(message: &'static str) -> Self {
+        RpcError {
+            code: INVALID_PARAMS,
+            message,
+        }
+    }
+}
+
+impl From<&'static str> for RpcError {
+    fn from(message: &'static str) -> Self {
+        RpcError {
+            code: TREE_ERROR,
+            message,
+        }
+    }
+}
+
+/// JSON-RPC handler for a shared tree.
+#[derive(Debug)]
+pub struct TreeRpc<N = IMTNode, S = MemoryStore<N>, C = StringCodec> {
+    tree: SharedLeanIMT<N, S>,
+    max_body_size: usize,
+    codec: PhantomData<C>,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>, C: NodeCodec<N>> TreeRpc<N, S, C> {
+    pub fn new(tree: SharedLeanIMT<N, S>) -> Self {
+        TreeRpc {
+            tree,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            codec: PhantomData,
+        }
+    }
+
+    pub fn get_max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    /// Sets the size limit of the request bodies read by `serve`, in bytes.
+    /// Larger requests are answered with status 413.
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.max_body_size = max_body_size;
+    }
+
+    /// Handles a JSON-RPC request or batch, returning the response body, or
+    /// `None` if the request only held notifications.
+    pub fn handle(&self, body: &str) -> Option<String> {
+        let response = match serde_json::from_str::<Value>(body) {
+            Ok(Value::Array(requests)) if !requests.is_empty() => {
+                let responses: Vec<_> = requests
+                    .into_iter()
+                    .filter_map(|request| self.handle_request(request))
+                    .collect();
+                if responses.is_empty() {
+                    return None;
+                }
+                Value::Array(responses)
+            }
+            Ok(Value::Array(_)) => error_response(Value::Null, INVALID_REQUEST, "Empty batch"),
+            Ok(request) => self.handle_request(request)?,
+            Err(_) => error_response(Value::Null, PARSE_ERROR, "Parse error"),
+        };
+        Some(response.to_string())
+    }
+
+    /// Serves the tree over HTTP until the server fails to accept requests.
+    ///
+    /// Failing to answer a client, e.g. because it hung up, does not stop the
+    /// server.
+    pub fn serve(&self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let server = Server::http(address).map_err(io::Error::other)?;
+        let content_type = Header::from_bytes("Content-Type", "application/json")
+            .map_err(|_| io::Error::other("Invalid header"))?;
+
+        for mut request in server.incoming_requests() {
+            let response = self.http_response(&mut request, &content_type);
+            // The error only concerns this client
+            let _ = request.respond(response);
+        }
+        Ok(())
+    }
+
+    fn http_response(&self, request: &mut Request, content_type: &Header) -> ResponseBox {
+        if *request.method() != Method::Post {
+            return Response::empty(405).boxed();
+        }
+        let body = match read_body(request.as_reader(), self.max_body_size) {
+            Ok(body) => body,
+            Err(status) => return Response::empty(status).boxed(),
+        };
+        match self.handle(&body) {
+            Some(response) => Response::from_string(response)
+                .with_header(content_type.clone())
+                .boxed(),
+            None => Response::empty(204).boxed(),
+        }
+    }
+
+    fn handle_request(&self, request: Value) -> Option<Value> {
+        let Value::Object(mut request) = request else {
+            return Some(error_response(
+                Value::Null,
+                INVALID_REQUEST,
+                "Invalid request",
+            ));
+        };
+        let id = request.remove("id");
+        let method = match request.remove("method") {
+            Some(Value::String(method)) if request.get("jsonrpc") == Some(&json!("2.0")) => method,
+            _ => {
+                return Some(error_response(
+                    id.unwrap_or(Value::Null),
+                    INVALID_REQUEST,
+                    "Invalid request",
+                ))
+            }
+        };
+        let params = request.remove("params").unwrap_or(Value::Null);
+
+        let result = self.call(&method, params);
+        // Requests without an id are notifications, which get no response
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_response(id, error.code, error.message),
+        })
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "insert" => {
+                let leaf = node::<N, C>(param(&params, 0, "leaf")?)?;
+                let root = self.tree.write().insert(leaf)?;
+                Ok(json!(C::to_text(&root)))
+            }
+            "insert_many" => {
+                let leaves = param(&params, 0, "leaves")?
+                    .as_array()
+                    .ok_or(RpcError::params("Invalid leaves"))?
+                    .iter()
+                    .map(node::<N, C>)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let root = self.tree.write().insert_many(leaves)?;
+                Ok(json!(C::to_text(&root)))
+            }
+            "root" => Ok(match self.tree.read().root() {
+                Some(root) => json!(C::to_text(&root)),
+                None => Value::Null,
+            }),
+            "size" => Ok(json!(self.tree.read().get_size())),
+            "proof" => {
+                let leaf = node::<N, C>(param(&params, 0, "leaf")?)?;
+                let tree = self.tree.read();
+                let proof = tree.generate_proof(tree.index_of(&leaf)?)?;
+                Ok(encode_proof::<N, C>(&proof))
+            }
+            "verify" => {
+                let proof = decode_proof::<N, C>(param(&params, 0, "proof")?)?;
+                Ok(json!(self.tree.read().verify_proof(&proof)))
+            }
+            _ => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: "Method not found",
+            }),
+        }
+    }
+}
+
+/// Reads a text body of at most `limit` bytes, or returns the status
+/// answering the request: 413 if the body is too large, 400 if it cannot be
+/// read as text.
+fn read_body(reader: impl Read, limit: usize) -> Result<String, u16> {
+    let mut body = String::new();
+    let limit = u64::try_from(limit).unwrap_or(u64::MAX);
+    if reader
+        .take(limit.saturating_add(1))
+        .read_to_string(&mut body)
+        .is_err()
+    {
+        return Err(400);
+    }
+    if body.len() as u64 > limit {
+        return Err(413);
+    }
+    Ok(body)
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+/// Returns a parameter given by position or by name.
+fn param<'a>(params: &'a Value, position: usize, name: &str) -> Result<&'a Value, RpcError> {
+    match params {
+        Value::Array(params) => params.get(position),
+        Value::Object(params) => params.get(name),
+        _ => None,
+    }
+    .ok_or(RpcError::params("Missing parameter"))
+}
+
+fn node<N, C: NodeCodec<N>>(value: &Value) -> Result<N, RpcError> {
+    let text = value.as_str().ok_or(RpcError::params("Invalid node"))?;
+    C::from_text(text).map_err(RpcError::params)
+}
+
+fn encode_proof<N, C: NodeCodec<N>>(proof: &MerkleProof<N>) -> Value {
+    json!(proof.encode::<C>())
+}
+
+fn decode_proof<N, C: NodeCodec<N>>(value: &Value) -> Result<MerkleProof<N>, RpcError> {
+    let proof =
+        MerkleProof::<String>::deserialize(value).map_err(|_| RpcError::params("Invalid proof"))?;
+    MerkleProof::decode::<C>(&proof).map_err(RpcError::params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn call(rpc: &TreeRpc, request: Value) -> Value {
+        serde_json::from_str(&rpc.handle(&request.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_rpc_methods() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let tree = SharedLeanIMT::new(LeanIMT::new(hash), 0);
+        let rpc: TreeRpc = TreeRpc::new(tree.clone());
+
+        let response = call(
+            &rpc,
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "insert", "params": ["leaf1"] }),
+        );
+        assert_eq!(
+            response,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": "leaf1" })
+        );
+
+        let response = call(
+            &rpc,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "insert_many",
+                "params": { "leaves": ["leaf2", "leaf3"] },
+            }),
+        );
+        assert_eq!(response["result"], "leaf1,leaf2,leaf3");
+        // The embedding process sees the same tree
+        assert_eq!(tree.read().get_size(), 3);
+
+        let response = call(
+            &rpc,
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "proof", "params": ["leaf2"] }),
+        );
+        let proof = response["result"].clone();
+        assert_eq!(
+            proof,
+            json!({
+                "root": "leaf1,leaf2,leaf3",
+                "leaf": "leaf2",
+                "index": 1,
+                "siblings": ["leaf1", "leaf3"],
+            })
+        );
+
+        // Batches answer each request in order
+        let response = call(
+            &rpc,
+            json!([
+                { "jsonrpc": "2.0", "id": 4, "method": "verify", "params": [proof] },
+                { "jsonrpc": "2.0", "id": 5, "method": "root" },
+                { "jsonrpc": "2.0", "id": 6, "method": "size" },
+            ]),
+        );
+        assert_eq!(response[0]["result"], true);
+        assert_eq!(response[1]["result"], "leaf1,leaf2,leaf3");
+        assert_eq!(response[2]["result"], 3);
+    }
+
+    #[test]
+    fn test_rpc_errors() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let rpc: TreeRpc = TreeRpc::new(SharedLeanIMT::new(LeanIMT::new(hash), 0));
+
+        let error = |request: Value| call(&rpc, request)["error"]["code"].clone();
+        assert_eq!(
+            error(json!({ "jsonrpc": "2.0", "id": 1, "method": "pop" })),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            error(json!({ "jsonrpc": "2.0", "id": 1, "method": "insert" })),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            error(json!({ "jsonrpc": "2.0", "id": 1, "method": "proof", "params": ["leaf"] })),
+            TREE_ERROR
+        );
+        assert_eq!(error(json!({ "id": 1, "method": "root" })), INVALID_REQUEST);
+        assert_eq!(
+            serde_json::from_str::<Value>(&rpc.handle("{").unwrap()).unwrap()["error"]["code"],
+            PARSE_ERROR
+        );
+
+        // Notifications are applied without a response
+        let notification = json!({ "jsonrpc": "2.0", "method": "insert", "params": ["leaf1"] });
+        assert_eq!(rpc.handle(&notification.to_string()), None);
+        let response = call(&rpc, json!({ "jsonrpc": "2.0", "id": 2, "method": "size" }));
+        assert_eq!(response["result"], 1);
+    }
+
+    #[test]
+    fn test_read_body() {
+        assert_eq!(read_body(&b"{}"[..], 2), Ok("{}".to_string()));
+        // Only one byte past the limit is read
+        assert_eq!(read_body(&b"[1,2]"[..], 2), Err(413));
+        assert_eq!(read_body(&b"\xff"[..], 2), Err(400));
+        assert_eq!(read_body(&b""[..], 0), Ok(String::new()));
+    }
+}
@@ -0,0 +1,201 @@
+//! Tree shared between threads, with root handles pinned to a version.
+//!
+//! A [`RootHandle`] records the root, size and version of the tree when it is
+//! taken. Proofs generated through the handle are always consistent with that
+//! root, even if writers have advanced the tree in the meantime: the state at
+//! the pinned version is rebuilt by undoing the newer changes recorded in the
+//! changelog. Once those changes are no longer retained, the handle is stale.
+
+use std::hash::Hash;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::proof::build_proof;
+use crate::{IMTNode, LeanIMT, LeanIMTStore, MemoryStore, MerkleProof};
+
+/// A tree behind an `Arc<RwLock<_>>` that hands out version-pinned root handles.
+#[derive(Debug)]
+pub struct SharedLeanIMT<N = IMTNode, S = MemoryStore<N>> {
+    tree: Arc<RwLock<LeanIMT<N, S>>>,
+}
+
+impl<N, S> Clone for SharedLeanIMT<N, S> {
+    fn clone(&self) -> Self {
+        SharedLeanIMT {
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> SharedLeanIMT<N, S> {
+    /// Wraps a tree, retaining up to `retention` change records so that root
+    /// handles stay usable until that many leaves have changed after them.
+    pub fn new(mut tree: LeanIMT<N, S>, retention: usize) -> Self {
+        tree.set_changelog_capacity(retention);
+        SharedLeanIMT {
+            tree: Arc::new(RwLock::new(tree)),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, LeanIMT<N, S>> {
+        self.tree
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, LeanIMT<N, S>> {
+        self.tree
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Pins the current root, or returns `None` if the tree is empty.
+    pub fn root_handle(&self) -> Option<RootHandle<N, S>> {
+        let tree = self.read();
+        Some(RootHandle {
+            tree: self.clone(),
+            root: tree.root()?,
+            size: tree.size,
+            version: tree.version,
+        })
+    }
+}
+
+/// The root, size and version of a shared tree at a given point in time.
+#[derive(Debug)]
+pub struct RootHandle<N = IMTNode, S = MemoryStore<N>> {
+    tree: SharedLeanIMT<N, S>,
+    root: N,
+    size: usize,
+    version: u64,
+}
+
+impl<N: Clone, S> Clone for RootHandle<N, S> {
+    fn clone(&self) -> Self {
+        RootHandle {
+            tree: self.tree.clone(),
+            root: self.root.clone(),
+            size: self.size,
+            version: self.version,
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> RootHandle<N, S> {
+    pub fn root(&self) -> &N {
+        &self.root
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns true once the changes needed to rebuild the pinned version are
+    /// no longer retained.
+    pub fn is_stale(&self) -> bool {
+        self.tree.read().changes_since(self.version).is_err()
+    }
+
+    /// Generates the proof of the leaf at `index` against the pinned root.
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        if index >= self.size {
+            return Err("Leaf index out of range");
+        }
+
+        let tree = self.tree.read();
+        tree.ensure_binary()?;
+        if tree.changes_since(self.version).is_err() {
+            return Err("Stale handle");
+        }
+        let leaves = tree.leaves_at(self.version)?;
+
+        build_proof(leaves, index, tree.hash.as_fn())
+    }
+
+    /// Checks that a proof is valid for the pinned root.
+    pub fn verify(&self, proof: &MerkleProof<N>) -> Result<bool, &'static str> {
+        let tree = self.tree.read();
+        if tree.changes_since(self.version).is_err() {
+            return Err("Stale handle");
+        }
+
+        Ok(tree.nodes_eq(&proof.root, &self.root)
+            && proof.verify_with(tree.hash.as_fn(), |a, b| tree.nodes_eq(a, b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use std::thread;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_root_handle_survives_writes() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let shared = SharedLeanIMT::new(LeanIMT::new(hash), 16);
+        assert!(shared.root_handle().is_none());
+
+        shared
+            .write()
+            .insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        let handle = shared.root_handle().unwrap();
+        assert_eq!(handle.root(), "leaf1,leaf2");
+        assert_eq!(handle.size(), 2);
+
+        // Advance the tree from another thread
+        let writer = shared.clone();
+        thread::spawn(move || {
+            let mut tree = writer.write();
+            tree.insert("leaf3".to_string()).unwrap();
+            let proof = tree.generate_proof(0).unwrap();
+            tree.update(&"leaf1".to_string(), "leaf4".to_string(), &proof.siblings)
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+        assert_eq!(shared.read().root().unwrap(), "leaf4,leaf2,leaf3");
+
+        let proof = handle.generate_proof(0).unwrap();
+        assert_eq!(proof.leaf, "leaf1");
+        assert_eq!(proof.root, "leaf1,leaf2");
+        assert_eq!(proof.siblings, vec!["leaf2".to_string()]);
+        assert!(handle.verify(&proof).unwrap());
+        assert!(!shared.read().verify_proof(&proof));
+
+        let current = shared.root_handle().unwrap();
+        assert!(!handle.verify(&current.generate_proof(0).unwrap()).unwrap());
+        assert_eq!(
+            handle.generate_proof(2).unwrap_err(),
+            "Leaf index out of range"
+        );
+    }
+
+    #[test]
+    fn test_stale_root_handle() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let shared = SharedLeanIMT::new(LeanIMT::new(hash), 2);
+
+        shared.write().insert("leaf1".to_string()).unwrap();
+        let handle = shared.root_handle().unwrap();
+        let proof = handle.generate_proof(0).unwrap();
+
+        shared.write().insert("leaf2".to_string()).unwrap();
+        shared.write().insert("leaf3".to_string()).unwrap();
+        assert!(!handle.is_stale());
+        assert!(handle.generate_proof(0).is_ok());
+
+        shared.write().insert("leaf4".to_string()).unwrap();
+        assert!(handle.is_stale());
+        assert_eq!(handle.generate_proof(0).unwrap_err(), "Stale handle");
+        assert_eq!(handle.verify(&proof).unwrap_err(), "Stale handle");
+    }
+}
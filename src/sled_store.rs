@@ -0,0 +1,411 @@
+//! Persistent store backed by a `sled` tree.
+//!
+//! Side nodes, leaves, payloads and metadata share one sled tree, under keys
+//! prefixed by `s` (followed by the big-endian side node key), `l` (followed by
+//! the encoded leaf), `p` (followed by the big-endian leaf index) and `m`. Writes are buffered until the tree operation commits, then
+//! applied as a single atomic batch and flushed to disk, so a crash leaves the
+//! database at the state of the last completed operation.
+
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::mem;
+use std::path::Path;
+
+use crate::codec::{write_varint, ByteReader};
+use crate::forest::ForestBackend;
+use crate::store::{StoreIter, StoreMetadata};
+use crate::{LeanIMTStore, NodeCodec};
+
+const SIDE_NODE_PREFIX: u8 = b's';
+const LEAF_PREFIX: u8 = b'l';
+const PAYLOAD_PREFIX: u8 = b'p';
+const METADATA_KEY: &[u8] = b"m";
+
+const STORAGE_ERROR: &str = "Storage error";
+
+/// Prefix of the names of the sled trees holding the trees of a forest.
+const FOREST_TREE_PREFIX: &str = "lean-imt/";
+
+/// Store keeping the nodes of a tree in `sled`, encoded with the codec `C`.
+#[derive(Debug)]
+pub struct SledStore<N, C> {
+    tree: sled::Tree,
+    /// Writes not committed yet, `None` marking a deletion.
+    pending: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    codec: PhantomData<fn() -> (N, C)>,
+}
+
+impl<N, C: NodeCodec<N>> SledStore<N, C> {
+    /// Uses `tree`, which should not hold anything else than this store.
+    pub fn new(tree: sled::Tree) -> Self {
+        SledStore {
+            tree,
+            pending: BTreeMap::new(),
+            codec: PhantomData,
+        }
+    }
+
+    /// Opens (or creates) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, &'static str> {
+        let db = sled::open(path).map_err(|_| STORAGE_ERROR)?;
+        let tree = db.open_tree("lean-imt").map_err(|_| STORAGE_ERROR)?;
+        Ok(Self::new(tree))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, &'static str> {
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.clone());
+        }
+        let value = self.tree.get(key).map_err(|_| STORAGE_ERROR)?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn put(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        self.pending.insert(key, value);
+    }
+
+    /// Iterates over the `(key without prefix, value)` entries under `prefix`,
+    /// including the pending writes.
+    fn scan(
+        &self,
+        prefix: u8,
+    ) -> impl Iterator<Item = Result<(Vec<u8>, Vec<u8>), &'static str>> + '_ {
+        let stored = self
+            .tree
+            .scan_prefix([prefix])
+            .filter_map(move |entry| match entry {
+                Ok((key, _)) if self.pending.contains_key(key.as_ref()) => None,
+                Ok((key, value)) => Some(Ok((key[1..].to_vec(), value.to_vec()))),
+                Err(_) => Some(Err(STORAGE_ERROR)),
+            });
+        let pending = self
+            .pending
+            .range(vec![prefix]..)
+            .take_while(move |(key, _)| key.first() == Some(&prefix))
+            .filter_map(|(key, value)| Some(Ok((key[1..].to_vec(), value.clone()?))));
+        stored.chain(pending)
+    }
+}
+
+fn side_node_key(level: usize) -> Vec<u8> {
+    let mut key = vec![SIDE_NODE_PREFIX];
+    key.extend_from_slice(&(level as u64).to_be_bytes());
+    key
+}
+
+fn payload_key(index: usize) -> Vec<u8> {
+    let mut key = vec![PAYLOAD_PREFIX];
+    key.extend_from_slice(&(index as u64).to_be_bytes());
+    key
+}
+
+fn leaf_key<N, C: NodeCodec<N>>(leaf: &N) -> Vec<u8> {
+    let mut key = vec![LEAF_PREFIX];
+    key.extend_from_slice(&C::encode(leaf));
+    key
+}
+
+/// Decodes the big-endian index following the prefix of a side node or
+/// payload key.
+fn decode_key(key: &[u8]) -> Result<usize, &'static str> {
+    let bytes = key.try_into().map_err(|_| "Invalid store key")?;
+    usize::try_from(u64::from_be_bytes(bytes)).map_err(|_| "Value out of range")
+}
+
+/// Encodes leaf indices, written 1-based as by earlier versions of the store.
+fn encode_indices(indices: &[usize]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for &index in indices {
+        write_varint(&mut bytes, index as u64 + 1);
+    }
+    bytes
+}
+
+fn decode_indices(bytes: &[u8]) -> Result<Vec<usize>, &'static str> {
+    let mut reader = ByteReader::new(bytes);
+    let mut indices = Vec::new();
+    while !reader.is_empty() {
+        let index = reader.read_usize()?;
+        indices.push(index.checked_sub(1).ok_or("Invalid leaf index")?);
+    }
+    Ok(indices)
+}
+
+impl<N, C: NodeCodec<N>> LeanIMTStore<N> for SledStore<N, C> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        self.get(&side_node_key(level))?
+            .map(|bytes| C::decode(&bytes))
+            .transpose()
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        self.put(side_node_key(level), Some(C::encode(&node)));
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        self.put(side_node_key(level), None);
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        Box::new(self.scan(SIDE_NODE_PREFIX).map(|entry| {
+            let (key, value) = entry?;
+            Ok((decode_key(&key)?, C::decode(&value)?))
+        }))
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        self.get(&leaf_key::<N, C>(leaf))?
+            .map(|bytes| decode_indices(&bytes))
+            .transpose()
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.put(leaf_key::<N, C>(&leaf), Some(encode_indices(&indices)));
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.put(leaf_key::<N, C>(leaf), None);
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        Box::new(self.scan(LEAF_PREFIX).map(|entry| {
+            let (key, value) = entry?;
+            Ok((C::decode(&key)?, decode_indices(&value)?))
+        }))
+    }
+
+    fn payload(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        self.get(&payload_key(index))
+    }
+
+    fn put_payload(&mut self, index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        self.put(payload_key(index), payload);
+        Ok(())
+    }
+
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        Box::new(self.scan(PAYLOAD_PREFIX).map(|entry| {
+            let (key, value) = entry?;
+            Ok((decode_key(&key)?, value))
+        }))
+    }
+
+    fn metadata(&self) -> Result<Option<StoreMetadata<N>>, &'static str> {
+        self.get(METADATA_KEY)?
+            .map(|bytes| StoreMetadata::from_bytes::<C>(&bytes))
+            .transpose()
+    }
+
+    fn put_metadata(&mut self, metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        self.put(METADATA_KEY.to_vec(), Some(metadata.to_bytes::<C>()));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), &'static str> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut batch = sled::Batch::default();
+        for (key, value) in mem::take(&mut self.pending) {
+            match value {
+                Some(value) => batch.insert(key, value),
+                None => batch.remove(key),
+            }
+        }
+        self.tree.apply_batch(batch).map_err(|_| STORAGE_ERROR)?;
+        self.tree.flush().map_err(|_| STORAGE_ERROR)?;
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        self.pending.clear();
+    }
+}
+
+/// Forest backend keeping each tree of a forest in its own tree of a sled
+/// database, named `lean-imt/` followed by the id of the tree.
+#[derive(Debug, Clone)]
+pub struct SledForestBackend<C> {
+    db: sled::Db,
+    codec: PhantomData<fn() -> C>,
+}
+
+impl<C> SledForestBackend<C> {
+    pub fn new(db: sled::Db) -> Self {
+        SledForestBackend {
+            db,
+            codec: PhantomData,
+        }
+    }
+}
+
+impl<N, C: NodeCodec<N>> ForestBackend<String, N> for SledForestBackend<C> {
+    type Store = SledStore<N, C>;
+
+    fn store(&mut self, id: &String) -> Result<SledStore<N, C>, &'static str> {
+        let tree = self
+            .db
+            .open_tree(format!("{}{}", FOREST_TREE_PREFIX, id))
+            .map_err(|_| STORAGE_ERROR)?;
+        Ok(SledStore::new(tree))
+    }
+
+    fn delete(&mut self, id: &String) -> Result<(), &'static str> {
+        self.db
+            .drop_tree(format!("{}{}", FOREST_TREE_PREFIX, id))
+            .map_err(|_| STORAGE_ERROR)?;
+        Ok(())
+    }
+
+    fn ids(&self) -> Result<Vec<String>, &'static str> {
+        self.db
+            .tree_names()
+            .iter()
+            .filter_map(|name| name.strip_prefix(FOREST_TREE_PREFIX.as_bytes()))
+            .map(|id| String::from_utf8(id.to_vec()).map_err(|_| "Invalid tree id"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::forest::Forest;
+    use crate::{DuplicatePolicy, IMTHashFunction, LeanIMT};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn temporary_db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn open_store(db: &sled::Db) -> SledStore<String, StringCodec> {
+        SledStore::new(db.open_tree("tree").unwrap())
+    }
+
+    #[test]
+    fn test_reopen_sled_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let db = temporary_db();
+        let mut reference = LeanIMT::new(hash);
+
+        {
+            let store = open_store(&db);
+            let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+            imt.set_duplicate_policy(DuplicatePolicy::Allow).unwrap();
+            imt.set_max_size(Some(100)).unwrap();
+            reference
+                .set_duplicate_policy(DuplicatePolicy::Allow)
+                .unwrap();
+            reference.set_max_size(Some(100)).unwrap();
+
+            let leaves: Vec<_> = (1..=5).map(|i| format!("leaf{}", i)).collect();
+            imt.insert_many(leaves.clone()).unwrap();
+            reference.insert_many(leaves).unwrap();
+            imt.insert("leaf1".to_string()).unwrap();
+            reference.insert("leaf1".to_string()).unwrap();
+            imt.insert_with_value("leaf6".to_string(), b"six".to_vec())
+                .unwrap();
+            reference
+                .insert_with_value("leaf6".to_string(), b"six".to_vec())
+                .unwrap();
+
+            let proof = imt.generate_proof(1).unwrap();
+            imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+            reference
+                .remove(&"leaf2".to_string(), &proof.siblings)
+                .unwrap();
+        }
+
+        let store = open_store(&db);
+        assert_eq!(
+            LeanIMT::with_store(hash, "0".to_string(), store).unwrap_err(),
+            "Store is not empty"
+        );
+
+        let store = open_store(&db);
+        let mut imt = LeanIMT::open(hash, store).unwrap();
+        assert_eq!(imt.root(), reference.root());
+        assert_eq!(imt.get_version(), reference.get_version());
+        assert_eq!(imt.get_duplicate_policy(), DuplicatePolicy::Allow);
+        assert_eq!(imt.get_max_size(), Some(100));
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![0, 5]);
+        assert_eq!(imt.value_at(6), Ok(Some(b"six".to_vec())));
+        assert_eq!(imt.snapshot(), reference.snapshot());
+
+        imt.insert("leaf7".to_string()).unwrap();
+        reference.insert("leaf7".to_string()).unwrap();
+        assert_eq!(imt.root(), reference.root());
+    }
+
+    #[test]
+    fn test_failed_operation_is_not_persisted() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let db = temporary_db();
+
+        let root = {
+            let store = open_store(&db);
+            let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+            imt.insert_many(vec![
+                "leaf1".to_string(),
+                "leaf2".to_string(),
+                "leaf3".to_string(),
+            ])
+            .unwrap();
+
+            // The side nodes written before the siblings are found wrong are discarded
+            let sibling_nodes = vec!["wrong".to_string(), "leaf1,leaf2".to_string()];
+            let result = imt.update(&"leaf3".to_string(), "leaf4".to_string(), &sibling_nodes);
+            assert_eq!(result.unwrap_err(), "Wrong sibling nodes");
+            assert_eq!(imt.get_side_nodes().get(&0), Some(&"leaf3".to_string()));
+            imt.root()
+        };
+
+        let store = open_store(&db);
+        let imt = LeanIMT::open(hash, store).unwrap();
+        assert_eq!(imt.root(), root);
+        assert_eq!(imt.get_size(), 3);
+    }
+
+    #[test]
+    fn test_reopen_sled_forest() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let db = temporary_db();
+        let backend = SledForestBackend::<StringCodec>::new(db.clone());
+
+        let mut forest = Forest::with_backend(hash, "0".to_string(), backend.clone());
+        for group in ["a", "b", "c"] {
+            forest
+                .create(group.to_string())
+                .unwrap()
+                .insert(format!("{}1", group))
+                .unwrap();
+        }
+        forest.delete(&"b".to_string()).unwrap();
+        // The store of the default tree is not part of the forest
+        open_store(&db);
+        drop(forest);
+
+        let forest = Forest::<String, String, _>::open(hash, "0".to_string(), backend).unwrap();
+        assert_eq!(forest.ids().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(forest.root(&"c".to_string()), Ok(Some("c1".to_string())));
+    }
+
+    #[test]
+    fn test_open_empty_store() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let store = open_store(&temporary_db());
+        assert_eq!(
+            LeanIMT::open(hash, store).unwrap_err(),
+            "Missing tree metadata"
+        );
+    }
+}
@@ -0,0 +1,702 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{write_bytes, write_max_size, write_varint, ByteReader};
+use crate::{
+    depth_of, DuplicatePolicy, HashMap, HashSet, IMTHashFunction, IMTNode, LeanIMT, LeanIMTStore,
+    MemoryStore, NodeCodec, RemovalPolicy, TreeHash,
+};
+
+/// Version of the binary format written by `to_bytes`, stored in its first byte.
+///
+/// Version 1 snapshots, written before trees had an arity, still decode as
+/// binary trees, version 2 snapshots, written before trees had a maximum
+/// size, as unlimited trees, version 3 snapshots, written before leaves had
+/// payloads, without payloads, and version 4 snapshots, written before
+/// tombstones were saved, as trees forgetting their removed leaves.
+pub const SNAPSHOT_FORMAT_VERSION: u8 = 5;
+
+/// Serializable copy of the state of a tree.
+///
+/// Side nodes, leaves and payloads are stored as `(key, node)`,
+/// `(leaf, index)` and `(index, payload)` pairs sorted by key and index, so
+/// equal trees produce equal snapshots. Side node keys are the levels of
+/// binary trees, and leaf indices are 0-based. Tombstones are stored as
+/// `(leaf, indices)` pairs sorted by indices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+)]
+pub struct LeanIMTSnapshot<N = IMTNode> {
+    pub zero: N,
+    pub duplicate_policy: DuplicatePolicy,
+    #[cfg_attr(feature = "serde", serde(default = "binary_arity"))]
+    pub arity: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_size: Option<usize>,
+    pub size: usize,
+    pub depth: usize,
+    pub side_nodes: Vec<(usize, N)>,
+    pub leaves: Vec<(N, usize)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub payloads: Vec<(usize, Vec<u8>)>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub removal_policy: RemovalPolicy,
+    #[cfg_attr(feature = "serde", serde(default = "Vec::new"))]
+    pub tombstones: Vec<(N, Vec<usize>)>,
+}
+
+#[cfg(feature = "serde")]
+fn binary_arity() -> usize {
+    2
+}
+
+impl<N> LeanIMTSnapshot<N> {
+    /// Encodes the snapshot in the compact binary format.
+    ///
+    /// The format version byte is followed by the zero value, the duplicate
+    /// policy byte, the arity, the maximum size plus one (zero for unlimited
+    /// trees and limits of `usize::MAX`), the size, the depth, the
+    /// `(key, node)` side nodes, the `(index, leaf)` leaves, the
+    /// `(index, payload)` payloads, the removal policy byte and the
+    /// `(leaf, indices)` tombstones, each list prefixed by its length.
+    /// Integers are LEB128 varints, leaf and payload indices are stored as
+    /// the difference with the previous index, and nodes and payloads are
+    /// length-prefixed bytes, nodes from `C`. Fails if the leaves or the
+    /// payloads are not sorted by index.
+    pub fn to_bytes<C: NodeCodec<N>>(&self) -> Result<Vec<u8>, &'static str> {
+        let mut bytes = vec![SNAPSHOT_FORMAT_VERSION];
+        write_bytes(&mut bytes, &C::encode(&self.zero));
+        bytes.push(match self.duplicate_policy {
+            DuplicatePolicy::Reject => 0,
+            DuplicatePolicy::Allow => 1,
+        });
+        write_varint(&mut bytes, self.arity as u64);
+        write_max_size(&mut bytes, self.max_size);
+        write_varint(&mut bytes, self.size as u64);
+        write_varint(&mut bytes, self.depth as u64);
+
+        write_varint(&mut bytes, self.side_nodes.len() as u64);
+        for (level, node) in &self.side_nodes {
+            write_varint(&mut bytes, *level as u64);
+            write_bytes(&mut bytes, &C::encode(node));
+        }
+
+        write_varint(&mut bytes, self.leaves.len() as u64);
+        let mut previous = 0;
+        for (leaf, index) in &self.leaves {
+            let delta = index
+                .checked_sub(previous)
+                .ok_or("Unsorted snapshot leaves")?;
+            write_varint(&mut bytes, delta as u64);
+            write_bytes(&mut bytes, &C::encode(leaf));
+            previous = *index;
+        }
+
+        write_varint(&mut bytes, self.payloads.len() as u64);
+        let mut previous = 0;
+        for (index, payload) in &self.payloads {
+            let delta = index
+                .checked_sub(previous)
+                .ok_or("Unsorted snapshot payloads")?;
+            write_varint(&mut bytes, delta as u64);
+            write_bytes(&mut bytes, payload);
+            previous = *index;
+        }
+
+        bytes.push(match self.removal_policy {
+            RemovalPolicy::Forget => 0,
+            RemovalPolicy::Tombstone => 1,
+            RemovalPolicy::RejectReinsertion => 2,
+        });
+        write_varint(&mut bytes, self.tombstones.len() as u64);
+        for (leaf, indices) in &self.tombstones {
+            write_bytes(&mut bytes, &C::encode(leaf));
+            write_varint(&mut bytes, indices.len() as u64);
+            for index in indices {
+                write_varint(&mut bytes, *index as u64);
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /// Decodes a snapshot written by `to_bytes`.
+    pub fn from_bytes<C: NodeCodec<N>>(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut reader = ByteReader::new(bytes);
+        let format_version = reader.read_byte()?;
+        if format_version == 0 || format_version > SNAPSHOT_FORMAT_VERSION {
+            return Err("Unsupported snapshot version");
+        }
+
+        let zero = C::decode(reader.read_bytes()?)?;
+        let duplicate_policy = match reader.read_byte()? {
+            0 => DuplicatePolicy::Reject,
+            1 => DuplicatePolicy::Allow,
+            _ => return Err("Invalid duplicate policy"),
+        };
+        let arity = match format_version {
+            1 => 2,
+            _ => reader.read_usize()?,
+        };
+        let max_size = match format_version {
+            1 | 2 => None,
+            _ => reader.read_usize()?.checked_sub(1),
+        };
+        let size = reader.read_usize()?;
+        let depth = reader.read_usize()?;
+
+        let count = reader.read_usize()?;
+        let mut side_nodes = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let level = reader.read_usize()?;
+            side_nodes.push((level, C::decode(reader.read_bytes()?)?));
+        }
+
+        let count = reader.read_usize()?;
+        let mut leaves = Vec::with_capacity(count.min(bytes.len()));
+        let mut index = 0usize;
+        for _ in 0..count {
+            index = index
+                .checked_add(reader.read_usize()?)
+                .ok_or("Value out of range")?;
+            leaves.push((C::decode(reader.read_bytes()?)?, index));
+        }
+
+        let count = match format_version {
+            1..=3 => 0,
+            _ => reader.read_usize()?,
+        };
+        let mut payloads = Vec::with_capacity(count.min(bytes.len()));
+        let mut index = 0usize;
+        for _ in 0..count {
+            index = index
+                .checked_add(reader.read_usize()?)
+                .ok_or("Value out of range")?;
+            payloads.push((index, reader.read_bytes()?.to_vec()));
+        }
+
+        let removal_policy = match format_version {
+            1..=4 => RemovalPolicy::Forget,
+            _ => match reader.read_byte()? {
+                0 => RemovalPolicy::Forget,
+                1 => RemovalPolicy::Tombstone,
+                2 => RemovalPolicy::RejectReinsertion,
+                _ => return Err("Invalid removal policy"),
+            },
+        };
+        let count = match format_version {
+            1..=4 => 0,
+            _ => reader.read_usize()?,
+        };
+        let mut tombstones = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            let leaf = C::decode(reader.read_bytes()?)?;
+            let count = reader.read_usize()?;
+            let mut indices = Vec::with_capacity(count.min(bytes.len()));
+            for _ in 0..count {
+                indices.push(reader.read_usize()?);
+            }
+            tombstones.push((leaf, indices));
+        }
+
+        if !reader.is_empty() {
+            return Err("Trailing bytes");
+        }
+
+        Ok(LeanIMTSnapshot {
+            zero,
+            duplicate_policy,
+            arity,
+            max_size,
+            size,
+            depth,
+            side_nodes,
+            leaves,
+            payloads,
+            removal_policy,
+            tombstones,
+        })
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMT<N> {
+    /// Restores a tree from the binary snapshot format.
+    pub fn from_bytes<C: NodeCodec<N>>(
+        bytes: &[u8],
+        hash: IMTHashFunction<N>,
+    ) -> Result<Self, &'static str> {
+        Self::from_snapshot(LeanIMTSnapshot::from_bytes::<C>(bytes)?, hash)
+    }
+
+    /// Restores a tree from a snapshot.
+    ///
+    /// The snapshot is checked for structural consistency, but the side nodes
+    /// are trusted: they are not recomputed from the leaves.
+    pub fn from_snapshot(
+        snapshot: LeanIMTSnapshot<N>,
+        hash: IMTHashFunction<N>,
+    ) -> Result<Self, &'static str> {
+        Self::from_snapshot_with_store(snapshot, hash, MemoryStore::default())
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Encodes the state of the tree in the compact binary snapshot format.
+    pub fn to_bytes<C: NodeCodec<N>>(&self) -> Result<Vec<u8>, &'static str> {
+        self.snapshot()?.to_bytes::<C>()
+    }
+
+    /// Returns a snapshot of the current state of the tree.
+    pub fn snapshot(&self) -> Result<LeanIMTSnapshot<N>, &'static str> {
+        let mut side_nodes = self.store.side_nodes().collect::<Result<Vec<_>, _>>()?;
+        side_nodes.sort_unstable_by_key(|(level, _)| *level);
+
+        let mut leaves = Vec::new();
+        for entry in self.store.leaves() {
+            let (leaf, indices) = entry?;
+            for index in indices {
+                leaves.push((leaf.clone(), index));
+            }
+        }
+        leaves.sort_unstable_by_key(|(_, index)| *index);
+
+        let mut payloads = self.store.payloads().collect::<Result<Vec<_>, _>>()?;
+        payloads.sort_unstable_by_key(|(index, _)| *index);
+
+        let mut tombstones = self.store.tombstones().collect::<Result<Vec<_>, _>>()?;
+        tombstones.sort_unstable_by(|(_, a), (_, b)| a.cmp(b));
+
+        Ok(LeanIMTSnapshot {
+            zero: self.zero.clone(),
+            duplicate_policy: self.duplicate_policy,
+            arity: self.arity,
+            max_size: self.max_size,
+            size: self.size,
+            depth: self.depth,
+            side_nodes,
+            leaves,
+            payloads,
+            removal_policy: self.removal_policy,
+            tombstones,
+        })
+    }
+
+    /// Restores a tree from a snapshot into `store`, which must be empty.
+    pub fn from_snapshot_with_store(
+        snapshot: LeanIMTSnapshot<N>,
+        hash: IMTHashFunction<N>,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        Self::restore(snapshot, TreeHash::Fn(hash), store)
+    }
+
+    /// Restores a tree from a snapshot like `from_snapshot_with_store`, with
+    /// any hash function.
+    pub(crate) fn restore(
+        snapshot: LeanIMTSnapshot<N>,
+        hash: TreeHash<N>,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        if snapshot.arity < 2 {
+            return Err("Invalid arity");
+        }
+        if snapshot.depth != depth_of(snapshot.size, snapshot.arity) {
+            return Err("Invalid tree depth");
+        }
+        if snapshot
+            .max_size
+            .is_some_and(|max_size| snapshot.size > max_size)
+        {
+            return Err("Tree exceeds max size");
+        }
+        let root_key = snapshot
+            .depth
+            .checked_mul(snapshot.arity - 1)
+            .ok_or("Invalid arity")?;
+        if snapshot.size > 0 && !snapshot.side_nodes.iter().any(|(key, _)| *key == root_key) {
+            return Err("Missing root");
+        }
+
+        let mut side_nodes: HashMap<_, _> = HashMap::default();
+        for (key, node) in snapshot.side_nodes {
+            if key > root_key || side_nodes.insert(key, node).is_some() {
+                return Err("Invalid side node");
+            }
+        }
+
+        let mut leaves: HashMap<N, Vec<usize>> = HashMap::default();
+        // The indices seen are kept in a set, as the size is not trusted
+        let mut seen: HashSet<_> =
+            HashSet::with_capacity_and_hasher(snapshot.leaves.len(), Default::default());
+        for (leaf, index) in snapshot.leaves {
+            if index >= snapshot.size || !seen.insert(index) || leaf == snapshot.zero {
+                return Err("Invalid leaf");
+            }
+            let indices = leaves.entry(leaf).or_default();
+            if !indices.is_empty() && snapshot.duplicate_policy == DuplicatePolicy::Reject {
+                return Err("Leaf already exists");
+            }
+            indices.push(index);
+        }
+
+        // Payloads belong to the leaves, and removed leaves have none
+        let mut previous = None;
+        for (index, _) in &snapshot.payloads {
+            if !seen.contains(index) || previous >= Some(*index) {
+                return Err("Invalid payload");
+            }
+            previous = Some(*index);
+        }
+
+        // Tombstones hold the indices their leaf was removed from
+        let mut removed: HashSet<&N> = HashSet::default();
+        if snapshot.tombstones.iter().any(|(leaf, indices)| {
+            indices.is_empty()
+                || indices.iter().any(|&index| index >= snapshot.size)
+                || *leaf == snapshot.zero
+                || !removed.insert(leaf)
+        }) {
+            return Err("Invalid tombstone");
+        }
+        drop(removed);
+
+        let mut imt = LeanIMT::create(hash, snapshot.zero, store)?;
+        for (key, node) in side_nodes {
+            imt.store.put_side_node(key, node)?;
+        }
+        for (leaf, mut indices) in leaves {
+            indices.sort_unstable();
+            for &index in &indices {
+                imt.store.put_leaf_at(index, Some(leaf.clone()))?;
+            }
+            imt.store.put_leaf_indices(leaf, indices)?;
+        }
+        for (index, payload) in snapshot.payloads {
+            imt.store.put_payload(index, Some(payload))?;
+        }
+        for (leaf, indices) in snapshot.tombstones {
+            imt.store.put_tombstone(leaf, indices)?;
+        }
+        imt.removal_policy = snapshot.removal_policy;
+        imt.size = snapshot.size;
+        imt.depth = snapshot.depth;
+        imt.arity = snapshot.arity;
+        imt.duplicate_policy = snapshot.duplicate_policy;
+        imt.max_size = snapshot.max_size;
+        imt.persist()?;
+
+        Ok(imt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+        let sibling_nodes = vec!["leaf1".to_string(), "leaf3".to_string()];
+        imt.remove(&"leaf2".to_string(), &sibling_nodes).unwrap();
+
+        let snapshot = imt.snapshot().unwrap();
+        assert_eq!(snapshot.size, 3);
+        assert_eq!(
+            snapshot.leaves,
+            vec![("leaf1".to_string(), 0), ("leaf3".to_string(), 2)]
+        );
+
+        let mut restored = LeanIMT::from_snapshot(snapshot.clone(), hash).unwrap();
+        assert_eq!(restored.root(), imt.root());
+        assert_eq!(restored.snapshot().unwrap(), snapshot);
+
+        // The restored tree keeps working like the original one
+        imt.insert("leaf4".to_string()).unwrap();
+        restored.insert("leaf4".to_string()).unwrap();
+        assert_eq!(restored.root(), imt.root());
+    }
+
+    #[test]
+    fn test_snapshot_with_duplicate_leaves() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_duplicate_policy(DuplicatePolicy::Allow).unwrap();
+        imt.insert_many(vec!["leaf1".to_string(), "leaf1".to_string()])
+            .unwrap();
+
+        let mut snapshot = imt.snapshot().unwrap();
+        assert_eq!(
+            snapshot.leaves,
+            vec![("leaf1".to_string(), 0), ("leaf1".to_string(), 1)]
+        );
+        let restored = LeanIMT::from_snapshot(snapshot.clone(), hash).unwrap();
+        assert_eq!(restored.indices_of(&"leaf1".to_string()), vec![0, 1]);
+
+        snapshot.duplicate_policy = DuplicatePolicy::Reject;
+        assert_eq!(
+            LeanIMT::from_snapshot(snapshot, hash).unwrap_err(),
+            "Leaf already exists"
+        );
+    }
+
+    #[test]
+    fn test_corrupted_snapshot_is_an_error() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.size = usize::MAX;
+        snapshot.depth = depth_of(usize::MAX, 2);
+        snapshot.side_nodes = vec![(snapshot.depth, "root".to_string())];
+        snapshot.max_size = None;
+        let mut restored = LeanIMT::from_snapshot(snapshot, hash).unwrap();
+        assert_eq!(
+            restored.insert("leaf3".to_string()).unwrap_err(),
+            "Tree is full"
+        );
+        assert_eq!(restored.truncate(1).unwrap_err(), "Tree is too large");
+
+        imt.insert("leaf3".to_string()).unwrap();
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.side_nodes.retain(|(key, _)| *key != 0);
+        let mut restored = LeanIMT::from_snapshot(snapshot, hash).unwrap();
+        assert_eq!(
+            restored.insert("leaf4".to_string()).unwrap_err(),
+            "No side node at this level"
+        );
+        assert_eq!(
+            restored.insert_many(vec!["leaf4".to_string()]).unwrap_err(),
+            "No side node at this level"
+        );
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn test_borsh_roundtrip() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+
+        let bytes = borsh::to_vec(&imt.snapshot().unwrap()).unwrap();
+        let snapshot: LeanIMTSnapshot = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(snapshot, imt.snapshot().unwrap());
+
+        let proof = imt.generate_proof(1).unwrap();
+        let bytes = borsh::to_vec(&proof).unwrap();
+        let decoded: crate::MerkleProof = borsh::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, proof);
+    }
+
+    #[test]
+    fn test_binary_snapshot_roundtrip() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((1..=5).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+
+        let bytes = imt.to_bytes::<StringCodec>().unwrap();
+        assert_eq!(bytes[0], SNAPSHOT_FORMAT_VERSION);
+        assert_eq!(
+            bytes,
+            imt.snapshot().unwrap().to_bytes::<StringCodec>().unwrap()
+        );
+
+        let snapshot = LeanIMTSnapshot::from_bytes::<StringCodec>(&bytes).unwrap();
+        assert_eq!(snapshot, imt.snapshot().unwrap());
+
+        let mut restored = LeanIMT::from_bytes::<StringCodec>(&bytes, hash).unwrap();
+        assert_eq!(restored.root(), imt.root());
+        restored.insert("leaf6".to_string()).unwrap();
+        imt.insert("leaf6".to_string()).unwrap();
+        assert_eq!(restored.root(), imt.root());
+    }
+
+    #[test]
+    fn test_snapshot_keeps_arity() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_arity(hash, 3).unwrap();
+        imt.set_max_depth(2).unwrap();
+        imt.insert_many((1..=7).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+
+        let bytes = imt.to_bytes::<StringCodec>().unwrap();
+        let mut restored = LeanIMT::from_bytes::<StringCodec>(&bytes, hash).unwrap();
+        assert_eq!(restored.get_arity(), 3);
+        assert_eq!(restored.get_max_size(), Some(9));
+        restored.insert("leaf8".to_string()).unwrap();
+        imt.insert("leaf8".to_string()).unwrap();
+        assert_eq!(restored.root(), imt.root());
+
+        // Version 4 snapshots have no removal policy and tombstones, version
+        // 3 snapshots no payloads either, version 2 snapshots no maximum size
+        // and version 1 snapshots no arity and decode as binary trees
+        let mut binary = LeanIMT::new(hash);
+        binary.insert("leaf1".to_string()).unwrap();
+        let mut bytes = binary.to_bytes::<StringCodec>().unwrap();
+        bytes[0] = 4;
+        bytes.truncate(bytes.len() - 2);
+        let snapshot = LeanIMTSnapshot::from_bytes::<StringCodec>(&bytes).unwrap();
+        assert_eq!(snapshot, binary.snapshot().unwrap());
+        bytes[0] = 3;
+        bytes.pop();
+        let snapshot = LeanIMTSnapshot::from_bytes::<StringCodec>(&bytes).unwrap();
+        assert_eq!(snapshot, binary.snapshot().unwrap());
+        bytes[0] = 2;
+        bytes.remove(5);
+        let snapshot = LeanIMTSnapshot::from_bytes::<StringCodec>(&bytes).unwrap();
+        assert_eq!(snapshot, binary.snapshot().unwrap());
+        bytes[0] = 1;
+        bytes.remove(4);
+        let snapshot = LeanIMTSnapshot::from_bytes::<StringCodec>(&bytes).unwrap();
+        assert_eq!(snapshot, binary.snapshot().unwrap());
+
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.max_size = Some(7);
+        assert_eq!(
+            LeanIMT::from_snapshot(snapshot, hash).unwrap_err(),
+            "Tree exceeds max size"
+        );
+
+        // A limit of usize::MAX leaves is saved as no limit
+        imt.set_max_size(Some(usize::MAX)).unwrap();
+        let bytes = imt.to_bytes::<StringCodec>().unwrap();
+        let restored = LeanIMT::from_bytes::<StringCodec>(&bytes, hash).unwrap();
+        assert_eq!(restored.get_max_size(), None);
+
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.arity = 1;
+        assert_eq!(
+            LeanIMT::from_snapshot(snapshot, hash).unwrap_err(),
+            "Invalid arity"
+        );
+    }
+
+    #[test]
+    fn test_snapshot_keeps_tombstones() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.set_removal_policy(RemovalPolicy::RejectReinsertion);
+        imt.insert_many((1..=3).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+
+        let snapshot = imt.snapshot().unwrap();
+        assert_eq!(snapshot.removal_policy, RemovalPolicy::RejectReinsertion);
+        assert_eq!(snapshot.tombstones, vec![("leaf2".to_string(), vec![1])]);
+
+        let bytes = imt.to_bytes::<StringCodec>().unwrap();
+        let mut restored = LeanIMT::from_bytes::<StringCodec>(&bytes, hash).unwrap();
+        assert_eq!(restored.removed_indices_of(&"leaf2".to_string()), vec![1]);
+        assert_eq!(
+            restored.insert("leaf2".to_string()).unwrap_err(),
+            "Leaf was removed"
+        );
+
+        let mut invalid = snapshot.clone();
+        invalid.tombstones = vec![("leaf2".to_string(), vec![3])];
+        assert_eq!(
+            LeanIMT::from_snapshot(invalid, hash).unwrap_err(),
+            "Invalid tombstone"
+        );
+    }
+
+    #[test]
+    fn test_invalid_binary_snapshot() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert("leaf1".to_string()).unwrap();
+        let bytes = imt.to_bytes::<StringCodec>().unwrap();
+
+        let mut unsupported = bytes.clone();
+        unsupported[0] = SNAPSHOT_FORMAT_VERSION + 1;
+        assert_eq!(
+            LeanIMTSnapshot::from_bytes::<StringCodec>(&unsupported).unwrap_err(),
+            "Unsupported snapshot version"
+        );
+
+        assert_eq!(
+            LeanIMTSnapshot::from_bytes::<StringCodec>(&bytes[..bytes.len() - 1]).unwrap_err(),
+            "Unexpected end of input"
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            LeanIMTSnapshot::from_bytes::<StringCodec>(&trailing).unwrap_err(),
+            "Trailing bytes"
+        );
+
+        let mut unsorted = imt.snapshot().unwrap();
+        unsorted.leaves.insert(0, ("leaf2".to_string(), 1));
+        assert_eq!(
+            unsorted.to_bytes::<StringCodec>().unwrap_err(),
+            "Unsorted snapshot leaves"
+        );
+        let mut unsorted = imt.snapshot().unwrap();
+        unsorted.payloads = vec![(1, vec![1]), (0, vec![0])];
+        assert_eq!(
+            unsorted.to_bytes::<StringCodec>().unwrap_err(),
+            "Unsorted snapshot payloads"
+        );
+    }
+
+    #[test]
+    fn test_from_invalid_snapshot() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.insert("leaf2".to_string()).unwrap();
+
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.depth = 3;
+        assert_eq!(
+            LeanIMT::from_snapshot(snapshot, hash).unwrap_err(),
+            "Invalid tree depth"
+        );
+
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.leaves[1].1 = 0;
+        assert_eq!(
+            LeanIMT::from_snapshot(snapshot, hash).unwrap_err(),
+            "Invalid leaf"
+        );
+
+        let mut snapshot = imt.snapshot().unwrap();
+        snapshot.side_nodes.retain(|(level, _)| *level != 1);
+        assert_eq!(
+            LeanIMT::from_snapshot(snapshot, hash).unwrap_err(),
+            "Missing root"
+        );
+    }
+}
@@ -0,0 +1,328 @@
+//! Sparse Merkle trees, committing to values at fixed keys.
+//!
+//! A [`SparseMerkleTree`] of depth `depth` has `2^depth` leaves, all zero
+//! until set, and each leaf sits at the position given by its key, so the
+//! root commits to a key-value map such as account state. An empty subtree
+//! at level `l` hashes to `zeros[l]`, as in padded fixed-depth trees, and
+//! only the nodes that differ from these zeros are stored.
+//!
+//! The nodes are kept in any [`LeanIMTStore`] through its side nodes, keyed
+//! by their index in a heap layout: the root is 1 and the children of `k`
+//! are `2k` and `2k + 1`. These keys are sparse, so [`SparseStore`] keeps
+//! them in a hash map rather than the vector of `MemoryStore`.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::store::StoreIter;
+use crate::{HashMap, IMTHashFunction, IMTNode, LeanIMTStore, MerkleProof};
+
+/// Sparse Merkle tree of fixed depth, keyed by leaf position.
+#[derive(Debug)]
+pub struct SparseMerkleTree<N = IMTNode, S = SparseStore<N>> {
+    hash: IMTHashFunction<N>,
+    store: S,
+    /// Roots of the empty subtrees of each level, up to `depth`.
+    zeros: Vec<N>,
+}
+
+impl SparseMerkleTree {
+    /// Creates a tree of string nodes of depth `depth`, using `"0"` as the
+    /// zero value.
+    pub fn new(hash: IMTHashFunction, depth: usize) -> Result<Self, &'static str> {
+        Self::with_zero(hash, "0".into(), depth)
+    }
+}
+
+impl<N: Clone + Eq + Hash> SparseMerkleTree<N> {
+    /// Creates a tree of depth `depth` using `zero` as the value of empty
+    /// leaves.
+    pub fn with_zero(
+        hash: IMTHashFunction<N>,
+        zero: N,
+        depth: usize,
+    ) -> Result<Self, &'static str> {
+        Self::with_store(hash, zero, depth, SparseStore::default())
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> SparseMerkleTree<N, S> {
+    /// Creates a tree of depth `depth` keeping its nodes in `store`.
+    ///
+    /// A persistent store already holding the nodes of a tree of the same
+    /// depth, zero and hash function reopens it.
+    pub fn with_store(
+        hash: IMTHashFunction<N>,
+        zero: N,
+        depth: usize,
+        store: S,
+    ) -> Result<Self, &'static str> {
+        if depth >= usize::BITS as usize {
+            return Err("Invalid tree depth");
+        }
+
+        let mut zeros = Vec::with_capacity(depth + 1);
+        zeros.push(zero);
+        for level in 0..depth {
+            let zero = zeros[level].clone();
+            zeros.push(hash(vec![zero.clone(), zero]));
+        }
+
+        Ok(SparseMerkleTree { hash, store, zeros })
+    }
+
+    pub fn get_depth(&self) -> usize {
+        self.zeros.len() - 1
+    }
+
+    /// Returns the roots of the empty subtrees of each level, from the zero
+    /// value to the root of the empty tree.
+    pub fn zeros(&self) -> &[N] {
+        &self.zeros
+    }
+
+    pub fn get_store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn root(&self) -> Result<N, &'static str> {
+        self.node(self.get_depth(), 0)
+    }
+
+    /// Returns the leaf at `key`, the zero value if it was never set.
+    pub fn get(&self, key: usize) -> Result<N, &'static str> {
+        self.check_key(key)?;
+        self.node(0, key)
+    }
+
+    /// Sets the leaf at `key`, returning the new root.
+    ///
+    /// The path is written in one operation: if a store write fails, the
+    /// store is rolled back.
+    pub fn update(&mut self, key: usize, leaf: N) -> Result<N, &'static str> {
+        self.check_key(key)?;
+
+        let result = self.write_path(key, leaf).and_then(|root| {
+            self.store.commit()?;
+            Ok(root)
+        });
+        if result.is_err() {
+            self.store.rollback();
+        }
+        result
+    }
+
+    /// Resets the leaf at `key` to zero, returning the new root.
+    pub fn remove(&mut self, key: usize) -> Result<N, &'static str> {
+        self.update(key, self.zeros[0].clone())
+    }
+
+    /// Generates the proof of the leaf at `key`, with one sibling per level.
+    ///
+    /// The proof of a key that was never set proves that its leaf is zero.
+    pub fn generate_proof(&self, key: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.check_key(key)?;
+
+        let mut siblings = Vec::with_capacity(self.get_depth());
+        for level in 0..self.get_depth() {
+            siblings.push(self.node(level, (key >> level) ^ 1)?);
+        }
+
+        Ok(MerkleProof {
+            root: self.root()?,
+            leaf: self.node(0, key)?,
+            index: key,
+            siblings,
+        })
+    }
+
+    /// Checks a proof against the current root.
+    pub fn verify_proof(&self, proof: &MerkleProof<N>) -> bool {
+        proof.siblings.len() == self.get_depth()
+            && self.root().is_ok_and(|root| root == proof.root)
+            && proof.verify(self.hash)
+    }
+
+    fn check_key(&self, key: usize) -> Result<(), &'static str> {
+        if key >> self.get_depth() != 0 {
+            return Err("Leaf index out of range");
+        }
+        Ok(())
+    }
+
+    /// Writes the leaf at `key` and the nodes above it, deleting the nodes
+    /// that become empty.
+    fn write_path(&mut self, key: usize, leaf: N) -> Result<N, &'static str> {
+        let mut node = leaf;
+        let mut position = key;
+        for level in 0..self.get_depth() {
+            let sibling = self.node(level, position ^ 1)?;
+            let parent = if position & 1 == 1 {
+                (self.hash)(vec![sibling, node.clone()])
+            } else {
+                (self.hash)(vec![node.clone(), sibling])
+            };
+            self.put_node(level, position, node)?;
+            node = parent;
+            position >>= 1;
+        }
+        self.put_node(self.get_depth(), 0, node.clone())?;
+        Ok(node)
+    }
+
+    /// Returns the node at `position` of `level`, counted from the leaves.
+    fn node(&self, level: usize, position: usize) -> Result<N, &'static str> {
+        Ok(self
+            .store
+            .side_node(self.key_of(level, position))?
+            .unwrap_or_else(|| self.zeros[level].clone()))
+    }
+
+    fn put_node(&mut self, level: usize, position: usize, node: N) -> Result<(), &'static str> {
+        let key = self.key_of(level, position);
+        if node == self.zeros[level] {
+            self.store.delete_side_node(key)
+        } else {
+            self.store.put_side_node(key, node)
+        }
+    }
+
+    /// Returns the heap index of a node.
+    fn key_of(&self, level: usize, position: usize) -> usize {
+        (1 << (self.get_depth() - level)) | position
+    }
+}
+
+/// In-memory store keeping its side nodes in a hash map, for sparse keys.
+#[derive(Debug, Clone)]
+pub struct SparseStore<N> {
+    side_nodes: HashMap<usize, N>,
+    leaves: HashMap<N, Vec<usize>>,
+}
+
+impl<N> Default for SparseStore<N> {
+    fn default() -> Self {
+        SparseStore {
+            side_nodes: HashMap::default(),
+            leaves: HashMap::default(),
+        }
+    }
+}
+
+impl<N> SparseStore<N> {
+    /// Returns the number of side nodes stored.
+    pub fn len(&self) -> usize {
+        self.side_nodes.len()
+    }
+
+    /// Returns whether the store holds no side node.
+    pub fn is_empty(&self) -> bool {
+        self.side_nodes.is_empty()
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMTStore<N> for SparseStore<N> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        Ok(self.side_nodes.get(&level).cloned())
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        self.side_nodes.insert(level, node);
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        self.side_nodes.remove(&level);
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        Box::new(
+            self.side_nodes
+                .iter()
+                .map(|(&level, node)| Ok((level, node.clone()))),
+        )
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self.leaves.get(leaf).cloned())
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.leaves.insert(leaf, indices);
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.leaves.remove(leaf);
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        Box::new(
+            self.leaves
+                .iter()
+                .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone()))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        format!("({})", nodes.join(","))
+    }
+
+    /// Root of the `2^depth` leaves, computed naively.
+    fn full_root(mut nodes: Vec<String>) -> String {
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| simple_hash_function(pair.to_vec()))
+                .collect();
+        }
+        nodes.swap_remove(0)
+    }
+
+    #[test]
+    fn test_sparse_merkle_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut smt = SparseMerkleTree::new(hash, 4).unwrap();
+        let mut leaves = vec!["0".to_string(); 16];
+        assert_eq!(smt.root(), Ok(full_root(leaves.clone())));
+
+        for (key, leaf) in [(9, "a"), (3, "b"), (15, "c"), (9, "d")] {
+            let root = smt.update(key, leaf.to_string()).unwrap();
+            leaves[key] = leaf.to_string();
+            assert_eq!(root, full_root(leaves.clone()));
+        }
+        assert_eq!(smt.get(9), Ok("d".to_string()));
+        assert_eq!(smt.get(4), Ok("0".to_string()));
+
+        // A key that was never set is proven to hold zero
+        for key in [9, 4] {
+            let proof = smt.generate_proof(key).unwrap();
+            assert_eq!(proof.siblings.len(), 4);
+            assert_eq!(proof.leaf, leaves[key]);
+            assert!(smt.verify_proof(&proof));
+        }
+
+        for key in [3, 9, 15] {
+            smt.remove(key).unwrap();
+        }
+        assert_eq!(smt.root(), Ok(smt.zeros()[4].clone()));
+        assert!(smt.get_store().is_empty());
+
+        assert_eq!(smt.get(16).unwrap_err(), "Leaf index out of range");
+        assert_eq!(
+            SparseMerkleTree::new(hash, usize::BITS as usize).unwrap_err(),
+            "Invalid tree depth"
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Persistent store backed by a SQLite database.
+//!
+//! Side nodes, leaves, payloads and metadata are kept in the tables
+//! `side_nodes(key, node)`, `leaves(leaf, indices)`, `leaves_by_index(position,
+//! leaf)`, `payloads(position, payload)` and `metadata(id, value)`, nodes
+//! encoded with a [`NodeCodec`] and leaf indices as LEB128 varints. The first
+//! write of a tree operation opens a transaction, committed when the
+//! operation completes and rolled back if it fails, so a crash leaves the
+//! database at the state of the last completed operation.
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::codec::{write_varint, ByteReader};
+use crate::store::{StoreIter, StoreMetadata};
+use crate::{LeanIMTStore, NodeCodec};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS side_nodes (key INTEGER PRIMARY KEY, node BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS leaves (leaf BLOB PRIMARY KEY, indices BLOB NOT NULL) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS leaves_by_index (position INTEGER PRIMARY KEY, leaf BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS payloads (position INTEGER PRIMARY KEY, payload BLOB NOT NULL);
+    CREATE TABLE IF NOT EXISTS metadata (id INTEGER PRIMARY KEY CHECK (id = 0), value BLOB NOT NULL);
+";
+
+const STORAGE_ERROR: &str = "Storage error";
+
+/// Store keeping the nodes of a tree in SQLite, encoded with the codec `C`.
+#[derive(Debug)]
+pub struct SqliteStore<N, C> {
+    connection: Connection,
+    /// Whether a transaction is open for the current tree operation.
+    in_transaction: Cell<bool>,
+    codec: PhantomData<fn() -> (N, C)>,
+}
+
+impl<N, C: NodeCodec<N>> SqliteStore<N, C> {
+    /// Uses `connection`, creating the tables of the store if needed. The
+    /// database should not hold other tables with the same names.
+    pub fn new(connection: Connection) -> Result<Self, &'static str> {
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|_| STORAGE_ERROR)?;
+        Ok(SqliteStore {
+            connection,
+            in_transaction: Cell::new(false),
+            codec: PhantomData,
+        })
+    }
+
+    /// Opens (or creates) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, &'static str> {
+        Self::new(Connection::open(path).map_err(|_| STORAGE_ERROR)?)
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Runs a write statement, opening the transaction of the operation
+    /// first.
+    fn write(&self, sql: &str, params: impl rusqlite::Params) -> Result<(), &'static str> {
+        if !self.in_transaction.get() {
+            self.connection
+                .execute_batch("BEGIN IMMEDIATE")
+                .map_err(|_| STORAGE_ERROR)?;
+            self.in_transaction.set(true);
+        }
+        self.connection
+            .prepare_cached(sql)
+            .and_then(|mut statement| statement.execute(params))
+            .map_err(|_| STORAGE_ERROR)?;
+        Ok(())
+    }
+
+    fn read(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        self.connection
+            .prepare_cached(sql)
+            .and_then(|mut statement| statement.query_row(params, |row| row.get(0)).optional())
+            .map_err(|_| STORAGE_ERROR)
+    }
+
+    /// Reads every `(key, value)` row returned by `sql`.
+    ///
+    /// Rows are collected before being iterated, as statements cannot
+    /// outlive the call.
+    fn read_all(&self, sql: &str) -> Result<Vec<(i64, Vec<u8>)>, &'static str> {
+        let mut statement = self
+            .connection
+            .prepare_cached(sql)
+            .map_err(|_| STORAGE_ERROR)?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|_| STORAGE_ERROR)?;
+        rows.collect::<Result<_, _>>().map_err(|_| STORAGE_ERROR)
+    }
+}
+
+fn to_key(index: usize) -> Result<i64, &'static str> {
+    i64::try_from(index).map_err(|_| "Value out of range")
+}
+
+fn from_key(key: i64) -> Result<usize, &'static str> {
+    usize::try_from(key).map_err(|_| "Invalid store key")
+}
+
+fn encode_indices(indices: &[usize]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for &index in indices {
+        write_varint(&mut bytes, index as u64);
+    }
+    bytes
+}
+
+fn decode_indices(bytes: &[u8]) -> Result<Vec<usize>, &'static str> {
+    let mut reader = ByteReader::new(bytes);
+    let mut indices = Vec::new();
+    while !reader.is_empty() {
+        indices.push(reader.read_usize()?);
+    }
+    Ok(indices)
+}
+
+impl<N, C: NodeCodec<N>> LeanIMTStore<N> for SqliteStore<N, C> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        self.read(
+            "SELECT node FROM side_nodes WHERE key = ?1",
+            [to_key(level)?],
+        )?
+        .map(|bytes| C::decode(&bytes))
+        .transpose()
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        self.write(
+            "INSERT OR REPLACE INTO side_nodes (key, node) VALUES (?1, ?2)",
+            params![to_key(level)?, C::encode(&node)],
+        )
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        self.write("DELETE FROM side_nodes WHERE key = ?1", [to_key(level)?])
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        match self.read_all("SELECT key, node FROM side_nodes") {
+            Ok(rows) => Box::new(
+                rows.into_iter()
+                    .map(|(key, node)| Ok((from_key(key)?, C::decode(&node)?))),
+            ),
+            Err(error) => Box::new(std::iter::once(Err(error))),
+        }
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        self.read(
+            "SELECT indices FROM leaves WHERE leaf = ?1",
+            [C::encode(leaf)],
+        )?
+        .map(|bytes| decode_indices(&bytes))
+        .transpose()
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.write(
+            "INSERT OR REPLACE INTO leaves (leaf, indices) VALUES (?1, ?2)",
+            params![C::encode(&leaf), encode_indices(&indices)],
+        )
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.write("DELETE FROM leaves WHERE leaf = ?1", [C::encode(leaf)])
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        let rows = self
+            .connection
+            .prepare_cached("SELECT leaf, indices FROM leaves")
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<Vec<(Vec<u8>, Vec<u8>)>, _>>()
+            });
+        match rows {
+            Ok(rows) => Box::new(
+                rows.into_iter()
+                    .map(|(leaf, indices)| Ok((C::decode(&leaf)?, decode_indices(&indices)?))),
+            ),
+            Err(_) => Box::new(std::iter::once(Err(STORAGE_ERROR))),
+        }
+    }
+
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        self.read(
+            "SELECT leaf FROM leaves_by_index WHERE position = ?1",
+            [to_key(index)?],
+        )?
+        .map(|bytes| C::decode(&bytes))
+        .transpose()
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        match leaf {
+            Some(leaf) => self.write(
+                "INSERT OR REPLACE INTO leaves_by_index (position, leaf) VALUES (?1, ?2)",
+                params![to_key(index)?, C::encode(&leaf)],
+            ),
+            None => self.write(
+                "DELETE FROM leaves_by_index WHERE position = ?1",
+                [to_key(index)?],
+            ),
+        }
+    }
+
+    fn payload(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        self.read(
+            "SELECT payload FROM payloads WHERE position = ?1",
+            [to_key(index)?],
+        )
+    }
+
+    fn put_payload(&mut self, index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        match payload {
+            Some(payload) => self.write(
+                "INSERT OR REPLACE INTO payloads (position, payload) VALUES (?1, ?2)",
+                params![to_key(index)?, payload],
+            ),
+            None => self.write("DELETE FROM payloads WHERE position = ?1", [to_key(index)?]),
+        }
+    }
+
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        match self.read_all("SELECT position, payload FROM payloads") {
+            Ok(rows) => Box::new(
+                rows.into_iter()
+                    .map(|(position, payload)| Ok((from_key(position)?, payload))),
+            ),
+            Err(error) => Box::new(std::iter::once(Err(error))),
+        }
+    }
+
+    fn metadata(&self) -> Result<Option<StoreMetadata<N>>, &'static str> {
+        self.read("SELECT value FROM metadata WHERE id = 0", [])?
+            .map(|bytes| StoreMetadata::from_bytes::<C>(&bytes))
+            .transpose()
+    }
+
+    fn put_metadata(&mut self, metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        self.write(
+            "INSERT OR REPLACE INTO metadata (id, value) VALUES (0, ?1)",
+            [metadata.to_bytes::<C>()],
+        )
+    }
+
+    fn commit(&mut self) -> Result<(), &'static str> {
+        if !self.in_transaction.get() {
+            return Ok(());
+        }
+        self.in_transaction.set(false);
+        self.connection
+            .execute_batch("COMMIT")
+            .map_err(|_| STORAGE_ERROR)
+    }
+
+    fn rollback(&mut self) {
+        if self.in_transaction.replace(false) {
+            let _ = self.connection.execute_batch("ROLLBACK");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::{DuplicatePolicy, IMTHashFunction, LeanIMT};
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn temporary_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("lean-imt-{}.sqlite", std::process::id()))
+    }
+
+    #[test]
+    fn test_reopen_sqlite_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let path = temporary_path();
+        let mut reference = LeanIMT::new(hash);
+
+        {
+            let store = SqliteStore::<String, StringCodec>::open(&path).unwrap();
+            let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+            imt.set_duplicate_policy(DuplicatePolicy::Allow).unwrap();
+            imt.set_max_size(Some(usize::MAX)).unwrap();
+            reference
+                .set_duplicate_policy(DuplicatePolicy::Allow)
+                .unwrap();
+
+            let leaves: Vec<_> = (1..=5).map(|i| format!("leaf{}", i)).collect();
+            imt.insert_many(leaves.clone()).unwrap();
+            reference.insert_many(leaves).unwrap();
+            imt.insert("leaf1".to_string()).unwrap();
+            reference.insert("leaf1".to_string()).unwrap();
+            imt.insert_with_value("leaf6".to_string(), b"six".to_vec())
+                .unwrap();
+            reference
+                .insert_with_value("leaf6".to_string(), b"six".to_vec())
+                .unwrap();
+
+            let proof = imt.generate_proof(1).unwrap();
+            imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+            reference
+                .remove(&"leaf2".to_string(), &proof.siblings)
+                .unwrap();
+        }
+
+        let store = SqliteStore::<String, StringCodec>::open(&path).unwrap();
+        let mut imt = LeanIMT::open(hash, store).unwrap();
+        assert_eq!(imt.root(), reference.root());
+        assert_eq!(imt.get_version(), reference.get_version());
+        assert_eq!(imt.get_max_size(), None);
+        assert_eq!(imt.indices_of(&"leaf1".to_string()), vec![0, 5]);
+        assert_eq!(imt.value_at(6), Ok(Some(b"six".to_vec())));
+        assert_eq!(imt.snapshot(), reference.snapshot());
+
+        imt.insert("leaf7".to_string()).unwrap();
+        reference.insert("leaf7".to_string()).unwrap();
+        assert_eq!(imt.root(), reference.root());
+        drop(imt);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_operation_is_rolled_back() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let connection = Connection::open_in_memory().unwrap();
+        let store = SqliteStore::<String, StringCodec>::new(connection).unwrap();
+        let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+        imt.insert_many(vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ])
+        .unwrap();
+        let root = imt.root();
+
+        // The side nodes written before the siblings are found wrong are discarded
+        let sibling_nodes = vec!["wrong".to_string(), "leaf1,leaf2".to_string()];
+        let result = imt.update(&"leaf3".to_string(), "leaf4".to_string(), &sibling_nodes);
+        assert_eq!(result.unwrap_err(), "Wrong sibling nodes");
+        assert!(!imt.store.in_transaction.get());
+        assert!(imt.store.connection().is_autocommit());
+
+        let reopened = LeanIMT::open(hash, imt.store).unwrap();
+        assert_eq!(reopened.root(), root);
+        assert_eq!(reopened.get_size(), 3);
+    }
+}
@@ -0,0 +1,151 @@
+//! Counters of the work done by a tree, to monitor its cost in production.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{LeanIMT, LeanIMTStore, TreeHash};
+
+/// Counts of the work done by a tree since it was created or its counters
+/// were reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TreeStats {
+    /// Calls to the hash function made to change the tree or generate its
+    /// proofs.
+    pub hashes: u64,
+    /// Leaves inserted.
+    pub inserts: u64,
+    /// Leaves updated or removed.
+    pub updates: u64,
+    /// Proofs generated, of any kind.
+    pub proofs: u64,
+    /// Side nodes and leaves written to the store.
+    pub nodes_stored: u64,
+    /// Size of the nodes written to the store, counting `size_of::<N>()` per
+    /// node: exact for fixed-size nodes such as `[u8; 32]`, without the heap
+    /// data of nodes such as `String`.
+    pub bytes_stored: u64,
+}
+
+/// Counters of a tree, updated through shared references so that proof
+/// generation can be counted.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    hashes: AtomicUsize,
+    inserts: AtomicUsize,
+    updates: AtomicUsize,
+    proofs: AtomicUsize,
+    nodes_stored: AtomicUsize,
+}
+
+impl Counters {
+    pub(crate) fn add_hashes(&self, count: usize) {
+        self.hashes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_inserts(&self, count: usize) {
+        self.inserts.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_update(&self) {
+        self.updates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_proof(&self) {
+        self.proofs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_nodes_stored(&self, count: usize) {
+        self.nodes_stored.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns a hash function calling `hash` and counting the calls.
+    pub(crate) fn counted<'a, N: 'a>(&'a self, hash: &'a TreeHash<N>) -> impl Fn(Vec<N>) -> N + 'a {
+        move |nodes| {
+            self.add_hashes(1);
+            hash.call(nodes)
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Returns the work done by the tree since it was created or
+    /// [`reset_stats`](Self::reset_stats) was called.
+    ///
+    /// Forks and restored trees start with zeroed counters. Operations that
+    /// fail are counted up to the point where they failed.
+    pub fn stats(&self) -> TreeStats {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed) as u64;
+        let nodes_stored = load(&self.stats.nodes_stored);
+        TreeStats {
+            hashes: load(&self.stats.hashes),
+            inserts: load(&self.stats.inserts),
+            updates: load(&self.stats.updates),
+            proofs: load(&self.stats.proofs),
+            nodes_stored,
+            bytes_stored: nodes_stored * size_of::<N>() as u64,
+        }
+    }
+
+    /// Resets the counters returned by [`stats`](Self::stats).
+    pub fn reset_stats(&mut self) {
+        self.stats = Counters::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_stats() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(imt.stats(), TreeStats::default());
+
+        imt.insert("leaf1".to_string()).unwrap();
+        imt.insert_many(vec!["leaf2".to_string(), "leaf3".to_string()])
+            .unwrap();
+        let stats = imt.stats();
+        assert_eq!(stats.inserts, 3);
+        // The batch hashes the first pair, then the root
+        assert_eq!(stats.hashes, 2);
+        assert_eq!(
+            stats.bytes_stored,
+            stats.nodes_stored * size_of::<String>() as u64
+        );
+
+        let proof = imt.generate_proof(0).unwrap();
+        assert_eq!(imt.stats().proofs, 1);
+        // The proof hashes the pairs of the two levels below the root
+        assert_eq!(imt.stats().hashes, 4);
+
+        imt.update(&"leaf1".to_string(), "leaf4".to_string(), &proof.siblings)
+            .unwrap();
+        assert_eq!(imt.stats().updates, 1);
+        // The new and the old path, over the two levels
+        assert_eq!(imt.stats().hashes, 8);
+        assert!(imt.stats().nodes_stored > stats.nodes_stored);
+
+        assert!(imt.insert("leaf4".to_string()).is_err());
+        assert_eq!(imt.stats().inserts, 3);
+
+        imt.reset_stats();
+        assert_eq!(imt.stats(), TreeStats::default());
+    }
+}
@@ -0,0 +1,603 @@
+//! Storage of the side nodes and leaves of a tree.
+//!
+//! A [`LeanIMTStore`] holds the maps a tree needs: the side node saved at
+//! each level, the indices of each leaf value and, optionally, the leaf at
+//! each index. [`MemoryStore`] keeps them in memory and is the default; other
+//! implementations can keep them on disk or in a database.
+//!
+//! Every tree operation ends with a call to `commit`, or to `rollback` if it
+//! failed, so stores that buffer their writes can apply each operation
+//! atomically. Stores writing in place, such as [`MemoryStore`], keep the
+//! writes of a failed operation, which is why operations make their checks
+//! before writing. Persistent stores also keep the [`StoreMetadata`] of the tree,
+//! which lets `LeanIMT::open` reopen it.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+#[cfg(any(
+    feature = "mmap",
+    feature = "postgres",
+    feature = "sled",
+    feature = "sqlite"
+))]
+use crate::codec::{write_bytes, write_max_size, write_varint, ByteReader};
+#[cfg(any(
+    feature = "mmap",
+    feature = "postgres",
+    feature = "sled",
+    feature = "sqlite"
+))]
+use crate::NodeCodec;
+use crate::{depth_of, DuplicatePolicy, HashMap};
+
+/// Iterator over the entries of a store.
+pub type StoreIter<'a, T> = Box<dyn Iterator<Item = Result<T, &'static str>> + 'a>;
+
+/// Tree state kept by persistent stores next to the nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreMetadata<N> {
+    pub size: usize,
+    pub version: u64,
+    pub zero: N,
+    pub duplicate_policy: DuplicatePolicy,
+    pub arity: usize,
+    /// Maximum number of leaves, if the tree is limited.
+    pub max_size: Option<usize>,
+}
+
+#[cfg(any(
+    feature = "mmap",
+    feature = "postgres",
+    feature = "sled",
+    feature = "sqlite"
+))]
+impl<N> StoreMetadata<N> {
+    /// Encodes the metadata as persistent stores save it: the size, the
+    /// version, the zero value, the duplicate policy byte, the arity and the
+    /// maximum size plus one (zero for unlimited trees and limits of
+    /// `usize::MAX`). Integers are LEB128 varints and the zero value is
+    /// length-prefixed bytes from `C`.
+    pub(crate) fn to_bytes<C: NodeCodec<N>>(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, self.size as u64);
+        write_varint(&mut bytes, self.version);
+        write_bytes(&mut bytes, &C::encode(&self.zero));
+        bytes.push(match self.duplicate_policy {
+            DuplicatePolicy::Reject => 0,
+            DuplicatePolicy::Allow => 1,
+        });
+        write_varint(&mut bytes, self.arity as u64);
+        write_max_size(&mut bytes, self.max_size);
+        bytes
+    }
+
+    /// Decodes metadata written by `to_bytes`.
+    pub(crate) fn from_bytes<C: NodeCodec<N>>(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut reader = ByteReader::new(bytes);
+        let size = reader.read_usize()?;
+        let version = reader.read_varint()?;
+        let zero = C::decode(reader.read_bytes()?)?;
+        let duplicate_policy = match reader.read_byte()? {
+            0 => DuplicatePolicy::Reject,
+            1 => DuplicatePolicy::Allow,
+            _ => return Err("Invalid duplicate policy"),
+        };
+        let arity = reader.read_usize()?;
+        // Metadata written before trees had a maximum size ends here
+        let max_size = if reader.is_empty() {
+            None
+        } else {
+            reader.read_usize()?.checked_sub(1)
+        };
+        if !reader.is_empty() {
+            return Err("Trailing bytes");
+        }
+
+        Ok(StoreMetadata {
+            size,
+            version,
+            zero,
+            duplicate_policy,
+            arity,
+            max_size,
+        })
+    }
+}
+
+/// Get/put/delete/iterate access to the side nodes and leaves of a tree.
+///
+/// Leaf indices are 0-based and kept in ascending order by the tree.
+pub trait LeanIMTStore<N> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str>;
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str>;
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str>;
+
+    /// Iterates over the `(level, node)` side nodes, in no particular order.
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)>;
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str>;
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str>;
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str>;
+
+    /// Iterates over the `(leaf, indices)` entries, in no particular order.
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)>;
+
+    fn has_leaf(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self.leaf_indices(leaf)?.is_some())
+    }
+
+    /// Reserves room for `leaves` more leaves, and for side node keys up to
+    /// `side_nodes`, ahead of a bulk load.
+    ///
+    /// Stores that do not preallocate can ignore it.
+    fn reserve(&mut self, _leaves: usize, _side_nodes: usize) {}
+
+    /// Returns the leaf at `index`, or `None` if it was removed or is past
+    /// the last leaf.
+    ///
+    /// Stores that do not override `put_leaf_at` find the leaf by scanning
+    /// `leaves`.
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        for entry in self.leaves() {
+            let (leaf, indices) = entry?;
+            if indices.contains(&index) {
+                return Ok(Some(leaf));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Saves the leaf at `index`, `None` marking a removed leaf.
+    ///
+    /// Stores keeping only the indices of each leaf can ignore it.
+    fn put_leaf_at(&mut self, _index: usize, _leaf: Option<N>) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Returns the leaves ordered by index, `None` marking removed leaves.
+    ///
+    /// The list can be shorter than the tree, missing its removed last
+    /// leaves.
+    fn leaves_by_index(&self) -> Result<Vec<Option<N>>, &'static str>
+    where
+        N: Clone,
+    {
+        let mut leaves = Vec::new();
+        for entry in self.leaves() {
+            let (leaf, indices) = entry?;
+            for index in indices {
+                if index >= leaves.len() {
+                    leaves.resize(index + 1, None);
+                }
+                leaves[index] = Some(leaf.clone());
+            }
+        }
+        Ok(leaves)
+    }
+
+    /// Returns the payload attached to the leaf at `index`, if any.
+    fn payload(&self, _index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        Ok(None)
+    }
+
+    /// Attaches `payload` to the leaf at `index`, `None` detaching it.
+    ///
+    /// Stores that do not keep payloads fail to attach one.
+    fn put_payload(&mut self, _index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        match payload {
+            Some(_) => Err("Store does not keep payloads"),
+            None => Ok(()),
+        }
+    }
+
+    /// Iterates over the `(index, payload)` entries, in no particular order.
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        Box::new(core::iter::empty())
+    }
+
+    /// Returns the indices `leaf` was removed from, if it is kept as a
+    /// tombstone.
+    fn tombstone(&self, _leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(None)
+    }
+
+    /// Keeps the removed `leaf` as a tombstone with the indices it was
+    /// removed from.
+    ///
+    /// Stores that do not keep tombstones fail.
+    fn put_tombstone(&mut self, _leaf: N, _indices: Vec<usize>) -> Result<(), &'static str> {
+        Err("Store does not keep tombstones")
+    }
+
+    /// Iterates over the `(leaf, indices)` tombstones, in no particular order.
+    fn tombstones<'a>(&'a self) -> StoreIter<'a, (N, Vec<usize>)>
+    where
+        N: 'a,
+    {
+        Box::new(core::iter::empty())
+    }
+
+    /// Returns the metadata saved by `put_metadata`, if any.
+    ///
+    /// Stores that are not persistent do not keep metadata.
+    fn metadata(&self) -> Result<Option<StoreMetadata<N>>, &'static str> {
+        Ok(None)
+    }
+
+    fn put_metadata(&mut self, _metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Applies the writes made since the last commit.
+    fn commit(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Discards the writes made since the last commit, if the store buffers
+    /// them or keeps a checkpoint or an undo log. Other stores keep them.
+    fn rollback(&mut self) {}
+}
+
+/// In-memory store.
+///
+/// Side nodes are kept in a vector indexed by their key: keys are dense and
+/// bounded by the depth of the tree, so they are read and written without
+/// hashing. Leaves are kept both in a hash map from value to indices and in
+/// a vector by index, payloads in a map by index and tombstones in a hash
+/// map from value to indices.
+#[derive(Debug, Clone)]
+pub struct MemoryStore<N> {
+    side_nodes: Vec<Option<N>>,
+    leaves: HashMap<N, Vec<usize>>,
+    leaves_by_index: Vec<Option<N>>,
+    payloads: BTreeMap<usize, Vec<u8>>,
+    tombstones: HashMap<N, Vec<usize>>,
+}
+
+impl<N> Default for MemoryStore<N> {
+    fn default() -> Self {
+        MemoryStore {
+            side_nodes: Vec::new(),
+            leaves: HashMap::default(),
+            leaves_by_index: Vec::new(),
+            payloads: BTreeMap::new(),
+            tombstones: HashMap::default(),
+        }
+    }
+}
+
+impl<N> MemoryStore<N> {
+    /// Creates a store with room for `leaves` leaves and the side nodes of a
+    /// binary tree of that size.
+    pub fn with_capacity(leaves: usize) -> Self {
+        MemoryStore {
+            side_nodes: Vec::with_capacity(depth_of(leaves, 2) + 1),
+            leaves: HashMap::with_capacity_and_hasher(leaves, Default::default()),
+            leaves_by_index: Vec::with_capacity(leaves),
+            payloads: BTreeMap::new(),
+            tombstones: HashMap::default(),
+        }
+    }
+
+    /// Iterates over the `(key, node)` side nodes by reference, ordered by
+    /// key.
+    pub fn side_node_refs(&self) -> impl Iterator<Item = (usize, &N)> {
+        self.side_nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(key, node)| Some((key, node.as_ref()?)))
+    }
+
+    /// Iterates over the leaves by reference, ordered by index, `None`
+    /// marking removed leaves.
+    pub fn leaf_refs(&self) -> impl Iterator<Item = Option<&N>> {
+        self.leaves_by_index.iter().map(Option::as_ref)
+    }
+
+    /// Iterates over the `(leaf, indices)` entries by reference, in no
+    /// particular order.
+    pub fn leaf_index_refs(&self) -> impl Iterator<Item = (&N, &[usize])> {
+        self.leaves
+            .iter()
+            .map(|(leaf, indices)| (leaf, indices.as_slice()))
+    }
+}
+
+impl<N: Clone + Eq + Hash> LeanIMTStore<N> for MemoryStore<N> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        Ok(self.side_nodes.get(level).cloned().flatten())
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        if level >= self.side_nodes.len() {
+            self.side_nodes.resize(level + 1, None);
+        }
+        self.side_nodes[level] = Some(node);
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        if let Some(node) = self.side_nodes.get_mut(level) {
+            *node = None;
+        }
+        while let Some(None) = self.side_nodes.last() {
+            self.side_nodes.pop();
+        }
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        Box::new(
+            self.side_nodes
+                .iter()
+                .enumerate()
+                .filter_map(|(level, node)| Some(Ok((level, node.clone()?)))),
+        )
+    }
+
+    fn reserve(&mut self, leaves: usize, side_nodes: usize) {
+        self.side_nodes
+            .reserve(side_nodes.saturating_sub(self.side_nodes.len()));
+        self.leaves.reserve(leaves);
+        self.leaves_by_index.reserve(leaves);
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self.leaves.get(leaf).cloned())
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.leaves.insert(leaf, indices);
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.leaves.remove(leaf);
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        Box::new(
+            self.leaves
+                .iter()
+                .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone()))),
+        )
+    }
+
+    fn has_leaf(&self, leaf: &N) -> Result<bool, &'static str> {
+        Ok(self.leaves.contains_key(leaf))
+    }
+
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        Ok(self.leaves_by_index.get(index).cloned().flatten())
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        if index >= self.leaves_by_index.len() {
+            self.leaves_by_index.resize(index + 1, None);
+        }
+        self.leaves_by_index[index] = leaf;
+        Ok(())
+    }
+
+    fn leaves_by_index(&self) -> Result<Vec<Option<N>>, &'static str>
+    where
+        N: Clone,
+    {
+        Ok(self.leaves_by_index.clone())
+    }
+
+    fn payload(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        Ok(self.payloads.get(&index).cloned())
+    }
+
+    fn put_payload(&mut self, index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        match payload {
+            Some(payload) => self.payloads.insert(index, payload),
+            None => self.payloads.remove(&index),
+        };
+        Ok(())
+    }
+
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        Box::new(
+            self.payloads
+                .iter()
+                .map(|(&index, payload)| Ok((index, payload.clone()))),
+        )
+    }
+
+    fn tombstone(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        Ok(self.tombstones.get(leaf).cloned())
+    }
+
+    fn put_tombstone(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.tombstones.insert(leaf, indices);
+        Ok(())
+    }
+
+    fn tombstones<'a>(&'a self) -> StoreIter<'a, (N, Vec<usize>)>
+    where
+        N: 'a,
+    {
+        Box::new(
+            self.tombstones
+                .iter()
+                .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone()))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IMTHashFunction, LeanIMT};
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    /// Memory store whose writes start failing once `writes` reaches zero.
+    #[derive(Default)]
+    struct FailingStore {
+        inner: MemoryStore<String>,
+        writes: usize,
+    }
+
+    impl FailingStore {
+        fn write(&mut self) -> Result<(), &'static str> {
+            self.writes = self.writes.checked_sub(1).ok_or("Store unavailable")?;
+            Ok(())
+        }
+    }
+
+    impl LeanIMTStore<String> for FailingStore {
+        fn side_node(&self, level: usize) -> Result<Option<String>, &'static str> {
+            self.inner.side_node(level)
+        }
+
+        fn put_side_node(&mut self, level: usize, node: String) -> Result<(), &'static str> {
+            self.write()?;
+            self.inner.put_side_node(level, node)
+        }
+
+        fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+            self.write()?;
+            self.inner.delete_side_node(level)
+        }
+
+        fn side_nodes(&self) -> StoreIter<'_, (usize, String)> {
+            self.inner.side_nodes()
+        }
+
+        fn leaf_indices(&self, leaf: &String) -> Result<Option<Vec<usize>>, &'static str> {
+            self.inner.leaf_indices(leaf)
+        }
+
+        fn put_leaf_indices(
+            &mut self,
+            leaf: String,
+            indices: Vec<usize>,
+        ) -> Result<(), &'static str> {
+            self.write()?;
+            self.inner.put_leaf_indices(leaf, indices)
+        }
+
+        fn delete_leaf(&mut self, leaf: &String) -> Result<(), &'static str> {
+            self.write()?;
+            self.inner.delete_leaf(leaf)
+        }
+
+        fn leaves(&self) -> StoreIter<'_, (String, Vec<usize>)> {
+            self.inner.leaves()
+        }
+    }
+
+    #[test]
+    fn test_tree_with_custom_store() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let store = FailingStore {
+            writes: usize::MAX,
+            ..Default::default()
+        };
+        let mut imt = LeanIMT::with_store(hash, "0".to_string(), store).unwrap();
+        let mut reference = LeanIMT::new(hash);
+
+        let leaves = vec![
+            "leaf1".to_string(),
+            "leaf2".to_string(),
+            "leaf3".to_string(),
+        ];
+        imt.insert_many(leaves.clone()).unwrap();
+        reference.insert_many(leaves).unwrap();
+        let proof = imt.generate_proof(1).unwrap();
+        imt.remove(&"leaf2".to_string(), &proof.siblings).unwrap();
+        reference
+            .remove(&"leaf2".to_string(), &proof.siblings)
+            .unwrap();
+
+        assert_eq!(imt.root(), reference.root());
+        assert_eq!(imt.snapshot(), reference.snapshot());
+        assert_eq!(
+            imt.get_store().inner.leaf_indices(&"leaf3".to_string()),
+            Ok(Some(vec![2]))
+        );
+
+        // A store holding nodes cannot back a new tree
+        let store = imt.get_store().inner.clone();
+        assert_eq!(
+            LeanIMT::with_store(hash, "0".to_string(), store).unwrap_err(),
+            "Store is not empty"
+        );
+    }
+
+    #[test]
+    fn test_memory_store_side_nodes() {
+        let mut store = MemoryStore::default();
+        store.put_side_node(3, "node3".to_string()).unwrap();
+        store.put_side_node(0, "node0".to_string()).unwrap();
+        assert_eq!(store.side_node(3), Ok(Some("node3".to_string())));
+        assert_eq!(store.side_node(1), Ok(None));
+        assert_eq!(store.side_node(7), Ok(None));
+
+        store.delete_side_node(3).unwrap();
+        store.delete_side_node(7).unwrap();
+        assert_eq!(store.side_nodes.len(), 1);
+        assert_eq!(
+            store.side_nodes().collect::<Result<Vec<_>, _>>(),
+            Ok(vec![(0, "node0".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_capacity(hash, 1000);
+        let store = imt.get_store();
+        let capacities = (
+            store.side_nodes.capacity(),
+            store.leaves.capacity(),
+            store.leaves_by_index.capacity(),
+        );
+        assert!(capacities.0 > depth_of(1000, 2));
+        assert!(capacities.1 >= 1000 && capacities.2 >= 1000);
+
+        for i in 1..=1000 {
+            imt.insert(i.to_string()).unwrap();
+        }
+        let store = imt.get_store();
+        assert_eq!(
+            (
+                store.side_nodes.capacity(),
+                store.leaves.capacity(),
+                store.leaves_by_index.capacity(),
+            ),
+            capacities
+        );
+    }
+
+    #[test]
+    fn test_store_errors_are_returned() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::with_store(hash, "0".to_string(), FailingStore::default()).unwrap();
+
+        assert_eq!(
+            imt.insert("leaf1".to_string()).unwrap_err(),
+            "Store unavailable"
+        );
+        assert_eq!(
+            imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+                .unwrap_err(),
+            "Store unavailable"
+        );
+    }
+}
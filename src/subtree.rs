@@ -0,0 +1,240 @@
+//! Export and import of complete subtrees.
+//!
+//! A large tree can be built in shards: each worker inserts its chunk of
+//! leaves into its own tree and exports it as a [`Subtree`], and a coordinator
+//! appends the subtrees in order with `import_subtree`. The coordinator only
+//! hashes the nodes above the subtree roots, so the final state matches the
+//! one `insert_many` would reach with all the leaves.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+use core::ops::Range;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::history::compute_root;
+use crate::{ChangeRecord, IMTNode, LeanIMT, LeanIMTStore};
+
+/// Leaves of a subtree together with its root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Subtree<N = IMTNode> {
+    pub leaves: Vec<N>,
+    pub root: N,
+}
+
+impl<N: Clone + PartialEq> Subtree<N> {
+    /// Checks that the leaves hash up to the root in a tree of `arity`.
+    pub fn verify(&self, hash: impl Fn(Vec<N>) -> N, arity: usize) -> bool {
+        arity >= 2 && compute_root(self.leaves.clone(), arity, hash).as_ref() == Some(&self.root)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Exports the leaves in `range` and the root of the subtree they form.
+    ///
+    /// The range must start at a multiple of its width, the smallest power of
+    /// the arity it fits in, and either fill that width or end with the tree,
+    /// so that its root is a node of the tree.
+    pub fn export_subtree(&self, range: Range<usize>) -> Result<Subtree<N>, &'static str> {
+        if range.start >= range.end || range.end > self.size {
+            return Err("Invalid range");
+        }
+        let (_, width) = self.subtree_level(range.len());
+        if !range.start.is_multiple_of(width) || (range.len() != width && range.end != self.size) {
+            return Err("Subtree is not aligned");
+        }
+
+        let mut leaves = self.ordered_leaves()?;
+        leaves.truncate(range.end);
+        leaves.drain(..range.start);
+        let root =
+            compute_root(leaves.clone(), self.arity, self.hash.as_fn()).ok_or("Invalid range")?;
+        Ok(Subtree { leaves, root })
+    }
+
+    /// Appends a complete subtree to the tree and returns the new root.
+    ///
+    /// The subtree must hold a power of the arity of leaves and the tree
+    /// size must be a multiple of it. Its root is trusted as given: call
+    /// `Subtree::verify` first if it comes from an untrusted worker. Leaves
+    /// that do not fill a complete subtree are appended with `insert_many`.
+    pub fn import_subtree(&mut self, subtree: Subtree<N>) -> Result<N, &'static str> {
+        self.transaction(|imt, changes| imt.append_subtree(subtree, changes))
+    }
+
+    fn append_subtree(
+        &mut self,
+        subtree: Subtree<N>,
+        changes: &mut Vec<ChangeRecord<N>>,
+    ) -> Result<N, &'static str> {
+        let Subtree { leaves, root } = subtree;
+        if leaves.is_empty() {
+            return Err("Empty batch");
+        }
+        let (level, width) = self.subtree_level(leaves.len());
+        if leaves.len() != width {
+            return Err("Subtree is not complete");
+        }
+        if !self.size.is_multiple_of(width) {
+            return Err("Subtree is not aligned");
+        }
+        self.check_new_leaves(&leaves)?;
+
+        let tree_size = self.size;
+        let root = self.append_node(root, level, tree_size + width)?;
+
+        self.version += 1;
+        for (i, leaf) in leaves.into_iter().enumerate() {
+            if self.changelog.is_active() {
+                changes.push(ChangeRecord {
+                    version: self.version,
+                    index: tree_size + i,
+                    old_leaf: None,
+                    appended: true,
+                    new_leaf: leaf.clone(),
+                    new_root: root.clone(),
+                });
+            }
+            self.add_leaf_index(leaf, tree_size + i)?;
+        }
+
+        Ok(root)
+    }
+
+    /// Returns the level of the smallest subtree holding `len` leaves, and
+    /// the number of leaves it holds.
+    fn subtree_level(&self, len: usize) -> (usize, usize) {
+        let mut level = 0;
+        let mut width = 1;
+        while width < len {
+            level += 1;
+            width = width.saturating_mul(self.arity);
+        }
+        (level, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_stitch_subtrees() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let leaves: Vec<String> = (0..19).map(|i| format!("leaf{}", i)).collect();
+        let mut expected = LeanIMT::new(hash);
+        expected.insert_many(leaves.clone()).unwrap();
+
+        // Workers build shards of 8 leaves, the tail is inserted directly
+        let mut imt = LeanIMT::new(hash);
+        for chunk in leaves[..16].chunks(8) {
+            let mut worker = LeanIMT::new(hash);
+            worker.insert_many(chunk.to_vec()).unwrap();
+            let subtree = worker.export_subtree(0..8).unwrap();
+            assert!(subtree.verify(hash, 2));
+            imt.import_subtree(subtree).unwrap();
+        }
+        imt.insert_many(leaves[16..].to_vec()).unwrap();
+
+        assert_eq!(imt.root(), expected.root());
+        assert_eq!(imt.get_depth(), expected.get_depth());
+        assert_eq!(imt.index_of(&"leaf9".to_string()), Ok(9));
+        assert_eq!(imt.generate_proof(5), expected.generate_proof(5));
+
+        // Exports of the stitched tree reassemble into the same tree
+        let mut copy = LeanIMT::new(hash);
+        copy.import_subtree(imt.export_subtree(0..16).unwrap())
+            .unwrap();
+        let tail = imt.export_subtree(16..19).unwrap();
+        assert_eq!(tail.root, "leaf16,leaf17,leaf18");
+        copy.insert_many(tail.leaves).unwrap();
+        assert_eq!(copy.root(), expected.root());
+
+        let mut ternary = LeanIMT::with_arity(hash, 3).unwrap();
+        let mut expected = LeanIMT::with_arity(hash, 3).unwrap();
+        expected.insert_many(leaves[..12].to_vec()).unwrap();
+        ternary.insert_many(leaves[..9].to_vec()).unwrap();
+        ternary
+            .import_subtree(expected.export_subtree(9..12).unwrap())
+            .unwrap();
+        assert_eq!(ternary.root(), expected.root());
+    }
+
+    #[test]
+    fn test_subtree_errors() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many((0..6).map(|i| format!("leaf{}", i)).collect())
+            .unwrap();
+
+        assert_eq!(imt.export_subtree(2..2).unwrap_err(), "Invalid range");
+        assert_eq!(imt.export_subtree(4..7).unwrap_err(), "Invalid range");
+        assert_eq!(
+            imt.export_subtree(1..3).unwrap_err(),
+            "Subtree is not aligned"
+        );
+        assert_eq!(
+            imt.export_subtree(0..3).unwrap_err(),
+            "Subtree is not aligned"
+        );
+        assert!(imt.export_subtree(4..6).is_ok());
+
+        let pair = |a: &str, b: &str| Subtree {
+            leaves: vec![a.to_string(), b.to_string()],
+            root: format!("{},{}", a, b),
+        };
+        let triple = Subtree {
+            leaves: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            root: "a,b,c".to_string(),
+        };
+        assert!(!Subtree {
+            root: "b,a".to_string(),
+            ..pair("a", "b")
+        }
+        .verify(hash, 2));
+        assert_eq!(
+            imt.import_subtree(triple).unwrap_err(),
+            "Subtree is not complete"
+        );
+        assert_eq!(
+            imt.import_subtree(pair("leaf0", "x")).unwrap_err(),
+            "Leaf already exists"
+        );
+        assert_eq!(
+            imt.import_subtree(pair("x", "x")).unwrap_err(),
+            "Leaf already exists"
+        );
+        let quad = Subtree {
+            leaves: vec![
+                "a".to_string(),
+                "b".to_string(),
+                "c".to_string(),
+                "d".to_string(),
+            ],
+            root: "a,b,c,d".to_string(),
+        };
+        assert_eq!(
+            imt.import_subtree(quad).unwrap_err(),
+            "Subtree is not aligned"
+        );
+        assert_eq!(imt.get_size(), 6);
+        assert_eq!(imt.get_version(), 1);
+
+        imt.import_subtree(pair("x", "y")).unwrap();
+        assert_eq!(imt.get_size(), 8);
+        assert_eq!(imt.export_subtree(6..8).unwrap(), pair("x", "y"));
+    }
+}
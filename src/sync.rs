@@ -0,0 +1,204 @@
+//! Mirror of a tree growing block by block, following chain reorganizations.
+//!
+//! An indexer mirroring an on-chain tree appends the leaves of each block and
+//! must drop them again when the block is reorganized away. [`BlockSync`]
+//! checks the root reached by every block against the root expected from the
+//! chain, and remembers the size and root of the tree before each of its
+//! last blocks, so a reverted block rewinds the tree with `truncate` to a
+//! root that is checked too.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::{IMTNode, LeanIMT, LeanIMTStore, MemoryStore};
+
+/// Size and root of the tree before an applied block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct AppliedBlock<N> {
+    size: usize,
+    root: Option<N>,
+}
+
+/// Local tree following the blocks of a chain, with their reorganizations.
+///
+/// A block whose leaves do not reach the expected root is rejected, and the
+/// tree left as before the block. Only the last `max_reorg_depth` blocks can be
+/// reverted with their root checked.
+#[derive(Debug)]
+pub struct BlockSync<N = IMTNode, S = MemoryStore<N>> {
+    tree: LeanIMT<N, S>,
+    /// Applied blocks that can be reverted, the most recent last.
+    blocks: VecDeque<AppliedBlock<N>>,
+    max_reorg_depth: usize,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> BlockSync<N, S> {
+    /// Mirrors the chain into `tree`, which must hold its state before the
+    /// first block applied, keeping the last `max_reorg_depth` blocks.
+    pub fn new(tree: LeanIMT<N, S>, max_reorg_depth: usize) -> Self {
+        BlockSync {
+            tree,
+            blocks: VecDeque::with_capacity(max_reorg_depth),
+            max_reorg_depth,
+        }
+    }
+
+    /// Appends the leaves of the next block and checks that the tree reaches
+    /// `expected_root`, returning it.
+    ///
+    /// The leaves are inserted in one operation, which fails as a whole if a
+    /// leaf is rejected. Leaves reaching another root are dropped again with
+    /// `truncate`, so the tree is left as before the block. A block without
+    /// leaves must leave the root at `expected_root`.
+    pub fn apply_block(&mut self, leaves: Vec<N>, expected_root: N) -> Result<N, &'static str> {
+        let block = AppliedBlock {
+            size: self.tree.get_size(),
+            root: self.tree.root(),
+        };
+
+        if leaves.is_empty() {
+            if !block
+                .root
+                .as_ref()
+                .is_some_and(|root| self.tree.nodes_eq(root, &expected_root))
+            {
+                return Err("Root mismatch");
+            }
+        } else {
+            let root = self.tree.insert_many(leaves)?;
+            if !self.tree.nodes_eq(&root, &expected_root) {
+                self.tree.truncate(block.size)?;
+                return Err("Root mismatch");
+            }
+        }
+
+        if self.max_reorg_depth > 0 {
+            if self.blocks.len() == self.max_reorg_depth {
+                self.blocks.pop_front();
+            }
+            self.blocks.push_back(block);
+        }
+        Ok(expected_root)
+    }
+
+    /// Reverts the last block, which appended `n_leaves` leaves, and returns
+    /// the root of the tree before it.
+    ///
+    /// If the block is among the last `max_reorg_depth` ones, `n_leaves`
+    /// must match its leaves and the tree is checked to be back at its root.
+    /// Older blocks are reverted by dropping `n_leaves` leaves unchecked. A
+    /// root mismatch after the revert means the mirror has diverged and
+    /// must be rebuilt.
+    pub fn revert_block(&mut self, n_leaves: usize) -> Result<Option<N>, &'static str> {
+        let size = self.tree.get_size();
+        let new_size = size.checked_sub(n_leaves).ok_or("Index out of range")?;
+        if let Some(block) = self.blocks.back() {
+            if block.size != new_size {
+                return Err("Block mismatch");
+            }
+        }
+
+        let root = self.tree.truncate(new_size)?;
+        if let Some(block) = self.blocks.pop_back() {
+            if block.root != root {
+                return Err("Root mismatch");
+            }
+        }
+        Ok(root)
+    }
+
+    /// Returns the number of blocks that can be reverted with their root
+    /// checked.
+    pub fn revertible_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        &self.tree
+    }
+
+    pub fn into_tree(self) -> LeanIMT<N, S> {
+        self.tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn leaves(range: core::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| format!("leaf{}", i)).collect()
+    }
+
+    #[test]
+    fn test_block_sync() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut sync = BlockSync::new(LeanIMT::new(hash), 2);
+
+        // The chain, and the roots after each block
+        let mut chain = LeanIMT::new(hash);
+        let mut roots = Vec::new();
+        for block in [0..3, 3..4, 4..7] {
+            roots.push(chain.insert_many(leaves(block)).unwrap());
+        }
+
+        assert_eq!(
+            sync.apply_block(leaves(0..3), roots[0].clone()),
+            Ok(roots[0].clone())
+        );
+        assert_eq!(
+            sync.apply_block(leaves(3..4), "wrong".to_string())
+                .unwrap_err(),
+            "Root mismatch"
+        );
+        assert_eq!(sync.tree().get_size(), 3);
+        sync.apply_block(leaves(3..4), roots[1].clone()).unwrap();
+        sync.apply_block(vec![], roots[1].clone()).unwrap();
+        sync.apply_block(leaves(4..7), roots[2].clone()).unwrap();
+        assert_eq!(sync.revertible_blocks(), 2);
+
+        // Reorganization of the last two blocks
+        assert_eq!(sync.revert_block(1).unwrap_err(), "Block mismatch");
+        assert_eq!(sync.revert_block(3), Ok(Some(roots[1].clone())));
+        assert_eq!(sync.revert_block(0), Ok(Some(roots[1].clone())));
+        assert_eq!(sync.revertible_blocks(), 0);
+        let root = sync.apply_block(leaves(4..6), "unchecked".to_string());
+        assert_eq!(root.unwrap_err(), "Root mismatch");
+
+        let mut fork = LeanIMT::new(hash);
+        fork.insert_many(leaves(0..4)).unwrap();
+        let root = fork.insert_many(leaves(4..6)).unwrap();
+        sync.apply_block(leaves(4..6), root.clone()).unwrap();
+        assert_eq!(sync.tree().root(), Some(root));
+
+        // Blocks older than the reorg depth are dropped unchecked
+        assert_eq!(sync.revert_block(2), Ok(Some(roots[1].clone())));
+        assert_eq!(sync.revert_block(1), Ok(Some(roots[0].clone())));
+        assert_eq!(sync.revert_block(4).unwrap_err(), "Index out of range");
+    }
+
+    #[test]
+    fn test_block_sync_with_node_eq() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut tree = LeanIMT::new(hash);
+        tree.set_node_eq(Some(|a: &String, b: &String| a.eq_ignore_ascii_case(b)));
+        let mut sync = BlockSync::new(tree, 2);
+
+        // Roots are checked with the comparison of the tree
+        let root = "LEAF0,LEAF1".to_string();
+        assert_eq!(
+            sync.apply_block(leaves(0..2), root.clone()),
+            Ok(root.clone())
+        );
+        assert_eq!(sync.apply_block(vec![], root.clone()), Ok(root));
+    }
+}
@@ -0,0 +1,78 @@
+//! Commitment trees of Tornado Cash style mixers.
+//!
+//! Mixers derived from Tornado Cash keep deposit commitments in the binary
+//! tree of their `MerkleTreeWithHistory` contract: [`LEVELS`] levels, empty
+//! leaves set to [`ZERO_VALUE`], and the zero hashes of each level hardcoded
+//! in the contract. The original pools hash with MiMC, later forks with
+//! Poseidon. The constructors here preset a `FixedDepthLeanIMT` that way, and
+//! `deposit` returns the index of the inserted commitment, the `leafIndex` of
+//! the `Deposit` event that withdrawers need to rebuild their path.
+
+use core::hash::Hash;
+
+use ark_bn254::Fr;
+use ark_ff::MontFp;
+
+use crate::fixed_depth::FixedDepthLeanIMT;
+use crate::hashers::mimc;
+use crate::{IMTHashFunction, LeanIMTStore};
+
+/// Number of levels of the tree, for `2^20` deposits.
+pub const LEVELS: usize = 20;
+
+/// Value of empty leaves, `keccak256("tornado")` reduced modulo the BN254
+/// scalar field order.
+pub const ZERO_VALUE: Fr =
+    MontFp!("21663839004416932945382355908790599225266501822907911457504978515578255421292");
+
+impl FixedDepthLeanIMT<Fr> {
+    /// Creates a commitment tree hashing with `hash`, e.g. the Poseidon of
+    /// two inputs of a fork.
+    pub fn tornado(hash: IMTHashFunction<Fr>) -> Result<Self, &'static str> {
+        Self::with_zero(hash, ZERO_VALUE, LEVELS)
+    }
+
+    /// Creates the commitment tree of the Tornado Cash pools, hashing with
+    /// circomlib's MiMC sponge.
+    pub fn tornado_mimc() -> Result<Self, &'static str> {
+        Self::tornado(mimc::hash_nodes)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> FixedDepthLeanIMT<N, S> {
+    /// Inserts a commitment, returning its index.
+    ///
+    /// Commitments already in the tree are rejected with "Leaf already
+    /// exists" unless the tree accepts duplicates, as the contracts do.
+    pub fn deposit(&mut self, commitment: N) -> Result<usize, &'static str> {
+        self.insert(commitment)?;
+        Ok(self.tree().get_size() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tornado_tree() {
+        let mut tree = FixedDepthLeanIMT::tornado_mimc().unwrap();
+        let zeros = tree.zeros();
+        assert_eq!(zeros.len(), LEVELS + 1);
+        assert_eq!(zeros[0], ZERO_VALUE);
+        assert_eq!(
+            zeros[2],
+            MontFp!("7833458610320835472520144237082236871909694928684820466656733259024982655488")
+        );
+        assert_eq!(tree.root(), Ok(zeros[LEVELS]));
+
+        assert_eq!(tree.deposit(Fr::from(1u64)), Ok(0));
+        assert_eq!(tree.deposit(Fr::from(2u64)), Ok(1));
+        assert_eq!(tree.deposit(Fr::from(1u64)), Err("Leaf already exists"));
+
+        let proof = tree.generate_proof(1).unwrap();
+        assert_eq!(proof.siblings.len(), LEVELS);
+        assert_eq!(proof.siblings[1], tree.zeros()[1]);
+        assert!(tree.verify_proof(&proof));
+    }
+}
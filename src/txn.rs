@@ -0,0 +1,577 @@
+//! Transactions grouping several operations into one commit.
+//!
+//! A [`TreeTxn`] stages inserts, updates and removes against a scratch view
+//! of the tree: each operation runs as on the tree itself, but writes to an
+//! overlay over its store, so later operations and proofs see the earlier
+//! ones while the tree is left untouched. `commit` applies the overlay in a
+//! single tree operation, with one root transition, and `abort` (or dropping
+//! the transaction) discards it. An operation that fails within the
+//! transaction leaves the staged ones in place.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+use crate::changelog::Changelog;
+use crate::stats::Counters;
+use crate::store::{StoreIter, StoreMetadata};
+use crate::{ChangeRecord, HashMap, LeanIMT, LeanIMTStore, MerkleProof};
+
+/// Writes staged over a store, `None` marking a deletion.
+#[derive(Debug)]
+struct Writes<N> {
+    side_nodes: BTreeMap<usize, Option<N>>,
+    leaves: HashMap<N, Option<Vec<usize>>>,
+    leaves_by_index: BTreeMap<usize, Option<N>>,
+    payloads: BTreeMap<usize, Option<Vec<u8>>>,
+    tombstones: HashMap<N, Vec<usize>>,
+}
+
+impl<N> Default for Writes<N> {
+    fn default() -> Self {
+        Writes {
+            side_nodes: BTreeMap::new(),
+            leaves: HashMap::default(),
+            leaves_by_index: BTreeMap::new(),
+            payloads: BTreeMap::new(),
+            tombstones: HashMap::default(),
+        }
+    }
+}
+
+impl<N: Eq + Hash> Writes<N> {
+    fn extend(&mut self, writes: Writes<N>) {
+        self.side_nodes.extend(writes.side_nodes);
+        self.leaves.extend(writes.leaves);
+        self.leaves_by_index.extend(writes.leaves_by_index);
+        self.payloads.extend(writes.payloads);
+        self.tombstones.extend(writes.tombstones);
+    }
+}
+
+/// Store seen by the operations of a transaction: the writes of the current
+/// operation, over those staged by the previous ones, over the tree's store.
+struct Overlay<'a, N, S> {
+    base: &'a S,
+    staged: &'a Writes<N>,
+    pending: Writes<N>,
+}
+
+impl<N, S> Overlay<'_, N, S> {
+    /// Iterates over the entries written to `pending` or `staged`, the first
+    /// one winning.
+    fn written<'b, V>(
+        pending: &'b BTreeMap<usize, Option<V>>,
+        staged: &'b BTreeMap<usize, Option<V>>,
+    ) -> impl Iterator<Item = (usize, &'b V)> {
+        staged
+            .iter()
+            .filter(|(key, _)| !pending.contains_key(key))
+            .chain(pending)
+            .filter_map(|(&key, value)| Some((key, value.as_ref()?)))
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMTStore<N> for Overlay<'_, N, S> {
+    fn side_node(&self, level: usize) -> Result<Option<N>, &'static str> {
+        match self
+            .pending
+            .side_nodes
+            .get(&level)
+            .or_else(|| self.staged.side_nodes.get(&level))
+        {
+            Some(node) => Ok(node.clone()),
+            None => self.base.side_node(level),
+        }
+    }
+
+    fn put_side_node(&mut self, level: usize, node: N) -> Result<(), &'static str> {
+        self.pending.side_nodes.insert(level, Some(node));
+        Ok(())
+    }
+
+    fn delete_side_node(&mut self, level: usize) -> Result<(), &'static str> {
+        self.pending.side_nodes.insert(level, None);
+        Ok(())
+    }
+
+    fn side_nodes(&self) -> StoreIter<'_, (usize, N)> {
+        let written = Self::written(&self.pending.side_nodes, &self.staged.side_nodes);
+        Box::new(
+            self.base
+                .side_nodes()
+                .filter(|entry| {
+                    entry.as_ref().map_or(true, |(level, _)| {
+                        !self.pending.side_nodes.contains_key(level)
+                            && !self.staged.side_nodes.contains_key(level)
+                    })
+                })
+                .chain(written.map(|(level, node)| Ok((level, node.clone())))),
+        )
+    }
+
+    fn leaf_indices(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        match self
+            .pending
+            .leaves
+            .get(leaf)
+            .or_else(|| self.staged.leaves.get(leaf))
+        {
+            Some(indices) => Ok(indices.clone()),
+            None => self.base.leaf_indices(leaf),
+        }
+    }
+
+    fn put_leaf_indices(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.pending.leaves.insert(leaf, Some(indices));
+        Ok(())
+    }
+
+    fn delete_leaf(&mut self, leaf: &N) -> Result<(), &'static str> {
+        self.pending.leaves.insert(leaf.clone(), None);
+        Ok(())
+    }
+
+    fn leaves(&self) -> StoreIter<'_, (N, Vec<usize>)> {
+        let written = self
+            .staged
+            .leaves
+            .iter()
+            .filter(|(leaf, _)| !self.pending.leaves.contains_key(*leaf))
+            .chain(&self.pending.leaves)
+            .filter_map(|(leaf, indices)| Some(Ok((leaf.clone(), indices.clone()?))));
+        Box::new(
+            self.base
+                .leaves()
+                .filter(|entry| {
+                    entry.as_ref().map_or(true, |(leaf, _)| {
+                        !self.pending.leaves.contains_key(leaf)
+                            && !self.staged.leaves.contains_key(leaf)
+                    })
+                })
+                .chain(written),
+        )
+    }
+
+    fn leaf_at(&self, index: usize) -> Result<Option<N>, &'static str> {
+        match self
+            .pending
+            .leaves_by_index
+            .get(&index)
+            .or_else(|| self.staged.leaves_by_index.get(&index))
+        {
+            Some(leaf) => Ok(leaf.clone()),
+            None => self.base.leaf_at(index),
+        }
+    }
+
+    fn put_leaf_at(&mut self, index: usize, leaf: Option<N>) -> Result<(), &'static str> {
+        self.pending.leaves_by_index.insert(index, leaf);
+        Ok(())
+    }
+
+    fn leaves_by_index(&self) -> Result<Vec<Option<N>>, &'static str> {
+        let mut leaves = self.base.leaves_by_index()?;
+        let written = self
+            .staged
+            .leaves_by_index
+            .iter()
+            .chain(&self.pending.leaves_by_index);
+        for (&index, leaf) in written {
+            if index >= leaves.len() {
+                leaves.resize(index + 1, None);
+            }
+            leaves[index] = leaf.clone();
+        }
+        Ok(leaves)
+    }
+
+    fn payload(&self, index: usize) -> Result<Option<Vec<u8>>, &'static str> {
+        match self
+            .pending
+            .payloads
+            .get(&index)
+            .or_else(|| self.staged.payloads.get(&index))
+        {
+            Some(payload) => Ok(payload.clone()),
+            None => self.base.payload(index),
+        }
+    }
+
+    fn put_payload(&mut self, index: usize, payload: Option<Vec<u8>>) -> Result<(), &'static str> {
+        self.pending.payloads.insert(index, payload);
+        Ok(())
+    }
+
+    fn payloads(&self) -> StoreIter<'_, (usize, Vec<u8>)> {
+        let written = Self::written(&self.pending.payloads, &self.staged.payloads);
+        Box::new(
+            self.base
+                .payloads()
+                .filter(|entry| {
+                    entry.as_ref().map_or(true, |(index, _)| {
+                        !self.pending.payloads.contains_key(index)
+                            && !self.staged.payloads.contains_key(index)
+                    })
+                })
+                .chain(written.map(|(index, payload)| Ok((index, payload.clone())))),
+        )
+    }
+
+    fn tombstone(&self, leaf: &N) -> Result<Option<Vec<usize>>, &'static str> {
+        match self
+            .pending
+            .tombstones
+            .get(leaf)
+            .or_else(|| self.staged.tombstones.get(leaf))
+        {
+            Some(indices) => Ok(Some(indices.clone())),
+            None => self.base.tombstone(leaf),
+        }
+    }
+
+    fn put_tombstone(&mut self, leaf: N, indices: Vec<usize>) -> Result<(), &'static str> {
+        self.pending.tombstones.insert(leaf, indices);
+        Ok(())
+    }
+
+    fn tombstones<'a>(&'a self) -> StoreIter<'a, (N, Vec<usize>)>
+    where
+        N: 'a,
+    {
+        let written = self
+            .staged
+            .tombstones
+            .iter()
+            .filter(|(leaf, _)| !self.pending.tombstones.contains_key(*leaf))
+            .chain(&self.pending.tombstones)
+            .map(|(leaf, indices)| Ok((leaf.clone(), indices.clone())));
+        Box::new(
+            self.base
+                .tombstones()
+                .filter(|entry| {
+                    entry.as_ref().map_or(true, |(leaf, _)| {
+                        !self.pending.tombstones.contains_key(leaf)
+                            && !self.staged.tombstones.contains_key(leaf)
+                    })
+                })
+                .chain(written),
+        )
+    }
+
+    fn metadata(&self) -> Result<Option<StoreMetadata<N>>, &'static str> {
+        self.base.metadata()
+    }
+
+    /// The tree saves its metadata when the transaction is committed.
+    fn put_metadata(&mut self, _metadata: StoreMetadata<N>) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Keeps the writes of the operation in `pending`, for the transaction to
+    /// stage them.
+    fn commit(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn rollback(&mut self) {
+        self.pending = Writes::default();
+    }
+}
+
+/// Operations staged against a tree, applied together by `commit`.
+#[derive(Debug)]
+pub struct TreeTxn<'a, N, S> {
+    tree: &'a mut LeanIMT<N, S>,
+    writes: Writes<N>,
+    size: usize,
+    depth: usize,
+    version: u64,
+    /// Change records of the staged operations, if the tree records them.
+    changes: Vec<ChangeRecord<N>>,
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Starts a transaction on the tree.
+    pub fn begin(&mut self) -> TreeTxn<'_, N, S> {
+        TreeTxn {
+            size: self.size,
+            depth: self.depth,
+            version: self.version,
+            tree: self,
+            writes: Writes::default(),
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> TreeTxn<'_, N, S> {
+    /// Returns the tree as it stands with the staged operations, over the
+    /// store of the tree and the staged writes.
+    fn scratch(&self) -> LeanIMT<N, Overlay<'_, N, S>> {
+        let mut changelog = Changelog::new();
+        if self.tree.changelog.is_active() {
+            changelog.set_capacity(usize::MAX, self.version);
+        }
+        LeanIMT {
+            size: self.size,
+            depth: self.depth,
+            arity: self.tree.arity,
+            store: Overlay {
+                base: &self.tree.store,
+                staged: &self.writes,
+                pending: Writes::default(),
+            },
+            duplicate_policy: self.tree.duplicate_policy,
+            removal_policy: self.tree.removal_policy,
+            gaps: None,
+            max_size: self.tree.max_size,
+            hash: self.tree.hash.clone(),
+            leaf_hash: self.tree.leaf_hash,
+            node_eq: self.tree.node_eq,
+            zero: self.tree.zero.clone(),
+            version: self.version,
+            changelog,
+            stats: Counters::default(),
+        }
+    }
+
+    /// Runs an operation on the scratch view and stages its writes.
+    fn stage<T>(
+        &mut self,
+        operation: impl FnOnce(&mut LeanIMT<N, Overlay<'_, N, S>>) -> Result<T, &'static str>,
+    ) -> Result<T, &'static str> {
+        let mut scratch = self.scratch();
+        let result = operation(&mut scratch);
+        // A failed operation has already rolled back its writes and fields
+        let (size, depth, version) = (scratch.size, scratch.depth, scratch.version);
+        let changes = scratch.changelog.take_records();
+        let pending = scratch.store.pending;
+
+        self.size = size;
+        self.depth = depth;
+        self.version = version;
+        self.changes.extend(changes);
+        self.writes.extend(pending);
+        result
+    }
+
+    /// Stages the insertion of `leaf`, returning the root it leads to.
+    pub fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        self.stage(|imt| imt.insert(leaf))
+    }
+
+    pub fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        self.stage(|imt| imt.insert_many(leaves))
+    }
+
+    /// Stages an update, with `sibling_nodes` taken from a proof of the
+    /// transaction, e.g. from `generate_proof`.
+    pub fn update(
+        &mut self,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.stage(|imt| imt.update(old_leaf, new_leaf, sibling_nodes))
+    }
+
+    pub fn update_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.stage(|imt| imt.update_at(index, old_leaf, new_leaf, sibling_nodes))
+    }
+
+    pub fn remove(&mut self, old_leaf: &N, sibling_nodes: &[N]) -> Result<N, &'static str> {
+        self.stage(|imt| imt.remove(old_leaf, sibling_nodes))
+    }
+
+    pub fn remove_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        self.stage(|imt| imt.remove_at(index, old_leaf, sibling_nodes))
+    }
+
+    /// Returns the root with the staged operations, or `None` if the tree
+    /// would be empty.
+    pub fn root(&self) -> Option<N> {
+        self.scratch().root()
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn has(&self, leaf: &N) -> bool {
+        self.scratch().has(leaf)
+    }
+
+    pub fn index_of(&self, leaf: &N) -> Result<usize, &'static str> {
+        self.scratch().index_of(leaf)
+    }
+
+    /// Generates the proof of the leaf at `index` against the staged root.
+    pub fn generate_proof(&self, index: usize) -> Result<MerkleProof<N>, &'static str> {
+        self.scratch().generate_proof(index)
+    }
+
+    /// Applies the staged operations to the tree as a single operation and
+    /// returns the new root.
+    ///
+    /// If a write to the store fails, the tree is left as it was before the
+    /// transaction. Payloads and tombstones, which some stores do not keep,
+    /// are written first; other writes only fail in stores that roll back.
+    pub fn commit(self) -> Result<Option<N>, &'static str> {
+        let TreeTxn {
+            tree,
+            writes,
+            size,
+            depth,
+            version,
+            changes,
+        } = self;
+
+        tree.transaction(|imt, records| {
+            // Stores without payloads or tombstones reject them, so they are
+            // written before anything else
+            for (leaf, indices) in writes.tombstones {
+                imt.store.put_tombstone(leaf, indices)?;
+            }
+            for (index, payload) in writes.payloads {
+                imt.store.put_payload(index, payload)?;
+            }
+            for (level, node) in writes.side_nodes {
+                match node {
+                    Some(node) => imt.store.put_side_node(level, node)?,
+                    None => imt.store.delete_side_node(level)?,
+                }
+            }
+            for (leaf, indices) in writes.leaves {
+                match indices {
+                    Some(indices) => imt.store.put_leaf_indices(leaf, indices)?,
+                    None => imt.store.delete_leaf(&leaf)?,
+                }
+            }
+            for (index, leaf) in writes.leaves_by_index {
+                if let Some(gaps) = &mut imt.gaps {
+                    match leaf {
+                        Some(_) => gaps.remove(&index),
+                        None => gaps.insert(index),
+                    };
+                }
+                imt.store.put_leaf_at(index, leaf)?;
+            }
+            imt.size = size;
+            if let Some(gaps) = &mut imt.gaps {
+                gaps.split_off(&size);
+            }
+            imt.depth = depth;
+            imt.version = version;
+            records.extend(changes);
+            Ok(())
+        })?;
+        Ok(tree.root())
+    }
+
+    /// Discards the staged operations, leaving the tree untouched.
+    pub fn abort(self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_transaction_commits_atomically() {
+        use std::sync::{Arc, Mutex};
+
+        let hash: IMTHashFunction = simple_hash_function;
+        let leaves = vec!["leaf1".to_string(), "leaf2".into(), "leaf3".into()];
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(leaves.clone()).unwrap();
+        imt.set_changelog_capacity(10);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        imt.on_root_change(move |change| {
+            log.lock()
+                .unwrap()
+                .push((change.old_root.cloned(), change.new_root.cloned()));
+        });
+
+        let mut reference = LeanIMT::new(hash);
+        reference.insert_many(leaves).unwrap();
+        reference.insert("leaf4".to_string()).unwrap();
+        let siblings = reference.generate_proof(1).unwrap().siblings;
+        reference
+            .update(&"leaf2".to_string(), "new".to_string(), &siblings)
+            .unwrap();
+        let siblings = reference.generate_proof(0).unwrap().siblings;
+        reference.remove(&"leaf1".to_string(), &siblings).unwrap();
+
+        // Proofs of the transaction see the operations staged before them
+        let mut txn = imt.begin();
+        txn.insert("leaf4".to_string()).unwrap();
+        let siblings = txn.generate_proof(1).unwrap().siblings;
+        txn.update(&"leaf2".to_string(), "new".to_string(), &siblings)
+            .unwrap();
+        // A failed operation leaves the staged ones in place
+        assert_eq!(
+            txn.insert("leaf3".to_string()).unwrap_err(),
+            "Leaf already exists"
+        );
+        let siblings = txn.generate_proof(0).unwrap().siblings;
+        txn.remove(&"leaf1".to_string(), &siblings).unwrap();
+        assert!(txn.has(&"new".to_string()));
+        assert_eq!(txn.index_of(&"leaf4".to_string()), Ok(3));
+        assert_eq!(txn.get_size(), 4);
+        assert_eq!(txn.root(), reference.root());
+
+        assert_eq!(txn.commit(), Ok(reference.root()));
+        assert_eq!(imt.snapshot(), reference.snapshot());
+        assert_eq!(imt.get_version(), reference.get_version());
+
+        // One root transition, with the records of every operation
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(Some("leaf1,leaf2,leaf3".to_string()), reference.root())]
+        );
+        assert_eq!(imt.changes_since(1).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_aborted_transaction_leaves_tree_untouched() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert("leaf1".to_string()).unwrap();
+        let snapshot = imt.snapshot();
+
+        let mut txn = imt.begin();
+        txn.insert_many(vec!["leaf2".into(), "leaf3".into()])
+            .unwrap();
+        assert_eq!(txn.root(), Some("leaf1,leaf2,leaf3".to_string()));
+        txn.abort();
+        assert_eq!(imt.snapshot(), snapshot);
+
+        let mut txn = imt.begin();
+        txn.insert("leaf2".to_string()).unwrap();
+        drop(txn);
+        assert_eq!(imt.snapshot(), snapshot);
+        assert_eq!(imt.begin().commit(), Ok(Some("leaf1".to_string())));
+    }
+}
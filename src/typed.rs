@@ -0,0 +1,309 @@
+//! Typed wrappers telling roots and leaves apart.
+//!
+//! Roots, leaves and siblings all have the node type, so a root passed where
+//! a leaf is expected still compiles. [`Root`] and [`Leaf`] wrap a node to
+//! keep them apart in application code: `typed_root` and `typed_leaf_at`
+//! return them, and `into_inner` gives the node back for the node-typed
+//! methods of the tree. Both print and parse as their node, and convert to
+//! hex and base64 through a [`NodeCodec`].
+//!
+//! [`LeafIndex`] keeps leaf indices as `u64` whatever the width of `usize`,
+//! with checked conversions and shifts, so that an index or path received
+//! from a 64-bit host is not silently truncated on 32-bit targets such as
+//! wasm32.
+
+use alloc::string::String;
+use core::fmt;
+use core::hash::Hash;
+use core::str::FromStr;
+
+#[cfg(feature = "borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{decode_base64, decode_hex, encode_base64, encode_hex};
+use crate::{LeanIMT, LeanIMTStore, MerkleProof, NodeCodec};
+
+macro_rules! typed_node {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+        #[repr(transparent)]
+        pub struct $name<N>(pub N);
+
+        impl<N> $name<N> {
+            pub fn into_inner(self) -> N {
+                self.0
+            }
+
+            pub fn as_node(&self) -> &N {
+                &self.0
+            }
+
+            /// Encodes the node as `0x`-prefixed hex of its bytes from `C`.
+            pub fn to_hex<C: NodeCodec<N>>(&self) -> String {
+                encode_hex(&C::encode(&self.0))
+            }
+
+            /// Decodes a node written by `to_hex`.
+            pub fn from_hex<C: NodeCodec<N>>(text: &str) -> Result<Self, &'static str> {
+                C::decode(&decode_hex(text)?).map($name)
+            }
+
+            /// Encodes the node as base64 of its bytes from `C`.
+            pub fn to_base64<C: NodeCodec<N>>(&self) -> String {
+                encode_base64(&C::encode(&self.0))
+            }
+
+            /// Decodes a node written by `to_base64`.
+            pub fn from_base64<C: NodeCodec<N>>(text: &str) -> Result<Self, &'static str> {
+                C::decode(&decode_base64(text)?).map($name)
+            }
+        }
+
+        impl<N> From<N> for $name<N> {
+            fn from(node: N) -> Self {
+                $name(node)
+            }
+        }
+
+        impl<N> AsRef<N> for $name<N> {
+            fn as_ref(&self) -> &N {
+                &self.0
+            }
+        }
+
+        impl<N: fmt::Display> fmt::Display for $name<N> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl<N: FromStr> FromStr for $name<N> {
+            type Err = N::Err;
+
+            fn from_str(text: &str) -> Result<Self, Self::Err> {
+                text.parse().map($name)
+            }
+        }
+    };
+}
+
+typed_node! {
+    /// Root of a tree.
+    Root
+}
+
+typed_node! {
+    /// Leaf of a tree, as stored in it.
+    Leaf
+}
+
+/// Index of a leaf, or of a node within its level, as a `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "borsh", derive(BorshSerialize, BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+#[repr(transparent)]
+pub struct LeafIndex(pub u64);
+
+impl LeafIndex {
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    /// Converts the index to a `usize`, failing with "Leaf index out of
+    /// range" if it does not fit, as on 32-bit targets.
+    pub fn to_usize(self) -> Result<usize, &'static str> {
+        usize::try_from(self.0).map_err(|_| "Leaf index out of range")
+    }
+
+    /// Returns the bit of the index at `level`, which is false past its 64
+    /// bits instead of overflowing the shift.
+    pub fn bit(self, level: usize) -> bool {
+        u32::try_from(level)
+            .ok()
+            .and_then(|level| self.0.checked_shr(level))
+            .is_some_and(|bits| bits & 1 == 1)
+    }
+
+    /// Returns the index of the node covering this index `levels` levels up
+    /// a tree of `arity`, which is 0 once the levels above have a single
+    /// node.
+    pub fn ancestor(self, levels: usize, arity: usize) -> Self {
+        let width = u32::try_from(levels)
+            .ok()
+            .zip(u64::try_from(arity).ok())
+            .and_then(|(levels, arity)| arity.checked_pow(levels));
+        LeafIndex(width.map_or(0, |width| self.0 / width))
+    }
+
+    /// Returns the index with its bit at `level` set, or `None` past its 64
+    /// bits.
+    pub fn with_bit(self, level: usize) -> Option<Self> {
+        let bit = u32::try_from(level)
+            .ok()
+            .and_then(|level| 1u64.checked_shl(level))?;
+        Some(LeafIndex(self.0 | bit))
+    }
+
+    pub fn checked_add(self, offset: u64) -> Option<Self> {
+        self.0.checked_add(offset).map(LeafIndex)
+    }
+}
+
+// Converting a usize to a LeafIndex cannot truncate it
+const _: () = assert!(usize::BITS <= u64::BITS);
+
+impl From<usize> for LeafIndex {
+    fn from(index: usize) -> Self {
+        // Never saturates, as usize is at most 64 bits wide
+        LeafIndex(u64::try_from(index).unwrap_or(u64::MAX))
+    }
+}
+
+impl From<LeafIndex> for u64 {
+    fn from(index: LeafIndex) -> Self {
+        index.0
+    }
+}
+
+impl TryFrom<LeafIndex> for usize {
+    type Error = &'static str;
+
+    fn try_from(index: LeafIndex) -> Result<Self, Self::Error> {
+        index.to_usize()
+    }
+}
+
+impl fmt::Display for LeafIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Returns the root of the tree, typed as a root.
+    pub fn typed_root(&self) -> Option<Root<N>> {
+        self.root().map(Root)
+    }
+
+    /// Returns the leaf at `index`, typed as a leaf.
+    pub fn typed_leaf_at(&self, index: usize) -> Result<Leaf<N>, &'static str> {
+        self.leaf_at(index).map(Leaf)
+    }
+
+    /// Returns the leaf at `index`, typed as a leaf, failing with "Leaf
+    /// index out of range" if the index does not fit a `usize`.
+    pub fn leaf_at_index(&self, index: LeafIndex) -> Result<Leaf<N>, &'static str> {
+        self.typed_leaf_at(index.to_usize()?)
+    }
+
+    /// Returns the index of a leaf, as by `index_of`.
+    pub fn leaf_index_of(&self, leaf: &N) -> Result<LeafIndex, &'static str> {
+        self.index_of(leaf).map(LeafIndex::from)
+    }
+}
+
+impl<N: Clone> MerkleProof<N> {
+    /// Returns the root of the proof, typed as a root.
+    pub fn typed_root(&self) -> Root<N> {
+        Root(self.root.clone())
+    }
+
+    /// Returns the proven leaf, typed as a leaf.
+    pub fn typed_leaf(&self) -> Leaf<N> {
+        Leaf(self.leaf.clone())
+    }
+
+    /// Returns the path of the proof, whose bit `i` tells the side of the
+    /// node hashed with `siblings[i]`.
+    pub fn path(&self) -> LeafIndex {
+        LeafIndex::from(self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{BytesCodec, StringCodec};
+    use crate::IMTHashFunction;
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_typed_nodes() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        assert_eq!(imt.typed_root(), None);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+
+        let root = imt.typed_root().unwrap();
+        assert_eq!(root, Root("leaf1,leaf2".to_string()));
+        assert_eq!(root.to_string(), "leaf1,leaf2");
+        assert_eq!("leaf1,leaf2".parse(), Ok(root.clone()));
+        assert_eq!(imt.typed_leaf_at(1), Ok(Leaf("leaf2".to_string())));
+
+        let proof = imt.generate_proof(1).unwrap();
+        assert_eq!(proof.typed_root(), root);
+        assert_eq!(proof.typed_leaf().into_inner(), "leaf2");
+
+        assert_eq!(root.to_hex::<StringCodec>(), "0x6c656166312c6c65616632");
+        assert_eq!(root.to_base64::<StringCodec>(), "bGVhZjEsbGVhZjI=");
+        assert_eq!(
+            Root::from_base64::<StringCodec>("bGVhZjEsbGVhZjI="),
+            Ok(root)
+        );
+
+        let leaf = Leaf(vec![0xab, 0xcd]);
+        assert_eq!(leaf.to_hex::<BytesCodec>(), "0xabcd");
+        assert_eq!(Leaf::from_hex::<BytesCodec>("0xabcd"), Ok(leaf));
+    }
+
+    #[test]
+    fn test_leaf_index() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        imt.insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        let index = imt.leaf_index_of(&"leaf2".to_string()).unwrap();
+        assert_eq!(index, LeafIndex(1));
+        assert_eq!(imt.leaf_at_index(index), Ok(Leaf("leaf2".to_string())));
+        assert_eq!(imt.generate_proof(1).unwrap().path(), index);
+
+        let index = LeafIndex(u64::MAX);
+        assert!(index.bit(63));
+        assert!(!index.bit(64));
+        assert_eq!(index.checked_add(1), None);
+        assert_eq!(LeafIndex(1).with_bit(63), Some(LeafIndex(1 | 1 << 63)));
+        assert_eq!(LeafIndex(1).with_bit(64), None);
+        assert_eq!(LeafIndex::from(usize::MAX).get(), usize::MAX as u64);
+        assert_eq!(LeafIndex(100).ancestor(2, 3), LeafIndex(11));
+        assert_eq!(index.ancestor(64, 2), LeafIndex(0));
+        if usize::BITS < 64 {
+            assert_eq!(index.to_usize(), Err("Leaf index out of range"));
+        }
+
+        // Proofs deeper than the width of the index verify without
+        // overflowing the shifts of the path
+        let siblings = vec!["s".to_string(); 70];
+        let root = siblings.iter().fold("a".to_string(), |node, sibling| {
+            hash(vec![node, sibling.clone()])
+        });
+        let proof = MerkleProof {
+            root,
+            leaf: "a".to_string(),
+            index: 0,
+            siblings,
+        };
+        assert!(proof.verify(hash));
+    }
+}
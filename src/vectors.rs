@@ -0,0 +1,147 @@
+//! Checks of a tree configuration against reference test vectors.
+//!
+//! A hasher written for this crate must reproduce the roots and proofs of the
+//! zk-kit JS and Solidity trees before the roots it produces are trusted on
+//! chain. Those implementations can dump their outputs as JSON test vectors,
+//! and [`check_vectors`] rebuilds each of them with a tree configured by the
+//! caller, failing on the first root or proof that differs.
+//!
+//! A vector file holds an array of objects with the inserted `leaves`, the
+//! expected `root` (`null` for no leaves), and optionally a `name` and the
+//! expected `proofs`, shaped as [`MerkleProof`]s:
+//!
+//! ```json
+//! [{"name": "three leaves", "leaves": ["1", "2", "3"], "root": "...",
+//!   "proofs": [{"root": "...", "leaf": "2", "index": 1, "siblings": ["1", "3"]}]}]
+//! ```
+//!
+//! zk-kit nodes are bigints, which are written as decimal strings.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use core::hash::Hash;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{IMTNode, LeanIMT, LeanIMTStore, MerkleProof};
+
+/// Expected outputs of a reference tree for a list of leaves.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "N: Deserialize<'de>"))]
+pub struct TestVector<N = IMTNode> {
+    #[serde(default)]
+    pub name: String,
+    pub leaves: Vec<N>,
+    pub root: Option<N>,
+    /// Proofs of some of the leaves, found in the tree by value.
+    #[serde(default)]
+    pub proofs: Vec<MerkleProof<N>>,
+}
+
+impl<N: Clone + Eq + Hash> TestVector<N> {
+    /// Inserts the leaves into `tree`, which must be empty, and checks its
+    /// root and proofs against the vector.
+    pub fn check<S: LeanIMTStore<N>>(&self, tree: &mut LeanIMT<N, S>) -> Result<(), &'static str> {
+        if tree.get_size() != 0 {
+            return Err("Tree is not empty");
+        }
+        if !self.leaves.is_empty() {
+            tree.insert_many(self.leaves.clone())?;
+        }
+        if tree.root() != self.root {
+            return Err("Root does not match the test vector");
+        }
+        for proof in &self.proofs {
+            let index = tree.index_of(&proof.leaf)?;
+            if tree.generate_proof(index)? != *proof || !tree.verify_proof(proof) {
+                return Err("Proof does not match the test vector");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the test vectors of the JSON file at `path`.
+pub fn load_vectors<N: DeserializeOwned>(
+    path: impl AsRef<Path>,
+) -> Result<Vec<TestVector<N>>, &'static str> {
+    let file = File::open(path).map_err(|_| "Failed to open test vectors")?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|_| "Invalid test vectors")
+}
+
+/// Checks every test vector of the JSON file at `path` against a tree
+/// returned by `new_tree`, which is called once per vector and should return
+/// an empty tree with the hash function, zero value and leaf hash under test.
+///
+/// Returns the number of vectors checked.
+pub fn check_vectors<N, S>(
+    path: impl AsRef<Path>,
+    new_tree: impl Fn() -> LeanIMT<N, S>,
+) -> Result<usize, &'static str>
+where
+    N: Clone + Eq + Hash + DeserializeOwned,
+    S: LeanIMTStore<N>,
+{
+    let vectors = load_vectors(path)?;
+    for vector in &vectors {
+        vector.check(&mut new_tree())?;
+    }
+    Ok(vectors.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IMTHashFunction;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_check_vectors() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let path =
+            std::env::temp_dir().join(format!("lean-imt-vectors-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"[
+                {"name": "empty", "leaves": [], "root": null},
+                {"leaves": ["a", "b", "c"], "root": "a,b,c", "proofs": [
+                    {"root": "a,b,c", "leaf": "b", "index": 1, "siblings": ["a", "c"]},
+                    {"root": "a,b,c", "leaf": "c", "index": 1, "siblings": ["a,b"]}
+                ]}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(check_vectors(&path, || LeanIMT::new(hash)), Ok(2));
+
+        // A tree hashing differently fails
+        let reversed: IMTHashFunction =
+            |nodes| nodes.into_iter().rev().collect::<Vec<_>>().join(",");
+        assert_eq!(
+            check_vectors(&path, || LeanIMT::new(reversed)),
+            Err("Root does not match the test vector")
+        );
+
+        let mut vectors = load_vectors::<String>(&path).unwrap();
+        vectors[1].proofs[0].siblings.reverse();
+        assert_eq!(
+            vectors[1].check(&mut LeanIMT::new(hash)),
+            Err("Proof does not match the test vector")
+        );
+
+        std::fs::write(&path, "{}").unwrap();
+        assert_eq!(
+            check_vectors(&path, || LeanIMT::new(hash)),
+            Err("Invalid test vectors")
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            check_vectors(&path, || LeanIMT::new(hash)),
+            Err("Failed to open test vectors")
+        );
+    }
+}
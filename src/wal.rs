@@ -0,0 +1,710 @@
+//! Append-only operation log for rebuilding a tree after a crash.
+//!
+//! A log is a sequence of length-prefixed [`LogRecord`] frames starting with a
+//! snapshot of the tree, followed by the operations applied to it. Operations
+//! are written once applied, so the log only holds operations that took
+//! effect. Applying operations is deterministic: a record that fails on
+//! replay does not match the tree it is applied to, and fails the replay.
+//!
+//! An operation is lost if the process crashes after applying it and before
+//! its record is written. If writing the record fails, the operation stays
+//! applied to the tree, and the error tells that the log misses it.
+//!
+//! A record cut short by a crash during its write is ignored when reading the
+//! log, and [`compact`] folds old records into a new base snapshot.
+//!
+//! Leaves are logged as stored in the tree, hashed by its leaf hash if it has
+//! one, so replaying a log needs no leaf hash. Set it again on the replayed
+//! tree before inserting into it.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+#[cfg(feature = "std")]
+use core::marker::PhantomData;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+use crate::codec::{write_bytes, write_varint, ByteReader};
+#[cfg(feature = "std")]
+use crate::MemoryStore;
+use crate::{
+    DuplicatePolicy, IMTHashFunction, IMTNode, LeanIMT, LeanIMTSnapshot, LeanIMTStore, NodeCodec,
+    RemovalPolicy,
+};
+
+const SNAPSHOT: u8 = 0;
+const INSERT: u8 = 1;
+const INSERT_MANY: u8 = 2;
+const UPDATE: u8 = 3;
+const REMOVE: u8 = 4;
+const SET_DUPLICATE_POLICY: u8 = 5;
+const SET_REMOVAL_POLICY: u8 = 6;
+const INSERT_WITH_VALUE: u8 = 7;
+const TRUNCATE: u8 = 8;
+
+/// Entry of an operation log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogRecord<N = IMTNode> {
+    /// Replaces the tree with the snapshot.
+    Snapshot(LeanIMTSnapshot<N>),
+    Insert(N),
+    InsertMany(Vec<N>),
+    Update {
+        index: usize,
+        old_leaf: N,
+        new_leaf: N,
+        siblings: Vec<N>,
+    },
+    Remove {
+        index: usize,
+        leaf: N,
+        siblings: Vec<N>,
+    },
+    SetDuplicatePolicy(DuplicatePolicy),
+    SetRemovalPolicy(RemovalPolicy),
+    InsertWithValue {
+        leaf: N,
+        value: Vec<u8>,
+    },
+    Truncate(usize),
+}
+
+impl<N: Clone + Eq + Hash> LogRecord<N> {
+    /// Applies the operation to `tree`.
+    ///
+    /// Fails for snapshots, which replace the tree instead.
+    pub fn apply<S: LeanIMTStore<N>>(&self, tree: &mut LeanIMT<N, S>) -> Result<(), &'static str> {
+        match self {
+            LogRecord::Snapshot(_) => return Err("Cannot apply a snapshot"),
+            LogRecord::Insert(leaf) => {
+                tree.insert(leaf.clone())?;
+            }
+            LogRecord::InsertMany(leaves) => {
+                tree.insert_many(leaves.clone())?;
+            }
+            LogRecord::Update {
+                index,
+                old_leaf,
+                new_leaf,
+                siblings,
+            } => {
+                tree.update_at(*index, old_leaf, new_leaf.clone(), siblings)?;
+            }
+            LogRecord::Remove {
+                index,
+                leaf,
+                siblings,
+            } => {
+                tree.remove_at(*index, leaf, siblings)?;
+            }
+            LogRecord::SetDuplicatePolicy(policy) => tree.set_duplicate_policy(*policy)?,
+            LogRecord::SetRemovalPolicy(policy) => tree.set_removal_policy(*policy),
+            LogRecord::InsertWithValue { leaf, value } => {
+                tree.insert_with_value(leaf.clone(), value.clone())?;
+            }
+            LogRecord::Truncate(new_size) => {
+                tree.truncate(*new_size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<N> LogRecord<N> {
+    /// Encodes the record, without the length prefix of its frame.
+    pub fn to_bytes<C: NodeCodec<N>>(&self) -> Result<Vec<u8>, &'static str> {
+        fn write_nodes<N, C: NodeCodec<N>>(bytes: &mut Vec<u8>, nodes: &[N]) {
+            write_varint(bytes, nodes.len() as u64);
+            for node in nodes {
+                write_bytes(bytes, &C::encode(node));
+            }
+        }
+
+        let mut bytes = Vec::new();
+        match self {
+            LogRecord::Snapshot(snapshot) => {
+                bytes.push(SNAPSHOT);
+                bytes.extend_from_slice(&snapshot.to_bytes::<C>()?);
+            }
+            LogRecord::Insert(leaf) => {
+                bytes.push(INSERT);
+                write_bytes(&mut bytes, &C::encode(leaf));
+            }
+            LogRecord::InsertMany(leaves) => {
+                bytes.push(INSERT_MANY);
+                write_nodes::<N, C>(&mut bytes, leaves);
+            }
+            LogRecord::Update {
+                index,
+                old_leaf,
+                new_leaf,
+                siblings,
+            } => {
+                bytes.push(UPDATE);
+                write_varint(&mut bytes, *index as u64);
+                write_bytes(&mut bytes, &C::encode(old_leaf));
+                write_bytes(&mut bytes, &C::encode(new_leaf));
+                write_nodes::<N, C>(&mut bytes, siblings);
+            }
+            LogRecord::Remove {
+                index,
+                leaf,
+                siblings,
+            } => {
+                bytes.push(REMOVE);
+                write_varint(&mut bytes, *index as u64);
+                write_bytes(&mut bytes, &C::encode(leaf));
+                write_nodes::<N, C>(&mut bytes, siblings);
+            }
+            LogRecord::SetDuplicatePolicy(policy) => {
+                bytes.push(SET_DUPLICATE_POLICY);
+                bytes.push(match policy {
+                    DuplicatePolicy::Reject => 0,
+                    DuplicatePolicy::Allow => 1,
+                });
+            }
+            LogRecord::SetRemovalPolicy(policy) => {
+                bytes.push(SET_REMOVAL_POLICY);
+                bytes.push(match policy {
+                    RemovalPolicy::Forget => 0,
+                    RemovalPolicy::Tombstone => 1,
+                    RemovalPolicy::RejectReinsertion => 2,
+                });
+            }
+            LogRecord::InsertWithValue { leaf, value } => {
+                bytes.push(INSERT_WITH_VALUE);
+                write_bytes(&mut bytes, &C::encode(leaf));
+                write_bytes(&mut bytes, value);
+            }
+            LogRecord::Truncate(new_size) => {
+                bytes.push(TRUNCATE);
+                write_varint(&mut bytes, *new_size as u64);
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Decodes a record written by `to_bytes`.
+    pub fn from_bytes<C: NodeCodec<N>>(bytes: &[u8]) -> Result<Self, &'static str> {
+        fn read_nodes<N, C: NodeCodec<N>>(
+            reader: &mut ByteReader,
+            limit: usize,
+        ) -> Result<Vec<N>, &'static str> {
+            let count = reader.read_usize()?;
+            let mut nodes = Vec::with_capacity(count.min(limit));
+            for _ in 0..count {
+                nodes.push(C::decode(reader.read_bytes()?)?);
+            }
+            Ok(nodes)
+        }
+
+        let (&tag, rest) = bytes.split_first().ok_or("Unexpected end of input")?;
+        if tag == SNAPSHOT {
+            return Ok(LogRecord::Snapshot(LeanIMTSnapshot::from_bytes::<C>(rest)?));
+        }
+
+        let mut reader = ByteReader::new(rest);
+        let record = match tag {
+            INSERT => LogRecord::Insert(C::decode(reader.read_bytes()?)?),
+            INSERT_MANY => LogRecord::InsertMany(read_nodes::<N, C>(&mut reader, bytes.len())?),
+            UPDATE => LogRecord::Update {
+                index: reader.read_usize()?,
+                old_leaf: C::decode(reader.read_bytes()?)?,
+                new_leaf: C::decode(reader.read_bytes()?)?,
+                siblings: read_nodes::<N, C>(&mut reader, bytes.len())?,
+            },
+            REMOVE => LogRecord::Remove {
+                index: reader.read_usize()?,
+                leaf: C::decode(reader.read_bytes()?)?,
+                siblings: read_nodes::<N, C>(&mut reader, bytes.len())?,
+            },
+            SET_DUPLICATE_POLICY => LogRecord::SetDuplicatePolicy(match reader.read_byte()? {
+                0 => DuplicatePolicy::Reject,
+                1 => DuplicatePolicy::Allow,
+                _ => return Err("Invalid duplicate policy"),
+            }),
+            SET_REMOVAL_POLICY => LogRecord::SetRemovalPolicy(match reader.read_byte()? {
+                0 => RemovalPolicy::Forget,
+                1 => RemovalPolicy::Tombstone,
+                2 => RemovalPolicy::RejectReinsertion,
+                _ => return Err("Invalid removal policy"),
+            }),
+            INSERT_WITH_VALUE => LogRecord::InsertWithValue {
+                leaf: C::decode(reader.read_bytes()?)?,
+                value: reader.read_bytes()?.to_vec(),
+            },
+            TRUNCATE => LogRecord::Truncate(reader.read_usize()?),
+            _ => return Err("Invalid log record"),
+        };
+        if !reader.is_empty() {
+            return Err("Trailing bytes");
+        }
+        Ok(record)
+    }
+}
+
+/// Appends `record` to `log` as a length-prefixed frame.
+pub fn write_record<N, C: NodeCodec<N>>(
+    log: &mut Vec<u8>,
+    record: &LogRecord<N>,
+) -> Result<(), &'static str> {
+    write_bytes(log, &record.to_bytes::<C>()?);
+    Ok(())
+}
+
+/// Reads the records of a log.
+///
+/// A truncated last frame, left by a crash during its write, is ignored.
+pub fn read_log<N, C: NodeCodec<N>>(log: &[u8]) -> Result<Vec<LogRecord<N>>, &'static str> {
+    let mut reader = ByteReader::new(log);
+    let mut records = Vec::new();
+    while !reader.is_empty() {
+        let frame = match reader.read_bytes() {
+            Ok(frame) => frame,
+            Err("Unexpected end of input") => break,
+            Err(error) => return Err(error),
+        };
+        records.push(LogRecord::from_bytes::<C>(frame)?);
+    }
+    Ok(records)
+}
+
+/// Rebuilds the tree recorded in a log.
+pub fn replay<N: Clone + Eq + Hash, C: NodeCodec<N>>(
+    log: &[u8],
+    hash: IMTHashFunction<N>,
+) -> Result<LeanIMT<N>, &'static str> {
+    replay_records(read_log::<N, C>(log)?, hash)
+}
+
+fn replay_records<N: Clone + Eq + Hash>(
+    records: Vec<LogRecord<N>>,
+    hash: IMTHashFunction<N>,
+) -> Result<LeanIMT<N>, &'static str> {
+    let mut records = records.into_iter();
+    let Some(LogRecord::Snapshot(snapshot)) = records.next() else {
+        return Err("Log does not start with a snapshot");
+    };
+
+    let mut tree = LeanIMT::from_snapshot(snapshot, hash)?;
+    replay_onto(&mut tree, records, hash)?;
+    Ok(tree)
+}
+
+/// Applies `records` to `tree`, snapshots replacing it.
+pub(crate) fn replay_onto<N: Clone + Eq + Hash>(
+    tree: &mut LeanIMT<N>,
+    records: impl IntoIterator<Item = LogRecord<N>>,
+    hash: IMTHashFunction<N>,
+) -> Result<(), &'static str> {
+    for record in records {
+        match record {
+            LogRecord::Snapshot(snapshot) => *tree = LeanIMT::from_snapshot(snapshot, hash)?,
+            record => record.apply(tree)?,
+        }
+    }
+    Ok(())
+}
+
+/// Compacts a log into a snapshot of its state followed by its last `tail`
+/// records.
+pub fn compact<N: Clone + Eq + Hash, C: NodeCodec<N>>(
+    log: &[u8],
+    hash: IMTHashFunction<N>,
+    tail: usize,
+) -> Result<Vec<u8>, &'static str> {
+    let mut records = read_log::<N, C>(log)?;
+    let tail = records.split_off(records.len().saturating_sub(tail).max(1));
+    let tree = replay_records(records, hash)?;
+
+    let mut compacted = Vec::new();
+    write_record::<N, C>(&mut compacted, &LogRecord::Snapshot(tree.snapshot()?))?;
+    for record in &tail {
+        write_record::<N, C>(&mut compacted, record)?;
+    }
+    Ok(compacted)
+}
+
+/// Log writer appending records to `W`, encoded with the codec `C`.
+///
+/// Each record is flushed as it is appended. `flush` does not sync files to
+/// disk: use a writer that does when records must survive a power loss.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct WriteAheadLog<W, C> {
+    writer: W,
+    codec: PhantomData<fn() -> C>,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write, C> WriteAheadLog<W, C> {
+    /// Starts a new log on `writer`, with a snapshot of `tree` as its base.
+    pub fn create<N, S>(writer: W, tree: &LeanIMT<N, S>) -> Result<Self, &'static str>
+    where
+        N: Clone + Eq + Hash,
+        S: LeanIMTStore<N>,
+        C: NodeCodec<N>,
+    {
+        let mut log = Self::append_to(writer);
+        log.append(&LogRecord::Snapshot(tree.snapshot()?))?;
+        Ok(log)
+    }
+
+    /// Continues an existing log, `writer` being positioned at its end.
+    pub fn append_to(writer: W) -> Self {
+        WriteAheadLog {
+            writer,
+            codec: PhantomData,
+        }
+    }
+
+    pub fn append<N>(&mut self, record: &LogRecord<N>) -> Result<(), &'static str>
+    where
+        C: NodeCodec<N>,
+    {
+        let mut frame = Vec::new();
+        write_record::<N, C>(&mut frame, record)?;
+        self.writer
+            .write_all(&frame)
+            .and_then(|_| self.writer.flush())
+            .map_err(|_| "Log write failed")
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Tree whose operations are written to a log once applied.
+///
+/// Operations that fail leave no record.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct LoggedLeanIMT<W, C, N = IMTNode, S = MemoryStore<N>> {
+    tree: LeanIMT<N, S>,
+    log: WriteAheadLog<W, C>,
+}
+
+#[cfg(feature = "std")]
+impl<W, C, N, S> LoggedLeanIMT<W, C, N, S>
+where
+    W: Write,
+    C: NodeCodec<N>,
+    N: Clone + Eq + Hash,
+    S: LeanIMTStore<N>,
+{
+    pub fn new(tree: LeanIMT<N, S>, log: WriteAheadLog<W, C>) -> Self {
+        LoggedLeanIMT { tree, log }
+    }
+
+    pub fn insert(&mut self, leaf: N) -> Result<N, &'static str> {
+        let leaf = self.tree.hash_leaf(leaf);
+        let root = self.apply_hashed(|tree| tree.insert(leaf.clone()))?;
+        self.log.append(&LogRecord::Insert(leaf))?;
+        Ok(root)
+    }
+
+    pub fn insert_many(&mut self, leaves: Vec<N>) -> Result<N, &'static str> {
+        let leaves: Vec<N> = match self.tree.leaf_hash {
+            Some(_) => leaves
+                .into_iter()
+                .map(|leaf| self.tree.hash_leaf(leaf))
+                .collect(),
+            None => leaves,
+        };
+        let root = self.apply_hashed(|tree| tree.insert_many(leaves.clone()))?;
+        self.log.append(&LogRecord::InsertMany(leaves))?;
+        Ok(root)
+    }
+
+    pub fn insert_with_value(&mut self, leaf: N, value: Vec<u8>) -> Result<N, &'static str> {
+        let leaf = self.tree.hash_leaf(leaf);
+        let root = self.apply_hashed(|tree| tree.insert_with_value(leaf.clone(), value.clone()))?;
+        self.log
+            .append(&LogRecord::InsertWithValue { leaf, value })?;
+        Ok(root)
+    }
+
+    /// Updates the first occurrence of `old_leaf`, logged by its index.
+    pub fn update(
+        &mut self,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        let index = self.tree.index_of(old_leaf)?;
+        self.update_at(index, old_leaf, new_leaf, sibling_nodes)
+    }
+
+    pub fn update_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        new_leaf: N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        // Updating to zero removes the leaf, whatever the leaf hash
+        let new_leaf = if new_leaf == self.tree.zero {
+            new_leaf
+        } else {
+            self.tree.hash_leaf(new_leaf)
+        };
+        let root = self.apply_hashed(|tree| {
+            tree.update_at(index, old_leaf, new_leaf.clone(), sibling_nodes)
+        })?;
+        self.log.append(&LogRecord::Update {
+            index,
+            old_leaf: old_leaf.clone(),
+            new_leaf,
+            siblings: sibling_nodes.to_vec(),
+        })?;
+        Ok(root)
+    }
+
+    /// Removes the first occurrence of `old_leaf`, logged by its index.
+    pub fn remove(&mut self, old_leaf: &N, sibling_nodes: &[N]) -> Result<N, &'static str> {
+        let index = self.tree.index_of(old_leaf)?;
+        self.remove_at(index, old_leaf, sibling_nodes)
+    }
+
+    pub fn remove_at(
+        &mut self,
+        index: usize,
+        old_leaf: &N,
+        sibling_nodes: &[N],
+    ) -> Result<N, &'static str> {
+        let root = self.tree.remove_at(index, old_leaf, sibling_nodes)?;
+        self.log.append(&LogRecord::Remove {
+            index,
+            leaf: old_leaf.clone(),
+            siblings: sibling_nodes.to_vec(),
+        })?;
+        Ok(root)
+    }
+
+    pub fn truncate(&mut self, new_size: usize) -> Result<Option<N>, &'static str> {
+        let root = self.tree.truncate(new_size)?;
+        self.log.append(&LogRecord::Truncate(new_size))?;
+        Ok(root)
+    }
+
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) -> Result<(), &'static str> {
+        self.tree.set_duplicate_policy(policy)?;
+        self.log.append(&LogRecord::SetDuplicatePolicy(policy))
+    }
+
+    pub fn set_removal_policy(&mut self, policy: RemovalPolicy) -> Result<(), &'static str> {
+        self.tree.set_removal_policy(policy);
+        self.log.append(&LogRecord::SetRemovalPolicy(policy))
+    }
+
+    pub fn tree(&self) -> &LeanIMT<N, S> {
+        &self.tree
+    }
+
+    pub fn into_parts(self) -> (LeanIMT<N, S>, WriteAheadLog<W, C>) {
+        (self.tree, self.log)
+    }
+
+    /// Runs `operation` on leaves hashed already, as they were logged.
+    fn apply_hashed<T>(&mut self, operation: impl FnOnce(&mut LeanIMT<N, S>) -> T) -> T {
+        let leaf_hash = self.tree.leaf_hash.take();
+        let result = operation(&mut self.tree);
+        self.tree.leaf_hash = leaf_hash;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    fn sample_log(hash: IMTHashFunction) -> (LeanIMT, Vec<u8>) {
+        let mut tree = LeanIMT::new(hash);
+        let mut log = Vec::new();
+        write_record::<_, StringCodec>(&mut log, &LogRecord::Snapshot(tree.snapshot().unwrap()))
+            .unwrap();
+
+        let records = vec![
+            LogRecord::InsertMany((1..=4).map(|i| format!("leaf{}", i)).collect()),
+            LogRecord::Insert("leaf5".to_string()),
+            LogRecord::Remove {
+                index: 1,
+                leaf: "leaf2".to_string(),
+                siblings: vec![
+                    "leaf1".to_string(),
+                    "leaf3,leaf4".to_string(),
+                    "leaf5".to_string(),
+                ],
+            },
+            LogRecord::Update {
+                index: 4,
+                old_leaf: "leaf5".to_string(),
+                new_leaf: "leaf6".to_string(),
+                siblings: vec!["leaf1,0,leaf3,leaf4".to_string()],
+            },
+        ];
+        for record in &records {
+            record.apply(&mut tree).unwrap();
+            write_record::<_, StringCodec>(&mut log, record).unwrap();
+        }
+        (tree, log)
+    }
+
+    #[test]
+    fn test_replay_log() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let (tree, log) = sample_log(hash);
+        assert_eq!(tree.root().unwrap(), "leaf1,0,leaf3,leaf4,leaf6");
+
+        let replayed = replay::<_, StringCodec>(&log, hash).unwrap();
+        assert_eq!(replayed.root(), tree.root());
+        assert_eq!(replayed.snapshot(), tree.snapshot());
+
+        // A record cut short by a crash is ignored
+        let mut torn = log.clone();
+        write_record::<_, StringCodec>(&mut torn, &LogRecord::Insert("leaf7".to_string())).unwrap();
+        torn.pop();
+        let replayed = replay::<_, StringCodec>(&torn, hash).unwrap();
+        assert_eq!(replayed.root(), tree.root());
+
+        assert_eq!(
+            replay::<_, StringCodec>(&log[..3], hash).unwrap_err(),
+            "Log does not start with a snapshot"
+        );
+
+        // A record that does not apply fails the replay
+        let mut invalid = log.clone();
+        write_record::<_, StringCodec>(&mut invalid, &LogRecord::Insert("leaf1".to_string()))
+            .unwrap();
+        assert_eq!(
+            replay::<_, StringCodec>(&invalid, hash).unwrap_err(),
+            "Leaf already exists"
+        );
+    }
+
+    #[test]
+    fn test_compact_log() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let (tree, log) = sample_log(hash);
+
+        let compacted = compact::<_, StringCodec>(&log, hash, 1).unwrap();
+        let records = read_log::<String, StringCodec>(&compacted).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[1], LogRecord::Update { .. }));
+        let replayed = replay::<_, StringCodec>(&compacted, hash).unwrap();
+        assert_eq!(replayed.root(), tree.root());
+
+        let compacted = compact::<_, StringCodec>(&log, hash, 0).unwrap();
+        assert_eq!(
+            read_log::<String, StringCodec>(&compacted).unwrap().len(),
+            1
+        );
+        let replayed = replay::<_, StringCodec>(&compacted, hash).unwrap();
+        assert_eq!(replayed.snapshot(), tree.snapshot());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_logged_tree() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let tree = LeanIMT::new(hash);
+        let log = WriteAheadLog::<_, StringCodec>::create(Vec::new(), &tree).unwrap();
+        let mut logged = LoggedLeanIMT::new(tree, log);
+
+        logged
+            .insert_many(vec!["leaf1".to_string(), "leaf2".to_string()])
+            .unwrap();
+        logged.set_duplicate_policy(DuplicatePolicy::Allow).unwrap();
+        logged.insert("leaf1".to_string()).unwrap();
+        let proof = logged.tree().generate_proof(2).unwrap();
+        logged
+            .remove_at(2, &"leaf1".to_string(), &proof.siblings)
+            .unwrap();
+        logged
+            .set_removal_policy(RemovalPolicy::RejectReinsertion)
+            .unwrap();
+        let proof = logged.tree().generate_proof(1).unwrap();
+        logged
+            .remove(&"leaf2".to_string(), &proof.siblings)
+            .unwrap();
+        logged
+            .insert_with_value("leaf3".to_string(), vec![3])
+            .unwrap();
+        logged.insert("leaf4".to_string()).unwrap();
+        let proof = logged.tree().generate_proof(0).unwrap();
+        logged
+            .update(&"leaf1".to_string(), "leaf5".to_string(), &proof.siblings)
+            .unwrap();
+        logged.truncate(4).unwrap();
+
+        // Operations that fail are not logged
+        let records = read_log::<String, StringCodec>(logged.log.get_ref()).unwrap();
+        assert!(logged.insert_many(vec![]).is_err());
+        assert_eq!(
+            logged.insert("leaf2".to_string()).unwrap_err(),
+            "Leaf was removed"
+        );
+        assert_eq!(
+            read_log::<String, StringCodec>(logged.log.get_ref()).unwrap(),
+            records
+        );
+
+        let (tree, log) = logged.into_parts();
+        let replayed = replay::<_, StringCodec>(log.get_ref(), hash).unwrap();
+        assert_eq!(replayed.snapshot(), tree.snapshot());
+        assert_eq!(replayed.get_duplicate_policy(), DuplicatePolicy::Allow);
+        assert_eq!(
+            replayed.get_removal_policy(),
+            RemovalPolicy::RejectReinsertion
+        );
+        assert_eq!(replayed.value_at(3).unwrap(), Some(vec![3]));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_logged_tree_with_leaf_hash() {
+        fn tag_leaf(leaf: String) -> String {
+            format!("H({})", leaf)
+        }
+
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut tree = LeanIMT::new(hash);
+        tree.set_leaf_hash(Some(tag_leaf));
+        let log = WriteAheadLog::<_, StringCodec>::create(Vec::new(), &tree).unwrap();
+        let mut logged = LoggedLeanIMT::new(tree, log);
+
+        logged.insert("a".to_string()).unwrap();
+        logged
+            .insert_many(vec!["b".to_string(), "c".to_string()])
+            .unwrap();
+        let proof = logged.tree().generate_proof(2).unwrap();
+        logged
+            .update_at(2, &"H(c)".to_string(), "d".to_string(), &proof.siblings)
+            .unwrap();
+        assert_eq!(logged.tree().root(), Some("H(a),H(b),H(d)".to_string()));
+
+        // The log holds the hashed leaves, which replay without the leaf hash
+        let (tree, log) = logged.into_parts();
+        let records = read_log::<String, StringCodec>(log.get_ref()).unwrap();
+        assert_eq!(records[1], LogRecord::Insert("H(a)".to_string()));
+        let replayed = replay::<_, StringCodec>(log.get_ref(), hash).unwrap();
+        assert_eq!(replayed.root(), tree.root());
+        assert_eq!(replayed.snapshot(), tree.snapshot());
+        let compacted = compact::<_, StringCodec>(log.get_ref(), hash, 1).unwrap();
+        let replayed = replay::<_, StringCodec>(&compacted, hash).unwrap();
+        assert_eq!(replayed.root(), tree.root());
+    }
+}
@@ -0,0 +1,280 @@
+//! `wasm-bindgen` bindings exposing the tree to JavaScript.
+//!
+//! The hash function is supplied by JavaScript as a function taking an array
+//! of node strings and returning the hashed node string. Each tree hashes
+//! with a shared hash function calling its JS function. A hash function that
+//! throws, or does not return a valid node, fails the call with an error, and
+//! the tree is restored to its state before the call: it is kept in a
+//! [`PersistentStore`], whose copies are cheap.
+//!
+//! Nodes cross the boundary as text produced by [`Codec`], independently of
+//! the node type used by the wrapped tree. Indices and sizes cross it as
+//! `u64`, JavaScript `BigInt`s, which do not depend on the 32-bit `usize` of
+//! wasm32.
+
+use std::cell::Cell;
+use std::sync::Arc;
+
+use js_sys::{Array, Function};
+use wasm_bindgen::prelude::*;
+
+use crate::codec::StringCodec;
+use crate::persistent_store::PersistentStore;
+use crate::{
+    IMTNode, LeafIndex, LeanIMT, LeanIMTSnapshot, MerkleProof, NodeCodec, SharedHashFunction,
+    TreeHash,
+};
+
+/// Node type of the wrapped tree.
+type Node = IMTNode;
+/// Codec translating nodes from and to the strings seen by JavaScript.
+type Codec = StringCodec;
+/// Tree wrapped by the bindings.
+type Tree = LeanIMT<Node, PersistentStore<Node>>;
+
+/// JS hash function of a tree, with the first error it raised since it was
+/// last checked.
+struct JsHash {
+    function: Function,
+    error: Cell<Option<&'static str>>,
+}
+
+// SAFETY: JS values can only be used on the thread of their JS realm. wasm32
+// without the `atomics` target feature runs on that thread only, so the hash
+// is never shared with or sent to another thread.
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Send for JsHash {}
+#[cfg(not(target_feature = "atomics"))]
+unsafe impl Sync for JsHash {}
+
+impl JsHash {
+    fn new(function: Function) -> Arc<Self> {
+        Arc::new(JsHash {
+            function,
+            error: Cell::new(None),
+        })
+    }
+
+    /// Hashes `nodes`, recording the error and returning an empty node if
+    /// the JS function fails.
+    fn call(&self, nodes: Vec<Node>) -> Node {
+        let nodes: Array = nodes
+            .iter()
+            .map(|node| JsValue::from(Codec::to_text(node)))
+            .collect();
+        let node = self
+            .function
+            .call1(&JsValue::NULL, &nodes)
+            .map_err(|_| "Hash function threw")
+            .and_then(|node| node.as_string().ok_or("Hash function must return a string"))
+            .and_then(|node| {
+                Codec::from_text(&node).map_err(|_| "Hash function must return a valid node")
+            });
+        node.unwrap_or_else(|error| {
+            if self.error.get().is_none() {
+                self.error.set(Some(error));
+            }
+            Node::default()
+        })
+    }
+
+    /// Returns the error raised since the last check, if any, and clears it.
+    fn take_error(&self) -> Option<&'static str> {
+        self.error.take()
+    }
+
+    fn shared(self: &Arc<Self>) -> TreeHash<Node> {
+        let hash = Arc::clone(self);
+        let shared: SharedHashFunction<Node> = Arc::new(move |nodes| hash.call(nodes));
+        TreeHash::Shared(shared)
+    }
+}
+
+fn decode(node: &str) -> Result<Node, JsError> {
+    Codec::from_text(node).map_err(JsError::new)
+}
+
+#[wasm_bindgen(js_name = LeanIMT)]
+pub struct WasmLeanIMT {
+    tree: Tree,
+    hash: Arc<JsHash>,
+}
+
+#[wasm_bindgen(js_class = LeanIMT)]
+impl WasmLeanIMT {
+    #[wasm_bindgen(constructor)]
+    pub fn new(hash: Function) -> Result<WasmLeanIMT, JsError> {
+        Self::create(hash, "0".to_string())
+    }
+
+    /// Creates a tree using `zero` as the value of removed leaves.
+    #[wasm_bindgen(js_name = withZero)]
+    pub fn with_zero(hash: Function, zero: String) -> Result<WasmLeanIMT, JsError> {
+        Self::create(hash, decode(&zero)?)
+    }
+
+    pub fn insert(&mut self, leaf: String) -> Result<String, JsError> {
+        let leaf = decode(&leaf)?;
+        let root = self.update(|tree| tree.insert(leaf))?;
+        Ok(Codec::to_text(&root))
+    }
+
+    #[wasm_bindgen(js_name = insertMany)]
+    pub fn insert_many(&mut self, leaves: Vec<String>) -> Result<String, JsError> {
+        let leaves = leaves
+            .iter()
+            .map(|leaf| decode(leaf))
+            .collect::<Result<_, _>>()?;
+        let root = self.update(|tree| tree.insert_many(leaves))?;
+        Ok(Codec::to_text(&root))
+    }
+
+    /// Returns the proof of the leaf at `index` as a JSON string.
+    #[wasm_bindgen(js_name = generateProof)]
+    pub fn generate_proof(&self, index: u64) -> Result<String, JsError> {
+        let index = LeafIndex(index).to_usize().map_err(JsError::new)?;
+        let proof = self.read(|tree| tree.generate_proof(index))?;
+        Ok(proof.to_json::<Codec>())
+    }
+
+    /// Checks a JSON proof against the current root of the tree.
+    pub fn verify(&self, proof: &str) -> Result<bool, JsError> {
+        let proof = MerkleProof::from_json::<Codec>(proof).map_err(JsError::new)?;
+        self.read(|tree| Ok(tree.verify_proof(&proof)))
+    }
+
+    pub fn root(&self) -> Option<String> {
+        self.tree.root().as_ref().map(Codec::to_text)
+    }
+
+    pub fn has(&self, leaf: String) -> Result<bool, JsError> {
+        Ok(self.tree.has(&decode(&leaf)?))
+    }
+
+    #[wasm_bindgen(js_name = indexOf)]
+    pub fn index_of(&self, leaf: String) -> Result<u64, JsError> {
+        let index = self.tree.leaf_index_of(&decode(&leaf)?);
+        index.map(LeafIndex::get).map_err(JsError::new)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn size(&self) -> u64 {
+        LeafIndex::from(self.tree.get_size()).get()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn depth(&self) -> usize {
+        self.tree.get_depth()
+    }
+
+    /// Exports the state of the tree as a JSON string.
+    #[wasm_bindgen(js_name = exportState)]
+    pub fn export_state(&self) -> Result<String, JsError> {
+        let snapshot = self.tree.snapshot().map_err(JsError::new)?;
+        Ok(serde_json::to_string(&snapshot.encode::<Codec>())?)
+    }
+
+    /// Restores a tree from a JSON string produced by `exportState`.
+    #[wasm_bindgen(js_name = importState)]
+    pub fn import_state(state: &str, hash: Function) -> Result<WasmLeanIMT, JsError> {
+        let snapshot: LeanIMTSnapshot<String> = serde_json::from_str(state)?;
+        let snapshot = LeanIMTSnapshot::decode::<Codec>(&snapshot).map_err(JsError::new)?;
+        let hash = JsHash::new(hash);
+        let tree = LeanIMT::restore(snapshot, hash.shared(), PersistentStore::default())
+            .map_err(JsError::new)?;
+        Ok(WasmLeanIMT { tree, hash })
+    }
+}
+
+impl WasmLeanIMT {
+    fn create(hash: Function, zero: Node) -> Result<WasmLeanIMT, JsError> {
+        let hash = JsHash::new(hash);
+        let tree = LeanIMT::create(hash.shared(), zero, PersistentStore::default())
+            .map_err(JsError::new)?;
+        Ok(WasmLeanIMT { tree, hash })
+    }
+
+    /// Runs an operation on the tree, restoring the tree if the hash
+    /// function failed during it.
+    fn update<T>(
+        &mut self,
+        operation: impl FnOnce(&mut Tree) -> Result<T, &'static str>,
+    ) -> Result<T, JsError> {
+        let backup = self.tree.fork();
+        let result = operation(&mut self.tree);
+        if let Some(error) = self.hash.take_error() {
+            self.tree = backup;
+            return Err(JsError::new(error));
+        }
+        result.map_err(JsError::new)
+    }
+
+    /// Runs a read-only operation on the tree, failing if the hash function
+    /// failed during it.
+    fn read<T>(
+        &self,
+        operation: impl FnOnce(&Tree) -> Result<T, &'static str>,
+    ) -> Result<T, JsError> {
+        let result = operation(&self.tree);
+        if let Some(error) = self.hash.take_error() {
+            return Err(JsError::new(error));
+        }
+        result.map_err(JsError::new)
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    /// Joins the nodes with commas, throwing on the node "bad".
+    fn join_hash() -> Function {
+        Function::new_with_args(
+            "nodes",
+            "if (nodes.includes('bad')) throw new Error('bad node'); return nodes.join(',');",
+        )
+    }
+
+    #[wasm_bindgen_test]
+    fn test_insert_and_prove() {
+        let mut tree = WasmLeanIMT::new(join_hash()).unwrap();
+        tree.insert("1".to_string()).unwrap();
+        let root = tree
+            .insert_many(vec!["2".to_string(), "3".to_string()])
+            .unwrap();
+        assert_eq!(root, "1,2,3");
+        assert_eq!(tree.size(), 3);
+        assert_eq!(tree.index_of("3".to_string()).unwrap(), 2);
+
+        let proof = tree.generate_proof(1).unwrap();
+        assert!(tree.verify(&proof).unwrap());
+        assert!(tree.generate_proof(3).is_err());
+        assert!(tree.generate_proof(u64::MAX).is_err());
+
+        let restored =
+            WasmLeanIMT::import_state(&tree.export_state().unwrap(), join_hash()).unwrap();
+        assert_eq!(restored.root(), tree.root());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_failing_hash_restores_the_tree() {
+        let mut tree = WasmLeanIMT::new(join_hash()).unwrap();
+        tree.insert("1".to_string()).unwrap();
+
+        // The hash throws when hashing "bad" with "1"
+        assert!(tree.insert("bad".to_string()).is_err());
+        assert_eq!(tree.size(), 1);
+        assert_eq!(tree.root(), Some("1".to_string()));
+        assert!(!tree.has("bad".to_string()).unwrap());
+
+        // An error does not outlive the call that raised it
+        assert_eq!(tree.insert("2".to_string()).unwrap(), "1,2");
+
+        let number = Function::new_with_args("nodes", "return nodes.length;");
+        let mut tree = WasmLeanIMT::new(number).unwrap();
+        tree.insert("1".to_string()).unwrap();
+        assert!(tree.insert("2".to_string()).is_err());
+        assert_eq!(tree.size(), 1);
+    }
+}
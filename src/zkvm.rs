@@ -0,0 +1,115 @@
+//! Support for recomputing trees inside RISC Zero and SP1 guests.
+//!
+//! A zkVM proves that a guest program ran on some inputs, so the guest must
+//! build without the standard library and behave the same on every run.
+//! Guests depend on the crate with `default-features = false` and the `zkvm`
+//! feature: the crate's hash maps then use a [`FixedState`] instead of a seed
+//! derived from memory addresses, and no operation reads a clock or a source
+//! of randomness. `MemoryStore::with_capacity` preallocates the store of a
+//! tree whose size is known, to keep allocations down.
+//!
+//! The guest commits the tree it recomputed to its journal as a
+//! [`RootCommitment`], and the host decodes it from the receipt. Neither SDK
+//! is a dependency: `commit_root` hands the encoded commitment to a closure,
+//! such as `env::commit_slice` of RISC Zero or `sp1_zkvm::io::commit_slice` of
+//! SP1.
+
+use alloc::vec::Vec;
+use core::hash::Hash;
+
+pub use foldhash::fast::FixedState;
+
+use crate::codec::NodeCodec;
+use crate::{LeanIMT, LeanIMTStore};
+
+/// Size and root of a tree, as committed to the journal of a guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootCommitment<N> {
+    pub size: u64,
+    pub root: Option<N>,
+}
+
+impl<N> RootCommitment<N> {
+    /// Encodes the commitment as the size, as 8 little-endian bytes, followed
+    /// by the root encoded with `C`, or nothing for an empty tree.
+    pub fn to_journal<C: NodeCodec<N>>(&self) -> Vec<u8> {
+        let mut bytes = self.size.to_le_bytes().to_vec();
+        if let Some(root) = &self.root {
+            bytes.extend(C::encode(root));
+        }
+        bytes
+    }
+
+    /// Decodes a commitment written by `to_journal`.
+    ///
+    /// Fails with "Invalid journal" if the size is missing, or if a root is
+    /// missing or present when it should not be.
+    pub fn from_journal<C: NodeCodec<N>>(bytes: &[u8]) -> Result<Self, &'static str> {
+        let (size, root) = bytes.split_first_chunk().ok_or("Invalid journal")?;
+        let size = u64::from_le_bytes(*size);
+        let root = match (size, root.is_empty()) {
+            (0, true) => None,
+            (0, false) | (_, true) => return Err("Invalid journal"),
+            _ => Some(C::decode(root)?),
+        };
+        Ok(RootCommitment { size, root })
+    }
+}
+
+impl<N: Clone + Eq + Hash, S: LeanIMTStore<N>> LeanIMT<N, S> {
+    /// Returns the size and root of the tree.
+    pub fn root_commitment(&self) -> RootCommitment<N> {
+        RootCommitment {
+            size: self.size as u64,
+            root: self.root(),
+        }
+    }
+
+    /// Passes the root commitment of the tree, encoded with `C`, to
+    /// `commit`, e.g. `|bytes| env::commit_slice(bytes)` in a RISC Zero
+    /// guest.
+    pub fn commit_root<C: NodeCodec<N>>(&self, commit: impl FnOnce(&[u8])) {
+        commit(&self.root_commitment().to_journal::<C>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StringCodec;
+    use crate::{HashMap, IMTHashFunction};
+    use alloc::string::String;
+
+    fn simple_hash_function(nodes: Vec<String>) -> String {
+        nodes.join(",")
+    }
+
+    #[test]
+    fn test_root_commitment() {
+        let hash: IMTHashFunction = simple_hash_function;
+        let mut imt = LeanIMT::new(hash);
+        let mut journal = Vec::new();
+        imt.commit_root::<StringCodec>(|bytes| journal.extend_from_slice(bytes));
+        assert_eq!(journal, [0; 8]);
+
+        imt.insert_many(["a", "b", "c"].map(String::from).to_vec())
+            .unwrap();
+        journal.clear();
+        imt.commit_root::<StringCodec>(|bytes| journal.extend_from_slice(bytes));
+        assert_eq!(journal[..8], [3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(&journal[8..], b"a,b,c");
+        assert_eq!(
+            RootCommitment::from_journal::<StringCodec>(&journal),
+            Ok(imt.root_commitment())
+        );
+        assert_eq!(
+            RootCommitment::<String>::from_journal::<StringCodec>(&journal[..8]),
+            Err("Invalid journal")
+        );
+
+        // Maps filled alike iterate alike
+        let maps: Vec<HashMap<usize, usize>> =
+            (0..2).map(|_| (0..64).map(|i| (i, i)).collect()).collect();
+        assert!(maps[0].keys().eq(maps[1].keys()));
+    }
+}